@@ -1,10 +1,16 @@
 //! Create a timer using the Machine Timer registers.
 
 use crate::csr;
+use core::cell::Cell;
 use kernel::common::cells::OptionalCell;
 use kernel::common::registers::{register_bitfields, ReadOnly, ReadWrite};
 use kernel::common::StaticRef;
 use kernel::hil;
+use kernel::hil::time::{FreqTicks, Ticks};
+use kernel::ReturnCode;
+
+/// The minimum number of tics between `mtime` and a compare value.
+const MIN_DT: u32 = 1;
 
 #[repr(C)]
 pub struct MachineTimerRegisters {
@@ -26,6 +32,8 @@ register_bitfields![u64,
 pub struct MachineTimer<'a> {
     registers: StaticRef<MachineTimerRegisters>,
     client: OptionalCell<&'a dyn hil::time::AlarmClient>,
+    /// Token passed to the most recent `set_alarm_with_token`.
+    token: Cell<u32>,
 }
 
 impl MachineTimer<'a> {
@@ -33,6 +41,7 @@ impl MachineTimer<'a> {
         MachineTimer {
             registers: base,
             client: OptionalCell::empty(),
+            token: Cell::new(0),
         }
     }
 
@@ -40,7 +49,7 @@ impl MachineTimer<'a> {
         self.disable_machine_timer();
 
         self.client.map(|client| {
-            client.fired();
+            client.fired_with_token(self.token.get());
         });
     }
 
@@ -55,13 +64,10 @@ impl MachineTimer<'a> {
 
 impl hil::time::Time for MachineTimer<'a> {
     type Frequency = hil::time::Freq32KHz;
+    type Ticks = FreqTicks<hil::time::Freq32KHz>;
 
-    fn now(&self) -> u32 {
-        self.registers.mtime.get() as u32
-    }
-
-    fn max_tics(&self) -> u32 {
-        core::u32::MAX
+    fn now(&self) -> Self::Ticks {
+        FreqTicks::new(self.registers.mtime.get() as u32)
     }
 }
 
@@ -70,19 +76,50 @@ impl hil::time::Alarm<'a> for MachineTimer<'a> {
         self.client.set(client);
     }
 
-    fn set_alarm(&self, tics: u32) {
+    fn clear_client(&self) {
+        self.disable();
+        self.client.clear();
+    }
+
+    /// `mtime` counts whenever the hart is clocked and cannot be stopped, so
+    /// this never returns `ReturnCode::EOFF`.
+    fn set_alarm_with_token(&self, tics: Self::Ticks, token: u32) -> ReturnCode {
+        let now = self.registers.mtime.get();
+        let tics = tics.into_u32();
+        // Reached at most `MIN_DT` tics ago: fire as soon as possible.
+        let tics = if hil::time::reached_within(now as u32, tics, MIN_DT, u32::max_value()) {
+            (now as u32).wrapping_add(MIN_DT)
+        } else {
+            tics
+        };
+        let dt = tics.wrapping_sub(now as u32);
+        if dt < MIN_DT {
+            return ReturnCode::EINVAL;
+        }
+
+        // mtimecmp is compared with the full 64-bit mtime using `>=`, so
+        // `tics` on its own would fire at once whenever it is numerically
+        // below the counter. Compare against the next time the low 32 bits
+        // of mtime reach `tics` instead.
         self.registers
             .mtimecmp
-            .write(MTimeCmp::MTIMECMP.val(tics as u64));
+            .write(MTimeCmp::MTIMECMP.val(now + dt as u64));
         csr::CSR.mie.modify(csr::mie::mie::mtimer::SET);
+        self.token.set(token);
+        ReturnCode::SUCCESS
+    }
+
+    fn get_alarm(&self) -> Self::Ticks {
+        FreqTicks::new(self.registers.mtimecmp.get() as u32)
     }
 
-    fn get_alarm(&self) -> u32 {
-        self.registers.mtimecmp.get() as u32
+    fn minimum_dt(&self) -> Self::Ticks {
+        FreqTicks::new(MIN_DT)
     }
 
-    fn disable(&self) {
+    fn disable(&self) -> ReturnCode {
         self.disable_machine_timer();
+        ReturnCode::SUCCESS
     }
 
     fn is_enabled(&self) -> bool {
@@ -1,7 +1,7 @@
 #![allow(dead_code)]
 
 use kernel::debug;
-use kernel::hil::time::{self, Alarm};
+use kernel::hil::time::{self, Alarm, Ticks};
 
 pub struct TimerTest<'a, A: Alarm<'a>> {
     alarm: &'a A,
@@ -15,7 +15,7 @@ impl<A: Alarm<'a>> TimerTest<'a, A> {
     pub fn start(&self) {
         debug!("starting");
         let start = self.alarm.now();
-        let exp = start + 99999;
+        let exp = start.wrapping_add(A::Ticks::from_u32(99999));
         self.alarm.set_alarm(exp);
     }
 }
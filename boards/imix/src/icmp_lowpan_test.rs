@@ -33,8 +33,8 @@ use capsules::virtual_alarm::{MuxAlarm, VirtualMuxAlarm};
 use core::cell::Cell;
 use kernel::debug;
 use kernel::hil::radio;
-use kernel::hil::time::Frequency;
 use kernel::hil::time::{self, Alarm};
+use kernel::hil::time::{Frequency, Ticks};
 use kernel::static_init;
 use kernel::ReturnCode;
 
@@ -181,7 +181,7 @@ impl<A: time::Alarm<'a>> LowpanICMPTest<'a, A> {
 
     fn schedule_next(&self) {
         let delta = (A::Frequency::frequency() * TEST_DELAY_MS) / 1000;
-        let next = self.alarm.now().wrapping_add(delta);
+        let next = self.alarm.now().wrapping_add(A::Ticks::from_u32(delta));
         self.alarm.set_alarm(next);
     }
 
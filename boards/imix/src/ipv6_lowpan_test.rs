@@ -52,8 +52,8 @@ use core::cell::Cell;
 use core::ptr;
 use kernel::debug;
 use kernel::hil::radio;
-use kernel::hil::time::Frequency;
 use kernel::hil::time::{self, Alarm};
+use kernel::hil::time::{Frequency, Ticks};
 use kernel::static_init;
 use kernel::ReturnCode;
 
@@ -246,7 +246,7 @@ impl<'a, A: time::Alarm<'a>> LowpanTest<'a, A> {
 
     fn schedule_next(&self) {
         let delta = (A::Frequency::frequency() * TEST_DELAY_MS) / 5000;
-        let next = self.alarm.now().wrapping_add(delta);
+        let next = self.alarm.now().wrapping_add(A::Ticks::from_u32(delta));
         self.alarm.set_alarm(next);
     }
 
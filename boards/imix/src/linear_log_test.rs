@@ -21,7 +21,7 @@ use kernel::common::dynamic_deferred_call::DynamicDeferredCall;
 use kernel::debug;
 use kernel::hil::flash;
 use kernel::hil::log::{LogRead, LogReadClient, LogWrite, LogWriteClient};
-use kernel::hil::time::{Alarm, AlarmClient, Frequency};
+use kernel::hil::time::{Alarm, AlarmClient, Frequency, Ticks};
 use kernel::static_init;
 use kernel::storage_volume;
 use kernel::ReturnCode;
@@ -238,7 +238,7 @@ impl<A: Alarm<'static>> LogTest<A> {
 
     fn wait(&self) {
         let interval = WAIT_MS * <A::Frequency>::frequency() / 1000;
-        let tics = self.alarm.now().wrapping_add(interval);
+        let tics = self.alarm.now().wrapping_add(A::Ticks::from_u32(interval));
         self.alarm.set_alarm(tics);
     }
 }
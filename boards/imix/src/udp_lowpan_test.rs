@@ -126,8 +126,8 @@ use capsules::virtual_alarm::{MuxAlarm, VirtualMuxAlarm};
 use core::cell::Cell;
 use kernel::component::Component;
 use kernel::debug;
-use kernel::hil::time::Frequency;
 use kernel::hil::time::{self, Alarm};
+use kernel::hil::time::{Frequency, Ticks};
 use kernel::static_init;
 use kernel::ReturnCode;
 
@@ -247,7 +247,7 @@ impl<'a, A: time::Alarm<'a>> LowpanTest<'a, A> {
 
     fn schedule_next(&self) {
         let delta = (A::Frequency::frequency() * TEST_DELAY_MS) / 1000;
-        let next = self.alarm.now().wrapping_add(delta);
+        let next = self.alarm.now().wrapping_add(A::Ticks::from_u32(delta));
         self.alarm.set_alarm(next);
     }
 
@@ -1,7 +1,7 @@
 //! Provides userspace applications with a alarm API.
 
 use core::cell::Cell;
-use kernel::hil::time::{self, Alarm, Frequency};
+use kernel::hil::time::{self, Alarm, Ticks};
 use kernel::{AppId, Callback, Driver, Grant, ReturnCode};
 
 /// Syscall driver number.
@@ -63,7 +63,7 @@ impl<A: Alarm<'a>> AlarmDriver<'a, A> {
             });
         }
         if next_alarm != u32::max_value() {
-            self.alarm.set_alarm(next_alarm);
+            self.alarm.set_alarm(A::Ticks::from_u32(next_alarm));
             Some(next_alarm)
         } else {
             None
@@ -108,11 +108,11 @@ impl<A: Alarm<'a>> Driver for AlarmDriver<'a, A> {
         // (i.e. no change to the alarms).
         self.app_alarm
             .enter(caller_id, |td, _alloc| {
-                let now = self.alarm.now();
+                let now = self.alarm.now().into_u32();
                 let (return_code, reset) = match cmd_type {
                     0 /* check if present */ => (ReturnCode::SuccessWithValue { value: 1 }, false),
                     1 /* Get clock frequency */ => {
-                        let freq = <A::Frequency>::frequency() as usize;
+                        let freq = self.alarm.frequency() as usize;
                         (ReturnCode::SuccessWithValue { value: freq }, false)
                     },
                     2 /* capture time */ => {
@@ -164,7 +164,7 @@ fn has_expired(alarm: u32, now: u32, prev: u32) -> bool {
 
 impl<A: Alarm<'a>> time::AlarmClient for AlarmDriver<'a, A> {
     fn fired(&self) {
-        let now = self.alarm.now();
+        let now = self.alarm.now().into_u32();
         self.app_alarm.each(|alarm| {
             if let Expiration::Abs(exp) = alarm.expiration {
                 let expired = has_expired(exp, now, self.prev.get());
@@ -183,7 +183,7 @@ impl<A: Alarm<'a>> time::AlarmClient for AlarmDriver<'a, A> {
         if self.num_armed.get() == 0 {
             self.alarm.disable();
         } else if let Some(next_alarm) = self.reset_active_alarm(now) {
-            let new_now = self.alarm.now();
+            let new_now = self.alarm.now().into_u32();
             if has_expired(next_alarm, new_now, now) {
                 self.fired();
             }
@@ -0,0 +1,399 @@
+//! Provide a `Timer` on top of an `Alarm`.
+//!
+//! `AlarmToTimer` lets capsules written against `hil::time::Timer` run on a
+//! board that only provides an `Alarm`. A oneshot timer arms the alarm once;
+//! a repeating timer re-arms it from the alarm callback, starting each period
+//! at the previous deadline so that it does not drift. Use a `TimerWheel`
+//! instead if many timers share the same alarm.
+//!
+//! The alarm is armed and disarmed by the adapter, so it must not be used for
+//! anything else, e.g. it should be its own virtual alarm. Its client must be
+//! the adapter.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust
+//! let timer = static_init!(
+//!     capsules::alarm_to_timer::AlarmToTimer<'static, VirtualMuxAlarm<'static, sam4l::ast::Ast>>,
+//!     capsules::alarm_to_timer::AlarmToTimer::new(virtual_alarm)
+//! );
+//! virtual_alarm.set_client(timer);
+//! hil::time::Timer::set_client(timer, client);
+//! ```
+
+use core::cell::Cell;
+use kernel::common::cells::OptionalCell;
+use kernel::hil::time::{self, Alarm, Ticks, Time, Timer};
+use kernel::ReturnCode;
+
+pub struct AlarmToTimer<'a, A: Alarm<'a>> {
+    alarm: &'a A,
+    /// When the timer was last armed, or its previous deadline for a
+    /// repeating timer.
+    reference: Cell<A::Ticks>,
+    /// Tics from `reference` until the timer fires.
+    dt: Cell<A::Ticks>,
+    /// The reload interval of a repeating timer.
+    interval: Cell<Option<A::Ticks>>,
+    /// Fires left of a `repeat_n` sequence, or `0` for no limit.
+    fires_left: Cell<u32>,
+    armed: Cell<bool>,
+    /// Tics that were left when the timer was paused.
+    paused: Cell<Option<A::Ticks>>,
+    /// Set while the client is being called.
+    firing: Cell<bool>,
+    client: OptionalCell<&'a dyn time::TimerClient>,
+}
+
+impl<A: Alarm<'a>> AlarmToTimer<'a, A> {
+    pub fn new(alarm: &'a A) -> AlarmToTimer<'a, A> {
+        AlarmToTimer {
+            alarm,
+            reference: Cell::new(A::Ticks::from_u32(0)),
+            dt: Cell::new(A::Ticks::from_u32(0)),
+            interval: Cell::new(None),
+            fires_left: Cell::new(0),
+            armed: Cell::new(false),
+            paused: Cell::new(None),
+            firing: Cell::new(false),
+            client: OptionalCell::empty(),
+        }
+    }
+
+    fn deadline(&self) -> A::Ticks {
+        self.reference.get().wrapping_add(self.dt.get())
+    }
+
+    fn arm(&self, interval: A::Ticks, repeat: Option<A::Ticks>) {
+        self.reference.set(self.alarm.now());
+        self.dt.set(interval);
+        self.interval.set(repeat);
+        self.fires_left.set(0);
+        self.armed.set(true);
+        self.paused.set(None);
+        self.alarm.set_alarm_from(self.reference.get(), interval);
+    }
+}
+
+impl<A: Alarm<'a>> Time for AlarmToTimer<'a, A> {
+    type Frequency = A::Frequency;
+    type Ticks = A::Ticks;
+    const WIDTH: u32 = A::WIDTH;
+
+    fn now(&self) -> A::Ticks {
+        self.alarm.now()
+    }
+
+    fn max_tics(&self) -> A::Ticks {
+        self.alarm.max_tics()
+    }
+
+    fn frequency(&self) -> u32 {
+        self.alarm.frequency()
+    }
+
+    fn persists_in_sleep(&self) -> bool {
+        self.alarm.persists_in_sleep()
+    }
+}
+
+impl<A: Alarm<'a>> Timer<'a> for AlarmToTimer<'a, A> {
+    fn set_client(&'a self, client: &'a dyn time::TimerClient) {
+        self.client.set(client);
+    }
+
+    fn oneshot(&self, interval: A::Ticks) {
+        self.arm(interval, None);
+    }
+
+    fn repeat(&self, interval: A::Ticks) {
+        self.arm(interval, Some(interval));
+    }
+
+    fn repeat_n(&self, interval: A::Ticks, count: u32) -> ReturnCode {
+        if interval == A::Ticks::from_u32(0) {
+            return ReturnCode::EINVAL;
+        }
+        self.arm(interval, Some(interval));
+        self.fires_left.set(count);
+        ReturnCode::SUCCESS
+    }
+
+    fn min_tick_divisor(&self) -> u32 {
+        self.alarm.minimum_dt().into_u32()
+    }
+
+    fn interval(&self) -> Option<A::Ticks> {
+        if self.armed.get() {
+            self.interval.get()
+        } else {
+            None
+        }
+    }
+
+    fn change_interval(&self, interval: A::Ticks) -> ReturnCode {
+        if self.interval().is_none() {
+            return ReturnCode::EINVAL;
+        }
+        self.interval.set(Some(interval));
+        if self.firing.get() {
+            // The period starting with this fire has been reloaded with the
+            // old interval but not armed yet; replace it.
+            self.dt.set(interval);
+        }
+        ReturnCode::SUCCESS
+    }
+
+    fn time_remaining(&self) -> Option<A::Ticks> {
+        if !self.armed.get() {
+            return None;
+        }
+        let now = self.alarm.now();
+        if time::has_expired(self.reference.get(), self.deadline(), now) {
+            Some(A::Ticks::from_u32(0))
+        } else {
+            Some(self.deadline().wrapping_sub(now))
+        }
+    }
+
+    fn cancel(&self) {
+        self.armed.set(false);
+        self.interval.set(None);
+        self.fires_left.set(0);
+        self.paused.set(None);
+        self.alarm.disable();
+    }
+
+    fn pause(&self) -> ReturnCode {
+        if self.paused.get().is_some() {
+            return ReturnCode::SUCCESS;
+        }
+        match self.time_remaining() {
+            Some(remaining) => {
+                self.paused.set(Some(remaining));
+                self.armed.set(false);
+                self.alarm.disable();
+                ReturnCode::SUCCESS
+            }
+            None => ReturnCode::EINVAL,
+        }
+    }
+
+    fn resume(&self) -> ReturnCode {
+        match self.paused.take() {
+            Some(remaining) => {
+                self.reference.set(self.now());
+                self.dt.set(remaining);
+                self.armed.set(true);
+                self.alarm.set_alarm_from(self.reference.get(), remaining);
+                ReturnCode::SUCCESS
+            }
+            None => ReturnCode::EINVAL,
+        }
+    }
+}
+
+impl<A: Alarm<'a>> time::AlarmClient for AlarmToTimer<'a, A> {
+    fn fired(&self) {
+        if !self.armed.get() {
+            return;
+        }
+
+        // Update the timer before calling the client, so that a client that
+        // re-arms or cancels the timer has the last word.
+        let reload = match (self.interval.get(), self.fires_left.get()) {
+            (Some(_), 1) | (None, _) => {
+                // A oneshot, or the last fire of a `repeat_n` sequence.
+                self.interval.set(None);
+                self.armed.set(false);
+                None
+            }
+            (Some(interval), left) => {
+                self.fires_left.set(left.saturating_sub(1));
+                self.reference.set(self.deadline());
+                self.dt.set(interval);
+                Some(interval)
+            }
+        };
+        self.firing.set(true);
+        self.client.map(|client| client.fired());
+        self.firing.set(false);
+
+        // Re-arm a repeating timer unless the client cancelled it or made it
+        // a oneshot, which has already set up the alarm.
+        if reload.is_some() && self.armed.get() && self.interval.get().is_some() {
+            self.alarm
+                .set_alarm_from(self.reference.get(), self.dt.get());
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::AlarmToTimer;
+    use core::cell::Cell;
+    use kernel::hil::time::mock::MockAlarm;
+    use kernel::hil::time::{Alarm, Time, Timer, TimerClient};
+    use kernel::ReturnCode;
+
+    type TestTimer<'a> = AlarmToTimer<'a, MockAlarm<'a>>;
+
+    struct Client<'a> {
+        fired: Cell<usize>,
+        fired_at: Cell<u32>,
+        alarm: &'a MockAlarm<'a>,
+        /// A timer to cancel from the callback.
+        cancel: Cell<Option<&'a TestTimer<'a>>>,
+    }
+
+    impl Client<'a> {
+        fn new(alarm: &'a MockAlarm<'a>) -> Client<'a> {
+            Client {
+                fired: Cell::new(0),
+                fired_at: Cell::new(0),
+                alarm,
+                cancel: Cell::new(None),
+            }
+        }
+    }
+
+    impl TimerClient for Client<'a> {
+        fn fired(&self) {
+            self.fired.set(self.fired.get() + 1);
+            self.fired_at.set(self.alarm.now());
+            if let Some(timer) = self.cancel.take() {
+                timer.cancel();
+            }
+        }
+    }
+
+    #[test]
+    fn test_oneshot() {
+        let alarm = MockAlarm::new();
+        let timer = AlarmToTimer::new(&alarm);
+        let client = Client::new(&alarm);
+        alarm.set_client(&timer);
+        timer.set_client(&client);
+
+        alarm.advance(5);
+        timer.oneshot(10);
+        assert!(timer.is_oneshot());
+        assert_eq!(alarm.get_alarm(), 15);
+        alarm.advance(4);
+        assert_eq!(timer.time_remaining(), Some(6));
+
+        alarm.advance(20);
+        assert_eq!(client.fired.get(), 1);
+        assert_eq!(client.fired_at.get(), 15);
+        assert_eq!(timer.time_remaining(), None);
+        assert!(!alarm.is_enabled());
+    }
+
+    #[test]
+    fn test_repeating_timer_does_not_drift() {
+        let alarm = MockAlarm::new();
+        let timer = AlarmToTimer::new(&alarm);
+        let client = Client::new(&alarm);
+        alarm.set_client(&timer);
+        timer.set_client(&client);
+
+        timer.repeat(10);
+        assert!(timer.is_repeating());
+        alarm.advance(35);
+        assert_eq!(client.fired.get(), 3);
+        assert_eq!(client.fired_at.get(), 30);
+        assert_eq!(timer.time_remaining(), Some(5));
+        assert_eq!(timer.interval(), Some(10));
+    }
+
+    #[test]
+    fn test_cancel_from_callback() {
+        let alarm = MockAlarm::new();
+        let timer = AlarmToTimer::new(&alarm);
+        let client = Client::new(&alarm);
+        alarm.set_client(&timer);
+        timer.set_client(&client);
+
+        timer.repeat(10);
+        client.cancel.set(Some(&timer));
+        alarm.advance(100);
+        assert_eq!(client.fired.get(), 1);
+        assert!(!timer.is_enabled());
+        assert!(!alarm.is_enabled());
+    }
+
+    #[test]
+    fn test_repeat_n() {
+        let alarm = MockAlarm::new();
+        let timer = AlarmToTimer::new(&alarm);
+        let client = Client::new(&alarm);
+        alarm.set_client(&timer);
+        timer.set_client(&client);
+
+        assert_eq!(timer.repeat_n(0, 2), ReturnCode::EINVAL);
+        assert_eq!(timer.repeat_n(10, 3), ReturnCode::SUCCESS);
+        alarm.advance(100);
+        assert_eq!(client.fired.get(), 3);
+        assert_eq!(client.fired_at.get(), 30);
+        assert!(!timer.is_enabled());
+        assert!(!alarm.is_enabled());
+    }
+
+    #[test]
+    fn test_tick_callback() {
+        let alarm = MockAlarm::new();
+        let timer = AlarmToTimer::new(&alarm);
+        let client = Client::new(&alarm);
+        alarm.set_client(&timer);
+        timer.set_client(&client);
+
+        assert_eq!(timer.min_tick_divisor(), alarm.minimum_dt());
+        assert_eq!(timer.set_tick_callback(0), ReturnCode::ENOSUPPORT);
+        assert!(!timer.is_enabled());
+        assert_eq!(timer.set_tick_callback(1), ReturnCode::SUCCESS);
+        alarm.advance(10);
+        assert_eq!(client.fired.get(), 10);
+        assert_eq!(timer.interval(), Some(1));
+    }
+
+    #[test]
+    fn test_change_interval() {
+        let alarm = MockAlarm::new();
+        let timer = AlarmToTimer::new(&alarm);
+        let client = Client::new(&alarm);
+        alarm.set_client(&timer);
+        timer.set_client(&client);
+
+        assert_eq!(timer.change_interval(5), ReturnCode::EINVAL);
+        timer.repeat(10);
+        alarm.advance(4);
+        assert_eq!(timer.change_interval(20), ReturnCode::SUCCESS);
+        alarm.advance(6);
+        assert_eq!(client.fired.get(), 1);
+        assert_eq!(alarm.get_alarm(), 30);
+    }
+
+    #[test]
+    fn test_pause_resume() {
+        let alarm = MockAlarm::new();
+        let timer = AlarmToTimer::new(&alarm);
+        let client = Client::new(&alarm);
+        alarm.set_client(&timer);
+        timer.set_client(&client);
+
+        timer.repeat(10);
+        alarm.advance(13);
+        assert_eq!(timer.pause(), ReturnCode::SUCCESS);
+        assert!(!alarm.is_enabled());
+        alarm.advance(100);
+        assert_eq!(client.fired.get(), 1);
+
+        assert_eq!(timer.resume(), ReturnCode::SUCCESS);
+        assert_eq!(alarm.get_alarm(), 120);
+        alarm.advance(17);
+        assert_eq!(client.fired.get(), 3);
+        assert_eq!(client.fired_at.get(), 130);
+        assert_eq!(timer.resume(), ReturnCode::EINVAL);
+    }
+}
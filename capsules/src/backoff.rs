@@ -0,0 +1,142 @@
+//! Exponential backoff for retries scheduled on an `Alarm`.
+//!
+//! `Backoff` arms an alarm for the next retry of an operation that failed,
+//! e.g. a radio transmission or a bus transaction, waiting twice as long
+//! each time up to a maximum. The capsule retrying is the client of the
+//! alarm and retries when it fires; once the operation succeeds it calls
+//! `reset()` so that the next failure starts from the base delay again.
+//!
+//! To keep several devices that failed at the same time from retrying in
+//! lockstep, each delay can be shortened by up to `jitter_percent` percent.
+//! The HIL has no source of randomness, so the caller passes a random value
+//! to `arm_next_with_jitter`, e.g. one it got from an `Rng` earlier.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust
+//! let backoff = static_init!(
+//!     capsules::backoff::Backoff<'static, VirtualMuxAlarm<'static, sam4l::ast::Ast>>,
+//!     capsules::backoff::Backoff::new(virtual_alarm, 100, 10_000, 25)
+//! );
+//! virtual_alarm.set_client(retrying_capsule);
+//! // On failure:
+//! backoff.arm_next();
+//! // On success:
+//! backoff.reset();
+//! ```
+
+use core::cell::Cell;
+use kernel::hil::time::{Alarm, Ticks};
+use kernel::ReturnCode;
+
+pub struct Backoff<'a, A: Alarm<'a>> {
+    alarm: &'a A,
+    base: A::Ticks,
+    max: A::Ticks,
+    jitter_percent: u32,
+    /// The delay before the next retry.
+    delay: Cell<A::Ticks>,
+}
+
+impl<A: Alarm<'a>> Backoff<'a, A> {
+    /// Creates a backoff whose first delay is `base` tics, doubling up to
+    /// `max` tics. `jitter_percent` is at most 100; `0` disables jitter.
+    pub fn new(alarm: &'a A, base: A::Ticks, max: A::Ticks, jitter_percent: u32) -> Backoff<'a, A> {
+        Backoff {
+            alarm,
+            base,
+            max,
+            jitter_percent: core::cmp::min(jitter_percent, 100),
+            delay: Cell::new(core::cmp::min(base, max)),
+        }
+    }
+
+    /// Arms the alarm for the next retry, the current delay from now, and
+    /// doubles the delay for the one after, up to the maximum.
+    ///
+    /// Returns what `set_alarm_from` returns.
+    pub fn arm_next(&self) -> ReturnCode {
+        self.arm(self.delay.get())
+    }
+
+    /// Like `arm_next`, but shortens the delay by `random` modulo up to
+    /// `jitter_percent` percent of it. `random` should be uniformly
+    /// distributed, and different for every call.
+    pub fn arm_next_with_jitter(&self, random: u32) -> ReturnCode {
+        let delay = self.delay.get().into_u32();
+        let spread = (delay as u64 * self.jitter_percent as u64 / 100) as u32;
+        let offset = if spread == u32::max_value() {
+            random
+        } else {
+            random % (spread + 1)
+        };
+        self.arm(A::Ticks::from_u32(delay - offset))
+    }
+
+    /// Goes back to the base delay, e.g. after the operation succeeded.
+    /// Does not touch an armed alarm.
+    pub fn reset(&self) {
+        self.delay.set(core::cmp::min(self.base, self.max));
+    }
+
+    /// The delay `arm_next` arms the alarm with next, in tics.
+    pub fn next_delay(&self) -> A::Ticks {
+        self.delay.get()
+    }
+
+    fn arm(&self, dt: A::Ticks) -> ReturnCode {
+        let delay = self.delay.get().into_u32();
+        let doubled = A::Ticks::from_u32(delay.saturating_mul(2));
+        self.delay.set(core::cmp::min(doubled, self.max));
+        self.alarm.set_alarm_from(self.alarm.now(), dt)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::Backoff;
+    use kernel::hil::time::mock::MockAlarm;
+    use kernel::hil::time::Alarm;
+    use kernel::ReturnCode;
+
+    #[test]
+    fn test_doubles_up_to_max() {
+        let alarm = MockAlarm::new();
+        let backoff = Backoff::new(&alarm, 10, 50, 0);
+
+        assert_eq!(backoff.arm_next(), ReturnCode::SUCCESS);
+        assert_eq!(alarm.get_alarm(), 10);
+        alarm.advance(10);
+        backoff.arm_next();
+        assert_eq!(alarm.get_alarm(), 30);
+        alarm.advance(20);
+        backoff.arm_next();
+        assert_eq!(alarm.get_alarm(), 70);
+        alarm.advance(40);
+        backoff.arm_next();
+        assert_eq!(alarm.get_alarm(), 120);
+        assert_eq!(backoff.next_delay(), 50);
+
+        backoff.reset();
+        assert_eq!(backoff.next_delay(), 10);
+    }
+
+    #[test]
+    fn test_jitter() {
+        let alarm = MockAlarm::new();
+        let backoff = Backoff::new(&alarm, 40, 1000, 50);
+
+        // Up to 20 tics shorter.
+        backoff.arm_next_with_jitter(7);
+        assert_eq!(alarm.get_alarm(), 33);
+        assert_eq!(backoff.next_delay(), 80);
+        backoff.arm_next_with_jitter(41 + 40);
+        assert_eq!(alarm.get_alarm(), 40);
+
+        // Without jitter the random value is ignored.
+        let backoff = Backoff::new(&alarm, 40, 1000, 0);
+        backoff.arm_next_with_jitter(7);
+        assert_eq!(alarm.get_alarm(), 40);
+    }
+}
@@ -101,7 +101,7 @@ use kernel::common::cells::OptionalCell;
 use kernel::debug;
 use kernel::hil::ble_advertising;
 use kernel::hil::ble_advertising::RadioChannel;
-use kernel::hil::time::Frequency;
+use kernel::hil::time::{Frequency, Ticks};
 use kernel::ReturnCode;
 
 /// Syscall driver number.
@@ -337,7 +337,7 @@ where
     // since any open grant will not be iterated over and the wrong timer will
     // likely be chosen.
     fn reset_active_alarm(&self) {
-        let now = self.alarm.now();
+        let now = self.alarm.now().into_u32();
         let mut next_alarm = u32::max_value();
         let mut next_dist = u32::max_value();
         for app in self.app.iter() {
@@ -353,7 +353,7 @@ where
             });
         }
         if next_alarm != u32::max_value() {
-            self.alarm.set_alarm(next_alarm);
+            self.alarm.set_alarm(A::Ticks::from_u32(next_alarm));
         }
     }
 }
@@ -377,7 +377,7 @@ where
     // TODO: perhaps break ties more fairly by prioritizing apps that have least
     // recently performed an operation.
     fn fired(&self) {
-        let now = self.alarm.now();
+        let now = self.alarm.now().into_u32();
 
         self.app.each(|app| {
             if let Expiration::Abs(exp) = app.alarm_data.expiration {
@@ -390,7 +390,7 @@ where
                         // operation for later. This is _kind_ of simulating actual
                         // on-air interference
                         debug!("BLE: operation delayed for app {:?}", app.appid());
-                        app.set_next_alarm::<A::Frequency>(self.alarm.now());
+                        app.set_next_alarm::<A::Frequency>(self.alarm.now().into_u32());
                         return;
                     }
 
@@ -483,7 +483,7 @@ where
                     Some(BLEState::Scanning(RadioChannel::AdvertisingChannel39)) => {
                         self.busy.set(false);
                         app.process_status = Some(BLEState::ScanningIdle);
-                        app.set_next_alarm::<A::Frequency>(self.alarm.now());
+                        app.set_next_alarm::<A::Frequency>(self.alarm.now().into_u32());
                     }
                     // Invalid state => don't care
                     _ => (),
@@ -524,7 +524,7 @@ where
                     Some(BLEState::Advertising(RadioChannel::AdvertisingChannel39)) => {
                         self.busy.set(false);
                         app.process_status = Some(BLEState::AdvertisingIdle);
-                        app.set_next_alarm::<A::Frequency>(self.alarm.now());
+                        app.set_next_alarm::<A::Frequency>(self.alarm.now().into_u32());
                     }
                     // Invalid state => don't care
                     _ => (),
@@ -559,9 +559,9 @@ where
                             ADV_IND | ADV_NONCONN_IND | ADV_SCAN_IND => {
                                 app.pdu_type = pdu_type;
                                 app.process_status = Some(BLEState::AdvertisingIdle);
-                                app.random_nonce = self.alarm.now();
+                                app.random_nonce = self.alarm.now().into_u32();
                                 app.advertisement_interval_ms = cmp::max(20, interval as u32);
-                                app.set_next_alarm::<A::Frequency>(self.alarm.now());
+                                app.set_next_alarm::<A::Frequency>(self.alarm.now().into_u32());
                                 self.reset_active_alarm();
                                 ReturnCode::SUCCESS
                             }
@@ -622,7 +622,7 @@ where
                 .enter(appid, |app, _| {
                     if let Some(BLEState::Initialized) = app.process_status {
                         app.process_status = Some(BLEState::ScanningIdle);
-                        app.set_next_alarm::<A::Frequency>(self.alarm.now());
+                        app.set_next_alarm::<A::Frequency>(self.alarm.now().into_u32());
                         self.reset_active_alarm();
                         ReturnCode::SUCCESS
                     } else {
@@ -40,7 +40,7 @@ use core::cmp;
 
 use kernel::common::cells::OptionalCell;
 use kernel::hil;
-use kernel::hil::time::Frequency;
+use kernel::hil::time::{Frequency, Ticks};
 use kernel::{AppId, Callback, Driver, Grant, ReturnCode};
 
 /// Syscall driver number.
@@ -137,7 +137,7 @@ impl<A: hil::time::Alarm<'a>> Buzzer<'a, A> {
 
                 // Now start a timer so we know when to stop the PWM.
                 let interval = (duration_ms as u32) * <A::Frequency>::frequency() / 1000;
-                let tics = self.alarm.now().wrapping_add(interval);
+                let tics = self.alarm.now().wrapping_add(A::Ticks::from_u32(interval));
                 self.alarm.set_alarm(tics);
                 ReturnCode::SUCCESS
             }
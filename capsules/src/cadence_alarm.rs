@@ -0,0 +1,199 @@
+//! Periodic callbacks that report how many periods have passed.
+//!
+//! Like `PeriodicAlarm`, `CadenceAlarm` schedules each deadline from the
+//! previous one, so the deadlines stay on multiples of the period after
+//! `start()`. When callbacks run late, for example because the system was
+//! busy, several deadlines may pass before the alarm fires. Instead of
+//! skipping or replaying them, `CadenceAlarm` calls its client once and
+//! passes the number of deadlines that have passed since the last callback,
+//! so that a client producing one sample per period knows how many to
+//! produce. The next deadline is the first one after those, which keeps the
+//! long-term cadence exact however late individual callbacks run.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust
+//! let cadence = static_init!(
+//!     capsules::cadence_alarm::CadenceAlarm<'static, VirtualMuxAlarm<'static, sam4l::ast::Ast>>,
+//!     capsules::cadence_alarm::CadenceAlarm::new(virtual_alarm, 1000)
+//! );
+//! virtual_alarm.set_client(cadence);
+//! cadence.set_client(audio);
+//! cadence.start();
+//! ```
+
+use core::cell::Cell;
+use kernel::common::cells::OptionalCell;
+use kernel::hil::time::{self, Alarm, Ticks};
+
+pub trait CadenceClient {
+    /// Called once one or more deadlines have passed. `periods` is the
+    /// number of them since the last call, at least 1.
+    fn fired(&self, periods: u32);
+}
+
+pub struct CadenceAlarm<'a, A: Alarm<'a>> {
+    alarm: &'a A,
+    period: A::Ticks,
+    /// The deadline the alarm is currently armed for.
+    deadline: Cell<A::Ticks>,
+    running: Cell<bool>,
+    client: OptionalCell<&'a dyn CadenceClient>,
+}
+
+impl<A: Alarm<'a>> CadenceAlarm<'a, A> {
+    pub fn new(alarm: &'a A, period: A::Ticks) -> CadenceAlarm<'a, A> {
+        CadenceAlarm {
+            alarm,
+            period,
+            deadline: Cell::new(A::Ticks::from_u32(0)),
+            running: Cell::new(false),
+            client: OptionalCell::empty(),
+        }
+    }
+
+    pub fn set_client(&self, client: &'a dyn CadenceClient) {
+        self.client.set(client);
+    }
+
+    /// Starts calling the client every period, the first time one period
+    /// from now.
+    pub fn start(&self) {
+        let now = self.alarm.now();
+        self.running.set(true);
+        self.deadline.set(now.wrapping_add(self.period));
+        self.alarm.set_alarm_from(now, self.period);
+    }
+
+    pub fn stop(&self) {
+        self.running.set(false);
+        self.alarm.disable();
+    }
+
+    pub fn is_running(&self) -> bool {
+        self.running.get()
+    }
+}
+
+impl<A: Alarm<'a>> time::AlarmClient for CadenceAlarm<'a, A> {
+    fn fired(&self) {
+        if !self.running.get() {
+            return;
+        }
+        let deadline = self.deadline.get();
+        let period = self.period.into_u32();
+        let late = self.alarm.now().wrapping_sub(deadline).into_u32();
+        let periods = late / period + 1;
+
+        // The last deadline that has passed. Set before calling the client,
+        // so that a client that stops or restarts us can be detected.
+        let last = deadline.wrapping_add(A::Ticks::from_u32((periods - 1).wrapping_mul(period)));
+        let next = last.wrapping_add(self.period);
+        self.deadline.set(next);
+        self.client.map(|client| client.fired(periods));
+        if !self.running.get() || self.deadline.get() != next {
+            return;
+        }
+
+        // Arming relative to the last deadline fires as soon as possible if
+        // the client ran past `next`, and that callback then counts it.
+        self.alarm.set_alarm_from(last, self.period);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{CadenceAlarm, CadenceClient};
+    use core::cell::Cell;
+    use kernel::hil::time::mock::MockAlarm;
+    use kernel::hil::time::{Alarm, Time};
+
+    struct Client<'a> {
+        alarm: &'a MockAlarm<'a>,
+        /// `(now, periods)` of the last callback.
+        last: Cell<Option<(u32, u32)>>,
+        /// Total periods reported.
+        periods: Cell<u32>,
+        /// Tics the next callback spends "working".
+        work: Cell<u32>,
+    }
+
+    impl Client<'a> {
+        fn new(alarm: &'a MockAlarm<'a>) -> Client<'a> {
+            Client {
+                alarm,
+                last: Cell::new(None),
+                periods: Cell::new(0),
+                work: Cell::new(0),
+            }
+        }
+    }
+
+    impl CadenceClient for Client<'a> {
+        fn fired(&self, periods: u32) {
+            self.last.set(Some((self.alarm.now(), periods)));
+            self.periods.set(self.periods.get() + periods);
+            self.alarm.advance(self.work.take());
+        }
+    }
+
+    #[test]
+    fn test_fires_every_period() {
+        let alarm = MockAlarm::new();
+        let cadence = CadenceAlarm::new(&alarm, 10);
+        let client = Client::new(&alarm);
+        alarm.set_client(&cadence);
+        cadence.set_client(&client);
+
+        alarm.advance(3);
+        cadence.start();
+        alarm.advance(40);
+        assert_eq!(client.last.get(), Some((43, 1)));
+        assert_eq!(client.periods.get(), 4);
+    }
+
+    #[test]
+    fn test_counts_missed_periods() {
+        let alarm = MockAlarm::new();
+        let cadence = CadenceAlarm::new(&alarm, 10);
+        let client = Client::new(&alarm);
+        alarm.set_client(&cadence);
+        cadence.set_client(&client);
+
+        cadence.start();
+        // Fired late, after the deadlines at 10, 20 and 30.
+        alarm.set_interrupts_enabled(false);
+        alarm.advance(35);
+        alarm.set_interrupts_enabled(true);
+        assert_eq!(client.last.get(), Some((35, 3)));
+        assert_eq!(alarm.get_alarm(), 40);
+
+        // A callback that runs past the next deadline is followed by one as
+        // soon as possible, which reports it.
+        client.work.set(12);
+        alarm.advance(5);
+        assert_eq!(client.last.get(), Some((40, 1)));
+        alarm.advance(1);
+        assert_eq!(client.last.get(), Some((53, 1)));
+        alarm.advance(7);
+        assert_eq!(client.last.get(), Some((60, 1)));
+        assert_eq!(client.periods.get(), 6);
+    }
+
+    #[test]
+    fn test_stop() {
+        let alarm = MockAlarm::new();
+        let cadence = CadenceAlarm::new(&alarm, 10);
+        let client = Client::new(&alarm);
+        alarm.set_client(&cadence);
+        cadence.set_client(&client);
+
+        cadence.start();
+        alarm.advance(10);
+        cadence.stop();
+        alarm.advance(100);
+        assert_eq!(client.periods.get(), 1);
+        assert!(!alarm.is_enabled());
+    }
+}
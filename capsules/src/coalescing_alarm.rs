@@ -0,0 +1,138 @@
+//! Coalesce bursts of alarm callbacks into one.
+//!
+//! `CoalescingAlarmClient` is an `AlarmClient` for any number of alarms. The
+//! first `fired()` it receives opens a window of a fixed number of tics,
+//! measured on a dedicated alarm. Further callbacks within the window are
+//! absorbed, and when the window closes the downstream client is called
+//! once. The next callback after that opens a new window.
+//!
+//! This trades latency for fewer callbacks: the downstream client always
+//! hears about an event up to one window after it happened, never as soon as
+//! it happened. Tokens passed to `fired_with_token` are not forwarded, since
+//! several of them may be collapsed into one call.
+//!
+//! The alarm passed to `new` is armed and disarmed by the coalescer, so it
+//! must not be used for anything else, e.g. it should be its own virtual
+//! alarm. Its client must be the coalescer.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust
+//! let coalescer = static_init!(
+//!     capsules::coalescing_alarm::CoalescingAlarmClient<'static, VirtualMuxAlarm<'static, sam4l::ast::Ast>>,
+//!     capsules::coalescing_alarm::CoalescingAlarmClient::new(window_alarm, 160)
+//! );
+//! window_alarm.set_client(coalescer);
+//! sample_alarm.set_client(coalescer);
+//! coalescer.set_client(sensor);
+//! ```
+
+use core::cell::Cell;
+use kernel::common::cells::OptionalCell;
+use kernel::hil::time::{self, Alarm, Ticks};
+
+pub struct CoalescingAlarmClient<'a, A: Alarm<'a>> {
+    alarm: &'a A,
+    window: A::Ticks,
+    /// When the current window was opened.
+    reference: Cell<A::Ticks>,
+    /// Whether a window is open and the downstream client is owed a call.
+    pending: Cell<bool>,
+    client: OptionalCell<&'a dyn time::AlarmClient>,
+}
+
+impl<A: Alarm<'a>> CoalescingAlarmClient<'a, A> {
+    /// Creates a coalescer that collapses all callbacks within `window` tics
+    /// of `alarm` into one.
+    pub fn new(alarm: &'a A, window: A::Ticks) -> CoalescingAlarmClient<'a, A> {
+        CoalescingAlarmClient {
+            alarm,
+            window,
+            reference: Cell::new(A::Ticks::from_u32(0)),
+            pending: Cell::new(false),
+            client: OptionalCell::empty(),
+        }
+    }
+
+    pub fn set_client(&self, client: &'a dyn time::AlarmClient) {
+        self.client.set(client);
+    }
+
+    /// Returns whether a window is open.
+    pub fn is_pending(&self) -> bool {
+        self.pending.get()
+    }
+}
+
+impl<A: Alarm<'a>> time::AlarmClient for CoalescingAlarmClient<'a, A> {
+    fn fired(&self) {
+        let now = self.alarm.now();
+        if !self.pending.get() {
+            self.pending.set(true);
+            self.reference.set(now);
+            self.alarm.set_alarm_from(now, self.window);
+            return;
+        }
+
+        let deadline = self.reference.get().wrapping_add(self.window);
+        if time::has_expired(self.reference.get(), deadline, now) {
+            // Either the window alarm, or another callback that arrived
+            // before the window alarm was serviced. Make sure the window
+            // alarm does not then open a new window.
+            self.pending.set(false);
+            self.alarm.disable();
+            self.client.map(|client| client.fired());
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::CoalescingAlarmClient;
+    use kernel::hil::time::mock::{CountingClient, MockAlarm};
+    use kernel::hil::time::{Alarm, AlarmClient};
+
+    #[test]
+    fn test_burst_is_one_callback() {
+        let alarm = MockAlarm::new();
+        let coalescer = CoalescingAlarmClient::new(&alarm, 10);
+        let client = CountingClient::new();
+        alarm.set_client(&coalescer);
+        coalescer.set_client(&client);
+
+        coalescer.fired();
+        alarm.advance(3);
+        coalescer.fired();
+        alarm.advance(3);
+        coalescer.fired();
+        assert_eq!(client.fires(), 0);
+        alarm.advance(4);
+        assert_eq!(client.fires(), 1);
+        assert!(!coalescer.is_pending());
+
+        // A later callback opens a new window.
+        coalescer.fired();
+        assert!(coalescer.is_pending());
+        alarm.advance(10);
+        assert_eq!(client.fires(), 2);
+    }
+
+    #[test]
+    fn test_callback_after_window_before_alarm() {
+        let alarm = MockAlarm::new();
+        let coalescer = CoalescingAlarmClient::new(&alarm, 10);
+        let client = CountingClient::new();
+        alarm.set_client(&coalescer);
+        coalescer.set_client(&client);
+
+        coalescer.fired();
+        // The window has closed, but the window alarm has not been
+        // serviced yet when another callback comes in.
+        alarm.set_now(12);
+        coalescer.fired();
+        assert_eq!(client.fires(), 1);
+        assert!(!alarm.is_enabled());
+        assert!(!coalescer.is_pending());
+    }
+}
@@ -0,0 +1,170 @@
+//! Convert timestamps between two independent counters.
+//!
+//! `CounterCorrelator<A, B>` maps a value of counter `A` to the value counter
+//! `B` had, or will have, at the same instant, e.g. to put events stamped in
+//! two clock domains on one trace timeline. It keeps a linear mapping: a pair
+//! of readings of both counters taken at (nearly) the same instant, and the
+//! ratio of `B` tics per `A` tic.
+//!
+//! The ratio starts out as the ratio of the nominal frequencies. Since the
+//! two oscillators drift against each other, the counters are sampled again
+//! once `resample_interval` tics of `A` have passed since the ratio was last
+//! measured, and the ratio is replaced by the one measured over that span.
+//! Longer intervals measure the ratio more precisely, shorter ones track
+//! drift more closely. Resampling happens in `a_to_b` when due, or whenever
+//! the owner calls `sample`.
+//!
+//! Both counters must be running. Spans are measured modulo the counter
+//! widths, so `resample_interval` must be shorter than the period of either
+//! counter, and `a_to_b` or `sample` must be called at least that often.
+//! Converted timestamps must be within half a period of `A` of the last
+//! sample.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust
+//! let correlator = static_init!(
+//!     capsules::counter_correlator::CounterCorrelator<'static, nrf5x::rtc::Rtc, nrf5x::timer::TimerAlarm>,
+//!     capsules::counter_correlator::CounterCorrelator::new(&nrf5x::rtc::RTC, &nrf5x::timer::TIMER0, 32768)
+//! );
+//! let timer_timestamp = correlator.a_to_b(rtc_timestamp);
+//! ```
+
+use core::cell::Cell;
+use kernel::hil::time::{Counter, Ticks};
+
+pub struct CounterCorrelator<'a, A: Counter<'a>, B: Counter<'a>> {
+    a: &'a A,
+    b: &'a B,
+    /// Tics of `A` after which the ratio is measured again.
+    resample_interval: u32,
+    /// The most recent pair of readings `(a, b)`.
+    last: Cell<(u32, u32)>,
+    /// The pair of readings the next ratio is measured from.
+    anchor: Cell<(u32, u32)>,
+    /// `B` tics per `A` tic, as `(numerator, denominator)`.
+    ratio: Cell<(u32, u32)>,
+}
+
+impl<A: Counter<'a>, B: Counter<'a>> CounterCorrelator<'a, A, B> {
+    /// Creates a correlator that measures the ratio of the two counters every
+    /// `resample_interval` tics of `a`, and takes the first sample.
+    pub fn new(a: &'a A, b: &'a B, resample_interval: u32) -> CounterCorrelator<'a, A, B> {
+        let correlator = CounterCorrelator {
+            a,
+            b,
+            resample_interval,
+            last: Cell::new((0, 0)),
+            anchor: Cell::new((0, 0)),
+            ratio: Cell::new((b.frequency(), a.frequency())),
+        };
+        let pair = correlator.read();
+        correlator.last.set(pair);
+        correlator.anchor.set(pair);
+        correlator
+    }
+
+    /// Reads both counters, updating the offset of the mapping, and measures
+    /// the ratio again if `resample_interval` tics of `A` have passed since
+    /// it was last measured.
+    pub fn sample(&self) {
+        let pair = self.read();
+        self.last.set(pair);
+        let (anchor_a, anchor_b) = self.anchor.get();
+        let da = pair.0.wrapping_sub(anchor_a) & self.a.max_tics().into_u32();
+        let db = pair.1.wrapping_sub(anchor_b) & self.b.max_tics().into_u32();
+        if da > 0 && da >= self.resample_interval {
+            self.ratio.set((db, da));
+            self.anchor.set(pair);
+        }
+    }
+
+    /// Returns the ratio of `B` tics per `A` tic currently used, as
+    /// `(numerator, denominator)`.
+    pub fn ratio(&self) -> (u32, u32) {
+        self.ratio.get()
+    }
+
+    /// Converts `a_tics`, a value of `A`'s `now()`, to the value of `B`'s
+    /// `now()` at the same instant, resampling first if it is due.
+    pub fn a_to_b(&self, a_tics: u32) -> u32 {
+        let a_mask = self.a.max_tics().into_u32();
+        if self.a.now().into_u32().wrapping_sub(self.anchor.get().0) & a_mask
+            >= self.resample_interval
+        {
+            self.sample();
+        }
+
+        let (last_a, last_b) = self.last.get();
+        let (num, den) = self.ratio.get();
+        let scale = |tics: u32| (tics as u64 * num as u64 / den as u64) as u32;
+        let after = a_tics.wrapping_sub(last_a) & a_mask;
+        let b = if after <= a_mask / 2 {
+            last_b.wrapping_add(scale(after))
+        } else {
+            // Before the last sample.
+            last_b.wrapping_sub(scale(last_a.wrapping_sub(a_tics) & a_mask))
+        };
+        b & self.b.max_tics().into_u32()
+    }
+
+    /// Reads both counters at (nearly) the same instant. `A` is read on both
+    /// sides of `B` and the midpoint used, which halves the error from the
+    /// time the reads take.
+    fn read(&self) -> (u32, u32) {
+        let before = self.a.now().into_u32();
+        let b = self.b.now().into_u32();
+        let after = self.a.now().into_u32();
+        let a_mask = self.a.max_tics().into_u32();
+        let a = before.wrapping_add((after.wrapping_sub(before) & a_mask) / 2) & a_mask;
+        (a, b)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::CounterCorrelator;
+    use kernel::hil::time::mock::MockCounter;
+
+    fn counter<'a>(frequency: u32, now: u32) -> MockCounter<'a> {
+        let counter = MockCounter::new();
+        counter.set_frequency(frequency);
+        counter.set_now(now);
+        counter
+    }
+
+    #[test]
+    fn test_nominal_ratio() {
+        let a = counter(32768, 1000);
+        let b = counter(1_000_000, u32::max_value() - 10);
+        let correlator = CounterCorrelator::new(&a, &b, 32768);
+        assert_eq!(correlator.ratio(), (1_000_000, 32768));
+
+        // Later and earlier timestamps, across a wrap of `b`.
+        assert_eq!(correlator.a_to_b(1000 + 32768), 1_000_000 - 11);
+        assert_eq!(correlator.a_to_b(1000), u32::max_value() - 10);
+        assert_eq!(
+            correlator.a_to_b(1000u32.wrapping_sub(16384)),
+            u32::max_value() - 10 - 500_000
+        );
+    }
+
+    #[test]
+    fn test_tracks_drift() {
+        let a = counter(32768, 0);
+        let b = counter(1_000_000, 0);
+        let correlator = CounterCorrelator::new(&a, &b, 32768);
+
+        // `b` runs 1% fast. Not yet due for resampling.
+        a.advance(16384);
+        b.advance(505_000);
+        assert_eq!(correlator.a_to_b(16384), 500_000);
+
+        a.advance(16384);
+        b.advance(505_000);
+        assert_eq!(correlator.a_to_b(32768), 1_010_000);
+        assert_eq!(correlator.ratio(), (1_010_000, 32768));
+        assert_eq!(correlator.a_to_b(32768 + 3277), 1_010_000 + 101_006);
+    }
+}
@@ -0,0 +1,174 @@
+//! Call a client once activity settles, but at least and at most every so
+//! often.
+//!
+//! `DebounceTimer` is for reacting to bursts of events, e.g. input from a
+//! button or touch controller, once instead of once per event. Every event
+//! calls `poke()`, and the client is called `interval` after the last poke.
+//! Two limits apply on top of that:
+//!
+//! - The client is never called less than `min_gap` after its previous call,
+//!   so pokes right after a call are folded into the next one.
+//! - Pokes that keep arriving closer together than `interval` would postpone
+//!   the call forever, so it is made at the latest `min_gap` after the first
+//!   poke since the previous call. Under continuous pokes the client is thus
+//!   called about every `min_gap`.
+//!
+//! `min_gap` should be at least `interval`. The timer must be the client of
+//! its alarm, which it does not share.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust
+//! let debounce = static_init!(
+//!     capsules::debounce_timer::DebounceTimer<'static, VirtualMuxAlarm<'static, sam4l::ast::Ast>>,
+//!     capsules::debounce_timer::DebounceTimer::new(virtual_alarm, 20, 100)
+//! );
+//! virtual_alarm.set_client(debounce);
+//! debounce.set_client(touch_handler);
+//! ```
+
+use core::cell::Cell;
+use kernel::common::cells::OptionalCell;
+use kernel::hil::time::{self, Alarm, Ticks};
+
+pub struct DebounceTimer<'a, A: Alarm<'a>> {
+    alarm: &'a A,
+    interval: A::Ticks,
+    min_gap: A::Ticks,
+    /// When the first poke since the last call came, while one is pending.
+    first: Cell<Option<A::Ticks>>,
+    /// When the client was last called, if it was.
+    last_fired: Cell<Option<A::Ticks>>,
+    client: OptionalCell<&'a dyn time::TimerClient>,
+}
+
+impl<A: Alarm<'a>> DebounceTimer<'a, A> {
+    pub fn new(alarm: &'a A, interval: A::Ticks, min_gap: A::Ticks) -> DebounceTimer<'a, A> {
+        DebounceTimer {
+            alarm,
+            interval,
+            min_gap,
+            first: Cell::new(None),
+            last_fired: Cell::new(None),
+            client: OptionalCell::empty(),
+        }
+    }
+
+    pub fn set_client(&self, client: &'a dyn time::TimerClient) {
+        self.client.set(client);
+    }
+
+    /// Records activity and arms the alarm for `interval` from now, within
+    /// the limits described above.
+    pub fn poke(&self) {
+        let now = self.alarm.now();
+        let first = match self.first.get() {
+            Some(first) => first,
+            None => {
+                self.first.set(Some(now));
+                now
+            }
+        };
+        let dt = core::cmp::max(
+            core::cmp::min(self.interval, self.until(first, now)),
+            self.last_fired
+                .get()
+                .map_or(A::Ticks::from_u32(0), |last| self.until(last, now)),
+        );
+        self.alarm.set_alarm_from(now, dt);
+    }
+
+    /// Drops a pending call.
+    pub fn cancel(&self) {
+        self.first.set(None);
+        self.alarm.disable();
+    }
+
+    /// Returns whether a call is pending, i.e. there was a poke since the
+    /// last one.
+    pub fn is_pending(&self) -> bool {
+        self.first.get().is_some()
+    }
+
+    /// Tics from `now` until `min_gap` after `reference`, `0` if that has
+    /// passed.
+    fn until(&self, reference: A::Ticks, now: A::Ticks) -> A::Ticks {
+        let deadline = reference.wrapping_add(self.min_gap);
+        if time::has_expired(reference, deadline, now) {
+            A::Ticks::from_u32(0)
+        } else {
+            deadline.wrapping_sub(now)
+        }
+    }
+}
+
+impl<A: Alarm<'a>> time::AlarmClient for DebounceTimer<'a, A> {
+    fn fired(&self) {
+        if self.first.take().is_none() {
+            return;
+        }
+        self.last_fired.set(Some(self.alarm.now()));
+        self.client.map(|client| client.fired());
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::DebounceTimer;
+    use kernel::hil::time::mock::{CountingClient, MockAlarm};
+    use kernel::hil::time::Alarm;
+
+    #[test]
+    fn test_fires_after_last_poke() {
+        let alarm = MockAlarm::new();
+        let debounce = DebounceTimer::new(&alarm, 10, 50);
+        let client = CountingClient::with_clock(&alarm);
+        alarm.set_client(&debounce);
+        debounce.set_client(&client);
+
+        debounce.poke();
+        alarm.advance(5);
+        debounce.poke();
+        assert!(debounce.is_pending());
+        alarm.advance(20);
+        assert_eq!((client.fires(), client.fired_at()), (1, Some(15)));
+        assert!(!debounce.is_pending());
+
+        // Not again within `min_gap` of that.
+        debounce.poke();
+        alarm.advance(100);
+        assert_eq!((client.fires(), client.fired_at()), (2, Some(65)));
+    }
+
+    #[test]
+    fn test_continuous_pokes_fire_every_min_gap() {
+        let alarm = MockAlarm::new();
+        let debounce = DebounceTimer::new(&alarm, 10, 50);
+        let client = CountingClient::with_clock(&alarm);
+        alarm.set_client(&debounce);
+        debounce.set_client(&client);
+
+        alarm.set_now(100);
+        for _ in 0..22 {
+            debounce.poke();
+            alarm.advance(5);
+        }
+        assert_eq!((client.fires(), client.fired_at()), (2, Some(200)));
+    }
+
+    #[test]
+    fn test_cancel() {
+        let alarm = MockAlarm::new();
+        let debounce = DebounceTimer::new(&alarm, 10, 50);
+        let client = CountingClient::with_clock(&alarm);
+        alarm.set_client(&debounce);
+        debounce.set_client(&client);
+
+        debounce.poke();
+        debounce.cancel();
+        alarm.advance(100);
+        assert_eq!(client.fires(), 0);
+        assert!(!alarm.is_enabled());
+    }
+}
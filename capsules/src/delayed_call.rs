@@ -0,0 +1,162 @@
+//! Run a small task once after a delay.
+//!
+//! Many capsules need to "do X in 50ms" and implement a whole `AlarmClient`
+//! type just for that. `DelayedCall` is that client: it owns an alarm,
+//! `schedule_ms` arms it, and when it fires the scheduled `DelayedTask` is
+//! run. The task is any object implementing `DelayedTask`, typically the
+//! capsule itself, so no closure or allocation is needed.
+//!
+//! Only one task is pending at a time. Scheduling again before the alarm
+//! fires replaces the pending task and its delay, and `cancel` drops it. A
+//! task may schedule itself or another task again from `run`.
+//!
+//! This is not the kernel's `common::deferred_call::DeferredCall`, which runs
+//! chip-level callbacks from the main loop as soon as possible instead of
+//! after a delay.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust
+//! let delayed = static_init!(
+//!     capsules::delayed_call::DelayedCall<'static, VirtualMuxAlarm<'static, sam4l::ast::Ast>>,
+//!     capsules::delayed_call::DelayedCall::new(virtual_alarm)
+//! );
+//! virtual_alarm.set_client(delayed);
+//! // In the capsule, which implements `DelayedTask`:
+//! delayed.schedule_ms(self, 50);
+//! ```
+
+use kernel::common::cells::OptionalCell;
+use kernel::hil::time::{self, Alarm};
+use kernel::ReturnCode;
+
+/// A task that a `DelayedCall` runs.
+pub trait DelayedTask {
+    /// Called once when the delay the task was scheduled with has passed.
+    fn run(&self);
+}
+
+pub struct DelayedCall<'a, A: Alarm<'a>> {
+    alarm: &'a A,
+    task: OptionalCell<&'a dyn DelayedTask>,
+}
+
+impl<A: Alarm<'a>> DelayedCall<'a, A> {
+    pub fn new(alarm: &'a A) -> DelayedCall<'a, A> {
+        DelayedCall {
+            alarm,
+            task: OptionalCell::empty(),
+        }
+    }
+
+    /// Runs `task` `ms` milliseconds from now, replacing any pending task.
+    ///
+    /// Returns `ReturnCode::ESIZE` if the delay is longer than the alarm can
+    /// represent (see `Alarm::try_set_alarm_after`), in which case nothing is
+    /// pending afterwards.
+    pub fn schedule_ms(&self, task: &'a dyn DelayedTask, ms: u32) -> ReturnCode {
+        let result = self.alarm.try_set_alarm_after(ms);
+        if result == ReturnCode::SUCCESS {
+            self.task.set(task);
+        } else {
+            self.cancel();
+        }
+        result
+    }
+
+    /// Drops the pending task, if any, without running it.
+    pub fn cancel(&self) {
+        self.task.clear();
+        self.alarm.disable();
+    }
+
+    pub fn is_pending(&self) -> bool {
+        self.task.is_some()
+    }
+}
+
+impl<A: Alarm<'a>> time::AlarmClient for DelayedCall<'a, A> {
+    fn fired(&self) {
+        // Taken first, so that the task can schedule itself again.
+        if let Some(task) = self.task.take() {
+            task.run();
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{DelayedCall, DelayedTask};
+    use core::cell::Cell;
+    use kernel::hil::time::mock::MockAlarm;
+    use kernel::hil::time::{Alarm, Time};
+    use kernel::ReturnCode;
+
+    type TestCall<'a> = DelayedCall<'a, MockAlarm<'a>>;
+
+    struct Task<'a> {
+        alarm: &'a MockAlarm<'a>,
+        ran_at: Cell<Option<u32>>,
+        /// A task to schedule this many ms later when run.
+        then: Cell<Option<(&'a TestCall<'a>, &'a dyn DelayedTask, u32)>>,
+    }
+
+    impl Task<'a> {
+        fn new(alarm: &'a MockAlarm<'a>) -> Task<'a> {
+            Task {
+                alarm,
+                ran_at: Cell::new(None),
+                then: Cell::new(None),
+            }
+        }
+    }
+
+    impl DelayedTask for Task<'a> {
+        fn run(&self) {
+            self.ran_at.set(Some(self.alarm.now()));
+            if let Some((call, task, ms)) = self.then.take() {
+                call.schedule_ms(task, ms);
+            }
+        }
+    }
+
+    #[test]
+    fn test_runs_once_after_delay() {
+        let alarm = MockAlarm::new();
+        let call = DelayedCall::new(&alarm);
+        alarm.set_client(&call);
+        let task = Task::new(&alarm);
+
+        assert_eq!(call.schedule_ms(&task, 50), ReturnCode::SUCCESS);
+        assert!(call.is_pending());
+        alarm.advance(49);
+        assert_eq!(task.ran_at.get(), None);
+        alarm.advance(100);
+        assert_eq!(task.ran_at.get(), Some(50));
+        assert!(!call.is_pending());
+    }
+
+    #[test]
+    fn test_reschedule_and_cancel() {
+        let alarm = MockAlarm::new();
+        let call = DelayedCall::new(&alarm);
+        alarm.set_client(&call);
+        let (first, second, third) = (Task::new(&alarm), Task::new(&alarm), Task::new(&alarm));
+
+        // Replaced before it runs; the replacement schedules another task.
+        call.schedule_ms(&first, 50);
+        call.schedule_ms(&second, 20);
+        second.then.set(Some((&call, &third, 30)));
+        alarm.advance(100);
+        assert_eq!(first.ran_at.get(), None);
+        assert_eq!(second.ran_at.get(), Some(20));
+        assert_eq!(third.ran_at.get(), Some(50));
+
+        call.schedule_ms(&first, 10);
+        call.cancel();
+        alarm.advance(100);
+        assert_eq!(first.ran_at.get(), None);
+        assert!(!alarm.is_enabled());
+    }
+}
@@ -0,0 +1,219 @@
+//! A repeating alarm kept in phase with an external reference.
+//!
+//! `DisciplinedAlarm` calls its client every `period` tics, like a repeating
+//! timer, and can be nudged into phase with a more accurate reference such as
+//! the pulse-per-second output of a GPS receiver. At each reference edge the
+//! owner calls `discipline` with the value of `now()` latched at the edge,
+//! e.g. by a `Capture`. The alarm compares it with its nearest tick and
+//! lengthens or shortens the period in progress by the phase error, so that
+//! the following ticks line up with the reference again.
+//!
+//! Each correction is clamped to `max_step` tics, so a noisy or spurious
+//! reference edge can only move the phase a little, and a large offset is
+//! corrected over several edges instead of in one jump. Corrections do not
+//! accumulate into the nominal period: when the local clock drifts by no
+//! more than `max_step` tics between reference edges, each correction cancels
+//! the drift since the previous one.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust
+//! let pps_alarm = static_init!(
+//!     capsules::disciplined_alarm::DisciplinedAlarm<'static, VirtualMuxAlarm<'static, sam4l::ast::Ast>>,
+//!     capsules::disciplined_alarm::DisciplinedAlarm::new(virtual_alarm, 1600, 4)
+//! );
+//! virtual_alarm.set_client(pps_alarm);
+//! pps_alarm.set_client(sampler);
+//! pps_alarm.start();
+//! // From the capture callback of the PPS input:
+//! pps_alarm.discipline(timestamp);
+//! ```
+
+use core::cell::Cell;
+use kernel::common::cells::OptionalCell;
+use kernel::hil::time::{self, Alarm, Ticks};
+
+pub struct DisciplinedAlarm<'a, A: Alarm<'a>> {
+    alarm: &'a A,
+    /// The nominal period in tics.
+    period: u32,
+    /// The largest correction applied per call to `discipline`.
+    max_step: u32,
+    /// The previous tick, or when the alarm was started.
+    reference: Cell<A::Ticks>,
+    /// Tics from `reference` to the next tick, including corrections.
+    dt: Cell<u32>,
+    running: Cell<bool>,
+    client: OptionalCell<&'a dyn time::AlarmClient>,
+}
+
+impl<A: Alarm<'a>> DisciplinedAlarm<'a, A> {
+    pub fn new(alarm: &'a A, period: u32, max_step: u32) -> DisciplinedAlarm<'a, A> {
+        DisciplinedAlarm {
+            alarm,
+            period,
+            max_step,
+            reference: Cell::new(A::Ticks::from_u32(0)),
+            dt: Cell::new(period),
+            running: Cell::new(false),
+            client: OptionalCell::empty(),
+        }
+    }
+
+    pub fn set_client(&self, client: &'a dyn time::AlarmClient) {
+        self.client.set(client);
+    }
+
+    /// Starts calling the client every `period` tics, the first time one
+    /// period from now.
+    pub fn start(&self) {
+        self.reference.set(self.alarm.now());
+        self.dt.set(self.period);
+        self.running.set(true);
+        self.arm();
+    }
+
+    pub fn stop(&self) {
+        self.running.set(false);
+        self.alarm.disable();
+    }
+
+    pub fn is_running(&self) -> bool {
+        self.running.get()
+    }
+
+    /// Corrects the phase against a reference edge that happened at
+    /// `reference_now`, a value of the alarm's `now()`, and returns the
+    /// correction applied to the period in progress in tics.
+    ///
+    /// The edge is compared with the nearest tick, the previous one or the
+    /// next one. If it came after that tick the period in progress is
+    /// lengthened, if before it is shortened, by at most `max_step` tics.
+    /// Returns `0` without doing anything if the alarm is not running.
+    pub fn discipline(&self, reference_now: u32) -> i32 {
+        if !self.running.get() {
+            return 0;
+        }
+        let since_tick = reference_now.wrapping_sub(self.reference.get().into_u32());
+        let error = if since_tick <= self.dt.get() / 2 {
+            since_tick as i32
+        } else {
+            // Closer to the next tick, which is then late.
+            -(self.dt.get().wrapping_sub(since_tick) as i32)
+        };
+        let max_step = self.max_step as i32;
+        let correction = core::cmp::max(-max_step, core::cmp::min(error, max_step));
+        self.dt
+            .set((self.dt.get() as i32).wrapping_add(correction) as u32);
+        self.arm();
+        correction
+    }
+
+    fn arm(&self) {
+        self.alarm
+            .set_alarm_from(self.reference.get(), A::Ticks::from_u32(self.dt.get()));
+    }
+}
+
+impl<A: Alarm<'a>> time::AlarmClient for DisciplinedAlarm<'a, A> {
+    fn fired(&self) {
+        if !self.running.get() {
+            return;
+        }
+        self.reference.set(
+            self.reference
+                .get()
+                .wrapping_add(A::Ticks::from_u32(self.dt.get())),
+        );
+        self.dt.set(self.period);
+        self.arm();
+        self.client.map(|client| client.fired());
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::DisciplinedAlarm;
+    use kernel::hil::time::mock::{CountingClient, MockAlarm};
+    use kernel::hil::time::Alarm;
+
+    #[test]
+    fn test_repeats_without_discipline() {
+        let alarm = MockAlarm::new();
+        let disciplined = DisciplinedAlarm::new(&alarm, 100, 5);
+        let client = CountingClient::with_clock(&alarm);
+        alarm.set_client(&disciplined);
+        disciplined.set_client(&client);
+
+        disciplined.start();
+        alarm.advance(350);
+        assert_eq!(client.fires(), 3);
+        assert_eq!(client.fired_at(), Some(300));
+    }
+
+    #[test]
+    fn test_edge_after_tick_lengthens_period() {
+        let alarm = MockAlarm::new();
+        let disciplined = DisciplinedAlarm::new(&alarm, 100, 5);
+        let client = CountingClient::with_clock(&alarm);
+        alarm.set_client(&disciplined);
+        disciplined.set_client(&client);
+
+        disciplined.start();
+        alarm.advance(110);
+        // The reference edge was 3 tics after the tick at 100.
+        assert_eq!(disciplined.discipline(103), 3);
+        alarm.advance(100);
+        assert_eq!(client.fired_at(), Some(203));
+        // Later periods are nominal again.
+        alarm.advance(100);
+        assert_eq!(client.fired_at(), Some(303));
+    }
+
+    #[test]
+    fn test_edge_before_tick_shortens_period() {
+        let alarm = MockAlarm::new();
+        let disciplined = DisciplinedAlarm::new(&alarm, 100, 5);
+        let client = CountingClient::with_clock(&alarm);
+        alarm.set_client(&disciplined);
+        disciplined.set_client(&client);
+
+        disciplined.start();
+        alarm.advance(97);
+        // The reference edge came 4 tics before the tick at 100. The shortened
+        // tick has already passed, so it fires as soon as possible.
+        assert_eq!(disciplined.discipline(96), -4);
+        assert_eq!(client.fires(), 0);
+        alarm.advance(1);
+        assert_eq!(client.fires(), 1);
+        assert_eq!(client.fired_at(), Some(98));
+        // The next tick is one period after the corrected one.
+        alarm.advance(100);
+        assert_eq!(client.fired_at(), Some(196));
+    }
+
+    #[test]
+    fn test_correction_is_bounded() {
+        let alarm = MockAlarm::new();
+        let disciplined = DisciplinedAlarm::new(&alarm, 100, 5);
+        let client = CountingClient::with_clock(&alarm);
+        alarm.set_client(&disciplined);
+        disciplined.set_client(&client);
+
+        disciplined.start();
+        alarm.advance(130);
+        assert_eq!(disciplined.discipline(120), 5);
+        assert_eq!(disciplined.discipline(120), 5);
+        alarm.advance(80);
+        assert_eq!(client.fired_at(), Some(210));
+    }
+
+    #[test]
+    fn test_discipline_when_stopped() {
+        let alarm = MockAlarm::new();
+        let disciplined = DisciplinedAlarm::new(&alarm, 100, 5);
+        assert_eq!(disciplined.discipline(3), 0);
+        assert!(!alarm.is_enabled());
+    }
+}
@@ -0,0 +1,292 @@
+//! Schedule an alarm at a wall-clock time.
+//!
+//! `EpochAlarm` calls its client once an `Rtc` reaches a given time, in
+//! seconds since the Unix epoch, instead of after a number of tics. It reads
+//! the RTC, converts the time left to tics of an `Alarm` and arms it. Times
+//! further away than half a period of the alarm are reached in several
+//! sub-alarms. Each time a sub-alarm fires the RTC is read again and the
+//! time left computed anew, so setting the RTC, or drift between the RTC and
+//! the alarm's clock, does not accumulate across sub-alarms.
+//!
+//! The RTC has a resolution of one second, so the client is called within
+//! about a second after the deadline, and right away if the deadline has
+//! already passed. If reading the RTC fails because it is busy, the read is
+//! retried after `RETRY_MS` milliseconds.
+//!
+//! `EpochAlarm` must be the client of both the `Rtc` and the `Alarm`.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust
+//! let epoch_alarm = static_init!(
+//!     capsules::epoch_alarm::EpochAlarm<'static, sam4l::rtc::Rtc, VirtualMuxAlarm<'static, sam4l::ast::Ast>>,
+//!     capsules::epoch_alarm::EpochAlarm::new(&sam4l::rtc::RTC, virtual_alarm)
+//! );
+//! sam4l::rtc::RTC.set_client(epoch_alarm);
+//! virtual_alarm.set_client(epoch_alarm);
+//! epoch_alarm.set_client(wakeup);
+//! epoch_alarm.set_epoch_alarm(1_600_000_000);
+//! ```
+
+use core::cell::Cell;
+use kernel::common::cells::OptionalCell;
+use kernel::hil::rtc::{self, DateTime, Rtc, RtcClient};
+use kernel::hil::time::{self, Alarm, Ticks};
+use kernel::ReturnCode;
+
+/// Delay before reading the RTC again when it is busy.
+pub const RETRY_MS: u32 = 10;
+
+pub struct EpochAlarm<'a, R: Rtc<'a>, A: Alarm<'a>> {
+    rtc: &'a R,
+    alarm: &'a A,
+    /// The deadline in seconds since the epoch, while one is pending.
+    deadline: Cell<Option<u64>>,
+    client: OptionalCell<&'a dyn time::AlarmClient>,
+}
+
+impl<R: Rtc<'a>, A: Alarm<'a>> EpochAlarm<'a, R, A> {
+    pub fn new(rtc: &'a R, alarm: &'a A) -> EpochAlarm<'a, R, A> {
+        EpochAlarm {
+            rtc,
+            alarm,
+            deadline: Cell::new(None),
+            client: OptionalCell::empty(),
+        }
+    }
+
+    pub fn set_client(&self, client: &'a dyn time::AlarmClient) {
+        self.client.set(client);
+    }
+
+    /// Calls the client once the RTC reaches `epoch_secs`, replacing any
+    /// pending deadline.
+    ///
+    /// Returns the result of requesting the current time from the RTC. On
+    /// failure nothing is pending afterwards.
+    pub fn set_epoch_alarm(&self, epoch_secs: u64) -> ReturnCode {
+        self.alarm.disable();
+        self.deadline.set(Some(epoch_secs));
+        let result = self.rtc.get_time();
+        if result != ReturnCode::SUCCESS {
+            self.deadline.set(None);
+        }
+        result
+    }
+
+    /// Drops the pending deadline, if any, without calling the client.
+    pub fn cancel(&self) {
+        self.deadline.set(None);
+        self.alarm.disable();
+    }
+
+    pub fn is_pending(&self) -> bool {
+        self.deadline.get().is_some()
+    }
+
+    /// Arms the alarm `tics` from now.
+    fn arm(&self, tics: u32) {
+        self.alarm
+            .set_alarm_from(self.alarm.now(), A::Ticks::from_u32(tics));
+    }
+}
+
+impl<R: Rtc<'a>, A: Alarm<'a>> RtcClient for EpochAlarm<'a, R, A> {
+    fn callback(&self, datetime: DateTime) {
+        let deadline = match self.deadline.get() {
+            Some(deadline) => deadline,
+            None => return,
+        };
+        let now = rtc::to_epoch(datetime);
+        if now >= deadline {
+            self.deadline.set(None);
+            self.client.map(|client| client.fired());
+            return;
+        }
+        // At most half a period, so that the sub-alarm cannot be mistaken
+        // for one in the past.
+        let max_chunk = self.alarm.max_tics().into_u32() / 2;
+        let tics = (deadline - now).saturating_mul(self.alarm.frequency() as u64);
+        self.arm(core::cmp::min(tics, max_chunk as u64) as u32);
+    }
+}
+
+impl<R: Rtc<'a>, A: Alarm<'a>> time::AlarmClient for EpochAlarm<'a, R, A> {
+    fn fired(&self) {
+        if self.deadline.get().is_some() && self.rtc.get_time() != ReturnCode::SUCCESS {
+            self.arm(time::convert_tics(RETRY_MS, 1000, self.alarm.frequency()));
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::EpochAlarm;
+    use core::cell::Cell;
+    use kernel::common::cells::OptionalCell;
+    use kernel::hil::rtc::{self, DateTime, Rtc, RtcClient};
+    use kernel::hil::time::mock::MockAlarm;
+    use kernel::hil::time::{Alarm, AlarmClient, Time};
+    use kernel::ReturnCode;
+
+    const START: u64 = 1_600_000_000;
+
+    /// An RTC that follows the mock alarm's clock, answering synchronously.
+    /// It must be read at least once per period of the alarm.
+    struct FakeRtc<'a> {
+        alarm: &'a MockAlarm<'a>,
+        /// Epoch time at `alarm.now() == 0`, in seconds.
+        offset: Cell<u64>,
+        /// Tics of the alarm, extended past its wraps.
+        tics: Cell<u64>,
+        last: Cell<u32>,
+        busy: Cell<bool>,
+        client: OptionalCell<&'a dyn RtcClient>,
+    }
+
+    impl FakeRtc<'a> {
+        fn new(alarm: &'a MockAlarm<'a>) -> FakeRtc<'a> {
+            FakeRtc {
+                alarm,
+                offset: Cell::new(START),
+                tics: Cell::new(0),
+                last: Cell::new(alarm.now()),
+                busy: Cell::new(false),
+                client: OptionalCell::empty(),
+            }
+        }
+
+        fn epoch(&self) -> u64 {
+            let now = self.alarm.now();
+            self.tics
+                .set(self.tics.get() + now.wrapping_sub(self.last.get()) as u64);
+            self.last.set(now);
+            self.offset.get() + self.tics.get() / self.alarm.frequency() as u64
+        }
+
+        /// Moves the clock forward by `seconds`, one second at a time.
+        fn run(&self, seconds: u32) {
+            for _ in 0..seconds {
+                self.alarm.advance(self.alarm.frequency());
+                self.epoch();
+            }
+        }
+    }
+
+    impl Rtc<'a> for FakeRtc<'a> {
+        fn set_client(&'a self, client: &'a dyn RtcClient) {
+            self.client.set(client);
+        }
+
+        fn get_time(&self) -> ReturnCode {
+            if self.busy.get() {
+                return ReturnCode::EBUSY;
+            }
+            let now = rtc::from_epoch(self.epoch());
+            self.client.map(|client| client.callback(now));
+            ReturnCode::SUCCESS
+        }
+
+        fn set_time(&self, datetime: DateTime) -> ReturnCode {
+            let now = self.epoch();
+            self.offset
+                .set(self.offset.get() + rtc::to_epoch(datetime) - now);
+            ReturnCode::SUCCESS
+        }
+    }
+
+    struct Client<'a> {
+        rtc: &'a FakeRtc<'a>,
+        fired_at: Cell<Option<u64>>,
+    }
+
+    impl AlarmClient for Client<'a> {
+        fn fired(&self) {
+            self.fired_at.set(Some(self.rtc.epoch()));
+        }
+    }
+
+    #[test]
+    fn test_chains_sub_alarms() {
+        let alarm = MockAlarm::new();
+        alarm.set_frequency(1_000_000);
+        let rtc = FakeRtc::new(&alarm);
+        let epoch_alarm = EpochAlarm::new(&rtc, &alarm);
+        let client = Client {
+            rtc: &rtc,
+            fired_at: Cell::new(None),
+        };
+        rtc.set_client(&epoch_alarm);
+        alarm.set_client(&epoch_alarm);
+        epoch_alarm.set_client(&client);
+
+        // Longer than one period of the 1MHz alarm, about 4295 seconds.
+        assert_eq!(
+            epoch_alarm.set_epoch_alarm(START + 5000),
+            ReturnCode::SUCCESS
+        );
+        rtc.run(4999);
+        assert_eq!(client.fired_at.get(), None);
+        assert!(epoch_alarm.is_pending());
+        rtc.run(2);
+        assert_eq!(client.fired_at.get(), Some(START + 5000));
+        assert!(!epoch_alarm.is_pending());
+    }
+
+    #[test]
+    fn test_revalidates_after_clock_set() {
+        let alarm = MockAlarm::new();
+        alarm.set_frequency(1_000_000);
+        let rtc = FakeRtc::new(&alarm);
+        let epoch_alarm = EpochAlarm::new(&rtc, &alarm);
+        let client = Client {
+            rtc: &rtc,
+            fired_at: Cell::new(None),
+        };
+        rtc.set_client(&epoch_alarm);
+        alarm.set_client(&epoch_alarm);
+        epoch_alarm.set_client(&client);
+
+        epoch_alarm.set_epoch_alarm(START + 5000);
+        rtc.run(1000);
+        // The clock is set 3000 seconds ahead, past the deadline by the end
+        // of the first sub-alarm, about 2147 seconds after the start.
+        rtc.set_time(rtc::from_epoch(START + 4000));
+        rtc.run(1148);
+        assert_eq!(client.fired_at.get(), Some(START + 5147));
+    }
+
+    #[test]
+    fn test_past_deadline_and_busy_rtc() {
+        let alarm = MockAlarm::new();
+        alarm.set_frequency(1_000_000);
+        let rtc = FakeRtc::new(&alarm);
+        let epoch_alarm = EpochAlarm::new(&rtc, &alarm);
+        let client = Client {
+            rtc: &rtc,
+            fired_at: Cell::new(None),
+        };
+        rtc.set_client(&epoch_alarm);
+        alarm.set_client(&epoch_alarm);
+        epoch_alarm.set_client(&client);
+
+        assert_eq!(epoch_alarm.set_epoch_alarm(START - 1), ReturnCode::SUCCESS);
+        assert_eq!(client.fired_at.get(), Some(START));
+
+        client.fired_at.set(None);
+        rtc.busy.set(true);
+        assert_eq!(epoch_alarm.set_epoch_alarm(START + 10), ReturnCode::EBUSY);
+        assert!(!epoch_alarm.is_pending());
+
+        // Busy when the alarm fires: read again a little later.
+        rtc.busy.set(false);
+        epoch_alarm.set_epoch_alarm(START + 10);
+        rtc.busy.set(true);
+        rtc.run(10);
+        assert_eq!(client.fired_at.get(), None);
+        rtc.busy.set(false);
+        alarm.advance(10_000);
+        assert_eq!(client.fired_at.get(), Some(START + 10));
+    }
+}
@@ -0,0 +1,226 @@
+//! Present an `Alarm` at a different frequency.
+//!
+//! `FrequencyConvertAlarm<A, F>` wraps an alarm running at one frequency and
+//! implements `Alarm` with `type Frequency = F`, converting `now()` and alarm
+//! values between the two. A capsule written against, say, `Freq1KHz` can
+//! then run unchanged on a board whose alarm counts at 32768 Hz. Capsules
+//! that bound their alarm with `kernel::hil::time::AlarmAt` reject an alarm at
+//! any other frequency at compile time, so this adapter is how such an alarm
+//! is wired to them.
+//!
+//! The two counters wrap at different real-time periods, so the converted
+//! time cannot be computed from the raw value of the underlying counter
+//! alone. The adapter instead reads it through a `ScaledTime`, which extends
+//! the underlying counter in software each time it is read, and presents a
+//! full 32-bit counter at frequency `F`. For
+//! this to be correct `now()`, `set_alarm` or the alarm itself must run at
+//! least once per period of the underlying counter. Alarms further away than
+//! half of that period are reached in several steps.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust
+//! let alarm_1khz = static_init!(
+//!     capsules::frequency_convert_alarm::FrequencyConvertAlarm<
+//!         'static,
+//!         VirtualMuxAlarm<'static, nrf5x::rtc::Rtc>,
+//!         kernel::hil::time::Freq1KHz,
+//!     >,
+//!     capsules::frequency_convert_alarm::FrequencyConvertAlarm::new(virtual_alarm)
+//! );
+//! virtual_alarm.set_client(alarm_1khz);
+//! ```
+
+use crate::scaled_time::ScaledTime;
+use core::cell::Cell;
+use kernel::common::cells::OptionalCell;
+use kernel::hil::time::{self, Alarm, Frequency, Ticks, Time};
+use kernel::ReturnCode;
+
+pub struct FrequencyConvertAlarm<'a, A: Alarm<'a>, F: Frequency> {
+    alarm: &'a A,
+    /// `alarm`'s clock at frequency `F`.
+    time: ScaledTime<'a, A, F>,
+    /// Converted time at which the current alarm was set.
+    reference: Cell<u32>,
+    /// Converted time at which the current alarm fires.
+    deadline: Cell<u32>,
+    token: Cell<u32>,
+    armed: Cell<bool>,
+    client: OptionalCell<&'a dyn time::AlarmClient>,
+}
+
+impl<A: Alarm<'a>, F: Frequency> FrequencyConvertAlarm<'a, A, F> {
+    pub fn new(alarm: &'a A) -> FrequencyConvertAlarm<'a, A, F> {
+        FrequencyConvertAlarm {
+            alarm,
+            time: ScaledTime::new(alarm),
+            reference: Cell::new(0),
+            deadline: Cell::new(0),
+            token: Cell::new(0),
+            armed: Cell::new(false),
+            client: OptionalCell::empty(),
+        }
+    }
+
+    /// Programs the underlying alarm for the converted deadline, or for as
+    /// far towards it as the underlying counter can safely reach.
+    fn arm(&self) {
+        let raw = self.time.update();
+        let now = self.time.converted();
+        let remaining = if time::has_expired(self.reference.get(), self.deadline.get(), now) {
+            0
+        } else {
+            self.deadline.get().wrapping_sub(now)
+        };
+        let max_step = (self.alarm.max_tics().into_u32() / 2) as u64;
+        // Rounded up, so the underlying alarm never fires before the
+        // converted deadline.
+        let dt = core::cmp::min(self.time.to_inner(remaining), max_step) as u32;
+        self.alarm
+            .set_alarm_from(A::Ticks::from_u32(raw), A::Ticks::from_u32(dt));
+    }
+}
+
+impl<A: Alarm<'a>, F: Frequency> Time for FrequencyConvertAlarm<'a, A, F> {
+    type Frequency = F;
+
+    fn now(&self) -> u32 {
+        self.time.now()
+    }
+
+    fn persists_in_sleep(&self) -> bool {
+        self.alarm.persists_in_sleep()
+    }
+}
+
+impl<A: Alarm<'a>, F: Frequency> Alarm<'a> for FrequencyConvertAlarm<'a, A, F> {
+    fn set_alarm_with_token(&self, tics: u32, token: u32) -> ReturnCode {
+        let now = self.now();
+        if tics.wrapping_sub(now) < self.minimum_dt() {
+            return ReturnCode::EINVAL;
+        }
+        self.reference.set(now);
+        self.deadline.set(tics);
+        self.token.set(token);
+        self.armed.set(true);
+        self.arm();
+        ReturnCode::SUCCESS
+    }
+
+    fn get_alarm(&self) -> u32 {
+        self.deadline.get()
+    }
+
+    /// The underlying alarm's `minimum_dt`, rounded up to whole `F` tics.
+    fn minimum_dt(&self) -> u32 {
+        let f_in = self.alarm.frequency() as u64;
+        let f_out = F::frequency() as u64;
+        let min_dt = self.alarm.minimum_dt().into_u32() as u64;
+        core::cmp::max((min_dt * f_out + f_in - 1) / f_in, 1) as u32
+    }
+
+    fn set_client(&'a self, client: &'a dyn time::AlarmClient) {
+        self.client.set(client);
+    }
+
+    fn clear_client(&self) {
+        self.disable();
+        self.client.clear();
+    }
+
+    fn is_enabled(&self) -> bool {
+        self.armed.get()
+    }
+
+    fn disable(&self) -> ReturnCode {
+        self.armed.set(false);
+        self.alarm.disable()
+    }
+}
+
+impl<A: Alarm<'a>, F: Frequency> time::AlarmClient for FrequencyConvertAlarm<'a, A, F> {
+    fn fired(&self) {
+        if !self.armed.get() {
+            return;
+        }
+        if time::has_expired(self.reference.get(), self.deadline.get(), self.now()) {
+            self.armed.set(false);
+            self.client
+                .map(|client| client.fired_with_token(self.token.get()));
+        } else {
+            // Only part of the way there.
+            self.arm();
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::FrequencyConvertAlarm;
+    use kernel::hil::time::mock::{CountingClient, MockAlarm};
+    use kernel::hil::time::{Alarm, Freq1KHz, Time};
+
+    fn mock_32khz<'a>(now: u32) -> MockAlarm<'a> {
+        let alarm = MockAlarm::new();
+        alarm.set_frequency(32768);
+        alarm.set_now(now);
+        alarm
+    }
+
+    #[test]
+    fn test_converts_now() {
+        let inner = mock_32khz(0);
+        let alarm: FrequencyConvertAlarm<_, Freq1KHz> = FrequencyConvertAlarm::new(&inner);
+        assert_eq!(alarm.now(), 0);
+        inner.advance(32768);
+        assert_eq!(alarm.now(), 1000);
+        // 33 tics at 32768 Hz are 1.007ms.
+        inner.advance(33);
+        assert_eq!(alarm.now(), 1001);
+        assert_eq!(alarm.minimum_dt(), 1);
+    }
+
+    #[test]
+    fn test_now_continues_across_inner_wrap() {
+        // Two seconds before the 32768 Hz counter wraps.
+        let inner = mock_32khz(u32::max_value() - 2 * 32768 + 1);
+        let alarm: FrequencyConvertAlarm<_, Freq1KHz> = FrequencyConvertAlarm::new(&inner);
+        let start = alarm.now();
+        inner.advance(4 * 32768);
+        assert_eq!(alarm.now().wrapping_sub(start), 4000);
+    }
+
+    #[test]
+    fn test_alarm_across_inner_wrap() {
+        let inner = mock_32khz(u32::max_value() - 2 * 32768 + 1);
+        let alarm: FrequencyConvertAlarm<_, Freq1KHz> = FrequencyConvertAlarm::new(&inner);
+        let client = CountingClient::new();
+        inner.set_client(&alarm);
+        alarm.set_client(&client);
+
+        alarm.set_alarm(alarm.now().wrapping_add(3000));
+        inner.advance(3 * 32768 - 1);
+        assert_eq!(client.fires(), 0);
+        inner.advance(1);
+        assert_eq!(client.fires(), 1);
+        assert!(!alarm.is_enabled());
+    }
+
+    #[test]
+    fn test_rounds_alarm_up() {
+        let inner = mock_32khz(0);
+        let alarm: FrequencyConvertAlarm<_, Freq1KHz> = FrequencyConvertAlarm::new(&inner);
+        let client = CountingClient::new();
+        inner.set_client(&alarm);
+        alarm.set_client(&client);
+
+        // 1ms is 32.768 tics, so the underlying alarm must not fire after 32.
+        alarm.set_alarm(1);
+        assert_eq!(inner.get_alarm(), 33);
+        inner.advance(33);
+        assert_eq!(client.fires(), 1);
+        assert_eq!(alarm.now(), 1);
+    }
+}
@@ -0,0 +1,192 @@
+//! Combine a persistent and a high-resolution clock into one.
+//!
+//! `HybridTime<P, V, F>` presents a single `Time` at frequency `F` built from
+//! two counters: `P`, a low-resolution counter that keeps running in deep
+//! sleep (e.g. a 32768 Hz RTC), and `V`, a high-resolution counter that is
+//! frozen in deep sleep (e.g. a 16 MHz timer). Between wakeups `now()`
+//! advances with `V`, so it has `V`'s granularity. After each wakeup the
+//! power manager calls `resync`, which moves the time forward by however long
+//! the chip slept, as measured by `P`.
+//!
+//! At `resync` the time is set to `P`'s reading if that is ahead, and
+//! otherwise left alone, so that `now()` never goes backwards. This also
+//! corrects `V` if it runs slow relative to `P`, but not if it runs fast.
+//! Between wakeup and `resync`, `now()` has not yet caught up with the time
+//! slept.
+//!
+//! Both counters are extended in software when read, so `now()` or `resync`
+//! must run at least once per period of each of them, and in particular the
+//! chip must not sleep longer than one period of `P`.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust
+//! let time = static_init!(
+//!     capsules::hybrid_time::HybridTime<
+//!         'static,
+//!         nrf5x::rtc::Rtc,
+//!         nrf5x::timer::TimerAlarm,
+//!         kernel::hil::time::Freq1MHz,
+//!     >,
+//!     capsules::hybrid_time::HybridTime::new(&nrf5x::rtc::RTC, &nrf5x::timer::TIMER0)
+//! );
+//! // After waking up from deep sleep:
+//! time.resync();
+//! ```
+
+use core::cell::Cell;
+use core::marker::PhantomData;
+use kernel::hil::time::{self, Frequency, Ticks, Time};
+
+pub struct HybridTime<'a, P: Time, V: Time, F: Frequency> {
+    persistent: &'a P,
+    volatile: &'a V,
+    /// Tics of `persistent`, extended past its wraps.
+    persistent_extended: Cell<u64>,
+    /// The last raw value read from `persistent`.
+    persistent_last: Cell<u32>,
+    /// The time at the last resynchronization, in `F` tics.
+    base: Cell<u64>,
+    /// Tics of `volatile` since the last resynchronization.
+    volatile_elapsed: Cell<u64>,
+    /// The last raw value read from `volatile`.
+    volatile_last: Cell<u32>,
+    _frequency: PhantomData<F>,
+}
+
+impl<P: Time, V: Time, F: Frequency> HybridTime<'a, P, V, F> {
+    /// Creates a clock that starts at the persistent counter's current time.
+    pub fn new(persistent: &'a P, volatile: &'a V) -> HybridTime<'a, P, V, F> {
+        let p_now = persistent.now().into_u32();
+        HybridTime {
+            persistent,
+            volatile,
+            persistent_extended: Cell::new(p_now as u64),
+            persistent_last: Cell::new(p_now),
+            base: Cell::new(time::convert_tics_u64(
+                p_now as u64,
+                persistent.frequency(),
+                F::frequency(),
+            )),
+            volatile_elapsed: Cell::new(0),
+            volatile_last: Cell::new(volatile.now().into_u32()),
+            _frequency: PhantomData,
+        }
+    }
+
+    /// Moves the time forward to the persistent counter's reading if that is
+    /// ahead, e.g. after the volatile counter was frozen in deep sleep.
+    pub fn resync(&self) {
+        let current = self.update();
+        let persistent = time::convert_tics_u64(
+            self.persistent_extended.get(),
+            self.persistent.frequency(),
+            F::frequency(),
+        );
+        self.base.set(core::cmp::max(current, persistent));
+        self.volatile_elapsed.set(0);
+    }
+
+    /// Reads both counters and returns the current time in `F` tics.
+    fn update(&self) -> u64 {
+        let p_now = self.persistent.now().into_u32();
+        let p_elapsed =
+            p_now.wrapping_sub(self.persistent_last.get()) & self.persistent.max_tics().into_u32();
+        self.persistent_extended
+            .set(self.persistent_extended.get() + p_elapsed as u64);
+        self.persistent_last.set(p_now);
+
+        let v_now = self.volatile.now().into_u32();
+        let v_elapsed =
+            v_now.wrapping_sub(self.volatile_last.get()) & self.volatile.max_tics().into_u32();
+        self.volatile_elapsed
+            .set(self.volatile_elapsed.get() + v_elapsed as u64);
+        self.volatile_last.set(v_now);
+
+        self.base.get()
+            + time::convert_tics_u64(
+                self.volatile_elapsed.get(),
+                self.volatile.frequency(),
+                F::frequency(),
+            )
+    }
+}
+
+impl<P: Time, V: Time, F: Frequency> Time for HybridTime<'a, P, V, F> {
+    type Frequency = F;
+
+    fn now(&self) -> u32 {
+        self.update() as u32
+    }
+
+    fn persists_in_sleep(&self) -> bool {
+        self.persistent.persists_in_sleep()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::HybridTime;
+    use kernel::hil::time::mock::MockAlarm;
+    use kernel::hil::time::{Freq1MHz, Time};
+
+    fn mock(hz: u32) -> MockAlarm<'static> {
+        let alarm = MockAlarm::new();
+        alarm.set_frequency(hz);
+        alarm
+    }
+
+    /// Moves both counters forward by `us` microseconds, as while awake.
+    fn run(persistent: &MockAlarm, volatile: &MockAlarm, start_us: u64, us: u64) {
+        let p = |us: u64| (us * 32768 / 1_000_000) as u32;
+        persistent.advance(p(start_us + us) - p(start_us));
+        volatile.advance((us * 16) as u32);
+    }
+
+    #[test]
+    fn test_uses_volatile_resolution() {
+        let persistent = mock(32768);
+        let volatile = mock(16_000_000);
+        let time: HybridTime<_, _, Freq1MHz> = HybridTime::new(&persistent, &volatile);
+
+        // Shorter than one persistent tic.
+        volatile.advance(16 * 7);
+        assert_eq!(time.now(), 7);
+        run(&persistent, &volatile, 7, 1000);
+        assert_eq!(time.now(), 1007);
+    }
+
+    #[test]
+    fn test_resync_after_sleep() {
+        let persistent = mock(32768);
+        let volatile = mock(16_000_000);
+        let time: HybridTime<_, _, Freq1MHz> = HybridTime::new(&persistent, &volatile);
+
+        run(&persistent, &volatile, 0, 1_000_000);
+        assert_eq!(time.now(), 1_000_000);
+
+        // Sleep for two seconds: only the persistent counter runs.
+        persistent.advance(2 * 32768);
+        assert_eq!(time.now(), 1_000_000);
+        time.resync();
+        assert_eq!(time.now(), 3_000_000);
+
+        // Fine resolution again after the resync.
+        volatile.advance(16 * 5);
+        assert_eq!(time.now(), 3_000_005);
+    }
+
+    #[test]
+    fn test_resync_does_not_go_backwards() {
+        let persistent = mock(32768);
+        let volatile = mock(16_000_000);
+        let time: HybridTime<_, _, Freq1MHz> = HybridTime::new(&persistent, &volatile);
+
+        // The volatile counter runs ahead of the persistent one.
+        persistent.advance(32768);
+        volatile.advance(16 * 1_000_100);
+        time.resync();
+        assert_eq!(time.now(), 1_000_100);
+    }
+}
@@ -82,7 +82,7 @@ use core::cell::Cell;
 use kernel::common::cells::{OptionalCell, TakeCell};
 use kernel::hil::radio;
 use kernel::hil::rng::{self, Rng};
-use kernel::hil::time::{self, Alarm, Frequency, Time};
+use kernel::hil::time::{self, Alarm, Frequency, Ticks, Time};
 use kernel::ReturnCode;
 
 // Time the radio will remain awake listening for packets before sleeping.
@@ -208,11 +208,10 @@ impl<R: radio::Radio, A: Alarm<'a>> XMac<'a, R, A> {
     // Sets the timer to fire a set number of milliseconds in the future based
     // on the current tick value.
     fn set_timer_ms<T: Time>(&self, ms: u32) {
-        self.alarm.set_alarm(
-            self.alarm
-                .now()
-                .wrapping_add(((ms as f32 / 1000.0) * <T::Frequency>::frequency() as f32) as u32),
-        );
+        self.alarm
+            .set_alarm(self.alarm.now().wrapping_add(A::Ticks::from_u32(
+                ((ms as f32 / 1000.0) * <T::Frequency>::frequency() as f32) as u32,
+            )));
     }
 
     fn transmit_preamble(&self) {
@@ -324,12 +323,15 @@ impl<R: radio::Radio, A: Alarm<'a>> rng::Client for XMac<'a, R, A> {
                     // as it should with this implementation. Since Rng is
                     // asynchronous, we account for the time spent waiting for
                     // the callback and randomly determine the remaining time
-                    // spent backing off.
-                    let time_remaining_ms =
-                        (((self.alarm.get_alarm().wrapping_sub(self.alarm.now())) as f32
-                            / <A::Frequency>::frequency() as f32)
-                            * 1000.0) as u32;
-                    self.set_timer_ms::<A>(random % time_remaining_ms);
+                    // spent backing off. If less than a millisecond is left,
+                    // the current alarm ends the backoff.
+                    let time_remaining_ms = ((self.alarm.remaining().map_or(0, Ticks::into_u32)
+                        as f32
+                        / <A::Frequency>::frequency() as f32)
+                        * 1000.0) as u32;
+                    if time_remaining_ms > 0 {
+                        self.set_timer_ms::<A>(random % time_remaining_ms);
+                    }
                 }
                 rng::Continue::Done
             }
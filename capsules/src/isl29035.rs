@@ -29,7 +29,7 @@ use core::cell::Cell;
 use kernel::common::cells::{OptionalCell, TakeCell};
 use kernel::hil::i2c::{Error, I2CClient, I2CDevice};
 use kernel::hil::sensors::{AmbientLight, AmbientLightClient};
-use kernel::hil::time::{self, Frequency};
+use kernel::hil::time::{self, Frequency, Ticks};
 use kernel::ReturnCode;
 
 pub static mut BUF: [u8; 3] = [0; 3];
@@ -118,7 +118,7 @@ impl<A: time::Alarm<'a>> I2CClient for Isl29035<'a, A> {
                 // Set a timer to wait for the conversion to be done.
                 // For 8 bits, thats 410 us (per Table 11 in the datasheet).
                 let interval = (410 as u32) * <A::Frequency>::frequency() / 1000000;
-                let tics = self.alarm.now().wrapping_add(interval);
+                let tics = self.alarm.now().wrapping_add(A::Ticks::from_u32(interval));
                 self.alarm.set_alarm(tics);
 
                 // Now wait for timer to expire
@@ -10,49 +10,72 @@ pub mod net;
 pub mod adc;
 pub mod aes_ccm;
 pub mod alarm;
+pub mod alarm_to_timer;
 pub mod ambient_light;
 pub mod analog_comparator;
 pub mod analog_sensor;
 pub mod app_flash_driver;
+pub mod backoff;
 pub mod ble_advertising_driver;
 pub mod button;
 pub mod buzzer_driver;
+pub mod cadence_alarm;
+pub mod coalescing_alarm;
 pub mod console;
+pub mod counter_correlator;
 pub mod crc;
 pub mod dac;
+pub mod debounce_timer;
 pub mod debug_process_restart;
+pub mod delayed_call;
+pub mod disciplined_alarm;
 pub mod driver;
+pub mod epoch_alarm;
 pub mod fm25cl;
+pub mod frequency_convert_alarm;
 pub mod fxos8700cq;
 pub mod gpio;
 pub mod gpio_async;
 pub mod humidity;
+pub mod hybrid_time;
 pub mod i2c_master;
 pub mod i2c_master_slave_driver;
 pub mod ieee802154;
 pub mod isl29035;
 pub mod led;
 pub mod log;
+pub mod long_alarm;
 pub mod low_level_debug;
 pub mod lps25hb;
 pub mod ltc294x;
 pub mod max17205;
 pub mod mcp230xx;
+pub mod monotonic_guard;
+pub mod monotonic_time;
 pub mod mx25r6435f;
 pub mod ninedof;
 pub mod nonvolatile_storage_driver;
 pub mod nonvolatile_to_pages;
 pub mod nrf51822_serialization;
 pub mod pca9544a;
+pub mod periodic_alarm;
+pub mod persistent_alarm;
+pub mod preferred_time;
 pub mod process_console;
+pub mod profiling_alarm;
 pub mod rf233;
 pub mod rf233_const;
 pub mod rng;
+pub mod scaled_time;
 pub mod sdcard;
 pub mod segger_rtt;
 pub mod si7021;
+pub mod soft_pwm;
+pub mod software_capture;
 pub mod spi;
 pub mod temperature;
+pub mod timer_to_alarm;
+pub mod timer_wheel;
 pub mod tmp006;
 pub mod tsl2561;
 pub mod usb;
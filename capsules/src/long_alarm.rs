@@ -0,0 +1,174 @@
+//! Alarms longer than one period of the hardware counter.
+//!
+//! An `Alarm` can only be set for a deadline within one counter period, which
+//! at 16MHz on a 32-bit counter is under five minutes. `LongAlarm` accepts a
+//! 64-bit delay and reaches it by arming the underlying alarm for a chain of
+//! shorter steps, calling its client once after the whole delay has elapsed.
+//! Each step starts at the previous step's deadline, so the chain does not
+//! drift however late the intermediate callbacks run.
+//!
+//! Steps are at most half a counter period long, so that an intermediate
+//! callback that runs late is never mistaken for one that runs early. A delay
+//! that fits in one step is a single `set_alarm`.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust
+//! let long_alarm = static_init!(
+//!     capsules::long_alarm::LongAlarm<'static, VirtualMuxAlarm<'static, sam4l::ast::Ast>>,
+//!     capsules::long_alarm::LongAlarm::new(virtual_alarm)
+//! );
+//! virtual_alarm.set_client(long_alarm);
+//! long_alarm.set_client(client);
+//! long_alarm.set_delay_ms(24 * 60 * 60 * 1000);
+//! ```
+
+use core::cell::Cell;
+use kernel::common::cells::OptionalCell;
+use kernel::hil::time::{self, Alarm, Ticks};
+
+pub struct LongAlarm<'a, A: Alarm<'a>> {
+    alarm: &'a A,
+    /// Start of the current step.
+    reference: Cell<A::Ticks>,
+    /// Tics from `reference` until the client is called.
+    remaining: Cell<u64>,
+    armed: Cell<bool>,
+    client: OptionalCell<&'a dyn time::AlarmClient>,
+}
+
+impl<A: Alarm<'a>> LongAlarm<'a, A> {
+    pub fn new(alarm: &'a A) -> LongAlarm<'a, A> {
+        LongAlarm {
+            alarm,
+            reference: Cell::new(A::Ticks::from_u32(0)),
+            remaining: Cell::new(0),
+            armed: Cell::new(false),
+            client: OptionalCell::empty(),
+        }
+    }
+
+    pub fn set_client(&self, client: &'a dyn time::AlarmClient) {
+        self.client.set(client);
+    }
+
+    /// Calls the client once `tics` tics of the underlying alarm have
+    /// elapsed, replacing any delay already in progress.
+    pub fn set_delay(&self, tics: u64) {
+        self.reference.set(self.alarm.now());
+        self.remaining.set(tics);
+        self.armed.set(true);
+        self.arm_step();
+    }
+
+    /// Calls the client after `ms` milliseconds, at the underlying alarm's
+    /// current frequency.
+    pub fn set_delay_ms(&self, ms: u32) {
+        self.set_delay(ms as u64 * self.alarm.frequency() as u64 / 1000);
+    }
+
+    /// Cancels the delay in progress, if any. The client is not called.
+    pub fn cancel(&self) {
+        self.armed.set(false);
+        self.alarm.disable();
+    }
+
+    pub fn is_armed(&self) -> bool {
+        self.armed.get()
+    }
+
+    /// The longest step the underlying alarm is armed for.
+    fn max_step(&self) -> u32 {
+        self.alarm.max_tics().into_u32() / 2
+    }
+
+    fn arm_step(&self) {
+        let step = core::cmp::min(self.remaining.get(), self.max_step() as u64) as u32;
+        self.alarm
+            .set_alarm_from(self.reference.get(), A::Ticks::from_u32(step));
+    }
+}
+
+impl<A: Alarm<'a>> time::AlarmClient for LongAlarm<'a, A> {
+    fn fired(&self) {
+        if !self.armed.get() {
+            return;
+        }
+        let step = core::cmp::min(self.remaining.get(), self.max_step() as u64);
+        self.remaining.set(self.remaining.get() - step);
+        if self.remaining.get() == 0 {
+            self.armed.set(false);
+            self.client.map(|client| client.fired());
+        } else {
+            self.reference.set(
+                self.reference
+                    .get()
+                    .wrapping_add(A::Ticks::from_u32(step as u32)),
+            );
+            self.arm_step();
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::LongAlarm;
+    use kernel::hil::time::mock::{CountingClient, MockAlarm};
+    use kernel::hil::time::Alarm;
+
+    #[test]
+    fn test_short_delay_is_single_alarm() {
+        let alarm = MockAlarm::new();
+        let long = LongAlarm::new(&alarm);
+        let client = CountingClient::with_clock(&alarm);
+        alarm.set_client(&long);
+        long.set_client(&client);
+
+        alarm.advance(5);
+        long.set_delay(100);
+        assert_eq!(alarm.get_alarm(), 105);
+        alarm.advance(100);
+        assert_eq!(client.fires(), 1);
+        assert!(!long.is_armed());
+    }
+
+    #[test]
+    fn test_delay_longer_than_period() {
+        let alarm = MockAlarm::new();
+        let long = LongAlarm::new(&alarm);
+        let client = CountingClient::with_clock(&alarm);
+        alarm.set_client(&long);
+        long.set_client(&client);
+
+        // Two and a half counter periods.
+        long.set_delay(5 << 31);
+        for _ in 0..2 {
+            alarm.advance(u32::max_value());
+            alarm.advance(1);
+        }
+        assert_eq!(client.fires(), 0);
+        alarm.advance(1 << 31);
+        assert_eq!(client.fires(), 1);
+        assert_eq!(client.fired_at(), Some(1 << 31));
+    }
+
+    #[test]
+    fn test_cancel_mid_chain() {
+        let alarm = MockAlarm::new();
+        let long = LongAlarm::new(&alarm);
+        let client = CountingClient::with_clock(&alarm);
+        alarm.set_client(&long);
+        long.set_client(&client);
+
+        long.set_delay(5 << 31);
+        alarm.advance(u32::max_value());
+        assert!(long.is_armed());
+        long.cancel();
+        assert!(!alarm.is_enabled());
+        for _ in 0..4 {
+            alarm.advance(u32::max_value());
+        }
+        assert_eq!(client.fires(), 0);
+    }
+}
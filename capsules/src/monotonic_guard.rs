@@ -0,0 +1,188 @@
+//! Keep a clock from reading backwards.
+//!
+//! Some timer peripherals latch their counter into a register that software
+//! reads, and a read that races with the latch update can return a value
+//! slightly smaller than the previous one. Clients computing durations from
+//! two reads then see a negative, or after wrapping a huge, span of time.
+//!
+//! `MonotonicGuard` wraps any `Time` (and forwards `Alarm` if the wrapped
+//! clock is one) and never returns a value from `now()` that is behind the
+//! value it returned last. It still follows the counter when it wraps.
+//!
+//! The guard cannot tell a single backwards glitch from a legitimate jump
+//! forward by looking at the raw values, since both can be numerically
+//! smaller than the previous reading. It therefore uses the forward distance
+//! from the previous reading, `(now - last) & max_tics()`:
+//!
+//! - a distance of at most half a counter period is time moving forward,
+//!   across a wrap or not, and `now` is returned;
+//! - a larger distance is the counter having moved backwards by less than
+//!   half a period, and the previous reading is returned again.
+//!
+//! This is only correct if `now()` is called at least once every half
+//! counter period, so that genuine progress never looks like more than half
+//! a period. A client that reads the clock less often would see time stand
+//! still until the counter catches up with the last reading. The distance is
+//! computed on the low 32 bits, so for counters wider than 32 bits "half a
+//! period" means 2^31 tics.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust
+//! let guarded = static_init!(
+//!     capsules::monotonic_guard::MonotonicGuard<'static, stm32f4xx::tim2::Tim2>,
+//!     capsules::monotonic_guard::MonotonicGuard::new(&stm32f4xx::tim2::TIM2)
+//! );
+//! ```
+
+use core::cell::Cell;
+use kernel::hil::time::{self, Alarm, Ticks, Time};
+use kernel::ReturnCode;
+
+pub struct MonotonicGuard<'a, T: Time> {
+    time: &'a T,
+    /// The value most recently returned by `now()`.
+    last: Cell<T::Ticks>,
+}
+
+impl<T: Time> MonotonicGuard<'a, T> {
+    pub fn new(time: &'a T) -> MonotonicGuard<'a, T> {
+        MonotonicGuard {
+            time,
+            last: Cell::new(time.now()),
+        }
+    }
+
+    /// Returns `now`, or the previous reading if `now` is behind it.
+    fn guard(&self, now: T::Ticks) -> T::Ticks {
+        let last = self.last.get();
+        let max = self.time.max_tics().into_u32();
+        let forward = now.wrapping_sub(last).into_u32() & max;
+        if forward > max / 2 {
+            last
+        } else {
+            self.last.set(now);
+            now
+        }
+    }
+}
+
+impl<T: Time> Time for MonotonicGuard<'a, T> {
+    type Frequency = T::Frequency;
+    type Ticks = T::Ticks;
+    const WIDTH: u32 = T::WIDTH;
+
+    fn now(&self) -> T::Ticks {
+        self.guard(self.time.now())
+    }
+
+    fn try_now(&self) -> Result<T::Ticks, ReturnCode> {
+        self.time.try_now().map(|now| self.guard(now))
+    }
+
+    fn max_tics(&self) -> T::Ticks {
+        self.time.max_tics()
+    }
+
+    fn frequency(&self) -> u32 {
+        self.time.frequency()
+    }
+
+    fn persists_in_sleep(&self) -> bool {
+        self.time.persists_in_sleep()
+    }
+}
+
+impl<A: Alarm<'a>> Alarm<'a> for MonotonicGuard<'a, A> {
+    fn set_alarm_with_token(&self, tics: A::Ticks, token: u32) -> ReturnCode {
+        self.time.set_alarm_with_token(tics, token)
+    }
+
+    fn get_alarm(&self) -> A::Ticks {
+        self.time.get_alarm()
+    }
+
+    fn now_and_alarm(&self) -> (A::Ticks, A::Ticks) {
+        let (now, alarm) = self.time.now_and_alarm();
+        (self.guard(now), alarm)
+    }
+
+    fn minimum_dt(&self) -> A::Ticks {
+        self.time.minimum_dt()
+    }
+
+    fn set_client(&'a self, client: &'a dyn time::AlarmClient) {
+        self.time.set_client(client);
+    }
+
+    fn clear_client(&self) {
+        self.time.clear_client();
+    }
+
+    fn is_enabled(&self) -> bool {
+        self.time.is_enabled()
+    }
+
+    fn enable(&self) -> ReturnCode {
+        self.time.enable()
+    }
+
+    fn disable(&self) -> ReturnCode {
+        self.time.disable()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::MonotonicGuard;
+    use core::cell::Cell;
+    use kernel::hil::time::{Freq1KHz, Time};
+
+    struct FakeTime {
+        now: Cell<u32>,
+        max: u32,
+    }
+
+    impl Time for FakeTime {
+        type Frequency = Freq1KHz;
+
+        fn now(&self) -> u32 {
+            self.now.get()
+        }
+
+        fn max_tics(&self) -> u32 {
+            self.max
+        }
+    }
+
+    #[test]
+    fn test_clamps_backward_glitch() {
+        let time = FakeTime {
+            now: Cell::new(1000),
+            max: u32::max_value(),
+        };
+        let guard = MonotonicGuard::new(&time);
+        time.now.set(1005);
+        assert_eq!(guard.now(), 1005);
+        time.now.set(1003);
+        assert_eq!(guard.now(), 1005);
+        time.now.set(1007);
+        assert_eq!(guard.now(), 1007);
+    }
+
+    #[test]
+    fn test_follows_wrap() {
+        let time = FakeTime {
+            now: Cell::new(0xff_fff0),
+            max: 0xff_ffff,
+        };
+        let guard = MonotonicGuard::new(&time);
+        // A 24-bit counter wrapping is not a glitch.
+        time.now.set(0x10);
+        assert_eq!(guard.now(), 0x10);
+        // Going back across the wrap is.
+        time.now.set(0xff_fffe);
+        assert_eq!(guard.now(), 0x10);
+    }
+}
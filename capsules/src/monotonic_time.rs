@@ -0,0 +1,115 @@
+//! Extend a hardware counter to a 64-bit monotonic clock.
+//!
+//! `MonotonicTime64` keeps a software high word that it increments each time
+//! the underlying `Counter` overflows, and combines it with the counter's
+//! current value. At 16MHz a 64-bit count of tics does not wrap for tens of
+//! thousands of years, which makes it suitable for timestamps that must stay
+//! ordered across long uptimes.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust
+//! let mono = static_init!(
+//!     capsules::monotonic_time::MonotonicTime64<'static, sam4l::ast::Ast>,
+//!     capsules::monotonic_time::MonotonicTime64::new(&sam4l::ast::AST)
+//! );
+//! hil::time::Counter::set_overflow_client(&sam4l::ast::AST, mono);
+//! ```
+
+use core::cell::Cell;
+use kernel::hil::time::{Counter, OverflowClient, Ticks};
+
+/// A 64-bit tic count built from a `Counter` of at most 32 bits.
+pub struct MonotonicTime64<'a, C: Counter<'a>> {
+    counter: &'a C,
+    /// Number of overflows of `counter` that have been signaled.
+    high: Cell<u32>,
+    /// The most recent value returned by `now64`, used to detect a wrap whose
+    /// overflow callback has not run yet.
+    last: Cell<u64>,
+}
+
+impl<C: Counter<'a>> MonotonicTime64<'a, C> {
+    pub fn new(counter: &'a C) -> MonotonicTime64<'a, C> {
+        MonotonicTime64 {
+            counter,
+            high: Cell::new(0),
+            last: Cell::new(0),
+        }
+    }
+
+    /// Number of tics in one full period of the underlying counter.
+    fn period(&self) -> u64 {
+        self.counter.max_tics().into_u32() as u64 + 1
+    }
+
+    /// Returns the number of tics since the counter started, at the
+    /// frequency of the underlying counter.
+    pub fn now64(&self) -> u64 {
+        // No overflow can be signaled while the counter is frozen, so the
+        // high word and the counter value belong to the same period.
+        let (high, low) = self.counter.with_frozen(|low| (self.high.get(), low));
+
+        let mut now = high as u64 * self.period() + low as u64;
+        // The counter can wrap before its overflow interrupt is serviced, in
+        // which case the high word is one period behind the counter value.
+        if now < self.last.get() {
+            now += self.period();
+        }
+        self.last.set(now);
+        now
+    }
+}
+
+impl<C: Counter<'a>> OverflowClient for MonotonicTime64<'a, C> {
+    fn overflow(&self) {
+        self.high.set(self.high.get().wrapping_add(1));
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::MonotonicTime64;
+    use kernel::hil::time::mock::MockCounter;
+    use kernel::hil::time::OverflowClient;
+
+    fn counter<'a>(now: u32) -> MockCounter<'a> {
+        let counter = MockCounter::new();
+        counter.set_now(now);
+        counter
+    }
+
+    #[test]
+    fn test_counts_overflows() {
+        let counter = counter(5);
+        let mono = MonotonicTime64::new(&counter);
+        assert_eq!(mono.now64(), 5);
+        assert_eq!(counter.frozen_reads(), 1);
+
+        counter.set_now(3);
+        mono.overflow();
+        assert_eq!(mono.now64(), (1 << 32) + 3);
+
+        counter.set_now(7);
+        mono.overflow();
+        assert_eq!(mono.now64(), (2 << 32) + 7);
+    }
+
+    #[test]
+    fn test_wrap_before_overflow_callback() {
+        let counter = counter(0xffff_fff0);
+        let mono = MonotonicTime64::new(&counter);
+        assert_eq!(mono.now64(), 0xffff_fff0);
+
+        // The counter wrapped but the overflow interrupt is still pending.
+        counter.set_now(4);
+        assert_eq!(mono.now64(), (1 << 32) + 4);
+        counter.set_now(9);
+        assert_eq!(mono.now64(), (1 << 32) + 9);
+
+        // Once the callback runs the high word catches up.
+        mono.overflow();
+        assert_eq!(mono.now64(), (1 << 32) + 9);
+    }
+}
@@ -51,7 +51,7 @@ use kernel::common::cells::OptionalCell;
 use kernel::common::cells::TakeCell;
 use kernel::debug;
 use kernel::hil;
-use kernel::hil::time::Frequency;
+use kernel::hil::time::{Frequency, Ticks};
 use kernel::ReturnCode;
 
 pub static mut TXBUFFER: [u8; PAGE_SIZE as usize + 4] = [0; PAGE_SIZE as usize + 4];
@@ -378,7 +378,7 @@ impl<
                 // Datasheet says erase takes 58 ms on average. So we wait that
                 // long.
                 let interval = (58 as u32) * <A::Frequency>::frequency() / 1000;
-                let tics = self.alarm.now().wrapping_add(interval);
+                let tics = self.alarm.now().wrapping_add(A::Ticks::from_u32(interval));
                 self.alarm.set_alarm(tics);
             }
             State::EraseSectorCheckDone { operation } => {
@@ -474,7 +474,7 @@ impl<
                 // Datasheet says write page takes 3.2 ms on average. So we wait
                 // that long.
                 let interval = (3200 as u32) * <A::Frequency>::frequency() / 1000000;
-                let tics = self.alarm.now().wrapping_add(interval);
+                let tics = self.alarm.now().wrapping_add(A::Ticks::from_u32(interval));
                 self.alarm.set_alarm(tics);
             }
             State::WriteSectorWaitDone {
@@ -25,7 +25,7 @@ use core::cell::Cell;
 use kernel::common::cells::{OptionalCell, TakeCell};
 use kernel::common::leasable_buffer::LeasableBuffer;
 use kernel::debug;
-use kernel::hil::time::{self, Frequency};
+use kernel::hil::time::{self, Frequency, Ticks};
 use kernel::ReturnCode;
 
 /// This trait must be implemented by upper layers in order to receive
@@ -263,7 +263,7 @@ impl<A: time::Alarm<'a>> TxClient for IP6SendStruct<'a, A> {
             // fragment, before passing the send_done callback back to the client. This
             // could be optimized by checking if it is the last fragment before setting the timer.
             let interval = (100000 as u32) * <A::Frequency>::frequency() / 1000000;
-            let tics = self.alarm.now().wrapping_add(interval);
+            let tics = self.alarm.now().wrapping_add(A::Ticks::from_u32(interval));
             self.alarm.set_alarm(tics);
         }
     }
@@ -0,0 +1,219 @@
+//! Drift-free periodic callbacks on top of a one-shot `Alarm`.
+//!
+//! Re-arming an alarm from its `fired()` callback with `now() + period` makes
+//! every period slightly longer than asked for, by however late the callback
+//! ran. `PeriodicAlarm` instead schedules each deadline from the previous
+//! one, so callbacks stay aligned to multiples of the period after `start()`.
+//!
+//! If a callback runs for longer than a period, one or more deadlines are
+//! missed. What happens then is chosen with `MissedPolicy`: either skip the
+//! missed deadlines and resume at the next future boundary, or fire once for
+//! each missed deadline, back-to-back, until caught up.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust
+//! let periodic = static_init!(
+//!     capsules::periodic_alarm::PeriodicAlarm<'static, VirtualMuxAlarm<'static, sam4l::ast::Ast>>,
+//!     capsules::periodic_alarm::PeriodicAlarm::new(
+//!         virtual_alarm,
+//!         1000,
+//!         capsules::periodic_alarm::MissedPolicy::Skip
+//!     )
+//! );
+//! virtual_alarm.set_client(periodic);
+//! periodic.set_client(sampler);
+//! periodic.start();
+//! ```
+
+use core::cell::Cell;
+use kernel::common::cells::OptionalCell;
+use kernel::hil::time::{self, Alarm, Ticks};
+
+/// What to do about deadlines that passed while a callback was running.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MissedPolicy {
+    /// Drop missed deadlines and fire next at the first boundary in the
+    /// future.
+    Skip,
+    /// Fire once for every missed deadline, as quickly as the alarm allows.
+    BackToBack,
+}
+
+pub struct PeriodicAlarm<'a, A: Alarm<'a>> {
+    alarm: &'a A,
+    period: A::Ticks,
+    policy: MissedPolicy,
+    /// The deadline the alarm is currently armed for.
+    deadline: Cell<A::Ticks>,
+    running: Cell<bool>,
+    client: OptionalCell<&'a dyn time::AlarmClient>,
+}
+
+impl<A: Alarm<'a>> PeriodicAlarm<'a, A> {
+    pub fn new(alarm: &'a A, period: A::Ticks, policy: MissedPolicy) -> PeriodicAlarm<'a, A> {
+        PeriodicAlarm {
+            alarm,
+            period,
+            policy,
+            deadline: Cell::new(A::Ticks::from_u32(0)),
+            running: Cell::new(false),
+            client: OptionalCell::empty(),
+        }
+    }
+
+    pub fn set_client(&self, client: &'a dyn time::AlarmClient) {
+        self.client.set(client);
+    }
+
+    /// Starts firing the client every period, the first time one period from
+    /// now.
+    pub fn start(&self) {
+        let now = self.alarm.now();
+        self.running.set(true);
+        self.deadline.set(now.wrapping_add(self.period));
+        self.alarm.set_alarm_from(now, self.period);
+    }
+
+    pub fn stop(&self) {
+        self.running.set(false);
+        self.alarm.disable();
+    }
+
+    pub fn is_running(&self) -> bool {
+        self.running.get()
+    }
+}
+
+impl<A: Alarm<'a>> time::AlarmClient for PeriodicAlarm<'a, A> {
+    fn fired(&self) {
+        if !self.running.get() {
+            return;
+        }
+        let previous = self.deadline.get();
+        self.client.map(|client| client.fired());
+        // The client may have stopped or restarted us.
+        if !self.running.get() || self.deadline.get() != previous {
+            return;
+        }
+
+        let now = self.alarm.now();
+        let mut next = previous.wrapping_add(self.period);
+        if self.policy == MissedPolicy::Skip && time::has_expired(previous, next, now) {
+            let period = self.period.into_u32();
+            let missed = now.wrapping_sub(previous).into_u32() / period;
+            next = previous.wrapping_add(A::Ticks::from_u32(
+                missed.wrapping_add(1).wrapping_mul(period),
+            ));
+        }
+
+        // Arming relative to the previous deadline fires as soon as possible
+        // if `next` has already passed, which gives back-to-back callbacks.
+        self.deadline.set(next);
+        self.alarm
+            .set_alarm_from(previous, next.wrapping_sub(previous));
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{MissedPolicy, PeriodicAlarm};
+    use core::cell::Cell;
+    use kernel::hil::time::mock::MockAlarm;
+    use kernel::hil::time::{Alarm, AlarmClient, Time};
+
+    struct Client<'a> {
+        alarm: &'a MockAlarm<'a>,
+        fired_at: Cell<[u32; 4]>,
+        fired: Cell<usize>,
+        /// Tics the next callback spends "working".
+        work: Cell<u32>,
+    }
+
+    impl Client<'a> {
+        fn new(alarm: &'a MockAlarm<'a>) -> Client<'a> {
+            Client {
+                alarm,
+                fired_at: Cell::new([0; 4]),
+                fired: Cell::new(0),
+                work: Cell::new(0),
+            }
+        }
+    }
+
+    impl AlarmClient for Client<'a> {
+        fn fired(&self) {
+            let mut fired_at = self.fired_at.get();
+            fired_at[self.fired.get() % 4] = self.alarm.now();
+            self.fired_at.set(fired_at);
+            self.fired.set(self.fired.get() + 1);
+            self.alarm.advance(self.work.take());
+        }
+    }
+
+    #[test]
+    fn test_fires_on_period_boundaries() {
+        let alarm = MockAlarm::new();
+        let periodic = PeriodicAlarm::new(&alarm, 10, MissedPolicy::Skip);
+        let client = Client::new(&alarm);
+        alarm.set_client(&periodic);
+        periodic.set_client(&client);
+
+        alarm.advance(3);
+        periodic.start();
+        alarm.advance(40);
+        assert_eq!(client.fired.get(), 4);
+        assert_eq!(client.fired_at.get(), [13, 23, 33, 43]);
+    }
+
+    #[test]
+    fn test_skip_missed_deadlines() {
+        let alarm = MockAlarm::new();
+        let periodic = PeriodicAlarm::new(&alarm, 10, MissedPolicy::Skip);
+        let client = Client::new(&alarm);
+        alarm.set_client(&periodic);
+        periodic.set_client(&client);
+
+        periodic.start();
+        client.work.set(25);
+        alarm.advance(10);
+        // The deadlines at 20 and 30 were missed.
+        assert_eq!(alarm.now(), 35);
+        assert_eq!(alarm.get_alarm(), 40);
+    }
+
+    #[test]
+    fn test_back_to_back_after_missed_deadlines() {
+        let alarm = MockAlarm::new();
+        let periodic = PeriodicAlarm::new(&alarm, 10, MissedPolicy::BackToBack);
+        let client = Client::new(&alarm);
+        alarm.set_client(&periodic);
+        periodic.set_client(&client);
+
+        periodic.start();
+        client.work.set(25);
+        alarm.advance(10);
+        assert_eq!(alarm.now(), 35);
+        // Catch up on the deadlines at 20 and 30, then get back in step.
+        alarm.advance(5);
+        assert_eq!(client.fired.get(), 4);
+        assert_eq!(client.fired_at.get(), [10, 36, 37, 40]);
+    }
+
+    #[test]
+    fn test_stop() {
+        let alarm = MockAlarm::new();
+        let periodic = PeriodicAlarm::new(&alarm, 10, MissedPolicy::Skip);
+        let client = Client::new(&alarm);
+        alarm.set_client(&periodic);
+        periodic.set_client(&client);
+
+        periodic.start();
+        alarm.advance(10);
+        periodic.stop();
+        alarm.advance(100);
+        assert_eq!(client.fired.get(), 1);
+        assert!(!alarm.is_enabled());
+    }
+}
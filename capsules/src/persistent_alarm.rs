@@ -0,0 +1,306 @@
+//! An alarm that stays set while its counter is stopped and started again.
+//!
+//! Stopping a `Counter` drops any alarm armed on it, so a power manager that
+//! stops and restarts a shared counter around power transitions silently
+//! loses its clients' alarms. `PersistentAlarm` wraps a counter with an
+//! alarm, implements both `Counter` and `Alarm` itself, and remembers the
+//! pending alarm as a delay from the time it was set. When the counter is
+//! started again through the wrapper the alarm is armed again.
+//!
+//! How the time the counter was stopped counts depends on whether the
+//! counter persists in sleep (`Time::persists_in_sleep`), which is reported
+//! by `restart_policy()`:
+//!
+//! - `RestartPolicy::Persistent`: the counter's `now()` is assumed to have
+//!   kept up with real time across the restart, so the alarm keeps its
+//!   deadline and fires as soon as possible if that passed in the meantime.
+//! - `RestartPolicy::Volatile`: the counter's value says nothing about the
+//!   time it was stopped, so no time is assumed to have passed. The delay
+//!   that was left when the counter stopped is counted from the restart.
+//!
+//! An alarm set while the counter is stopped is armed when it starts. The
+//! counter must only be stopped and started through `PersistentAlarm`, and
+//! `PersistentAlarm` must be the client of the underlying alarm.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust
+//! let persistent_alarm = static_init!(
+//!     capsules::persistent_alarm::PersistentAlarm<'static, sam4l::ast::Ast>,
+//!     capsules::persistent_alarm::PersistentAlarm::new(&sam4l::ast::AST)
+//! );
+//! sam4l::ast::AST.set_client(persistent_alarm);
+//! let mux_alarm = static_init!(
+//!     MuxAlarm<'static, capsules::persistent_alarm::PersistentAlarm<'static, sam4l::ast::Ast>>,
+//!     MuxAlarm::new(persistent_alarm)
+//! );
+//! persistent_alarm.set_client(mux_alarm);
+//! // In the power manager:
+//! persistent_alarm.stop();
+//! persistent_alarm.start();
+//! ```
+
+use core::cell::Cell;
+use kernel::common::cells::OptionalCell;
+use kernel::hil::time::{self, Alarm, Counter, OverflowClient, Ticks, Time};
+use kernel::ReturnCode;
+
+/// How `PersistentAlarm` accounts for the time its counter was stopped.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RestartPolicy {
+    /// The counter kept up with real time; the alarm keeps its deadline.
+    Persistent,
+    /// No time passed while stopped; the alarm keeps its remaining delay.
+    Volatile,
+}
+
+pub struct PersistentAlarm<'a, C: Counter<'a> + Alarm<'a>> {
+    counter: &'a C,
+    policy: RestartPolicy,
+    /// `now()` when the pending alarm was set, or for a volatile counter
+    /// when it was last stopped or started.
+    reference: Cell<C::Ticks>,
+    /// Tics after `reference` at which the pending alarm fires.
+    dt: Cell<C::Ticks>,
+    token: Cell<u32>,
+    pending: Cell<bool>,
+    client: OptionalCell<&'a dyn time::AlarmClient>,
+}
+
+impl<C: Counter<'a> + Alarm<'a>> PersistentAlarm<'a, C> {
+    pub fn new(counter: &'a C) -> PersistentAlarm<'a, C> {
+        PersistentAlarm {
+            counter,
+            policy: if counter.persists_in_sleep() {
+                RestartPolicy::Persistent
+            } else {
+                RestartPolicy::Volatile
+            },
+            reference: Cell::new(C::Ticks::from_u32(0)),
+            dt: Cell::new(C::Ticks::from_u32(0)),
+            token: Cell::new(0),
+            pending: Cell::new(false),
+            client: OptionalCell::empty(),
+        }
+    }
+
+    /// Returns how the time the counter is stopped is accounted for.
+    pub fn restart_policy(&self) -> RestartPolicy {
+        self.policy
+    }
+
+    /// Arms the underlying alarm for the pending deadline after a restart.
+    fn arm(&self) {
+        if self.policy == RestartPolicy::Volatile {
+            // Counted from now, whatever the counter's value after a restart.
+            self.reference.set(self.counter.now());
+        }
+        self.counter
+            .set_alarm_from(self.reference.get(), self.dt.get());
+    }
+}
+
+impl<C: Counter<'a> + Alarm<'a>> Time for PersistentAlarm<'a, C> {
+    type Frequency = C::Frequency;
+    type Ticks = C::Ticks;
+    const WIDTH: u32 = C::WIDTH;
+
+    fn now(&self) -> C::Ticks {
+        self.counter.now()
+    }
+
+    fn try_now(&self) -> Result<C::Ticks, ReturnCode> {
+        self.counter.try_now()
+    }
+
+    fn frequency(&self) -> u32 {
+        self.counter.frequency()
+    }
+
+    fn persists_in_sleep(&self) -> bool {
+        self.counter.persists_in_sleep()
+    }
+}
+
+impl<C: Counter<'a> + Alarm<'a>> Counter<'a> for PersistentAlarm<'a, C> {
+    fn set_overflow_client(&'a self, client: &'a dyn OverflowClient) {
+        self.counter.set_overflow_client(client);
+    }
+
+    /// Starts the counter and arms the pending alarm, if any.
+    fn start(&self) -> ReturnCode {
+        let result = self.counter.start();
+        if result == ReturnCode::SUCCESS && self.pending.get() {
+            self.arm();
+        }
+        result
+    }
+
+    /// Stops the counter, keeping the pending alarm, if any.
+    fn stop(&self) -> ReturnCode {
+        if self.pending.get() && self.policy == RestartPolicy::Volatile {
+            let now = self.counter.now();
+            let reference = self.reference.get();
+            let deadline = reference.wrapping_add(self.dt.get());
+            let remaining = if time::has_expired(reference, deadline, now) {
+                C::Ticks::from_u32(0)
+            } else {
+                deadline.wrapping_sub(now)
+            };
+            self.reference.set(now);
+            self.dt.set(remaining);
+        }
+        self.counter.disable();
+        self.counter.stop()
+    }
+
+    fn is_running(&self) -> bool {
+        self.counter.is_running()
+    }
+}
+
+impl<C: Counter<'a> + Alarm<'a>> Alarm<'a> for PersistentAlarm<'a, C> {
+    /// While the counter is stopped the alarm is only recorded, and armed
+    /// when the counter starts.
+    fn set_alarm_with_token(&self, tics: C::Ticks, token: u32) -> ReturnCode {
+        let now = self.counter.now();
+        if tics.wrapping_sub(now) < self.counter.minimum_dt() {
+            return ReturnCode::EINVAL;
+        }
+        self.reference.set(now);
+        self.dt.set(tics.wrapping_sub(now));
+        self.token.set(token);
+        self.pending.set(true);
+        if self.counter.is_running() {
+            self.counter.set_alarm(tics)
+        } else {
+            self.counter.disable();
+            ReturnCode::SUCCESS
+        }
+    }
+
+    fn get_alarm(&self) -> C::Ticks {
+        self.reference.get().wrapping_add(self.dt.get())
+    }
+
+    fn minimum_dt(&self) -> C::Ticks {
+        self.counter.minimum_dt()
+    }
+
+    fn set_client(&'a self, client: &'a dyn time::AlarmClient) {
+        self.client.set(client);
+    }
+
+    fn clear_client(&self) {
+        self.disable();
+        self.client.clear();
+    }
+
+    /// `true` while an alarm is pending, also while the counter is stopped.
+    fn is_enabled(&self) -> bool {
+        self.pending.get()
+    }
+
+    fn disable(&self) -> ReturnCode {
+        self.pending.set(false);
+        self.counter.disable()
+    }
+}
+
+impl<C: Counter<'a> + Alarm<'a>> time::AlarmClient for PersistentAlarm<'a, C> {
+    fn fired(&self) {
+        if self.pending.take() {
+            self.client
+                .map(|client| client.fired_with_token(self.token.get()));
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{PersistentAlarm, RestartPolicy};
+    use kernel::hil::time::mock::{CountingClient, MockAlarm};
+    use kernel::hil::time::{Alarm, Counter};
+    use kernel::ReturnCode;
+
+    fn counter<'a>(persistent: bool) -> MockAlarm<'a> {
+        let counter = MockAlarm::new();
+        counter.set_persists_in_sleep(persistent);
+        counter
+    }
+
+    #[test]
+    fn test_persistent_keeps_deadline() {
+        let counter = counter(true);
+        let alarm = PersistentAlarm::new(&counter);
+        let client = CountingClient::with_clock(&counter);
+        counter.set_client(&alarm);
+        alarm.set_client(&client);
+        assert_eq!(alarm.restart_policy(), RestartPolicy::Persistent);
+
+        alarm.set_alarm(100);
+        counter.advance(30);
+        alarm.stop();
+        counter.advance(50);
+        assert_eq!(client.fired_at(), None);
+        assert!(alarm.is_enabled());
+        alarm.start();
+        counter.advance(20);
+        assert_eq!(client.fired_at(), Some(100));
+
+        // Passed while stopped: fires right after the restart.
+        client.reset();
+        alarm.set_alarm(150);
+        alarm.stop();
+        counter.advance(100);
+        alarm.start();
+        counter.advance(1);
+        assert_eq!(client.fired_at(), Some(201));
+        assert!(!alarm.is_enabled());
+    }
+
+    #[test]
+    fn test_volatile_keeps_remaining_delay() {
+        let counter = counter(false);
+        let alarm = PersistentAlarm::new(&counter);
+        let client = CountingClient::with_clock(&counter);
+        counter.set_client(&alarm);
+        alarm.set_client(&client);
+        assert_eq!(alarm.restart_policy(), RestartPolicy::Volatile);
+
+        counter.set_now(1000);
+        alarm.set_alarm(1100);
+        counter.advance(30);
+        alarm.stop();
+        // Restarts from 0 with 70 tics to go.
+        alarm.start();
+        counter.advance(69);
+        assert_eq!(client.fired_at(), None);
+        counter.advance(1);
+        assert_eq!(client.fired_at(), Some(70));
+    }
+
+    #[test]
+    fn test_set_while_stopped_and_disable() {
+        let counter = counter(false);
+        let alarm = PersistentAlarm::new(&counter);
+        let client = CountingClient::with_clock(&counter);
+        counter.set_client(&alarm);
+        alarm.set_client(&client);
+
+        alarm.stop();
+        assert_eq!(alarm.set_alarm(40), ReturnCode::SUCCESS);
+        alarm.start();
+        counter.advance(40);
+        assert_eq!(client.fired_at(), Some(40));
+
+        client.reset();
+        alarm.set_alarm(80);
+        alarm.stop();
+        alarm.disable();
+        alarm.start();
+        counter.advance(100);
+        assert_eq!(client.fired_at(), None);
+    }
+}
@@ -0,0 +1,193 @@
+//! Present whichever of several counters is running as one clock.
+//!
+//! `PreferredTime<F>` is constructed from a list of counters in order of
+//! preference, e.g. highest resolution first, and implements `Time` with
+//! `type Frequency = F`. Each `now()` reads the first counter in the list
+//! that `is_running()`, so that higher layers keep a clock while counters are
+//! powered up and down, without knowing which one is in use.
+//!
+//! Counters have different frequencies and widths, so their raw readings
+//! cannot be compared. The output is instead a full 32-bit counter at
+//! frequency `F` that advances by the time elapsed on the counter in use,
+//! rescaled. When the counter in use changes, the output continues from its
+//! last value and follows the new counter from then on; the time between the
+//! last read of the old counter and the first read of the new one is lost,
+//! so reads should be frequent around such switches. While no counter is
+//! running, `now()` returns the last value it returned and does not advance.
+//! As with `ScaledTime`, the clock must be read at least once per period of
+//! the counter in use to stay correct.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust
+//! let sources = static_init!(
+//!     [&'static dyn capsules::preferred_time::ClockSource; 2],
+//!     [&sam4l::ast::AST, &rtc_counter]
+//! );
+//! let time = static_init!(
+//!     capsules::preferred_time::PreferredTime<'static, kernel::hil::time::Freq1KHz>,
+//!     capsules::preferred_time::PreferredTime::new(sources)
+//! );
+//! ```
+
+use core::cell::Cell;
+use core::marker::PhantomData;
+use kernel::hil::time::{Counter, Frequency, Ticks, Time};
+
+/// The parts of a `Counter` that `PreferredTime` reads, without its
+/// associated types, so that counters of different frequencies can be put
+/// in one list. Implemented for every `Counter`.
+pub trait ClockSource {
+    fn is_running(&self) -> bool;
+    /// The current value of the counter, as in `Time::now`.
+    fn read(&self) -> u32;
+    /// The value at which the counter wraps, as in `Time::max_tics`.
+    fn wrap_tics(&self) -> u32;
+    /// The frequency of the counter in Hz, as in `Time::frequency`.
+    fn rate(&self) -> u32;
+}
+
+impl<C: Counter<'a>> ClockSource for C {
+    fn is_running(&self) -> bool {
+        Counter::is_running(self)
+    }
+
+    fn read(&self) -> u32 {
+        self.now().into_u32()
+    }
+
+    fn wrap_tics(&self) -> u32 {
+        self.max_tics().into_u32()
+    }
+
+    fn rate(&self) -> u32 {
+        self.frequency()
+    }
+}
+
+pub struct PreferredTime<'a, F: Frequency> {
+    sources: &'a [&'a dyn ClockSource],
+    /// The index of the counter read last, if any was running.
+    active: Cell<Option<usize>>,
+    /// The last raw value read from the active counter.
+    last: Cell<u32>,
+    /// Tics of the active counter times `F` not yet counted in `now`.
+    remainder: Cell<u64>,
+    now: Cell<u32>,
+    _frequency: PhantomData<F>,
+}
+
+impl<F: Frequency> PreferredTime<'a, F> {
+    pub fn new(sources: &'a [&'a dyn ClockSource]) -> PreferredTime<'a, F> {
+        PreferredTime {
+            sources,
+            active: Cell::new(None),
+            last: Cell::new(0),
+            remainder: Cell::new(0),
+            now: Cell::new(0),
+            _frequency: PhantomData,
+        }
+    }
+
+    /// Returns the index in the list of the counter `now()` read last, or
+    /// `None` if none was running then.
+    pub fn active(&self) -> Option<usize> {
+        self.active.get()
+    }
+}
+
+impl<F: Frequency> Time for PreferredTime<'a, F> {
+    type Frequency = F;
+
+    fn now(&self) -> u32 {
+        let index = match self.sources.iter().position(|source| source.is_running()) {
+            Some(index) => index,
+            None => {
+                self.active.set(None);
+                return self.now.get();
+            }
+        };
+        let source = self.sources[index];
+        let raw = source.read();
+        if self.active.get() == Some(index) {
+            let elapsed = raw.wrapping_sub(self.last.get()) & source.wrap_tics();
+            let scaled = elapsed as u64 * F::frequency() as u64 + self.remainder.get();
+            let rate = source.rate() as u64;
+            self.now
+                .set(self.now.get().wrapping_add((scaled / rate) as u32));
+            self.remainder.set(scaled % rate);
+        } else {
+            self.active.set(Some(index));
+            self.remainder.set(0);
+        }
+        self.last.set(raw);
+        self.now.get()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{ClockSource, PreferredTime};
+    use kernel::hil::time::mock::MockCounter;
+    use kernel::hil::time::{Counter, Freq1KHz, Time};
+
+    fn counter<'a>(max: u32, frequency: u32) -> MockCounter<'a> {
+        let counter = MockCounter::new();
+        counter.set_max_tics(max);
+        counter.set_frequency(frequency);
+        counter
+    }
+
+    #[test]
+    fn test_follows_first_running_counter() {
+        let fast = counter(0xFFFF, 32768);
+        let slow = counter(u32::max_value(), 1000);
+        let sources: [&dyn ClockSource; 2] = [&fast, &slow];
+        let time: PreferredTime<Freq1KHz> = PreferredTime::new(&sources);
+
+        assert_eq!(time.now(), 0);
+        assert_eq!(time.active(), Some(0));
+        // Two seconds, across a wrap of the 16-bit counter, in two reads.
+        fast.advance(32768);
+        slow.advance(1000);
+        assert_eq!(time.now(), 1000);
+        fast.advance(32768);
+        slow.advance(1000);
+        assert_eq!(time.now(), 2000);
+
+        // Switches to the slow counter, continuing from where it was.
+        fast.stop();
+        slow.advance(5);
+        assert_eq!(time.now(), 2000);
+        assert_eq!(time.active(), Some(1));
+        slow.advance(250);
+        assert_eq!(time.now(), 2250);
+
+        // None running: the last value.
+        slow.stop();
+        assert_eq!(time.now(), 2250);
+        assert_eq!(time.active(), None);
+
+        fast.start();
+        fast.advance(16384);
+        assert_eq!(time.now(), 2250);
+        fast.advance(16384);
+        assert_eq!(time.now(), 2750);
+    }
+
+    #[test]
+    fn test_keeps_fractions() {
+        let counter = counter(u32::max_value(), 32768);
+        let sources: [&dyn ClockSource; 1] = [&counter];
+        let time: PreferredTime<Freq1KHz> = PreferredTime::new(&sources);
+
+        time.now();
+        for _ in 0..1000 {
+            counter.advance(32);
+            time.now();
+        }
+        // 32000 tics is 976.56 ms.
+        assert_eq!(time.now(), 976);
+    }
+}
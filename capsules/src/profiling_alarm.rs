@@ -0,0 +1,189 @@
+//! Measure how late an `Alarm` fires.
+//!
+//! `ProfilingAlarm` wraps any `Alarm` and implements `Alarm` itself. Each time
+//! the underlying alarm fires it records the latency, `now()` minus
+//! `get_alarm()` in tics, into a histogram and then calls its own client
+//! unchanged. The histogram can be read at any time, e.g. from a debug
+//! console, to see how interrupt and scheduling latency is distributed over a
+//! run.
+//!
+//! The histogram has `BUCKETS` buckets on a power-of-two scale: bucket `0`
+//! counts alarms with no latency, bucket `i` latencies of `2^(i-1)` up to
+//! `2^i - 1` tics, and the last bucket everything longer. Counts saturate
+//! instead of wrapping.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust
+//! let profiled = static_init!(
+//!     capsules::profiling_alarm::ProfilingAlarm<'static, VirtualMuxAlarm<'static, sam4l::ast::Ast>>,
+//!     capsules::profiling_alarm::ProfilingAlarm::new(virtual_alarm)
+//! );
+//! virtual_alarm.set_client(profiled);
+//! profiled.set_client(scheduler);
+//! // Later:
+//! let histogram = profiled.histogram();
+//! ```
+
+use core::cell::Cell;
+use kernel::common::cells::OptionalCell;
+use kernel::hil::time::{self, Alarm, Ticks, Time};
+use kernel::ReturnCode;
+
+/// Number of buckets in the latency histogram.
+pub const BUCKETS: usize = 16;
+
+pub struct ProfilingAlarm<'a, A: Alarm<'a>> {
+    alarm: &'a A,
+    histogram: Cell<[u32; BUCKETS]>,
+    client: OptionalCell<&'a dyn time::AlarmClient>,
+}
+
+impl<A: Alarm<'a>> ProfilingAlarm<'a, A> {
+    pub fn new(alarm: &'a A) -> ProfilingAlarm<'a, A> {
+        ProfilingAlarm {
+            alarm,
+            histogram: Cell::new([0; BUCKETS]),
+            client: OptionalCell::empty(),
+        }
+    }
+
+    /// Returns the number of firings recorded in each latency bucket.
+    pub fn histogram(&self) -> [u32; BUCKETS] {
+        self.histogram.get()
+    }
+
+    /// Clears the histogram.
+    pub fn reset(&self) {
+        self.histogram.set([0; BUCKETS]);
+    }
+
+    fn record(&self) {
+        let (now, alarm) = self.alarm.now_and_alarm();
+        let latency = now.wrapping_sub(alarm).into_u32() & self.alarm.max_tics().into_u32();
+        let bucket = core::cmp::min((32 - latency.leading_zeros()) as usize, BUCKETS - 1);
+        let mut histogram = self.histogram.get();
+        histogram[bucket] = histogram[bucket].saturating_add(1);
+        self.histogram.set(histogram);
+    }
+}
+
+impl<A: Alarm<'a>> Time for ProfilingAlarm<'a, A> {
+    type Frequency = A::Frequency;
+    type Ticks = A::Ticks;
+    const WIDTH: u32 = A::WIDTH;
+
+    fn now(&self) -> A::Ticks {
+        self.alarm.now()
+    }
+
+    fn max_tics(&self) -> A::Ticks {
+        self.alarm.max_tics()
+    }
+
+    fn frequency(&self) -> u32 {
+        self.alarm.frequency()
+    }
+
+    fn persists_in_sleep(&self) -> bool {
+        self.alarm.persists_in_sleep()
+    }
+}
+
+impl<A: Alarm<'a>> Alarm<'a> for ProfilingAlarm<'a, A> {
+    fn set_alarm_with_token(&self, tics: A::Ticks, token: u32) -> ReturnCode {
+        self.alarm.set_alarm_with_token(tics, token)
+    }
+
+    fn get_alarm(&self) -> A::Ticks {
+        self.alarm.get_alarm()
+    }
+
+    fn now_and_alarm(&self) -> (A::Ticks, A::Ticks) {
+        self.alarm.now_and_alarm()
+    }
+
+    fn minimum_dt(&self) -> A::Ticks {
+        self.alarm.minimum_dt()
+    }
+
+    fn set_client(&'a self, client: &'a dyn time::AlarmClient) {
+        self.client.set(client);
+    }
+
+    fn clear_client(&self) {
+        self.disable();
+        self.client.clear();
+    }
+
+    fn is_enabled(&self) -> bool {
+        self.alarm.is_enabled()
+    }
+
+    fn enable(&self) -> ReturnCode {
+        self.alarm.enable()
+    }
+
+    fn disable(&self) -> ReturnCode {
+        self.alarm.disable()
+    }
+}
+
+impl<A: Alarm<'a>> time::AlarmClient for ProfilingAlarm<'a, A> {
+    fn fired(&self) {
+        self.record();
+        self.client.map(|client| client.fired());
+    }
+
+    fn fired_with_token(&self, token: u32) {
+        self.record();
+        self.client.map(|client| client.fired_with_token(token));
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{ProfilingAlarm, BUCKETS};
+    use kernel::hil::time::mock::{CountingClient, MockAlarm};
+    use kernel::hil::time::{Alarm, AlarmClient};
+
+    #[test]
+    fn test_records_latency_buckets() {
+        let alarm = MockAlarm::new();
+        let profiled = ProfilingAlarm::new(&alarm);
+        let client = CountingClient::new();
+        alarm.set_client(&profiled);
+        profiled.set_client(&client);
+
+        // The mock fires exactly on time, so late interrupts are simulated
+        // by moving the clock past the deadline and calling the callback.
+        for &(late, bucket) in &[(0, 0), (1, 1), (3, 2), (4, 3), (100_000, BUCKETS - 1)] {
+            profiled.set_alarm_with_token(100, 7);
+            alarm.set_now(100 + late);
+            profiled.fired_with_token(7);
+            assert_eq!(profiled.histogram()[bucket], 1, "latency {}", late);
+            alarm.set_now(0);
+        }
+        assert_eq!(profiled.histogram().iter().sum::<u32>(), 5);
+        assert_eq!(client.token(), Some(7));
+
+        profiled.reset();
+        assert_eq!(profiled.histogram(), [0; BUCKETS]);
+    }
+
+    #[test]
+    fn test_forwards_on_time_fire() {
+        let alarm = MockAlarm::new();
+        let profiled = ProfilingAlarm::new(&alarm);
+        let client = CountingClient::new();
+        alarm.set_client(&profiled);
+        profiled.set_client(&client);
+
+        profiled.set_alarm_with_token(10, 3);
+        alarm.advance(20);
+        assert_eq!(client.token(), Some(3));
+        assert_eq!(profiled.histogram()[0], 1);
+        assert!(!profiled.is_enabled());
+    }
+}
@@ -0,0 +1,148 @@
+//! Present a clock at a different frequency.
+//!
+//! `ScaledTime<T, F>` is a read-only view of a `Time` that implements `Time`
+//! with `type Frequency = F`, rescaling `now()` on every read. Several views
+//! of one hardware counter can present it in, say, milliseconds, microseconds
+//! and raw tics at the same time, without using another hardware timer.
+//!
+//! The converted clock is a full 32-bit counter at frequency `F`. Since it
+//! wraps at a different real-time period than the underlying counter, the
+//! view extends the underlying counter in software each time it is read, and
+//! must be read at least once per period of the underlying counter to stay
+//! correct. Apart from that it holds no resources.
+//!
+//! Scaling loses precision. Scaling down rounds down to whole `F` tics, so a
+//! 32768 Hz counter viewed at `Freq1KHz` advances by one every 32 or 33 tics
+//! and spans shorter than one millisecond may read as zero. Scaling up adds
+//! no resolution: the view advances in steps of several `F` tics.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust
+//! let time_ms = static_init!(
+//!     capsules::scaled_time::ScaledTime<'static, nrf5x::rtc::Rtc, kernel::hil::time::Freq1KHz>,
+//!     capsules::scaled_time::ScaledTime::new(&nrf5x::rtc::RTC)
+//! );
+//! ```
+
+use core::cell::Cell;
+use core::marker::PhantomData;
+use kernel::hil::time::{Frequency, Ticks, Time};
+
+pub struct ScaledTime<'a, T: Time, F: Frequency> {
+    time: &'a T,
+    /// Tics of `time` since it was first read, extended past its wraps.
+    extended: Cell<u64>,
+    /// The last raw value read from `time`.
+    last: Cell<u32>,
+    _frequency: PhantomData<F>,
+}
+
+impl<T: Time, F: Frequency> ScaledTime<'a, T, F> {
+    pub fn new(time: &'a T) -> ScaledTime<'a, T, F> {
+        let now = time.now().into_u32();
+        ScaledTime {
+            time,
+            extended: Cell::new(now as u64),
+            last: Cell::new(now),
+            _frequency: PhantomData,
+        }
+    }
+
+    /// Reads the underlying counter, folds it into the extended count and
+    /// returns the new raw value.
+    pub(crate) fn update(&self) -> u32 {
+        let raw = self.time.now().into_u32();
+        let elapsed = raw.wrapping_sub(self.last.get()) & self.time.max_tics().into_u32();
+        self.extended.set(self.extended.get() + elapsed as u64);
+        self.last.set(raw);
+        raw
+    }
+
+    /// The converted time as of the last `update`.
+    pub(crate) fn converted(&self) -> u32 {
+        self.to_outer(self.extended.get())
+    }
+
+    /// Converts a count of underlying tics to a count of `F` tics, rounding
+    /// down and keeping only the low 32 bits.
+    pub(crate) fn to_outer(&self, tics: u64) -> u32 {
+        let f_in = self.time.frequency() as u64;
+        let f_out = F::frequency() as u64;
+        // Split into whole seconds and a remainder so that no intermediate
+        // overflows 64 bits.
+        let seconds = (tics / f_in) as u32;
+        let rest = (tics % f_in * f_out / f_in) as u32;
+        seconds.wrapping_mul(f_out as u32).wrapping_add(rest)
+    }
+
+    /// Converts a span of `F` tics to underlying tics, rounding up so that
+    /// the converted span has fully elapsed once that many underlying tics
+    /// have.
+    pub(crate) fn to_inner(&self, tics: u32) -> u64 {
+        let f_in = self.time.frequency() as u64;
+        let f_out = F::frequency() as u64;
+        (tics as u64 * f_in + f_out - 1) / f_out
+    }
+}
+
+impl<T: Time, F: Frequency> Time for ScaledTime<'a, T, F> {
+    type Frequency = F;
+
+    fn now(&self) -> u32 {
+        self.update();
+        self.converted()
+    }
+
+    fn persists_in_sleep(&self) -> bool {
+        self.time.persists_in_sleep()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::ScaledTime;
+    use kernel::hil::time::mock::MockAlarm;
+    use kernel::hil::time::{Freq1KHz, Freq1MHz, Time};
+
+    fn mock_32khz<'a>(now: u32) -> MockAlarm<'a> {
+        let alarm = MockAlarm::new();
+        alarm.set_frequency(32768);
+        alarm.set_now(now);
+        alarm
+    }
+
+    #[test]
+    fn test_views_of_one_counter() {
+        let inner = mock_32khz(0);
+        let ms: ScaledTime<_, Freq1KHz> = ScaledTime::new(&inner);
+        let us: ScaledTime<_, Freq1MHz> = ScaledTime::new(&inner);
+        inner.advance(32768 + 33);
+        // 33 tics at 32768 Hz are 1007.08us.
+        assert_eq!(ms.now(), 1001);
+        assert_eq!(us.now(), 1_001_007);
+        assert_eq!(inner.now(), 32801);
+        assert_eq!(ms.frequency(), 1000);
+    }
+
+    #[test]
+    fn test_scaling_down_rounds_down() {
+        let inner = mock_32khz(0);
+        let ms: ScaledTime<_, Freq1KHz> = ScaledTime::new(&inner);
+        inner.advance(32);
+        assert_eq!(ms.now(), 0);
+        inner.advance(1);
+        assert_eq!(ms.now(), 1);
+    }
+
+    #[test]
+    fn test_continues_across_inner_wrap() {
+        // Two seconds before the 32768 Hz counter wraps.
+        let inner = mock_32khz(u32::max_value() - 2 * 32768 + 1);
+        let ms: ScaledTime<_, Freq1KHz> = ScaledTime::new(&inner);
+        let start = ms.now();
+        inner.advance(4 * 32768);
+        assert_eq!(ms.now().wrapping_sub(start), 4000);
+    }
+}
@@ -41,7 +41,7 @@ use core::cell::Cell;
 use core::cmp;
 use kernel::common::cells::{MapCell, OptionalCell, TakeCell};
 use kernel::hil;
-use kernel::hil::time::Frequency;
+use kernel::hil::time::{Frequency, Ticks};
 use kernel::{AppId, AppSlice, Callback, Driver, ReturnCode, Shared};
 
 /// Syscall driver number.
@@ -507,7 +507,7 @@ impl<A: hil::time::Alarm<'a>> SDCard<'a, A> {
                     // try again after 10 ms
                     self.alarm_state.set(AlarmState::RepeatHCSInit);
                     let interval = (10 as u32) * <A::Frequency>::frequency() / 1000;
-                    let tics = self.alarm.now().wrapping_add(interval);
+                    let tics = self.alarm.now().wrapping_add(A::Ticks::from_u32(interval));
                     self.alarm.set_alarm(tics);
                 } else {
                     // error, send callback and quit
@@ -604,7 +604,7 @@ impl<A: hil::time::Alarm<'a>> SDCard<'a, A> {
                     // try again after 10 ms
                     self.alarm_state.set(AlarmState::RepeatAppSpecificInit);
                     let interval = (10 as u32) * <A::Frequency>::frequency() / 1000;
-                    let tics = self.alarm.now().wrapping_add(interval);
+                    let tics = self.alarm.now().wrapping_add(A::Ticks::from_u32(interval));
                     self.alarm.set_alarm(tics);
                 } else {
                     // error, send callback and quit
@@ -643,7 +643,7 @@ impl<A: hil::time::Alarm<'a>> SDCard<'a, A> {
                     // try again after 10 ms
                     self.alarm_state.set(AlarmState::RepeatGenericInit);
                     let interval = (10 as u32) * <A::Frequency>::frequency() / 1000;
-                    let tics = self.alarm.now().wrapping_add(interval);
+                    let tics = self.alarm.now().wrapping_add(A::Ticks::from_u32(interval));
                     self.alarm.set_alarm(tics);
                 } else {
                     // error, send callback and quit
@@ -786,7 +786,7 @@ impl<A: hil::time::Alarm<'a>> SDCard<'a, A> {
                     // try again after 1 ms
                     self.alarm_state.set(AlarmState::WaitForDataBlock);
                     let interval = (1 as u32) * <A::Frequency>::frequency() / 1000;
-                    let tics = self.alarm.now().wrapping_add(interval);
+                    let tics = self.alarm.now().wrapping_add(A::Ticks::from_u32(interval));
                     self.alarm.set_alarm(tics);
                 } else {
                     // error, send callback and quit
@@ -845,7 +845,7 @@ impl<A: hil::time::Alarm<'a>> SDCard<'a, A> {
                     self.alarm_state
                         .set(AlarmState::WaitForDataBlocks { count: count });
                     let interval = (1 as u32) * <A::Frequency>::frequency() / 1000;
-                    let tics = self.alarm.now().wrapping_add(interval);
+                    let tics = self.alarm.now().wrapping_add(A::Ticks::from_u32(interval));
                     self.alarm.set_alarm(tics);
                 } else {
                     // error, send callback and quit
@@ -1033,7 +1033,7 @@ impl<A: hil::time::Alarm<'a>> SDCard<'a, A> {
                     // try again after 1 ms
                     self.alarm_state.set(AlarmState::WaitForWriteBusy);
                     let interval = (1 as u32) * <A::Frequency>::frequency() / 1000;
-                    let tics = self.alarm.now().wrapping_add(interval);
+                    let tics = self.alarm.now().wrapping_add(A::Ticks::from_u32(interval));
                     self.alarm.set_alarm(tics);
                 }
             }
@@ -1376,7 +1376,7 @@ impl<A: hil::time::Alarm<'a>> hil::gpio::Client for SDCard<'a, A> {
         // run a timer for 500 ms in order to let the sd card settle
         self.alarm_state.set(AlarmState::DetectionChange);
         let interval = (500 as u32) * <A::Frequency>::frequency() / 1000;
-        let tics = self.alarm.now().wrapping_add(interval);
+        let tics = self.alarm.now().wrapping_add(A::Ticks::from_u32(interval));
         self.alarm.set_alarm(tics);
     }
 }
@@ -94,7 +94,7 @@ use core::cell::Cell;
 use core::marker::PhantomData;
 use kernel::common::cells::{OptionalCell, TakeCell, VolatileCell};
 use kernel::hil;
-use kernel::hil::time::Frequency;
+use kernel::hil::time::{Frequency, Ticks};
 use kernel::hil::uart;
 use kernel::ReturnCode;
 
@@ -241,7 +241,7 @@ impl<'a, A: hil::time::Alarm<'a>> uart::Transmit<'a> for SeggerRtt<'a, A> {
                     // Start a short timer so that we get a callback and
                     // can issue the callback to the client.
                     let interval = (100 as u32) * <A::Frequency>::frequency() / 1000000;
-                    let tics = self.alarm.now().wrapping_add(interval);
+                    let tics = self.alarm.now().wrapping_add(A::Ticks::from_u32(interval));
                     self.alarm.set_alarm(tics);
                 })
             });
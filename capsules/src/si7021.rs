@@ -37,7 +37,7 @@ use core::cell::Cell;
 use kernel::common::cells::{OptionalCell, TakeCell};
 use kernel::hil::i2c;
 use kernel::hil::time;
-use kernel::hil::time::Frequency;
+use kernel::hil::time::{Frequency, Ticks};
 use kernel::ReturnCode;
 
 // Buffer to use for I2C messages
@@ -135,7 +135,7 @@ impl<A: time::Alarm<'a>> SI7021<'a, A> {
     fn init_measurement(&self, buffer: &'static mut [u8]) {
         let interval = (20 as u32) * <A::Frequency>::frequency() / 1000;
 
-        let tics = self.alarm.now().wrapping_add(interval);
+        let tics = self.alarm.now().wrapping_add(A::Ticks::from_u32(interval));
         self.alarm.set_alarm(tics);
 
         // Now wait for timer to expire
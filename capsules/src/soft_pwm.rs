@@ -0,0 +1,289 @@
+//! Generate PWM on a GPIO pin from an alarm.
+//!
+//! `SoftPwm` drives a pin high for the first `numerator` tics of every
+//! period of `period_tics` tics and low for the rest, for boards whose PWM
+//! peripherals are all in use. It arms its alarm twice per period, once for
+//! each edge. Each edge is scheduled from the deadline of the previous one
+//! rather than from when its callback ran, so interrupt latency delays
+//! individual edges but does not accumulate into drift or a change of
+//! frequency.
+//!
+//! At 0% and 100% duty the pin is held low or high and the alarm is
+//! disabled. Edges closer than the alarm's `minimum_dt`, or closer than the
+//! time it takes to handle the alarm interrupt, come late, so the shortest
+//! usable pulse depends on the board. The alarm must not be shared with
+//! other clients, and `SoftPwm` must be its client.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust
+//! let pwm = static_init!(
+//!     capsules::soft_pwm::SoftPwm<'static, VirtualMuxAlarm<'static, sam4l::ast::Ast>>,
+//!     capsules::soft_pwm::SoftPwm::new(virtual_alarm, &sam4l::gpio::PA[13])
+//! );
+//! virtual_alarm.set_client(pwm);
+//! pwm.set_duty(25, 100);
+//! ```
+
+use core::cell::Cell;
+use kernel::hil::gpio;
+use kernel::hil::time::{self, Alarm, Ticks};
+use kernel::ReturnCode;
+
+pub struct SoftPwm<'a, A: Alarm<'a>> {
+    alarm: &'a A,
+    pin: &'a dyn gpio::Pin,
+    /// Tics per period the pin is high.
+    on: Cell<u32>,
+    period: Cell<u32>,
+    /// Whether the pin is toggling, i.e. the duty is neither 0% nor 100%.
+    running: Cell<bool>,
+    /// Whether the pin was set high at the last edge.
+    high: Cell<bool>,
+    /// The deadline of the next edge, which the alarm is armed for.
+    next_edge: Cell<A::Ticks>,
+}
+
+impl<A: Alarm<'a>> SoftPwm<'a, A> {
+    pub fn new(alarm: &'a A, pin: &'a dyn gpio::Pin) -> SoftPwm<'a, A> {
+        SoftPwm {
+            alarm,
+            pin,
+            on: Cell::new(0),
+            period: Cell::new(0),
+            running: Cell::new(false),
+            high: Cell::new(false),
+            next_edge: Cell::new(A::Ticks::from_u32(0)),
+        }
+    }
+
+    /// Drives the pin high for `numerator` tics out of every `period_tics`.
+    ///
+    /// If the pin is already toggling, the new duty cycle applies to the
+    /// edges after the one already scheduled, without a glitch or a restart
+    /// of the period. Otherwise a period starts now, with the pin high.
+    ///
+    /// Returns `ReturnCode::EINVAL` without changing anything if
+    /// `period_tics` is `0`, `numerator` is larger than it, or the period is
+    /// longer than the alarm's counter period.
+    pub fn set_duty(&self, numerator: u32, period_tics: u32) -> ReturnCode {
+        if period_tics == 0
+            || numerator > period_tics
+            || period_tics > self.alarm.max_tics().into_u32()
+        {
+            return ReturnCode::EINVAL;
+        }
+        self.on.set(numerator);
+        self.period.set(period_tics);
+        self.pin.make_output();
+
+        if numerator == 0 || numerator == period_tics {
+            self.running.set(false);
+            self.alarm.disable();
+            if numerator == 0 {
+                self.pin.clear();
+            } else {
+                self.pin.set();
+            }
+        } else if !self.running.get() {
+            self.running.set(true);
+            self.high.set(true);
+            self.pin.set();
+            self.arm(self.alarm.now(), numerator);
+        }
+        ReturnCode::SUCCESS
+    }
+
+    /// Stops toggling and drives the pin low.
+    pub fn stop(&self) {
+        self.running.set(false);
+        self.alarm.disable();
+        self.pin.clear();
+    }
+
+    pub fn is_running(&self) -> bool {
+        self.running.get()
+    }
+
+    /// Arms the alarm for the edge `dt` tics after the one at `edge`.
+    fn arm(&self, edge: A::Ticks, dt: u32) {
+        let dt = A::Ticks::from_u32(dt);
+        self.next_edge.set(edge.wrapping_add(dt));
+        self.alarm.set_alarm_from(edge, dt);
+    }
+}
+
+impl<A: Alarm<'a>> time::AlarmClient for SoftPwm<'a, A> {
+    fn fired(&self) {
+        if !self.running.get() {
+            return;
+        }
+        let on = self.on.get();
+        let dt = if self.high.get() {
+            self.pin.clear();
+            self.period.get() - on
+        } else {
+            self.pin.set();
+            on
+        };
+        self.high.set(!self.high.get());
+        self.arm(self.next_edge.get(), dt);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::SoftPwm;
+    use core::cell::Cell;
+    use kernel::hil::gpio::{self, Configuration, FloatingState};
+    use kernel::hil::time::mock::MockAlarm;
+    use kernel::hil::time::Alarm;
+    use kernel::ReturnCode;
+
+    struct FakePin {
+        high: Cell<bool>,
+        edges: Cell<usize>,
+    }
+
+    impl FakePin {
+        fn new() -> FakePin {
+            FakePin {
+                high: Cell::new(false),
+                edges: Cell::new(0),
+            }
+        }
+
+        fn write(&self, high: bool) {
+            if self.high.replace(high) != high {
+                self.edges.set(self.edges.get() + 1);
+            }
+        }
+    }
+
+    impl gpio::Pin for FakePin {}
+
+    impl gpio::Input for FakePin {
+        fn read(&self) -> bool {
+            self.high.get()
+        }
+    }
+
+    impl gpio::Output for FakePin {
+        fn set(&self) {
+            self.write(true);
+        }
+
+        fn clear(&self) {
+            self.write(false);
+        }
+
+        fn toggle(&self) -> bool {
+            self.write(!self.high.get());
+            self.high.get()
+        }
+    }
+
+    impl gpio::Configure for FakePin {
+        fn configuration(&self) -> Configuration {
+            Configuration::Output
+        }
+
+        fn make_output(&self) -> Configuration {
+            Configuration::Output
+        }
+
+        fn disable_output(&self) -> Configuration {
+            Configuration::Output
+        }
+
+        fn make_input(&self) -> Configuration {
+            Configuration::Output
+        }
+
+        fn disable_input(&self) -> Configuration {
+            Configuration::Output
+        }
+
+        fn deactivate_to_low_power(&self) {}
+
+        fn set_floating_state(&self, _state: FloatingState) {}
+
+        fn floating_state(&self) -> FloatingState {
+            FloatingState::PullNone
+        }
+    }
+
+    #[test]
+    fn test_toggles_on_duty_cycle() {
+        let alarm = MockAlarm::new();
+        let pin = FakePin::new();
+        let pwm = SoftPwm::new(&alarm, &pin);
+        alarm.set_client(&pwm);
+
+        assert_eq!(pwm.set_duty(3, 10), ReturnCode::SUCCESS);
+        assert!(pin.high.get());
+        alarm.advance(3);
+        assert!(!pin.high.get());
+        alarm.advance(6);
+        assert!(!pin.high.get());
+        alarm.advance(1);
+        assert!(pin.high.get());
+        alarm.advance(1000);
+        assert_eq!(pin.edges.get(), 203);
+
+        // Set 2 tics into the high phase, which still ends at 3 tics.
+        alarm.advance(2);
+        pwm.set_duty(5, 10);
+        alarm.advance(1);
+        assert!(!pin.high.get());
+        alarm.advance(4);
+        assert!(!pin.high.get());
+        alarm.advance(1);
+        assert!(pin.high.get());
+        alarm.advance(4);
+        assert!(pin.high.get());
+        alarm.advance(1);
+        assert!(!pin.high.get());
+    }
+
+    #[test]
+    fn test_late_edge_does_not_drift() {
+        let alarm = MockAlarm::new();
+        let pin = FakePin::new();
+        let pwm = SoftPwm::new(&alarm, &pin);
+        alarm.set_client(&pwm);
+
+        pwm.set_duty(3, 10);
+        alarm.set_interrupts_enabled(false);
+        alarm.advance(5);
+        alarm.set_interrupts_enabled(true);
+        assert!(!pin.high.get());
+        alarm.advance(4);
+        assert!(!pin.high.get());
+        alarm.advance(1);
+        assert!(pin.high.get());
+    }
+
+    #[test]
+    fn test_static_duty_cycles() {
+        let alarm = MockAlarm::new();
+        let pin = FakePin::new();
+        let pwm = SoftPwm::new(&alarm, &pin);
+        alarm.set_client(&pwm);
+
+        pwm.set_duty(3, 10);
+        assert_eq!(pwm.set_duty(10, 10), ReturnCode::SUCCESS);
+        assert!(pin.high.get());
+        assert!(!alarm.is_enabled());
+        assert!(!pwm.is_running());
+
+        pwm.set_duty(0, 10);
+        alarm.advance(100);
+        assert!(!pin.high.get());
+        assert!(!alarm.is_enabled());
+
+        assert_eq!(pwm.set_duty(11, 10), ReturnCode::EINVAL);
+        assert_eq!(pwm.set_duty(0, 0), ReturnCode::EINVAL);
+    }
+}
@@ -0,0 +1,117 @@
+//! Timestamp GPIO edges on chips without input capture hardware.
+//!
+//! `SoftwareCapture` implements `hil::time::Capture` on top of any `Time` and
+//! a GPIO pin that can interrupt: it reads `now()` from the GPIO interrupt
+//! handler. Timestamps are therefore late by the interrupt latency, including
+//! the time the interrupt spends pending while the kernel is busy, and are
+//! much less precise than hardware capture.
+//!
+//! If `time` is a `Counter`, `SoftwareCapture` can also be set as its overflow
+//! client. Captures are then reported with `captured_at` and the current high
+//! word, and overflows are passed on to its own overflow client with the same
+//! high word, so a client can order both kinds of event. A capture whose
+//! `now()` was read after a wrap that has not been signaled yet is reported
+//! with the previous high word.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust
+//! let capture = static_init!(
+//!     capsules::software_capture::SoftwareCapture<'static, sam4l::ast::Ast, sam4l::gpio::GPIOPin>,
+//!     capsules::software_capture::SoftwareCapture::new(&sam4l::ast::AST, &sam4l::gpio::PA[16])
+//! );
+//! sam4l::gpio::PA[16].set_client(capture);
+//! ```
+
+use core::cell::Cell;
+use kernel::common::cells::OptionalCell;
+use kernel::hil::gpio;
+use kernel::hil::time::{self, Capture, Ticks, Time};
+use kernel::ReturnCode;
+
+pub struct SoftwareCapture<'a, T: Time, P: gpio::InterruptPin> {
+    time: &'a T,
+    pin: &'a P,
+    /// Number of overflows signaled by `time`.
+    high: Cell<u32>,
+    client: OptionalCell<&'a dyn time::CaptureClient>,
+    overflow_client: OptionalCell<&'a dyn time::OverflowClient>,
+}
+
+impl<T: Time, P: gpio::InterruptPin> SoftwareCapture<'a, T, P> {
+    pub fn new(time: &'a T, pin: &'a P) -> SoftwareCapture<'a, T, P> {
+        SoftwareCapture {
+            time,
+            pin,
+            high: Cell::new(0),
+            client: OptionalCell::empty(),
+            overflow_client: OptionalCell::empty(),
+        }
+    }
+
+    /// Sets the client for overflows of `time`, when `SoftwareCapture` is its
+    /// overflow client.
+    pub fn set_overflow_client(&self, client: &'a dyn time::OverflowClient) {
+        self.overflow_client.set(client);
+    }
+}
+
+impl<T: Time, P: gpio::InterruptPin> Time for SoftwareCapture<'a, T, P> {
+    type Frequency = T::Frequency;
+    type Ticks = T::Ticks;
+    const WIDTH: u32 = T::WIDTH;
+
+    fn now(&self) -> T::Ticks {
+        self.time.now()
+    }
+
+    fn max_tics(&self) -> T::Ticks {
+        self.time.max_tics()
+    }
+
+    fn frequency(&self) -> u32 {
+        self.time.frequency()
+    }
+
+    fn persists_in_sleep(&self) -> bool {
+        self.time.persists_in_sleep()
+    }
+}
+
+impl<T: Time, P: gpio::InterruptPin> Capture<'a> for SoftwareCapture<'a, T, P> {
+    fn set_capture_client(&'a self, client: &'a dyn time::CaptureClient) {
+        self.client.set(client);
+    }
+
+    fn enable_capture(&self, edge: gpio::InterruptEdge) -> ReturnCode {
+        self.pin.make_input();
+        self.pin.enable_interrupts(edge);
+        ReturnCode::SUCCESS
+    }
+
+    fn disable_capture(&self) {
+        self.pin.disable_interrupts();
+    }
+}
+
+impl<T: Time, P: gpio::InterruptPin> gpio::Client for SoftwareCapture<'a, T, P> {
+    fn fired(&self) {
+        let timestamp = self.time.now().into_u32();
+        let high = self.high.get();
+        self.client
+            .map(|client| client.captured_at(high, timestamp));
+    }
+}
+
+impl<T: Time, P: gpio::InterruptPin> time::OverflowClient for SoftwareCapture<'a, T, P> {
+    fn overflow(&self) {
+        self.overflow_at(self.high.get().wrapping_add(1));
+    }
+
+    fn overflow_at(&self, count_high: u32) {
+        self.high.set(count_high);
+        self.overflow_client
+            .map(|client| client.overflow_at(count_high));
+    }
+}
@@ -5,7 +5,7 @@
 //! Last Modified: 1/10/2020
 use core::cell::Cell;
 use kernel::debug;
-use kernel::hil::time::{Alarm, AlarmClient, Frequency};
+use kernel::hil::time::{Alarm, AlarmClient, Frequency, Ticks};
 
 pub struct TestAlarm<'a, A: Alarm<'a>> {
     alarm: &'a A,
@@ -32,8 +32,8 @@ impl<A: Alarm<'a>> TestAlarm<'a, A> {
         let freq: u64 = <A::Frequency>::frequency() as u64;
         let lticks: u64 = ms as u64 * freq;
         let ticks: u32 = (lticks / 1000) as u32;
-        let t = now.wrapping_add(ticks);
-        debug!("Setting alarm to {}", t);
+        let t = now.wrapping_add(A::Ticks::from_u32(ticks));
+        debug!("Setting alarm to {:?}", t);
         self.alarm.set_alarm(t);
     }
 }
@@ -0,0 +1,204 @@
+//! Test that an `Alarm` implementation follows the semantics the `Alarm`
+//! trait requires, whatever its comparator hardware does. Depends on a
+//! working UART and debug! macro.
+//!
+//! The checks, in order:
+//!
+//! - `set_alarm` rejects a deadline one tic ahead with `EINVAL`, if that is
+//!   closer than `minimum_dt`.
+//! - A deadline one tic short of a full counter period ahead, the farthest
+//!   that can be set, is accepted and still pending 10 ms later, instead of
+//!   being taken for one in the past. The test busy-waits for those 10 ms.
+//! - A deadline the counter has just reached, set with `set_alarm`, fires
+//!   promptly instead of after the counter wraps.
+//! - A deadline 10 ms ahead fires no earlier than that, and not much later.
+//! - A deadline that has already passed, set with `set_alarm_from`, fires
+//!   promptly instead of after the counter wraps.
+//! - A deadline 40 ms ahead that lies past the counter's wrap fires after
+//!   40 ms and not immediately, as it would on a comparator that matches on
+//!   `>=` without handling the wrap. To set this up the test first waits
+//!   until the counter is about to wrap, which can take up to one counter
+//!   period.
+//! - A deadline `minimum_dt` ahead, set from within `fired()` as repeating
+//!   clients do, fires promptly.
+//!
+//! Each check prints whether it passed, and the number of failed checks is
+//! printed at the end. Boards run it against each alarm backend, or a
+//! simulated one, to check it against the `Alarm` contract. The alarm must
+//! not be shared, so that no other client moves it, and
+//! `TestAlarmConformance` must be its client.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust
+//! let test = static_init!(
+//!     capsules::test::alarm_conformance::TestAlarmConformance<'static, sam4l::ast::Ast>,
+//!     capsules::test::alarm_conformance::TestAlarmConformance::new(&sam4l::ast::AST)
+//! );
+//! sam4l::ast::AST.set_client(test);
+//! test.run();
+//! ```
+
+use core::cell::Cell;
+use kernel::debug;
+use kernel::hil::time::{self, Alarm, AlarmClient, BusyDelay, Ticks};
+use kernel::ReturnCode;
+
+/// How much later than its deadline an alarm may fire and still pass.
+const LATE_MS: u32 = 5;
+
+#[derive(Clone, Copy, PartialEq)]
+enum Step {
+    Idle,
+    Reached,
+    Ahead,
+    Passed,
+    NearWrap,
+    PastWrap,
+    Rearm,
+}
+
+pub struct TestAlarmConformance<'a, A: Alarm<'a>> {
+    alarm: &'a A,
+    step: Cell<Step>,
+    /// `now()` when the alarm under test was set.
+    start: Cell<u32>,
+    failures: Cell<usize>,
+}
+
+impl<A: Alarm<'a>> TestAlarmConformance<'a, A> {
+    pub fn new(alarm: &'a A) -> TestAlarmConformance<'a, A> {
+        TestAlarmConformance {
+            alarm,
+            step: Cell::new(Step::Idle),
+            start: Cell::new(0),
+            failures: Cell::new(0),
+        }
+    }
+
+    pub fn run(&self) {
+        debug!("Alarm conformance: starting.");
+        self.failures.set(0);
+        let max = self.alarm.max_tics().into_u32();
+        if self.alarm.minimum_dt().into_u32() > 1 {
+            let deadline = A::Ticks::from_u32(self.now().wrapping_add(1) & max);
+            let result = self.alarm.set_alarm(deadline);
+            self.report(
+                "deadline closer than minimum_dt",
+                result == ReturnCode::EINVAL,
+                0,
+            );
+        }
+
+        let deadline = A::Ticks::from_u32(self.now().wrapping_add(max) & max);
+        let result = self.alarm.set_alarm(deadline);
+        BusyDelay::new(self.alarm).delay_ms(10);
+        self.report(
+            "deadline a full period ahead",
+            result == ReturnCode::SUCCESS
+                && self.alarm.is_enabled()
+                && self.alarm.get_alarm() == deadline,
+            0,
+        );
+        self.alarm.disable();
+
+        self.step.set(Step::Reached);
+        let now = self.now();
+        self.start.set(now);
+        self.alarm.set_alarm(A::Ticks::from_u32(now));
+    }
+
+    fn tics(&self, ms: u32) -> u32 {
+        time::convert_tics(ms, 1000, self.alarm.frequency())
+    }
+
+    fn now(&self) -> u32 {
+        self.alarm.now().into_u32()
+    }
+
+    fn elapsed(&self) -> u32 {
+        self.now().wrapping_sub(self.start.get()) & self.alarm.max_tics().into_u32()
+    }
+
+    /// Moves to `step` and sets the alarm `dt` tics from now.
+    fn set_from_now(&self, step: Step, dt: u32) {
+        self.step.set(step);
+        let now = self.now();
+        self.start.set(now);
+        self.alarm
+            .set_alarm_from(A::Ticks::from_u32(now), A::Ticks::from_u32(dt));
+    }
+
+    /// Checks that the alarm fired between `dt` and `dt + LATE_MS` after it
+    /// was set.
+    fn check_on_time(&self, name: &str, dt: u32) {
+        let elapsed = self.elapsed();
+        self.report(
+            name,
+            elapsed >= dt && elapsed <= dt + self.tics(LATE_MS),
+            elapsed,
+        );
+    }
+
+    fn report(&self, name: &str, ok: bool, elapsed: u32) {
+        if !ok {
+            self.failures.set(self.failures.get() + 1);
+        }
+        debug!(
+            "Alarm conformance: {}: {} ({} tics)",
+            name,
+            if ok { "ok" } else { "FAILED" },
+            elapsed
+        );
+    }
+}
+
+impl<A: Alarm<'a>> AlarmClient for TestAlarmConformance<'a, A> {
+    fn fired(&self) {
+        match self.step.get() {
+            Step::Idle => {}
+            Step::Reached => {
+                self.check_on_time("deadline just reached", 0);
+
+                self.set_from_now(Step::Ahead, self.tics(10));
+            }
+            Step::Ahead => {
+                self.check_on_time("deadline ahead", self.tics(10));
+
+                // Passed 5 ms ago.
+                self.step.set(Step::Passed);
+                let now = self.now();
+                let max = self.alarm.max_tics().into_u32();
+                self.start.set(now);
+                self.alarm.set_alarm_from(
+                    A::Ticks::from_u32(now.wrapping_sub(self.tics(10)) & max),
+                    A::Ticks::from_u32(self.tics(5)),
+                );
+            }
+            Step::Passed => {
+                self.check_on_time("deadline passed", 0);
+
+                self.step.set(Step::NearWrap);
+                let max = self.alarm.max_tics().into_u32();
+                let _ = self
+                    .alarm
+                    .set_alarm_clamped(A::Ticks::from_u32(max - self.tics(20)));
+            }
+            Step::NearWrap => {
+                self.set_from_now(Step::PastWrap, self.tics(40));
+            }
+            Step::PastWrap => {
+                self.check_on_time("deadline past wrap", self.tics(40));
+
+                self.set_from_now(Step::Rearm, self.alarm.minimum_dt().into_u32());
+            }
+            Step::Rearm => {
+                let min_dt = self.alarm.minimum_dt().into_u32();
+                self.check_on_time("minimum_dt deadline set in fired()", min_dt);
+                self.step.set(Step::Idle);
+                debug!("Alarm conformance: done, {} failed.", self.failures.get());
+            }
+        }
+    }
+}
@@ -1,6 +1,7 @@
 pub mod aes;
 pub mod aes_ccm;
 pub mod alarm;
+pub mod alarm_conformance;
 pub mod rng;
 pub mod udp;
 pub mod virtual_uart;
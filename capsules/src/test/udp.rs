@@ -12,7 +12,7 @@ use crate::net::udp::udp_send::{UDPSendClient, UDPSender};
 use core::cell::Cell;
 use kernel::common::cells::MapCell;
 use kernel::common::leasable_buffer::LeasableBuffer;
-use kernel::hil::time::{self, Alarm, Frequency};
+use kernel::hil::time::{self, Alarm, Frequency, Ticks};
 use kernel::{debug, ReturnCode};
 
 pub const DST_ADDR: IPAddr = IPAddr([
@@ -63,11 +63,10 @@ impl<'a, A: Alarm<'a>> MockUdp<'a, A> {
     pub fn start_sending(&self) {
         // Set alarm bc if you try to send immediately there are initialization issues
         self.send_loop.set(true);
-        self.alarm.set_alarm(
-            self.alarm
-                .now()
-                .wrapping_add(<A::Frequency>::frequency() * SEND_INTERVAL_SECONDS),
-        );
+        self.alarm
+            .set_alarm(self.alarm.now().wrapping_add(A::Ticks::from_u32(
+                <A::Frequency>::frequency() * SEND_INTERVAL_SECONDS,
+            )));
     }
 
     pub fn stop_sending(&self) {
@@ -172,11 +171,10 @@ impl<'a, A: Alarm<'a>> UDPSendClient for MockUdp<'a, A> {
         dgram.reset();
         self.udp_dgram.replace(dgram);
         debug!("");
-        self.alarm.set_alarm(
-            self.alarm
-                .now()
-                .wrapping_add(<A::Frequency>::frequency() * SEND_INTERVAL_SECONDS),
-        );
+        self.alarm
+            .set_alarm(self.alarm.now().wrapping_add(A::Ticks::from_u32(
+                <A::Frequency>::frequency() * SEND_INTERVAL_SECONDS,
+            )));
     }
 }
 
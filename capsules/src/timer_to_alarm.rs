@@ -0,0 +1,258 @@
+//! Provide an `Alarm` on top of a `Timer`.
+//!
+//! `TimerToAlarm` lets capsules written against `hil::time::Alarm` run on a
+//! board that only provides a `Timer`. Setting the alarm for an absolute
+//! deadline arms the timer as a oneshot for the tics between `now()` and the
+//! deadline, replacing any earlier deadline.
+//!
+//! By default `now()` is the timer's own `now()`. A timer whose `now()` does
+//! not count, e.g. one that only reports time remaining, can be wrapped with
+//! `new_synthesized` instead, which builds `now()` by accumulating the
+//! intervals the timer was armed for. That clock has limits:
+//!
+//! - It only advances while an alarm is armed, and stands still in between,
+//!   so it does not measure real time across idle periods.
+//! - Within an armed interval its resolution is that of the timer's
+//!   `time_remaining()`.
+//! - An expired interval is counted when the timer fires, not when the
+//!   callback runs, so callback latency is invisible to it.
+//!
+//! The timer is armed and cancelled by the adapter, so it must not be used for
+//! anything else. Its client must be the adapter.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust
+//! let alarm = static_init!(
+//!     capsules::timer_to_alarm::TimerToAlarm<'static, SomeTimer<'static>>,
+//!     capsules::timer_to_alarm::TimerToAlarm::new(timer)
+//! );
+//! hil::time::Timer::set_client(timer, alarm);
+//! hil::time::Alarm::set_client(alarm, client);
+//! ```
+
+use core::cell::Cell;
+use kernel::common::cells::OptionalCell;
+use kernel::hil::time::{self, Alarm, Ticks, Time, Timer};
+use kernel::ReturnCode;
+
+pub struct TimerToAlarm<'a, T: Timer<'a>> {
+    timer: &'a T,
+    /// Whether `now()` is built from armed intervals rather than read from
+    /// the timer.
+    synthesized: bool,
+    /// The synthesized `now()` when the current interval was armed.
+    base: Cell<T::Ticks>,
+    /// The interval the timer is currently armed for.
+    interval: Cell<T::Ticks>,
+    when: Cell<T::Ticks>,
+    token: Cell<u32>,
+    armed: Cell<bool>,
+    client: OptionalCell<&'a dyn time::AlarmClient>,
+}
+
+impl<T: Timer<'a>> TimerToAlarm<'a, T> {
+    /// Creates an alarm whose `now()` is the timer's `now()`.
+    pub fn new(timer: &'a T) -> TimerToAlarm<'a, T> {
+        TimerToAlarm::create(timer, false)
+    }
+
+    /// Creates an alarm whose `now()` is synthesized from the intervals the
+    /// timer is armed for. See the module documentation for its limits.
+    pub fn new_synthesized(timer: &'a T) -> TimerToAlarm<'a, T> {
+        TimerToAlarm::create(timer, true)
+    }
+
+    fn create(timer: &'a T, synthesized: bool) -> TimerToAlarm<'a, T> {
+        TimerToAlarm {
+            timer,
+            synthesized,
+            base: Cell::new(T::Ticks::from_u32(0)),
+            interval: Cell::new(T::Ticks::from_u32(0)),
+            when: Cell::new(T::Ticks::from_u32(0)),
+            token: Cell::new(0),
+            armed: Cell::new(false),
+            client: OptionalCell::empty(),
+        }
+    }
+
+    /// Tics of the current interval that have elapsed, as far as the timer
+    /// can tell.
+    fn elapsed(&self) -> T::Ticks {
+        match self.timer.time_remaining() {
+            Some(remaining) => self.interval.get().wrapping_sub(remaining),
+            None => T::Ticks::from_u32(0),
+        }
+    }
+}
+
+impl<T: Timer<'a>> Time for TimerToAlarm<'a, T> {
+    type Frequency = T::Frequency;
+    type Ticks = T::Ticks;
+    const WIDTH: u32 = T::WIDTH;
+
+    fn now(&self) -> T::Ticks {
+        if self.synthesized {
+            self.base.get().wrapping_add(self.elapsed())
+        } else {
+            self.timer.now()
+        }
+    }
+
+    fn max_tics(&self) -> T::Ticks {
+        self.timer.max_tics()
+    }
+
+    fn frequency(&self) -> u32 {
+        self.timer.frequency()
+    }
+
+    fn persists_in_sleep(&self) -> bool {
+        self.timer.persists_in_sleep()
+    }
+}
+
+impl<T: Timer<'a>> Alarm<'a> for TimerToAlarm<'a, T> {
+    fn set_alarm_with_token(&self, tics: T::Ticks, token: u32) -> ReturnCode {
+        let now = self.now();
+        // Fold the part of the previous interval that has elapsed into the
+        // synthesized clock before the timer forgets about it.
+        self.base.set(now);
+        self.interval.set(tics.wrapping_sub(now));
+        self.when.set(tics);
+        self.token.set(token);
+        self.armed.set(true);
+        self.timer.oneshot(self.interval.get());
+        ReturnCode::SUCCESS
+    }
+
+    fn get_alarm(&self) -> T::Ticks {
+        self.when.get()
+    }
+
+    fn minimum_dt(&self) -> T::Ticks {
+        T::Ticks::from_u32(1)
+    }
+
+    fn set_client(&'a self, client: &'a dyn time::AlarmClient) {
+        self.client.set(client);
+    }
+
+    fn clear_client(&self) {
+        self.disable();
+        self.client.clear();
+    }
+
+    fn is_enabled(&self) -> bool {
+        self.armed.get()
+    }
+
+    fn disable(&self) -> ReturnCode {
+        if !self.armed.get() {
+            return ReturnCode::SUCCESS;
+        }
+        self.base.set(self.now());
+        self.interval.set(T::Ticks::from_u32(0));
+        self.armed.set(false);
+        self.timer.cancel();
+        ReturnCode::SUCCESS
+    }
+}
+
+impl<T: Timer<'a>> time::TimerClient for TimerToAlarm<'a, T> {
+    fn fired(&self) {
+        if !self.armed.get() {
+            return;
+        }
+        self.base
+            .set(self.base.get().wrapping_add(self.interval.get()));
+        self.interval.set(T::Ticks::from_u32(0));
+        self.armed.set(false);
+        self.client
+            .map(|client| client.fired_with_token(self.token.get()));
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::TimerToAlarm;
+    use crate::alarm_to_timer::AlarmToTimer;
+    use kernel::hil::time::mock::{CountingClient, MockAlarm};
+    use kernel::hil::time::{Alarm, Time, Timer};
+
+    #[test]
+    fn test_back_to_back_set_alarm() {
+        let mock = MockAlarm::new();
+        let timer = AlarmToTimer::new(&mock);
+        let alarm = TimerToAlarm::new(&timer);
+        let client = CountingClient::new();
+        mock.set_client(&timer);
+        timer.set_client(&alarm);
+        alarm.set_client(&client);
+
+        // The second deadline replaces the first, whether it is later or
+        // earlier.
+        alarm.set_alarm_with_token(10, 1);
+        alarm.set_alarm_with_token(20, 2);
+        assert_eq!(alarm.get_alarm(), 20);
+        mock.advance(15);
+        assert_eq!(client.fires(), 0);
+
+        alarm.set_alarm_with_token(18, 3);
+        mock.advance(2);
+        assert_eq!(client.fires(), 0);
+        mock.advance(1);
+        assert_eq!(client.fires(), 1);
+        assert_eq!(client.token(), Some(3));
+        assert!(!alarm.is_enabled());
+
+        mock.advance(10);
+        assert_eq!(client.fires(), 1);
+    }
+
+    #[test]
+    fn test_disable() {
+        let mock = MockAlarm::new();
+        let timer = AlarmToTimer::new(&mock);
+        let alarm = TimerToAlarm::new(&timer);
+        let client = CountingClient::new();
+        mock.set_client(&timer);
+        timer.set_client(&alarm);
+        alarm.set_client(&client);
+
+        alarm.set_alarm(10);
+        alarm.disable();
+        assert!(!timer.is_enabled());
+        mock.advance(20);
+        assert_eq!(client.fires(), 0);
+        assert_eq!(alarm.now(), 20);
+    }
+
+    #[test]
+    fn test_synthesized_now() {
+        let mock = MockAlarm::new();
+        let timer = AlarmToTimer::new(&mock);
+        let alarm = TimerToAlarm::new_synthesized(&timer);
+        let client = CountingClient::new();
+        mock.set_client(&timer);
+        timer.set_client(&alarm);
+        alarm.set_client(&client);
+
+        // Stands still while no alarm is armed.
+        mock.advance(100);
+        assert_eq!(alarm.now(), 0);
+
+        alarm.set_alarm(10);
+        mock.advance(4);
+        assert_eq!(alarm.now(), 4);
+        // Back to back: the elapsed part of the replaced interval is kept.
+        alarm.set_alarm(alarm.now().wrapping_add(10));
+        mock.advance(10);
+        assert_eq!(client.fires(), 1);
+        assert_eq!(alarm.now(), 14);
+
+        mock.advance(50);
+        assert_eq!(alarm.now(), 14);
+    }
+}
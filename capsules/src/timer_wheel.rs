@@ -0,0 +1,548 @@
+//! Provide many independent `Timer`s on top of a single `Alarm`.
+//!
+//! Each `WheelTimer` is a oneshot or repeating `hil::time::Timer`. The
+//! `TimerWheel` programs the underlying alarm for the earliest deadline of all
+//! active timers, and when it fires calls every timer that has expired.
+//! Repeating timers are re-armed for their next deadline, computed from the
+//! previous deadline so that they do not drift. Timers may be cancelled or
+//! re-armed, including other timers on the same wheel, from within their
+//! `fired()` callback. A repeating timer's interval can be changed with
+//! `change_interval` without restarting it.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust
+//! let wheel = static_init!(
+//!     capsules::timer_wheel::TimerWheel<'static, sam4l::ast::Ast>,
+//!     capsules::timer_wheel::TimerWheel::new(&sam4l::ast::AST)
+//! );
+//! hil::time::Alarm::set_client(&sam4l::ast::AST, wheel);
+//!
+//! let retransmit = static_init!(
+//!     capsules::timer_wheel::WheelTimer<'static, sam4l::ast::Ast>,
+//!     capsules::timer_wheel::WheelTimer::new(wheel)
+//! );
+//! ```
+
+use core::cell::Cell;
+use kernel::common::cells::OptionalCell;
+use kernel::common::{List, ListLink, ListNode};
+use kernel::hil::time::{self, Alarm, Ticks, Time, Timer};
+use kernel::ReturnCode;
+
+pub struct WheelTimer<'a, A: Alarm<'a>> {
+    wheel: &'a TimerWheel<'a, A>,
+    /// When the timer was last armed, or its previous deadline for a
+    /// repeating timer.
+    reference: Cell<A::Ticks>,
+    /// Tics from `reference` until the timer fires.
+    dt: Cell<A::Ticks>,
+    /// The reload interval of a repeating timer.
+    interval: Cell<Option<A::Ticks>>,
+    /// Fires left of a `repeat_n` sequence, or `0` for no limit.
+    fires_left: Cell<u32>,
+    armed: Cell<bool>,
+    /// Tics that were left when the timer was paused.
+    paused: Cell<Option<A::Ticks>>,
+    /// Set while the timer's client is being called.
+    firing: Cell<bool>,
+    next: ListLink<'a, WheelTimer<'a, A>>,
+    client: OptionalCell<&'a dyn time::TimerClient>,
+}
+
+impl<A: Alarm<'a>> ListNode<'a, WheelTimer<'a, A>> for WheelTimer<'a, A> {
+    fn next(&self) -> &'a ListLink<WheelTimer<'a, A>> {
+        &self.next
+    }
+}
+
+impl<A: Alarm<'a>> WheelTimer<'a, A> {
+    pub fn new(wheel: &'a TimerWheel<'a, A>) -> WheelTimer<'a, A> {
+        WheelTimer {
+            wheel,
+            reference: Cell::new(A::Ticks::from_u32(0)),
+            dt: Cell::new(A::Ticks::from_u32(0)),
+            interval: Cell::new(None),
+            fires_left: Cell::new(0),
+            armed: Cell::new(false),
+            paused: Cell::new(None),
+            firing: Cell::new(false),
+            next: ListLink::empty(),
+            client: OptionalCell::empty(),
+        }
+    }
+
+    fn deadline(&self) -> A::Ticks {
+        self.reference.get().wrapping_add(self.dt.get())
+    }
+
+    fn has_expired(&self, now: A::Ticks) -> bool {
+        time::has_expired(self.reference.get(), self.deadline(), now)
+    }
+
+    /// Tics from `now` until the timer fires, `0` if it has expired.
+    fn remaining(&self, now: A::Ticks) -> A::Ticks {
+        if self.has_expired(now) {
+            A::Ticks::from_u32(0)
+        } else {
+            self.deadline().wrapping_sub(now)
+        }
+    }
+
+    fn arm(&self, interval: A::Ticks, repeat: Option<A::Ticks>) {
+        self.reference.set(self.wheel.alarm.now());
+        self.dt.set(interval);
+        self.interval.set(repeat);
+        self.fires_left.set(0);
+        self.armed.set(true);
+        self.paused.set(None);
+        self.wheel.schedule();
+    }
+}
+
+impl<A: Alarm<'a>> Time for WheelTimer<'a, A> {
+    type Frequency = A::Frequency;
+    type Ticks = A::Ticks;
+    const WIDTH: u32 = A::WIDTH;
+
+    fn now(&self) -> A::Ticks {
+        self.wheel.alarm.now()
+    }
+
+    fn max_tics(&self) -> A::Ticks {
+        self.wheel.alarm.max_tics()
+    }
+
+    fn frequency(&self) -> u32 {
+        self.wheel.alarm.frequency()
+    }
+
+    fn persists_in_sleep(&self) -> bool {
+        self.wheel.alarm.persists_in_sleep()
+    }
+}
+
+impl<A: Alarm<'a>> Timer<'a> for WheelTimer<'a, A> {
+    fn set_client(&'a self, client: &'a dyn time::TimerClient) {
+        self.wheel.timers.push_head(self);
+        self.client.set(client);
+    }
+
+    fn oneshot(&self, interval: A::Ticks) {
+        self.arm(interval, None);
+    }
+
+    fn repeat(&self, interval: A::Ticks) {
+        self.arm(interval, Some(interval));
+    }
+
+    fn repeat_n(&self, interval: A::Ticks, count: u32) -> ReturnCode {
+        if interval == A::Ticks::from_u32(0) {
+            return ReturnCode::EINVAL;
+        }
+        self.arm(interval, Some(interval));
+        self.fires_left.set(count);
+        ReturnCode::SUCCESS
+    }
+
+    fn min_tick_divisor(&self) -> u32 {
+        self.wheel.alarm.minimum_dt().into_u32()
+    }
+
+    fn interval(&self) -> Option<A::Ticks> {
+        if self.armed.get() {
+            self.interval.get()
+        } else {
+            None
+        }
+    }
+
+    fn change_interval(&self, interval: A::Ticks) -> ReturnCode {
+        if self.interval().is_none() {
+            return ReturnCode::EINVAL;
+        }
+        self.interval.set(Some(interval));
+        if self.firing.get() {
+            // The period starting with this fire has already been reloaded
+            // with the old interval; it has not run yet, so replace it. The
+            // wheel reschedules once the callback returns.
+            self.dt.set(interval);
+        }
+        ReturnCode::SUCCESS
+    }
+
+    fn time_remaining(&self) -> Option<A::Ticks> {
+        if self.armed.get() {
+            Some(self.remaining(self.now()))
+        } else {
+            None
+        }
+    }
+
+    fn cancel(&self) {
+        self.armed.set(false);
+        self.interval.set(None);
+        self.fires_left.set(0);
+        self.paused.set(None);
+        self.wheel.schedule();
+    }
+
+    fn pause(&self) -> ReturnCode {
+        if self.paused.get().is_some() {
+            return ReturnCode::SUCCESS;
+        }
+        match self.time_remaining() {
+            Some(remaining) => {
+                self.paused.set(Some(remaining));
+                self.armed.set(false);
+                self.wheel.schedule();
+                ReturnCode::SUCCESS
+            }
+            None => ReturnCode::EINVAL,
+        }
+    }
+
+    fn resume(&self) -> ReturnCode {
+        match self.paused.take() {
+            Some(remaining) => {
+                self.reference.set(self.now());
+                self.dt.set(remaining);
+                self.armed.set(true);
+                self.wheel.schedule();
+                ReturnCode::SUCCESS
+            }
+            None => ReturnCode::EINVAL,
+        }
+    }
+}
+
+pub struct TimerWheel<'a, A: Alarm<'a>> {
+    timers: List<'a, WheelTimer<'a, A>>,
+    /// Set while expired timers are being called, so that timers re-armed
+    /// from a callback do not reprogram the alarm once each.
+    dispatching: Cell<bool>,
+    alarm: &'a A,
+}
+
+impl<A: Alarm<'a>> TimerWheel<'a, A> {
+    pub fn new(alarm: &'a A) -> TimerWheel<'a, A> {
+        TimerWheel {
+            timers: List::new(),
+            dispatching: Cell::new(false),
+            alarm,
+        }
+    }
+
+    /// Programs the underlying alarm for the earliest deadline of all armed
+    /// timers, or disables it if there are none.
+    fn schedule(&self) {
+        if self.dispatching.get() {
+            return;
+        }
+
+        let now = self.alarm.now();
+        let next = self
+            .timers
+            .iter()
+            .filter(|timer| timer.armed.get())
+            .min_by_key(|timer| timer.remaining(now));
+
+        match next {
+            Some(timer) => {
+                // Fires as soon as possible if the deadline has already passed.
+                self.alarm
+                    .set_alarm_from(timer.reference.get(), timer.dt.get());
+            }
+            None => {
+                self.alarm.disable();
+            }
+        }
+    }
+}
+
+impl<A: Alarm<'a>> time::AlarmClient for TimerWheel<'a, A> {
+    fn fired(&self) {
+        let now = self.alarm.now();
+
+        self.dispatching.set(true);
+        self.timers
+            .iter()
+            .filter(|timer| timer.armed.get() && timer.has_expired(now))
+            .for_each(|timer| {
+                // Update the timer before calling the client, so that a
+                // client that re-arms or cancels the timer has the last word.
+                match (timer.interval.get(), timer.fires_left.get()) {
+                    (Some(_), 1) | (None, _) => {
+                        // A oneshot, or the last fire of a `repeat_n`
+                        // sequence.
+                        timer.interval.set(None);
+                        timer.armed.set(false);
+                    }
+                    (Some(interval), left) => {
+                        timer.fires_left.set(left.saturating_sub(1));
+                        timer.reference.set(timer.deadline());
+                        timer.dt.set(interval);
+                    }
+                }
+                timer.firing.set(true);
+                timer.client.map(|client| client.fired());
+                timer.firing.set(false);
+            });
+        self.dispatching.set(false);
+
+        self.schedule();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{TimerWheel, WheelTimer};
+    use core::cell::Cell;
+    use kernel::hil::time::mock::MockAlarm;
+    use kernel::hil::time::{Alarm, Time, Timer, TimerClient};
+    use kernel::ReturnCode;
+
+    type TestTimer<'a> = WheelTimer<'a, MockAlarm<'a>>;
+
+    struct Client<'a> {
+        fired: Cell<usize>,
+        fired_at: Cell<u32>,
+        alarm: &'a MockAlarm<'a>,
+        /// A timer to cancel from the callback.
+        cancel: Cell<Option<&'a TestTimer<'a>>>,
+        /// A timer to arm as a oneshot from the callback.
+        oneshot: Cell<Option<(&'a TestTimer<'a>, u32)>>,
+        /// A timer whose interval to change from the callback.
+        change_interval: Cell<Option<(&'a TestTimer<'a>, u32)>>,
+    }
+
+    impl Client<'a> {
+        fn new(alarm: &'a MockAlarm<'a>) -> Client<'a> {
+            Client {
+                fired: Cell::new(0),
+                fired_at: Cell::new(0),
+                alarm,
+                cancel: Cell::new(None),
+                oneshot: Cell::new(None),
+                change_interval: Cell::new(None),
+            }
+        }
+    }
+
+    impl TimerClient for Client<'a> {
+        fn fired(&self) {
+            self.fired.set(self.fired.get() + 1);
+            self.fired_at.set(self.alarm.now());
+            if let Some(timer) = self.cancel.take() {
+                timer.cancel();
+            }
+            if let Some((timer, interval)) = self.oneshot.take() {
+                timer.oneshot(interval);
+            }
+            if let Some((timer, interval)) = self.change_interval.take() {
+                timer.change_interval(interval);
+            }
+        }
+    }
+
+    #[test]
+    fn test_fires_earliest_first() {
+        let alarm = MockAlarm::new();
+        let wheel = TimerWheel::new(&alarm);
+        alarm.set_client(&wheel);
+        let (timer1, timer2) = (WheelTimer::new(&wheel), WheelTimer::new(&wheel));
+        let (client1, client2) = (Client::new(&alarm), Client::new(&alarm));
+        timer1.set_client(&client1);
+        timer2.set_client(&client2);
+
+        timer1.oneshot(30);
+        timer2.oneshot(10);
+        assert_eq!(alarm.get_alarm(), 10);
+
+        alarm.advance(10);
+        assert_eq!((client1.fired.get(), client2.fired.get()), (0, 1));
+        assert_eq!(alarm.get_alarm(), 30);
+        assert_eq!(timer1.time_remaining(), Some(20));
+        assert_eq!(timer2.time_remaining(), None);
+
+        alarm.advance(20);
+        assert_eq!((client1.fired.get(), client2.fired.get()), (1, 1));
+        assert!(!alarm.is_enabled());
+    }
+
+    #[test]
+    fn test_repeating_timer_does_not_drift() {
+        let alarm = MockAlarm::new();
+        let wheel = TimerWheel::new(&alarm);
+        alarm.set_client(&wheel);
+        let timer = WheelTimer::new(&wheel);
+        let client = Client::new(&alarm);
+        timer.set_client(&client);
+
+        timer.repeat(10);
+        assert!(timer.is_repeating());
+        alarm.advance(35);
+        assert_eq!(client.fired.get(), 3);
+        assert_eq!(client.fired_at.get(), 30);
+        assert_eq!(timer.time_remaining(), Some(5));
+    }
+
+    #[test]
+    fn test_repeat_n_stops_after_count() {
+        let alarm = MockAlarm::new();
+        let wheel = TimerWheel::new(&alarm);
+        alarm.set_client(&wheel);
+        let (timer1, timer2) = (WheelTimer::new(&wheel), WheelTimer::new(&wheel));
+        let (client1, client2) = (Client::new(&alarm), Client::new(&alarm));
+        timer1.set_client(&client1);
+        timer2.set_client(&client2);
+
+        assert_eq!(timer1.repeat_n(10, 2), ReturnCode::SUCCESS);
+        timer2.repeat(15);
+        alarm.advance(50);
+        assert_eq!((client1.fired.get(), client2.fired.get()), (2, 3));
+        assert_eq!(client1.fired_at.get(), 20);
+        assert!(!timer1.is_enabled());
+        assert!(timer2.is_repeating());
+
+        // The count is not remembered once the sequence is cancelled.
+        timer1.repeat_n(10, 2);
+        alarm.advance(10);
+        timer1.cancel();
+        timer1.repeat(10);
+        alarm.advance(30);
+        assert_eq!(client1.fired.get(), 6);
+    }
+
+    #[test]
+    fn test_cancel_from_callback() {
+        let alarm = MockAlarm::new();
+        let wheel = TimerWheel::new(&alarm);
+        alarm.set_client(&wheel);
+        let (timer1, timer2) = (WheelTimer::new(&wheel), WheelTimer::new(&wheel));
+        let (client1, client2) = (Client::new(&alarm), Client::new(&alarm));
+        timer1.set_client(&client1);
+        timer2.set_client(&client2);
+
+        // Both expire at the same time. timer2 was added last and is called
+        // first; it cancels timer1, which must then not be called.
+        timer1.repeat(10);
+        timer2.oneshot(10);
+        client2.cancel.set(Some(&timer1));
+        alarm.advance(100);
+        assert_eq!((client1.fired.get(), client2.fired.get()), (0, 1));
+        assert!(!timer1.is_enabled());
+        assert!(!alarm.is_enabled());
+    }
+
+    #[test]
+    fn test_rearm_from_callback() {
+        let alarm = MockAlarm::new();
+        let wheel = TimerWheel::new(&alarm);
+        alarm.set_client(&wheel);
+        let (timer1, timer2) = (WheelTimer::new(&wheel), WheelTimer::new(&wheel));
+        let (client1, client2) = (Client::new(&alarm), Client::new(&alarm));
+        timer1.set_client(&client1);
+        timer2.set_client(&client2);
+
+        // timer1 re-arms itself once, and arms timer2.
+        timer1.oneshot(10);
+        client1.oneshot.set(Some((&timer1, 5)));
+        alarm.advance(10);
+        assert_eq!(timer1.time_remaining(), Some(5));
+
+        client1.oneshot.set(Some((&timer2, 7)));
+        alarm.advance(5);
+        assert_eq!(client1.fired.get(), 2);
+        assert_eq!(timer2.time_remaining(), Some(7));
+
+        alarm.advance(7);
+        assert_eq!(client2.fired.get(), 1);
+        assert_eq!(client2.fired_at.get(), 22);
+    }
+
+    #[test]
+    fn test_change_interval_keeps_current_period() {
+        let alarm = MockAlarm::new();
+        let wheel = TimerWheel::new(&alarm);
+        alarm.set_client(&wheel);
+        let timer = WheelTimer::new(&wheel);
+        let client = Client::new(&alarm);
+        timer.set_client(&client);
+
+        timer.repeat(10);
+        alarm.advance(4);
+        assert_eq!(timer.change_interval(20), ReturnCode::SUCCESS);
+        assert_eq!(timer.interval(), Some(20));
+        assert_eq!(timer.time_remaining(), Some(6));
+
+        alarm.advance(6);
+        assert_eq!(client.fired.get(), 1);
+        assert_eq!(timer.time_remaining(), Some(20));
+        alarm.advance(20);
+        assert_eq!(client.fired.get(), 2);
+        assert_eq!(client.fired_at.get(), 30);
+    }
+
+    #[test]
+    fn test_change_interval_from_callback() {
+        let alarm = MockAlarm::new();
+        let wheel = TimerWheel::new(&alarm);
+        alarm.set_client(&wheel);
+        let timer = WheelTimer::new(&wheel);
+        let client = Client::new(&alarm);
+        timer.set_client(&client);
+
+        timer.repeat(10);
+        client.change_interval.set(Some((&timer, 5)));
+        alarm.advance(10);
+        assert_eq!(timer.time_remaining(), Some(5));
+        assert_eq!(alarm.get_alarm(), 15);
+        alarm.advance(10);
+        assert_eq!(client.fired.get(), 3);
+        assert_eq!(client.fired_at.get(), 20);
+    }
+
+    #[test]
+    fn test_change_interval_requires_repeating_timer() {
+        let alarm = MockAlarm::new();
+        let wheel = TimerWheel::new(&alarm);
+        alarm.set_client(&wheel);
+        let timer = WheelTimer::new(&wheel);
+        let client = Client::new(&alarm);
+        timer.set_client(&client);
+
+        assert_eq!(timer.change_interval(5), ReturnCode::EINVAL);
+        timer.oneshot(10);
+        assert_eq!(timer.change_interval(5), ReturnCode::EINVAL);
+        assert_eq!(timer.time_remaining(), Some(10));
+    }
+
+    #[test]
+    fn test_pause_resume() {
+        let alarm = MockAlarm::new();
+        let wheel = TimerWheel::new(&alarm);
+        alarm.set_client(&wheel);
+        let (timer1, timer2) = (WheelTimer::new(&wheel), WheelTimer::new(&wheel));
+        let (client1, client2) = (Client::new(&alarm), Client::new(&alarm));
+        timer1.set_client(&client1);
+        timer2.set_client(&client2);
+
+        timer1.repeat(10);
+        timer2.oneshot(50);
+        alarm.advance(13);
+        assert_eq!(timer1.pause(), ReturnCode::SUCCESS);
+        assert_eq!(timer1.pause(), ReturnCode::SUCCESS);
+        assert_eq!(alarm.get_alarm(), 50);
+        alarm.advance(100);
+        assert_eq!((client1.fired.get(), client2.fired.get()), (1, 1));
+
+        // The 7 tics left, then every 10 tics again.
+        assert_eq!(timer1.resume(), ReturnCode::SUCCESS);
+        assert_eq!(alarm.get_alarm(), 120);
+        alarm.advance(17);
+        assert_eq!(client1.fired.get(), 3);
+        assert_eq!(client1.fired_at.get(), 130);
+    }
+}
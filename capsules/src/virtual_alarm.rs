@@ -1,15 +1,47 @@
 //! Virtualize the Alarm interface to enable multiple users of an underlying
 //! alarm hardware peripheral.
+//!
+//! Each virtual alarm counts how often it has fired, see
+//! `VirtualMuxAlarm::fires`. To keep one client that re-arms a very short
+//! alarm in a loop from starving the others, a virtual alarm can also be
+//! given a minimum re-arm interval with `set_min_rearm_interval`: deadlines
+//! set less than that many tics after it last fired are deferred until the
+//! interval has passed.
+//!
+//! While only one virtual alarm is armed, the mux fires it directly instead
+//! of dispatching over all virtual alarms and searching for the next
+//! deadline afterwards, and programs the hardware only when the client sets
+//! a new alarm. As soon as a second virtual alarm is armed the general path
+//! is taken again.
+//!
+//! Setting or disabling several virtual alarms together, e.g. during startup,
+//! can be wrapped in `MuxAlarm::batch`, so that the underlying alarm is
+//! programmed once for the earliest deadline at the end instead of after
+//! every call.
 
 use core::cell::Cell;
 use kernel::common::cells::OptionalCell;
 use kernel::common::{List, ListLink, ListNode};
-use kernel::hil::time::{self, Alarm, Time};
+use kernel::hil::time::{self, Alarm, Ticks, Time, TimeCapabilities};
+use kernel::ReturnCode;
 
 pub struct VirtualMuxAlarm<'a, A: Alarm<'a>> {
     mux: &'a MuxAlarm<'a, A>,
-    when: Cell<u32>,
+    when: Cell<A::Ticks>,
+    token: Cell<u32>,
     armed: Cell<bool>,
+    /// Set while the alarm has expired but its client has not been called
+    /// yet in the current dispatch pass.
+    due: Cell<bool>,
+    masked: Cell<bool>,
+    /// Set when the alarm expired while masked.
+    held: Cell<bool>,
+    /// How often the client has been called.
+    fires: Cell<u32>,
+    /// When the alarm last expired, if it has.
+    last_fired: Cell<Option<A::Ticks>>,
+    /// The shortest time after `last_fired` the alarm may fire again.
+    min_rearm_interval: Cell<A::Ticks>,
     next: ListLink<'a, VirtualMuxAlarm<'a, A>>,
     client: OptionalCell<&'a dyn time::AlarmClient>,
 }
@@ -24,37 +56,113 @@ impl<A: Alarm<'a>> VirtualMuxAlarm<'a, A> {
     pub fn new(mux_alarm: &'a MuxAlarm<'a, A>) -> VirtualMuxAlarm<'a, A> {
         VirtualMuxAlarm {
             mux: mux_alarm,
-            when: Cell::new(0),
+            when: Cell::new(A::Ticks::from_u32(0)),
+            token: Cell::new(0),
             armed: Cell::new(false),
+            due: Cell::new(false),
+            masked: Cell::new(false),
+            held: Cell::new(false),
+            fires: Cell::new(0),
+            last_fired: Cell::new(None),
+            min_rearm_interval: Cell::new(A::Ticks::from_u32(0)),
             next: ListLink::empty(),
             client: OptionalCell::empty(),
         }
     }
+
+    /// Returns how many times the client has been called, wrapping at
+    /// `u32::MAX`. For diagnostics.
+    pub fn fires(&self) -> u32 {
+        self.fires.get()
+    }
+
+    /// Defers deadlines that are less than `tics` tics after this alarm last
+    /// fired until `tics` tics after it, so the client cannot fire more often
+    /// than once per `tics`. `0`, the default, disables the limit.
+    pub fn set_min_rearm_interval(&self, tics: A::Ticks) {
+        self.min_rearm_interval.set(tics);
+    }
+
+    /// Returns `when`, or the end of the minimum re-arm interval if `when`
+    /// is before it.
+    fn rate_limited(&self, when: A::Ticks) -> A::Ticks {
+        let last_fired = match self.last_fired.get() {
+            Some(last_fired) => last_fired,
+            None => return when,
+        };
+        let now = self.mux.alarm.now();
+        let interval = self.min_rearm_interval.get();
+        let since_fired = now.wrapping_sub(last_fired);
+        if since_fired >= interval {
+            return when;
+        }
+        // Deadlines more than half a counter period away have passed.
+        let dt = self.mux.tics_until(now, when);
+        let max = self.mux.alarm.max_tics().into_u64();
+        if dt > max / 2 || dt < interval.wrapping_sub(since_fired).into_u64() {
+            last_fired.wrapping_add(interval)
+        } else {
+            when
+        }
+    }
 }
 
 impl<A: Alarm<'a>> Time for VirtualMuxAlarm<'a, A> {
     type Frequency = A::Frequency;
+    type Ticks = A::Ticks;
+    const WIDTH: u32 = A::WIDTH;
 
-    fn max_tics(&self) -> u32 {
+    fn max_tics(&self) -> A::Ticks {
         self.mux.alarm.max_tics()
     }
 
-    fn now(&self) -> u32 {
+    fn now(&self) -> A::Ticks {
         self.mux.alarm.now()
     }
+
+    fn try_now(&self) -> Result<A::Ticks, ReturnCode> {
+        self.mux.alarm.try_now()
+    }
+
+    fn frequency(&self) -> u32 {
+        self.mux.alarm.frequency()
+    }
+
+    fn persists_in_sleep(&self) -> bool {
+        self.mux.alarm.persists_in_sleep()
+    }
+
+    fn capabilities(&self) -> TimeCapabilities {
+        // The virtual alarm cannot start or stop the underlying counter, even
+        // if it is one.
+        TimeCapabilities {
+            min_alarm_dt: self.minimum_dt().into_u32(),
+            is_counter: false,
+            ..self.mux.alarm.capabilities()
+        }
+    }
 }
 
 impl<A: Alarm<'a>> Alarm<'a> for VirtualMuxAlarm<'a, A> {
     fn set_client(&'a self, client: &'a dyn time::AlarmClient) {
         self.mux.virtual_alarms.push_head(self);
-        self.when.set(0);
+        self.when.set(A::Ticks::from_u32(0));
         self.armed.set(false);
         self.client.set(client);
     }
 
-    fn disable(&self) {
+    fn clear_client(&self) {
+        self.disable();
+        self.client.clear();
+    }
+
+    fn disable(&self) -> ReturnCode {
+        // Also cancels a callback that is due in the current dispatch pass,
+        // or held by a mask.
+        self.due.set(false);
+        self.held.set(false);
         if !self.armed.get() {
-            return;
+            return ReturnCode::SUCCESS;
         }
 
         self.armed.set(false);
@@ -65,15 +173,41 @@ impl<A: Alarm<'a>> Alarm<'a> for VirtualMuxAlarm<'a, A> {
         // If there are not more enabled alarms, disable the underlying alarm
         // completely.
         if enabled == 0 {
-            self.mux.alarm.disable();
+            if self.mux.batch_depth.get() > 0 {
+                self.mux.batched.set(None);
+            } else {
+                self.mux.alarm.disable();
+            }
         }
+        ReturnCode::SUCCESS
     }
 
     fn is_enabled(&self) -> bool {
-        self.armed.get()
+        self.armed.get() || self.held.get()
     }
 
-    fn set_alarm(&self, when: u32) {
+    fn mask(&self) -> ReturnCode {
+        self.masked.set(true);
+        ReturnCode::SUCCESS
+    }
+
+    fn unmask(&self) -> ReturnCode {
+        self.masked.set(false);
+        if self.held.get() {
+            // Deliver it from a fresh pass rather than from within this call.
+            self.set_alarm_with_token(self.mux.alarm.now(), self.token.get());
+        }
+        ReturnCode::SUCCESS
+    }
+
+    /// Unlike hardware alarms, virtual alarms never return
+    /// `ReturnCode::EINVAL`: a deadline closer to `now()` than the underlying
+    /// alarm's `minimum_dt` is accepted and fires that many tics late at
+    /// most, as the mux clamps it when programming the underlying alarm.
+    /// Clients of a shared alarm thus need no retry path.
+    fn set_alarm_with_token(&self, when: A::Ticks, token: u32) -> ReturnCode {
+        let when = self.mux.wrap(self.rate_limited(when));
+        self.held.set(false);
         let enabled = self.mux.enabled.get();
 
         if !self.armed.get() {
@@ -81,30 +215,72 @@ impl<A: Alarm<'a>> Alarm<'a> for VirtualMuxAlarm<'a, A> {
             self.armed.set(true);
         }
 
-        if enabled > 0 {
-            let cur_alarm = self.mux.alarm.get_alarm();
-            let now = self.now();
+        let when = if enabled > 0 {
+            let (now, mut cur_alarm) = self.mux.alarm.now_and_alarm();
+            if let Some(batched) = self.mux.batched.get() {
+                cur_alarm = batched;
+            }
 
-            if cur_alarm.wrapping_sub(now) > when.wrapping_sub(now) {
+            let when = self.mux.catch_up(now, when);
+            if self.mux.tics_until(now, cur_alarm) > self.mux.tics_until(now, when) {
                 self.mux.prev.set(self.mux.alarm.now());
-                self.mux.alarm.set_alarm(when);
+                self.mux.program(when);
             }
+            when
         } else {
-            self.mux.prev.set(self.mux.alarm.now());
-            self.mux.alarm.set_alarm(when);
-        }
+            let now = self.mux.alarm.now();
+            let when = self.mux.catch_up(now, when);
+            self.mux.prev.set(now);
+            self.mux.program(when);
+            when
+        };
 
         self.when.set(when);
+        self.token.set(token);
+        ReturnCode::SUCCESS
     }
 
-    fn get_alarm(&self) -> u32 {
+    fn get_alarm(&self) -> A::Ticks {
         self.when.get()
     }
+
+    /// The underlying alarm's, which only bounds how late a short deadline
+    /// fires (see `set_alarm_with_token`).
+    fn minimum_dt(&self) -> A::Ticks {
+        self.mux.alarm.minimum_dt()
+    }
 }
 
 impl<A: Alarm<'a>> time::AlarmClient for VirtualMuxAlarm<'a, A> {
     fn fired(&self) {
-        self.client.map(|client| client.fired());
+        self.client
+            .map(|client| client.fired_with_token(self.token.get()));
+    }
+}
+
+impl<A: Alarm<'a>> time::DispatchClient<A::Ticks> for VirtualMuxAlarm<'a, A> {
+    fn mark_if_expired(&self, now: A::Ticks) -> bool {
+        if !self.armed.get() || !time::has_expired(self.mux.prev.get(), self.when.get(), now) {
+            return false;
+        }
+        self.armed.set(false);
+        self.mux.enabled.set(self.mux.enabled.get() - 1);
+        self.due.set(true);
+        self.last_fired.set(Some(now));
+        true
+    }
+
+    fn take_due(&self) -> bool {
+        self.due.replace(false)
+    }
+
+    fn fire(&self) {
+        if self.masked.get() {
+            self.held.set(true);
+            return;
+        }
+        self.fires.set(self.fires.get().wrapping_add(1));
+        time::AlarmClient::fired(self);
     }
 }
 
@@ -113,45 +289,156 @@ impl<A: Alarm<'a>> time::AlarmClient for VirtualMuxAlarm<'a, A> {
 pub struct MuxAlarm<'a, A: Alarm<'a>> {
     virtual_alarms: List<'a, VirtualMuxAlarm<'a, A>>,
     enabled: Cell<usize>,
-    prev: Cell<u32>,
+    prev: Cell<A::Ticks>,
+    /// How many `batch` calls are running, nested.
+    batch_depth: Cell<usize>,
+    /// The deadline to program at the end of the batch, if it moved.
+    batched: Cell<Option<A::Ticks>>,
     alarm: &'a A,
 }
 
 impl<A: Alarm<'a>> MuxAlarm<'a, A> {
-    pub const fn new(alarm: &'a A) -> MuxAlarm<'a, A> {
+    pub fn new(alarm: &'a A) -> MuxAlarm<'a, A> {
         MuxAlarm {
             virtual_alarms: List::new(),
             enabled: Cell::new(0),
-            prev: Cell::new(0),
+            prev: Cell::new(A::Ticks::from_u32(0)),
+            batch_depth: Cell::new(0),
+            batched: Cell::new(None),
             alarm: alarm,
         }
     }
-}
 
-fn has_expired(alarm: u32, now: u32, prev: u32) -> bool {
-    now.wrapping_sub(prev) >= alarm.wrapping_sub(prev)
+    /// Runs `f`, deferring programming the underlying alarm for virtual
+    /// alarms set or disabled in it until it returns, and then programs it
+    /// once for the earliest deadline, or disables it if none is armed.
+    ///
+    /// Batches nested in `f` are part of the outermost one. Virtual alarms
+    /// behave the same as without batching, except that a deadline that
+    /// passes before the batch ends fires as soon as possible after it.
+    pub fn batch(&self, f: impl FnOnce()) {
+        self.batch_depth.set(self.batch_depth.get() + 1);
+        f();
+        let depth = self.batch_depth.get() - 1;
+        self.batch_depth.set(depth);
+        if depth > 0 {
+            return;
+        }
+
+        match self.batched.take() {
+            Some(when) if self.enabled.get() > 0 => {
+                if time::has_expired(self.prev.get(), when, self.alarm.now()) {
+                    self.arm(self.alarm.now());
+                } else {
+                    self.arm(when);
+                }
+            }
+            _ => {
+                if self.enabled.get() == 0 {
+                    self.alarm.disable();
+                }
+            }
+        }
+    }
+
+    /// Programs the underlying alarm for `when` like `arm`, or within a batch
+    /// records it to be programmed when the batch ends.
+    fn program(&self, when: A::Ticks) {
+        if self.batch_depth.get() > 0 {
+            self.batched.set(Some(when));
+        } else {
+            self.arm(when);
+        }
+    }
+
+    /// Returns `tics` reduced to the width of the underlying counter.
+    fn wrap(&self, tics: A::Ticks) -> A::Ticks {
+        A::Ticks::from_u64(tics.into_u64() & self.alarm.max_tics().into_u64())
+    }
+
+    /// Returns the number of tics from `now` until `when`, within one period
+    /// of the underlying counter.
+    fn tics_until(&self, now: A::Ticks, when: A::Ticks) -> u64 {
+        when.wrapping_sub(now).into_u64() & self.alarm.max_tics().into_u64()
+    }
+
+    /// Returns `now` if the counter reached `when` at most `minimum_dt` tics
+    /// before `now`, so that the deadline fires as soon as possible instead
+    /// of a full counter period later, and `when` otherwise.
+    fn catch_up(&self, now: A::Ticks, when: A::Ticks) -> A::Ticks {
+        let min_dt = self.alarm.minimum_dt();
+        if time::reached_within(now, when, min_dt, self.alarm.max_tics()) {
+            now
+        } else {
+            when
+        }
+    }
+
+    /// Programs the underlying alarm for `when`. If `when` is too close to
+    /// `now()` for the hardware to fire reliably, or was reached at most
+    /// `minimum_dt` tics ago, the underlying alarm is set for the earliest
+    /// time it can fire instead.
+    fn arm(&self, when: A::Ticks) {
+        let min_dt = self.alarm.minimum_dt();
+        let now = self.alarm.now();
+        let dt = if time::reached_within(now, when, min_dt, self.alarm.max_tics()) {
+            min_dt
+        } else {
+            core::cmp::max(when.wrapping_sub(now), min_dt)
+        };
+
+        // The counter may still tick past the clamped value before the
+        // hardware is programmed, in which case we try once more. Not in a
+        // loop, as the alarm also returns `EINVAL` for deadlines it can never
+        // accept.
+        if self.alarm.set_alarm(now.wrapping_add(dt)) == ReturnCode::EINVAL {
+            self.alarm.set_alarm(self.alarm.now().wrapping_add(min_dt));
+        }
+    }
+
+    /// Handles a fire of the underlying alarm while only one virtual alarm
+    /// is armed, which is the common case on many boards.
+    ///
+    /// The armed alarm is found by scanning only up to it, and it is fired
+    /// without the two passes of `dispatch_fired` or a search for the next
+    /// deadline afterwards. Nothing needs to be reprogrammed once the client
+    /// returns: the underlying alarm has disarmed itself by firing, and an
+    /// alarm the client set from its callback has already programmed it.
+    fn fire_single(&self, now: A::Ticks) {
+        let valarm = match self.virtual_alarms.iter().find(|cur| cur.armed.get()) {
+            Some(valarm) => valarm,
+            None => return,
+        };
+        if time::DispatchClient::mark_if_expired(valarm, now) {
+            self.prev.set(now);
+            valarm.due.set(false);
+            time::DispatchClient::fire(valarm);
+        } else {
+            // Fired early, e.g. for a deadline that was since moved.
+            self.prev.set(now);
+            self.arm(valarm.when.get());
+        }
+    }
 }
 
 impl<A: Alarm<'a>> time::AlarmClient for MuxAlarm<'a, A> {
     fn fired(&self) {
         let now = self.alarm.now();
+        if self.enabled.get() == 1 {
+            self.fire_single(now);
+            return;
+        }
 
-        // Capture this before the loop because it can change while checking
-        // each alarm. If a timer fires, it can immediately set a new timer
-        // by calling `VirtualMuxAlarm.set_alarm()` which can change `self.prev`
+        // Capture this before dispatching because it can change in a
+        // callback. If a timer fires, it can immediately set a new timer by
+        // calling `VirtualMuxAlarm.set_alarm()` which can change `self.prev`
         // to the current timer time.
         let prev = self.prev.get();
 
-        // Check whether to fire each alarm. At this level, alarms are one-shot,
-        // so a repeating client will set it again in the fired() callback.
-        self.virtual_alarms
-            .iter()
-            .filter(|cur| cur.armed.get() && has_expired(cur.when.get(), now, prev))
-            .for_each(|cur| {
-                cur.armed.set(false);
-                self.enabled.set(self.enabled.get() - 1);
-                cur.fired();
-            });
+        // Fire every expired alarm. At this level, alarms are one-shot, so a
+        // repeating client will set it again in the fired() callback. Alarms
+        // set from a callback are not fired until the next pass.
+        time::dispatch_fired(self.virtual_alarms.iter(), now);
 
         // Find the soonest alarm client (if any) and set the "next" underlying
         // alarm based on it.  This needs to happen after firing all expired
@@ -160,17 +447,556 @@ impl<A: Alarm<'a>> time::AlarmClient for MuxAlarm<'a, A> {
             .virtual_alarms
             .iter()
             .filter(|cur| cur.armed.get())
-            .min_by_key(|cur| cur.when.get().wrapping_sub(now));
+            .min_by_key(|cur| self.tics_until(now, cur.when.get()));
 
         self.prev.set(now);
-        // If there is an alarm to fire, set the underlying alarm to it
+        // If there is an alarm to fire, set the underlying alarm to it. One
+        // that has already expired starts a new pass as soon as the hardware
+        // allows, instead of recursing from here.
         if let Some(valrm) = next {
-            self.alarm.set_alarm(valrm.when.get());
-            if has_expired(valrm.when.get(), self.alarm.now(), prev) {
-                self.fired();
+            if time::has_expired(prev, valrm.when.get(), self.alarm.now()) {
+                self.arm(self.alarm.now());
+            } else {
+                self.arm(valrm.when.get());
             }
         } else {
             self.alarm.disable();
         }
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::{MuxAlarm, VirtualMuxAlarm};
+    use core::cell::Cell;
+    use kernel::common::cells::OptionalCell;
+    use kernel::hil::time::{Alarm, AlarmClient, Freq1KHz, FreqTicks, Ticks, Time};
+    use kernel::ReturnCode;
+
+    type Tics = FreqTicks<Freq1KHz>;
+
+    fn tics(tics: u32) -> Tics {
+        FreqTicks::new(tics)
+    }
+
+    struct FakeAlarm<'a> {
+        now: Cell<u32>,
+        alarm: Cell<u32>,
+        armed: Cell<bool>,
+        /// Calls of `now`, `set_alarm_with_token`, `get_alarm` and `disable`,
+        /// as a measure of the mux's overhead.
+        ops: Cell<usize>,
+        /// Calls of `set_alarm_with_token`.
+        sets: Cell<usize>,
+        /// Whether every value is rejected with `EINVAL`.
+        rejects: Cell<bool>,
+        /// The largest counter value, for counters narrower than 32 bits.
+        max: u32,
+        client: OptionalCell<&'a dyn AlarmClient>,
+    }
+
+    impl FakeAlarm<'a> {
+        fn new(now: u32) -> FakeAlarm<'a> {
+            FakeAlarm::with_max(now, u32::max_value())
+        }
+
+        fn with_max(now: u32, max: u32) -> FakeAlarm<'a> {
+            FakeAlarm {
+                now: Cell::new(now),
+                alarm: Cell::new(0),
+                armed: Cell::new(false),
+                ops: Cell::new(0),
+                sets: Cell::new(0),
+                rejects: Cell::new(false),
+                max,
+                client: OptionalCell::empty(),
+            }
+        }
+
+        /// Moves the clock forward and fires the client if the armed value
+        /// was crossed.
+        fn advance(&self, tics: u32) {
+            let prev = self.now.get();
+            self.now.set(prev.wrapping_add(tics) & self.max);
+            let dt = self.alarm.get().wrapping_sub(prev) & self.max;
+            if self.armed.get() && tics >= dt {
+                self.armed.set(false);
+                self.client.map(|client| client.fired());
+            }
+        }
+    }
+
+    impl Time for FakeAlarm<'a> {
+        type Frequency = Freq1KHz;
+        type Ticks = Tics;
+
+        fn max_tics(&self) -> Tics {
+            tics(self.max)
+        }
+
+        fn now(&self) -> Tics {
+            self.ops.set(self.ops.get() + 1);
+            tics(self.now.get())
+        }
+
+        fn persists_in_sleep(&self) -> bool {
+            true
+        }
+    }
+
+    impl Alarm<'a> for FakeAlarm<'a> {
+        fn set_alarm_with_token(&self, tics: Tics, _token: u32) -> ReturnCode {
+            self.ops.set(self.ops.get() + 1);
+            self.sets.set(self.sets.get() + 1);
+            let tics = tics.into_u32() & self.max;
+            if self.rejects.get() || tics.wrapping_sub(self.now.get()) & self.max < 2 {
+                return ReturnCode::EINVAL;
+            }
+            self.alarm.set(tics);
+            self.armed.set(true);
+            ReturnCode::SUCCESS
+        }
+
+        fn get_alarm(&self) -> Tics {
+            self.ops.set(self.ops.get() + 1);
+            tics(self.alarm.get())
+        }
+
+        fn minimum_dt(&self) -> Tics {
+            tics(2)
+        }
+
+        fn set_client(&'a self, client: &'a dyn AlarmClient) {
+            self.client.set(client);
+        }
+
+        fn clear_client(&self) {
+            self.disable();
+            self.client.clear();
+        }
+
+        fn is_enabled(&self) -> bool {
+            self.armed.get()
+        }
+
+        fn disable(&self) -> ReturnCode {
+            self.ops.set(self.ops.get() + 1);
+            self.armed.set(false);
+            ReturnCode::SUCCESS
+        }
+    }
+
+    struct Client<'a> {
+        alarm: &'a VirtualMuxAlarm<'a, FakeAlarm<'a>>,
+        fired: Cell<usize>,
+        token: Cell<u32>,
+        rearm: Cell<Option<u32>>,
+    }
+
+    impl Client<'a> {
+        fn new(alarm: &'a VirtualMuxAlarm<'a, FakeAlarm<'a>>) -> Client<'a> {
+            Client {
+                alarm,
+                fired: Cell::new(0),
+                token: Cell::new(0),
+                rearm: Cell::new(None),
+            }
+        }
+    }
+
+    impl AlarmClient for Client<'a> {
+        fn fired(&self) {
+            self.fired.set(self.fired.get() + 1);
+            if let Some(dt) = self.rearm.take() {
+                self.alarm
+                    .set_alarm(self.alarm.now().wrapping_add(tics(dt)));
+            }
+        }
+
+        fn fired_with_token(&self, token: u32) {
+            self.token.set(token);
+            self.fired();
+        }
+    }
+
+    #[test]
+    fn test_fires_in_deadline_order() {
+        let fake = FakeAlarm::new(0);
+        let mux = MuxAlarm::new(&fake);
+        fake.set_client(&mux);
+        let valarm1 = VirtualMuxAlarm::new(&mux);
+        let valarm2 = VirtualMuxAlarm::new(&mux);
+        let client1 = Client::new(&valarm1);
+        let client2 = Client::new(&valarm2);
+        valarm1.set_client(&client1);
+        valarm2.set_client(&client2);
+
+        valarm1.set_alarm(tics(200));
+        valarm2.set_alarm(tics(100));
+        assert_eq!(fake.get_alarm(), tics(100));
+
+        fake.advance(100);
+        assert_eq!((client1.fired.get(), client2.fired.get()), (0, 1));
+        assert_eq!(fake.get_alarm(), tics(200));
+
+        fake.advance(100);
+        assert_eq!((client1.fired.get(), client2.fired.get()), (1, 1));
+        assert!(!fake.is_enabled());
+    }
+
+    #[test]
+    fn test_rejected_deadline_does_not_hang() {
+        let fake = FakeAlarm::new(0);
+        let mux = MuxAlarm::new(&fake);
+        fake.set_client(&mux);
+        let valarm = VirtualMuxAlarm::new(&mux);
+        let client = Client::new(&valarm);
+        valarm.set_client(&client);
+
+        fake.rejects.set(true);
+        valarm.set_alarm(tics(100));
+        // Tried once and retried once.
+        assert_eq!(fake.sets.get(), 2);
+        assert!(!fake.is_enabled());
+    }
+
+    #[test]
+    fn test_batch_programs_once() {
+        let fake = FakeAlarm::new(0);
+        let mux = MuxAlarm::new(&fake);
+        fake.set_client(&mux);
+        let valarm1 = VirtualMuxAlarm::new(&mux);
+        let valarm2 = VirtualMuxAlarm::new(&mux);
+        let valarm3 = VirtualMuxAlarm::new(&mux);
+        let client1 = Client::new(&valarm1);
+        let client2 = Client::new(&valarm2);
+        let client3 = Client::new(&valarm3);
+        valarm1.set_client(&client1);
+        valarm2.set_client(&client2);
+        valarm3.set_client(&client3);
+
+        mux.batch(|| {
+            valarm1.set_alarm(tics(300));
+            mux.batch(|| {
+                valarm2.set_alarm(tics(100));
+                valarm3.set_alarm(tics(200));
+            });
+            assert_eq!(fake.sets.get(), 0);
+        });
+        assert_eq!(fake.sets.get(), 1);
+        assert_eq!(fake.get_alarm(), tics(100));
+
+        fake.advance(100);
+        assert_eq!(client2.fired.get(), 1);
+        assert_eq!(fake.get_alarm(), tics(200));
+
+        // Disabling everything disables the underlying alarm at the end.
+        let sets = fake.sets.get();
+        mux.batch(|| {
+            valarm3.set_alarm(tics(150));
+            valarm1.disable();
+            valarm3.disable();
+        });
+        assert_eq!(fake.sets.get(), sets);
+        assert!(!fake.is_enabled());
+    }
+
+    #[test]
+    fn test_deadline_across_wrap() {
+        let fake = FakeAlarm::new(u32::max_value() - 10);
+        let mux = MuxAlarm::new(&fake);
+        fake.set_client(&mux);
+        let valarm = VirtualMuxAlarm::new(&mux);
+        let client = Client::new(&valarm);
+        valarm.set_client(&client);
+
+        valarm.set_alarm(tics(20));
+        fake.advance(20);
+        assert_eq!(client.fired.get(), 0);
+        fake.advance(11);
+        assert_eq!(client.fired.get(), 1);
+    }
+
+    #[test]
+    fn test_rearm_from_fired() {
+        let fake = FakeAlarm::new(0);
+        let mux = MuxAlarm::new(&fake);
+        fake.set_client(&mux);
+        let valarm = VirtualMuxAlarm::new(&mux);
+        let client = Client::new(&valarm);
+        valarm.set_client(&client);
+
+        client.rearm.set(Some(50));
+        valarm.set_alarm(tics(10));
+        fake.advance(10);
+        assert_eq!(client.fired.get(), 1);
+        assert!(valarm.is_enabled());
+        assert_eq!(fake.get_alarm(), tics(60));
+
+        fake.advance(50);
+        assert_eq!(client.fired.get(), 2);
+        assert!(!valarm.is_enabled());
+    }
+
+    #[test]
+    fn test_rearm_for_now_fires_once_per_pass() {
+        let fake = FakeAlarm::new(0);
+        let mux = MuxAlarm::new(&fake);
+        fake.set_client(&mux);
+        let valarm = VirtualMuxAlarm::new(&mux);
+        let client = Client::new(&valarm);
+        valarm.set_client(&client);
+
+        // The new deadline has already been reached when the callback
+        // returns, but it is left for the next pass rather than fired again.
+        client.rearm.set(Some(0));
+        valarm.set_alarm(tics(10));
+        fake.advance(10);
+        assert_eq!(client.fired.get(), 1);
+        assert!(valarm.is_enabled());
+        assert_eq!(fake.get_alarm(), tics(12));
+
+        fake.advance(2);
+        assert_eq!(client.fired.get(), 2);
+        assert!(!valarm.is_enabled());
+    }
+
+    #[test]
+    fn test_just_passed_deadline_fires_promptly() {
+        let fake = FakeAlarm::new(100);
+        let mux = MuxAlarm::new(&fake);
+        fake.set_client(&mux);
+        let valarm = VirtualMuxAlarm::new(&mux);
+        let client = Client::new(&valarm);
+        valarm.set_client(&client);
+
+        // Reached within `minimum_dt`, so not a full period away.
+        valarm.set_alarm(tics(99));
+        assert_eq!(fake.get_alarm(), tics(102));
+        fake.advance(2);
+        assert_eq!(client.fired.get(), 1);
+    }
+
+    #[test]
+    fn test_min_rearm_interval() {
+        let fake = FakeAlarm::new(0);
+        let mux = MuxAlarm::new(&fake);
+        fake.set_client(&mux);
+        let valarm = VirtualMuxAlarm::new(&mux);
+        let client = Client::new(&valarm);
+        valarm.set_client(&client);
+        let other = VirtualMuxAlarm::new(&mux);
+        let other_client = Client::new(&other);
+        other.set_client(&other_client);
+
+        valarm.set_min_rearm_interval(tics(20));
+        client.rearm.set(Some(1));
+        valarm.set_alarm(tics(10));
+        fake.advance(10);
+        assert_eq!(valarm.fires(), 1);
+        // Re-armed for 11 from the callback, deferred to 30.
+        assert_eq!(valarm.get_alarm(), tics(30));
+
+        // Other clients are not held back.
+        other.set_alarm(tics(15));
+        fake.advance(5);
+        assert_eq!(other_client.fired.get(), 1);
+        assert_eq!(other.fires(), 1);
+        assert_eq!(valarm.fires(), 1);
+
+        fake.advance(15);
+        assert_eq!(valarm.fires(), 2);
+
+        // Once the interval has passed deadlines are taken as they are.
+        fake.advance(20);
+        valarm.set_alarm(tics(51));
+        assert_eq!(valarm.get_alarm(), tics(51));
+    }
+
+    #[test]
+    fn test_single_client_fast_path() {
+        let fake = FakeAlarm::new(0);
+        let mux = MuxAlarm::new(&fake);
+        fake.set_client(&mux);
+        let valarm = VirtualMuxAlarm::new(&mux);
+        let client = Client::new(&valarm);
+        valarm.set_client(&client);
+        let other = VirtualMuxAlarm::new(&mux);
+        let other_client = Client::new(&other);
+        other.set_client(&other_client);
+
+        // One alarm armed: the fire reads the clock once and touches the
+        // hardware no further.
+        valarm.set_alarm(tics(10));
+        fake.ops.set(0);
+        fake.advance(10);
+        assert_eq!(client.fired.get(), 1);
+        assert_eq!(fake.ops.get(), 1);
+
+        // Re-arming from the callback programs the hardware once.
+        client.rearm.set(Some(10));
+        valarm.set_alarm(tics(30));
+        fake.ops.set(0);
+        fake.advance(20);
+        assert_eq!(client.fired.get(), 2);
+        assert_eq!(fake.get_alarm(), tics(40));
+        assert_eq!(fake.ops.get(), 7);
+
+        // A second armed alarm falls back to the general path.
+        other.set_alarm(tics(45));
+        fake.advance(10);
+        assert_eq!(client.fired.get(), 3);
+        assert_eq!(fake.get_alarm(), tics(45));
+        fake.advance(5);
+        assert_eq!(other_client.fired.get(), 1);
+        assert!(!fake.is_enabled());
+    }
+
+    #[test]
+    fn test_short_deadline_is_clamped() {
+        let fake = FakeAlarm::new(1000);
+        let mux = MuxAlarm::new(&fake);
+        fake.set_client(&mux);
+        let valarm = VirtualMuxAlarm::new(&mux);
+        let client = Client::new(&valarm);
+        valarm.set_client(&client);
+
+        valarm.set_alarm(tics(1001));
+        assert_eq!(fake.get_alarm(), tics(1002));
+        fake.advance(2);
+        assert_eq!(client.fired.get(), 1);
+    }
+
+    #[test]
+    fn test_passes_token_to_client() {
+        let fake = FakeAlarm::new(0);
+        let mux = MuxAlarm::new(&fake);
+        fake.set_client(&mux);
+        let valarm1 = VirtualMuxAlarm::new(&mux);
+        let valarm2 = VirtualMuxAlarm::new(&mux);
+        let client1 = Client::new(&valarm1);
+        let client2 = Client::new(&valarm2);
+        valarm1.set_client(&client1);
+        valarm2.set_client(&client2);
+
+        valarm1.set_alarm_with_token(tics(100), 7);
+        valarm2.set_alarm_with_token(tics(200), 9);
+
+        fake.advance(100);
+        assert_eq!((client1.token.get(), client2.token.get()), (7, 0));
+        fake.advance(100);
+        assert_eq!((client1.token.get(), client2.token.get()), (7, 9));
+
+        // A plain set_alarm carries the default token.
+        valarm1.set_alarm(tics(300));
+        fake.advance(100);
+        assert_eq!(client1.token.get(), 0);
+    }
+
+    #[test]
+    fn test_disable_leaves_clock_running() {
+        let fake = FakeAlarm::new(0);
+        let mux = MuxAlarm::new(&fake);
+        fake.set_client(&mux);
+        let valarm1 = VirtualMuxAlarm::new(&mux);
+        let valarm2 = VirtualMuxAlarm::new(&mux);
+        let client1 = Client::new(&valarm1);
+        let client2 = Client::new(&valarm2);
+        valarm1.set_client(&client1);
+        valarm2.set_client(&client2);
+
+        valarm1.set_alarm(tics(100));
+        valarm2.set_alarm(tics(200));
+        valarm1.disable();
+        fake.advance(50);
+        assert_eq!(valarm1.now(), tics(50));
+
+        // Disabling the last virtual alarm disables the underlying alarm,
+        // but not the clock.
+        valarm2.disable();
+        assert!(!fake.is_enabled());
+        fake.advance(50);
+        assert_eq!((valarm1.now(), valarm2.now()), (tics(100), tics(100)));
+        assert_eq!((client1.fired.get(), client2.fired.get()), (0, 0));
+    }
+
+    #[test]
+    fn test_mask_holds_fire_until_unmask() {
+        let fake = FakeAlarm::new(0);
+        let mux = MuxAlarm::new(&fake);
+        fake.set_client(&mux);
+        let valarm1 = VirtualMuxAlarm::new(&mux);
+        let valarm2 = VirtualMuxAlarm::new(&mux);
+        let client1 = Client::new(&valarm1);
+        let client2 = Client::new(&valarm2);
+        valarm1.set_client(&client1);
+        valarm2.set_client(&client2);
+
+        valarm1.set_alarm_with_token(tics(100), 7);
+        valarm2.set_alarm(tics(200));
+        assert_eq!(valarm1.mask(), ReturnCode::SUCCESS);
+        fake.advance(150);
+        assert_eq!(client1.fired.get(), 0);
+        assert!(valarm1.is_enabled());
+        assert_eq!(valarm1.get_alarm(), tics(100));
+
+        // Delivered from the next pass, with its token.
+        valarm1.unmask();
+        assert_eq!(client1.fired.get(), 0);
+        fake.advance(2);
+        assert_eq!((client1.fired.get(), client1.token.get()), (1, 7));
+        assert!(!valarm1.is_enabled());
+        fake.advance(50);
+        assert_eq!(client2.fired.get(), 1);
+
+        // Disabling drops a held fire.
+        valarm1.mask();
+        valarm1.set_alarm(tics(300));
+        fake.advance(100);
+        valarm1.disable();
+        valarm1.unmask();
+        fake.advance(100);
+        assert_eq!(client1.fired.get(), 1);
+    }
+
+    #[test]
+    fn test_capabilities_follow_underlying_alarm() {
+        let fake = FakeAlarm::new(0);
+        let mux = MuxAlarm::new(&fake);
+        let valarm = VirtualMuxAlarm::new(&mux);
+
+        let caps = valarm.capabilities();
+        assert_eq!(caps.frequency, 1000);
+        assert_eq!(caps.width_bits, 32);
+        assert_eq!(caps.min_alarm_dt, 2);
+        assert!(!caps.is_counter);
+        assert!(valarm.persists_in_sleep());
+    }
+
+    #[test]
+    fn test_orders_deadlines_across_24_bit_wrap() {
+        let fake = FakeAlarm::with_max(0xFF_FF00, 0xFF_FFFF);
+        let mux = MuxAlarm::new(&fake);
+        fake.set_client(&mux);
+        let valarm1 = VirtualMuxAlarm::new(&mux);
+        let valarm2 = VirtualMuxAlarm::new(&mux);
+        let client1 = Client::new(&valarm1);
+        let client2 = Client::new(&valarm2);
+        valarm1.set_client(&client1);
+        valarm2.set_client(&client2);
+
+        // Clients add to `now()` without masking, so both deadlines are past
+        // the 24-bit counter's range while the programmed value is not.
+        valarm1.set_alarm(valarm1.now().wrapping_add(tics(0x100)));
+        valarm2.set_alarm(valarm2.now().wrapping_add(tics(0x200)));
+        assert_eq!(fake.get_alarm(), tics(0x00));
+        assert_eq!(valarm2.get_alarm(), tics(0x100));
+
+        fake.advance(0x100);
+        assert_eq!((client1.fired.get(), client2.fired.get()), (1, 0));
+        assert_eq!(fake.get_alarm(), tics(0x100));
+
+        fake.advance(0x100);
+        assert_eq!((client1.fired.get(), client2.fired.get()), (1, 1));
+    }
+}
@@ -1,9 +1,16 @@
 //! RTC driver
 
+use core::cell::Cell;
+use cortexm4::support::atomic;
 use kernel::common::cells::OptionalCell;
 use kernel::common::registers::{register_bitfields, ReadOnly, ReadWrite};
 use kernel::common::StaticRef;
-use kernel::hil::time::{self, Alarm, Frequency, Time};
+use kernel::hil::time::{self, Alarm, FreqTicks, Frequency, Ticks, Time};
+use kernel::ReturnCode;
+
+/// Compare values are only evaluated on the 32KHz SCLK_LF edges, which are
+/// two `tics` apart, so leave room for a couple of them.
+const MIN_DT: u32 = 4;
 
 #[repr(C)]
 struct RtcRegisters {
@@ -63,6 +70,8 @@ const RTC_BASE: StaticRef<RtcRegisters> =
 pub struct Rtc<'a> {
     registers: StaticRef<RtcRegisters>,
     callback: OptionalCell<&'a dyn time::AlarmClient>,
+    /// Token passed to the most recent `set_alarm_with_token`.
+    token: Cell<u32>,
 }
 
 pub static mut RTC: Rtc<'static> = Rtc::new();
@@ -72,6 +81,7 @@ impl Rtc<'a> {
         Rtc {
             registers: RTC_BASE,
             callback: OptionalCell::empty(),
+            token: Cell::new(0),
         }
     }
 
@@ -129,7 +139,8 @@ impl Rtc<'a> {
 
         regs.sync.get();
 
-        self.callback.map(|cb| cb.fired());
+        self.callback
+            .map(|cb| cb.fired_with_token(self.token.get()));
     }
 
     pub fn set_upd_en(&self, value: bool) {
@@ -155,13 +166,15 @@ impl Frequency for RtcFreq {
 
 impl Time for Rtc<'a> {
     type Frequency = RtcFreq;
+    type Ticks = FreqTicks<RtcFreq>;
 
-    fn now(&self) -> u32 {
-        self.read_counter()
+    fn now(&self) -> Self::Ticks {
+        FreqTicks::new(self.read_counter())
     }
 
-    fn max_tics(&self) -> u32 {
-        core::u32::MAX
+    /// The RTC is in the always-on domain and keeps running in standby.
+    fn persists_in_sleep(&self) -> bool {
+        true
     }
 }
 
@@ -170,28 +183,71 @@ impl Alarm<'a> for Rtc<'a> {
         self.callback.set(client);
     }
 
-    fn set_alarm(&self, tics: u32) {
+    fn clear_client(&self) {
+        self.disable();
+        self.callback.clear();
+    }
+
+    fn set_alarm_with_token(&self, tics: Self::Ticks, token: u32) -> ReturnCode {
         let regs = &*self.registers;
+        let tics = tics.into_u32();
+
+        if !regs.ctl.is_set(Control::ENABLE) {
+            return ReturnCode::EOFF;
+        }
+        let now = self.read_counter();
+        // Reached at most `MIN_DT` tics ago: fire as soon as possible.
+        let tics = if time::reached_within(now, tics, MIN_DT, u32::max_value()) {
+            now.wrapping_add(MIN_DT)
+        } else {
+            tics
+        };
+        if tics.wrapping_sub(now) < MIN_DT {
+            return ReturnCode::EINVAL;
+        }
 
         regs.ctl.modify(Control::COMB_EV_MASK::Channel1);
-        regs.channel1_cmp.set(tics);
+        let mut now = now;
+        let mut compare = tics;
+        loop {
+            regs.channel1_cmp.set(compare);
+            let after = self.read_counter();
+            if !time::compare_missed(now, compare, after, MIN_DT, u32::max_value()) {
+                break;
+            }
+            // Passed while being written; fire as soon as possible instead
+            // of after a full wrap.
+            now = after;
+            compare = after.wrapping_add(MIN_DT);
+        }
         regs.channel_ctl.modify(ChannelControl::CH1_EN::SET);
 
         regs.sync.get();
+        self.token.set(token);
+        ReturnCode::SUCCESS
     }
 
-    fn get_alarm(&self) -> u32 {
+    fn get_alarm(&self) -> Self::Ticks {
         let regs = &*self.registers;
-        regs.channel1_cmp.get()
+        FreqTicks::new(regs.channel1_cmp.get())
+    }
+
+    fn now_and_alarm(&self) -> (Self::Ticks, Self::Ticks) {
+        unsafe { atomic(|| (self.now(), self.get_alarm())) }
+    }
+
+    fn minimum_dt(&self) -> Self::Ticks {
+        FreqTicks::new(MIN_DT)
     }
 
-    fn disable(&self) {
+    fn disable(&self) -> ReturnCode {
         let regs = &*self.registers;
 
         regs.ctl.modify(Control::COMB_EV_MASK::NoEvent);
         regs.channel_ctl.modify(ChannelControl::CH1_EN::CLEAR);
 
         regs.sync.get();
+        ReturnCode::SUCCESS
     }
 
     fn is_enabled(&self) -> bool {
@@ -1,14 +1,19 @@
 //! Timer driver.
 
+use core::cell::Cell;
 use kernel::common::cells::OptionalCell;
 use kernel::common::registers::{register_bitfields, register_structs, ReadWrite, WriteOnly};
 use kernel::common::StaticRef;
-use kernel::hil::time;
+use kernel::hil::time::{self, FreqTicks, Ticks};
+use kernel::ReturnCode;
 
 use crate::chip::CHIP_FREQ;
 
 const PRESCALE: u16 = ((CHIP_FREQ / 10_000) - 1) as u16; // 10Khz
 
+/// The minimum number of tics between the counter and a compare value.
+const MIN_DT: u32 = 1;
+
 /// 10KHz `Frequency`
 #[derive(Debug)]
 pub struct Freq10KHz;
@@ -55,6 +60,8 @@ register_bitfields![u32,
 pub struct RvTimer<'a> {
     registers: StaticRef<TimerRegisters>,
     client: OptionalCell<&'a dyn time::AlarmClient>,
+    /// Token passed to the most recent `set_alarm_with_token`.
+    token: Cell<u32>,
 }
 
 impl RvTimer<'a> {
@@ -62,6 +69,7 @@ impl RvTimer<'a> {
         RvTimer {
             registers: base,
             client: OptionalCell::empty(),
+            token: Cell::new(0),
         }
     }
 
@@ -81,19 +89,17 @@ impl RvTimer<'a> {
         regs.intr_enable.write(intr::timer0::CLEAR);
         regs.intr_state.write(intr::timer0::SET);
         self.client.map(|client| {
-            client.fired();
+            client.fired_with_token(self.token.get());
         });
     }
 }
 
 impl time::Time for RvTimer<'a> {
     type Frequency = Freq10KHz;
+    type Ticks = FreqTicks<Freq10KHz>;
 
-    fn now(&self) -> u32 {
-        self.registers.value_low.get()
-    }
-    fn max_tics(&self) -> u32 {
-        core::u32::MAX
+    fn now(&self) -> Self::Ticks {
+        FreqTicks::new(self.registers.value_low.get())
     }
 }
 
@@ -102,23 +108,54 @@ impl time::Alarm<'a> for RvTimer<'a> {
         self.client.set(client);
     }
 
-    fn set_alarm(&self, tics: u32) {
+    fn clear_client(&self) {
+        self.disable();
+        self.client.clear();
+    }
+
+    fn set_alarm_with_token(&self, tics: Self::Ticks, token: u32) -> ReturnCode {
         let regs = self.registers;
+        let tics = tics.into_u32();
+
+        if !regs.ctrl.is_set(ctrl::enable) {
+            return ReturnCode::EOFF;
+        }
+        let now = regs.value_low.get();
+        // Reached at most `MIN_DT` tics ago: fire as soon as possible.
+        let tics = if time::reached_within(now, tics, MIN_DT, u32::max_value()) {
+            now.wrapping_add(MIN_DT)
+        } else {
+            tics
+        };
+        if tics.wrapping_sub(now) < MIN_DT {
+            return ReturnCode::EINVAL;
+        }
 
         // Make sure that any overlow into the high bits of the timer (which we are ignoring for
         // now) do not have an effect on the alarm.
         regs.value_high.set(0);
 
+        // The comparator matches while the 64-bit value is `>=` the compare value, so a `tics`
+        // numerically below the counter would fire at once. Put it in the next period of the low
+        // word instead, where the counter reaches it after wrapping.
+        regs.compare_high.set(if tics < now { 1 } else { 0 });
         regs.compare_low.set(tics);
         regs.intr_enable.write(intr::timer0::SET);
+        self.token.set(token);
+        ReturnCode::SUCCESS
+    }
+
+    fn get_alarm(&self) -> Self::Ticks {
+        FreqTicks::new(self.registers.compare_low.get())
     }
 
-    fn get_alarm(&self) -> u32 {
-        self.registers.compare_low.get()
+    fn minimum_dt(&self) -> Self::Ticks {
+        FreqTicks::new(MIN_DT)
     }
 
-    fn disable(&self) {
+    fn disable(&self) -> ReturnCode {
         self.registers.intr_enable.write(intr::timer0::CLEAR);
+        ReturnCode::SUCCESS
     }
 
     fn is_enabled(&self) -> bool {
@@ -1,10 +1,16 @@
 //! RTC driver, nRF5X-family
 
+use core::cell::Cell;
 use kernel::common::cells::OptionalCell;
 use kernel::common::registers::{register_bitfields, ReadOnly, ReadWrite, WriteOnly};
 use kernel::common::StaticRef;
-use kernel::hil::time::{self, Alarm, Freq32KHz, Time};
+use kernel::hil::time::{self, Alarm, Freq32KHz, FreqTicks, Ticks, Time};
 use kernel::hil::Controller;
+use kernel::ReturnCode;
+
+/// The COMPARE event is not guaranteed to be generated if CC is set to less
+/// than two tics after the current counter value.
+const MIN_DT: u32 = 2;
 
 const RTC1_BASE: StaticRef<RtcRegisters> =
     unsafe { StaticRef::new(0x40011000 as *const RtcRegisters) };
@@ -85,11 +91,17 @@ register_bitfields![u32,
 pub struct Rtc<'a> {
     registers: StaticRef<RtcRegisters>,
     callback: OptionalCell<&'a dyn time::AlarmClient>,
+    /// Token passed to the most recent `set_alarm_with_token`.
+    token: Cell<u32>,
+    /// Whether the counter was started, which the RTC cannot report.
+    running: Cell<bool>,
 }
 
 pub static mut RTC: Rtc = Rtc {
     registers: RTC1_BASE,
     callback: OptionalCell::empty(),
+    token: Cell::new(0),
+    running: Cell::new(false),
 };
 
 impl Controller for Rtc<'a> {
@@ -111,35 +123,45 @@ impl Rtc<'a> {
         // So it should only be called during initialization, not each tick
         self.registers.prescaler.write(Prescaler::PRESCALER.val(0));
         self.registers.tasks_start.write(Task::ENABLE::SET);
+        self.running.set(true);
     }
 
     pub fn stop(&self) {
         self.registers.cc[0].write(Counter::VALUE.val(0));
         self.registers.tasks_stop.write(Task::ENABLE::SET);
+        self.running.set(false);
     }
 
     fn is_running(&self) -> bool {
         self.registers.evten.is_set(Inte::COMPARE0)
     }
 
+    fn counter(&self) -> u32 {
+        self.registers.counter.read(Counter::VALUE)
+    }
+
     pub fn handle_interrupt(&self) {
         self.registers.events_compare[0].write(Event::READY::CLEAR);
         self.registers.intenclr.write(Inte::COMPARE0::SET);
         self.callback.map(|cb| {
-            cb.fired();
+            cb.fired_with_token(self.token.get());
         });
     }
 }
 
 impl Time for Rtc<'a> {
     type Frequency = Freq32KHz;
+    type Ticks = FreqTicks<Freq32KHz>;
+    const WIDTH: u32 = 24;
 
-    fn now(&self) -> u32 {
-        self.registers.counter.read(Counter::VALUE)
+    fn now(&self) -> Self::Ticks {
+        FreqTicks::new(self.counter())
     }
 
-    fn max_tics(&self) -> u32 {
-        (1 << 24) - 1
+    /// The RTC runs from the low-frequency clock, which keeps running in
+    /// System ON sleep.
+    fn persists_in_sleep(&self) -> bool {
+        true
     }
 }
 
@@ -148,21 +170,64 @@ impl Alarm<'a> for Rtc<'a> {
         self.callback.set(client);
     }
 
-    fn set_alarm(&self, tics: u32) {
+    fn clear_client(&self) {
+        self.disable();
+        self.callback.clear();
+    }
+
+    fn set_alarm_with_token(&self, tics: Self::Ticks, token: u32) -> ReturnCode {
+        if !self.running.get() {
+            return ReturnCode::EOFF;
+        }
+        let now = self.counter();
+        let tics = tics.into_u32();
+        let max = self.max_tics().into_u32();
+        // Reached at most `MIN_DT` tics ago: fire as soon as possible.
+        let tics = if time::reached_within(now, tics, MIN_DT, max) {
+            now.wrapping_add(MIN_DT)
+        } else {
+            tics
+        };
+        // The counter is 24 bits wide, so compute the distance to the alarm
+        // modulo 2^24.
+        if tics.wrapping_sub(now) & max < MIN_DT {
+            return ReturnCode::EINVAL;
+        }
+
         // Similarly to the disable function, here we don't restart the timer
         // Instead, we just listen for it again
         self.registers.intenset.write(Inte::COMPARE0::SET);
-        self.registers.cc[0].write(Counter::VALUE.val(tics));
+        let mut now = now;
+        let mut compare = tics & max;
+        loop {
+            self.registers.cc[0].write(Counter::VALUE.val(compare));
+            let after = self.counter();
+            if !time::compare_missed(now, compare, after, MIN_DT, max) {
+                break;
+            }
+            // The counter got past the compare value while it was being
+            // written and would only match it after a full wrap. Fire as
+            // soon as possible instead.
+            now = after;
+            compare = after.wrapping_add(MIN_DT) & max;
+        }
         self.registers.events_compare[0].write(Event::READY::CLEAR);
+        self.token.set(token);
+        ReturnCode::SUCCESS
+    }
+
+    fn get_alarm(&self) -> Self::Ticks {
+        FreqTicks::new(self.registers.cc[0].read(Counter::VALUE))
     }
 
-    fn get_alarm(&self) -> u32 {
-        self.registers.cc[0].read(Counter::VALUE)
+    fn minimum_dt(&self) -> Self::Ticks {
+        FreqTicks::new(MIN_DT)
     }
 
-    fn disable(&self) {
+    fn disable(&self) -> ReturnCode {
         self.registers.intenclr.write(Inte::COMPARE0::SET);
         self.registers.events_compare[0].write(Event::READY::CLEAR);
+        ReturnCode::SUCCESS
     }
 
     fn is_enabled(&self) -> bool {
@@ -22,10 +22,13 @@
 //! * Philip Levis <pal@cs.stanford.edu>
 //! * Date: August 18, 2016
 
+use core::cell::Cell;
 use kernel::common::cells::OptionalCell;
 use kernel::common::registers::{self, register_bitfields, ReadWrite, WriteOnly};
 use kernel::common::StaticRef;
 use kernel::hil;
+use kernel::hil::time::{FreqTicks, Ticks};
+use kernel::ReturnCode;
 
 const INSTANCES: [StaticRef<TimerRegisters>; 3] = unsafe {
     [
@@ -261,6 +264,10 @@ impl Timer {
 pub struct TimerAlarm<'a> {
     registers: StaticRef<TimerRegisters>,
     client: OptionalCell<&'a dyn hil::time::AlarmClient>,
+    /// Token passed to the most recent `set_alarm_with_token`.
+    token: Cell<u32>,
+    /// Whether the timer was started, which the timer cannot report.
+    running: Cell<bool>,
 }
 
 // CC0 is used for capture
@@ -269,26 +276,47 @@ const ALARM_CAPTURE: usize = 0;
 const ALARM_COMPARE: usize = 1;
 const ALARM_INTERRUPT_BIT: registers::Field<u32, Inte::Register> = Inte::COMPARE1;
 const ALARM_INTERRUPT_BIT_SET: registers::FieldValue<u32, Inte::Register> = Inte::COMPARE1::SET;
+/// The minimum number of tics between the counter and a compare value.
+const MIN_DT: u32 = 1;
 
 impl TimerAlarm<'a> {
     const fn new(instance: usize) -> TimerAlarm<'a> {
         TimerAlarm {
             registers: INSTANCES[instance],
             client: OptionalCell::empty(),
+            token: Cell::new(0),
+            running: Cell::new(false),
         }
     }
 
+    /// Starts the timer counting from `0` in 32-bit mode. Alarms can only be
+    /// set while it is running. Does nothing if it is already running.
+    pub fn start(&self) {
+        if self.running.get() {
+            return;
+        }
+        self.registers.bitmode.write(Bitmode::BITMODE::Bit32);
+        self.registers.tasks_clear.write(Task::ENABLE::SET);
+        self.registers.tasks_start.write(Task::ENABLE::SET);
+        self.running.set(true);
+    }
+
+    /// Stops the timer and disables the alarm.
+    pub fn stop(&self) {
+        self.clear_alarm();
+        self.registers.tasks_stop.write(Task::ENABLE::SET);
+        self.running.set(false);
+    }
+
     fn clear_alarm(&self) {
         self.registers.events_compare[ALARM_COMPARE].write(Event::READY::CLEAR);
-        self.registers.tasks_stop.write(Task::ENABLE::SET);
-        self.registers.tasks_clear.write(Task::ENABLE::SET);
         self.disable_interrupts();
     }
 
     pub fn handle_interrupt(&self) {
         self.clear_alarm();
         self.client.map(|client| {
-            client.fired();
+            client.fired_with_token(self.token.get());
         });
     }
 
@@ -315,13 +343,10 @@ impl TimerAlarm<'a> {
 
 impl hil::time::Time for TimerAlarm<'a> {
     type Frequency = hil::time::Freq16KHz;
+    type Ticks = FreqTicks<hil::time::Freq16KHz>;
 
-    fn now(&self) -> u32 {
-        self.value()
-    }
-
-    fn max_tics(&self) -> u32 {
-        core::u32::MAX
+    fn now(&self) -> Self::Ticks {
+        FreqTicks::new(self.value())
     }
 }
 
@@ -330,23 +355,60 @@ impl hil::time::Alarm<'a> for TimerAlarm<'a> {
         self.client.set(client);
     }
 
-    fn disable(&self) {
+    fn clear_client(&self) {
+        self.disable();
+        self.client.clear();
+    }
+
+    fn disable(&self) -> ReturnCode {
         self.disable_interrupts();
+        ReturnCode::SUCCESS
     }
 
     fn is_enabled(&self) -> bool {
         self.interrupts_enabled()
     }
 
-    fn set_alarm(&self, tics: u32) {
+    fn set_alarm_with_token(&self, tics: Self::Ticks, token: u32) -> ReturnCode {
+        if !self.running.get() {
+            return ReturnCode::EOFF;
+        }
+        let now = self.value();
+        let tics = tics.into_u32();
+        // Reached at most `MIN_DT` tics ago: fire as soon as possible.
+        let tics = if hil::time::reached_within(now, tics, MIN_DT, u32::max_value()) {
+            now.wrapping_add(MIN_DT)
+        } else {
+            tics
+        };
+        if tics.wrapping_sub(now) < MIN_DT {
+            return ReturnCode::EINVAL;
+        }
+
         self.disable_interrupts();
-        self.registers.bitmode.write(Bitmode::BITMODE::Bit32);
-        self.registers.cc[ALARM_COMPARE].write(CC::CC.val(tics));
-        self.registers.tasks_start.write(Task::ENABLE::SET);
+        let mut now = now;
+        let mut compare = tics;
+        loop {
+            self.registers.cc[ALARM_COMPARE].write(CC::CC.val(compare));
+            let after = self.value();
+            if !hil::time::compare_missed(now, compare, after, MIN_DT, u32::max_value()) {
+                break;
+            }
+            // Passed while being written; fire as soon as possible instead
+            // of after a full wrap.
+            now = after;
+            compare = after.wrapping_add(MIN_DT);
+        }
         self.enable_interrupts();
+        self.token.set(token);
+        ReturnCode::SUCCESS
+    }
+
+    fn get_alarm(&self) -> Self::Ticks {
+        FreqTicks::new(self.registers.cc[ALARM_COMPARE].read(CC::CC))
     }
 
-    fn get_alarm(&self) -> u32 {
-        self.registers.cc[ALARM_COMPARE].read(CC::CC)
+    fn minimum_dt(&self) -> Self::Ticks {
+        FreqTicks::new(MIN_DT)
     }
 }
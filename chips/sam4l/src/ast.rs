@@ -5,11 +5,16 @@
 //! - Date: July 16, 2015
 
 use crate::pm::{self, PBDClock};
+use core::cell::Cell;
+use cortexm4::support::atomic;
 use kernel::common::cells::OptionalCell;
 use kernel::common::registers::{register_bitfields, ReadOnly, ReadWrite, WriteOnly};
 use kernel::common::StaticRef;
-use kernel::hil::time::{self, Alarm, Freq16KHz, Time};
+use kernel::hil::time::{
+    self, Alarm, Counter, Freq16KHz, FreqTicks, Frequency, Ticks, Time, TimeCapabilities,
+};
 use kernel::hil::Controller;
+use kernel::ReturnCode;
 
 /// Minimum number of clock tics to make sure ALARM0 register is synchronized
 ///
@@ -167,11 +172,25 @@ const AST_ADDRESS: StaticRef<AstRegisters> =
 pub struct Ast<'a> {
     registers: StaticRef<AstRegisters>,
     callback: OptionalCell<&'a dyn time::AlarmClient>,
+    /// Token passed to the most recent `set_alarm_with_token`.
+    token: Cell<u32>,
+    /// Deadline passed to the most recent `set_alarm_with_token`, which AR0
+    /// is programmed ahead of by up to `latency` tics.
+    deadline: Cell<u32>,
+    latency: Cell<u32>,
+    /// Number of overflows signaled, the high word for `overflow_at`.
+    overflows: Cell<u32>,
+    overflow_client: OptionalCell<&'a dyn time::OverflowClient>,
 }
 
 pub static mut AST: Ast<'static> = Ast {
     registers: AST_ADDRESS,
     callback: OptionalCell::empty(),
+    token: Cell::new(0),
+    deadline: Cell::new(0),
+    latency: Cell::new(0),
+    overflows: Cell::new(0),
+    overflow_client: OptionalCell::empty(),
 };
 
 impl Controller for Ast<'a> {
@@ -187,6 +206,8 @@ impl Controller for Ast<'a> {
         self.set_prescalar(0); // 32KHz / (2^(0 + 1)) = 16KHz
         self.enable_alarm_wake();
         self.clear_alarm();
+        // Alarms do not start the counter, so start it here.
+        self.enable();
     }
 }
 
@@ -253,13 +274,6 @@ impl Ast<'a> {
         while self.busy() {}
     }
 
-    /// Returns if an alarm is currently set
-    fn is_alarm_enabled(&self) -> bool {
-        let regs: &AstRegisters = &*self.registers;
-        while self.busy() {}
-        regs.sr.is_set(Status::ALARM0)
-    }
-
     fn set_prescalar(&self, val: u8) {
         let regs: &AstRegisters = &*self.registers;
         while self.busy() {}
@@ -277,6 +291,34 @@ impl Ast<'a> {
         regs.idr.write(Interrupt::ALARM0::SET);
     }
 
+    fn is_alarm_irq_enabled(&self) -> bool {
+        let regs: &AstRegisters = &*self.registers;
+        regs.imr.is_set(Interrupt::ALARM0)
+    }
+
+    fn enable_overflow_irq(&self) {
+        let regs: &AstRegisters = &*self.registers;
+        regs.ier.write(Interrupt::OVF::SET);
+    }
+
+    fn disable_overflow_irq(&self) {
+        let regs: &AstRegisters = &*self.registers;
+        regs.idr.write(Interrupt::OVF::SET);
+    }
+
+    fn is_overflow_irq_enabled(&self) -> bool {
+        let regs: &AstRegisters = &*self.registers;
+        regs.imr.is_set(Interrupt::OVF)
+    }
+
+    /// Clears the overflow bit in the status register.
+    fn clear_overflow(&self) {
+        let regs: &AstRegisters = &*self.registers;
+        while self.busy() {}
+        regs.scr.write(Interrupt::OVF::SET);
+        while self.busy() {}
+    }
+
     fn enable_alarm_wake(&self) {
         let regs: &AstRegisters = &*self.registers;
         while self.busy() {}
@@ -284,6 +326,22 @@ impl Ast<'a> {
         while self.busy() {}
     }
 
+    fn set_counter(&self, val: u32) {
+        let regs: &AstRegisters = &*self.registers;
+        while self.busy() {}
+        regs.cv.write(Value::VALUE.val(val));
+        while self.busy() {}
+    }
+
+    /// Clears the prescaler, so that the first tic after a reset is a full
+    /// period long.
+    fn clear_prescalar(&self) {
+        let regs: &AstRegisters = &*self.registers;
+        while self.busy() {}
+        regs.cr.modify(Control::PCLR::SET);
+        while self.busy() {}
+    }
+
     fn get_counter(&self) -> u32 {
         let regs: &AstRegisters = &*self.registers;
         while self.busy() {}
@@ -291,22 +349,55 @@ impl Ast<'a> {
     }
 
     pub fn handle_interrupt(&mut self) {
-        self.clear_alarm();
-        self.callback.map(|cb| {
-            cb.fired();
-        });
+        let regs: &AstRegisters = &*self.registers;
+        if regs.sr.is_set(Status::OVF) {
+            self.clear_overflow();
+            self.overflows.set(self.overflows.get().wrapping_add(1));
+            self.overflow_client.map(|client| {
+                client.overflow_at(self.overflows.get());
+            });
+        }
+        if regs.sr.is_set(Status::ALARM0) {
+            self.clear_alarm();
+            // ALARM0 is set on every compare match, also while the alarm is
+            // disabled, so it may be set when the interrupt is for OVF.
+            if self.is_alarm_irq_enabled() {
+                self.callback.map(|cb| {
+                    cb.fired_with_token(self.token.get());
+                });
+            }
+        }
     }
 }
 
 impl Time for Ast<'a> {
     type Frequency = Freq16KHz;
+    type Ticks = FreqTicks<Freq16KHz>;
+
+    fn now(&self) -> Self::Ticks {
+        FreqTicks::new(self.get_counter())
+    }
+
+    /// `Freq16KHz` with the default prescaler, and a power of two slower for
+    /// each doubling of the divisor set with `Counter::set_prescaler`.
+    fn frequency(&self) -> u32 {
+        let regs: &AstRegisters = &*self.registers;
+        Self::Frequency::frequency() >> regs.cr.read(Control::PSEL)
+    }
 
-    fn now(&self) -> u32 {
-        self.get_counter()
+    /// The AST is in the backup domain and keeps running in the sleep modes
+    /// the kernel uses.
+    fn persists_in_sleep(&self) -> bool {
+        true
     }
 
-    fn max_tics(&self) -> u32 {
-        core::u32::MAX
+    fn capabilities(&self) -> TimeCapabilities {
+        TimeCapabilities {
+            frequency: self.frequency(),
+            width_bits: Self::WIDTH,
+            min_alarm_dt: ALARM0_SYNC_TICS,
+            is_counter: true,
+        }
     }
 }
 
@@ -315,37 +406,249 @@ impl Alarm<'a> for Ast<'a> {
         self.callback.set(client);
     }
 
-    fn set_alarm(&self, mut tics: u32) {
+    fn clear_client(&self) {
+        // Not the inherent `disable`, which stops the whole counter.
+        Alarm::disable(self);
+        self.callback.clear();
+    }
+
+    fn set_alarm_with_token(&self, tics: Self::Ticks, token: u32) -> ReturnCode {
         let regs: &AstRegisters = &*self.registers;
+        let tics = tics.into_u32();
+        if !regs.cr.is_set(Control::EN) {
+            return ReturnCode::EOFF;
+        }
         let now = self.get_counter();
-        if tics.wrapping_sub(now) <= ALARM0_SYNC_TICS {
-            tics = now.wrapping_add(ALARM0_SYNC_TICS);
+        // Reached at most `ALARM0_SYNC_TICS` tics ago: fire as soon as possible.
+        let tics = if time::reached_within(now, tics, ALARM0_SYNC_TICS, u32::max_value()) {
+            now.wrapping_add(ALARM0_SYNC_TICS)
+        } else {
+            tics
+        };
+        if tics.wrapping_sub(now) < ALARM0_SYNC_TICS {
+            return ReturnCode::EINVAL;
         }
 
         // Clear any alarm event that may be pending before setting the new alarm.
         self.clear_alarm();
 
-        while self.busy() {}
-        regs.ar0.write(Value::VALUE.val(tics));
-        while self.busy() {}
+        let mut now = now;
+        let mut compare = time::compensate_latency(now, tics, self.latency.get(), ALARM0_SYNC_TICS);
+        loop {
+            while self.busy() {}
+            regs.ar0.write(Value::VALUE.val(compare));
+            while self.busy() {}
+            let after = self.get_counter();
+            if !time::compare_missed(now, compare, after, ALARM0_SYNC_TICS, u32::max_value()) {
+                break;
+            }
+            // The counter got past the compare value while it was being
+            // written, e.g. because of an interrupt, and would only match
+            // it after a full wrap. Fire as soon as possible instead.
+            now = after;
+            compare = after.wrapping_add(ALARM0_SYNC_TICS);
+        }
         self.enable_alarm_irq();
-        self.enable();
+        self.deadline.set(tics);
+        self.token.set(token);
+        ReturnCode::SUCCESS
     }
 
-    fn get_alarm(&self) -> u32 {
-        let regs: &AstRegisters = &*self.registers;
-        while self.busy() {}
-        regs.ar0.read(Value::VALUE)
+    fn get_alarm(&self) -> Self::Ticks {
+        FreqTicks::new(self.deadline.get())
     }
 
-    fn disable(&self) {
+    fn now_and_alarm(&self) -> (Self::Ticks, Self::Ticks) {
+        unsafe { atomic(|| (self.now(), self.get_alarm())) }
+    }
+
+    fn minimum_dt(&self) -> Self::Ticks {
+        FreqTicks::new(ALARM0_SYNC_TICS)
+    }
+
+    fn set_latency_compensation(&self, tics: u32) -> ReturnCode {
+        self.latency.set(tics);
+        ReturnCode::SUCCESS
+    }
+
+    fn disable(&self) -> ReturnCode {
         // After disable the IRQ and clearing the alarmn bit in the status register, the NVIC bit
         // is also guaranteed to be clear.
         self.disable_alarm_irq();
         self.clear_alarm();
+        ReturnCode::SUCCESS
     }
 
     fn is_enabled(&self) -> bool {
-        self.is_alarm_enabled()
+        // The SR ALARM0 bit only records compare matches, which happen
+        // whether or not an alarm is set, so check the interrupt mask that
+        // `handle_interrupt` also goes by.
+        self.is_alarm_irq_enabled()
+    }
+}
+
+impl Counter<'a> for Ast<'a> {
+    fn set_overflow_client(&self, client: &'a dyn time::OverflowClient) {
+        self.overflow_client.set(client);
+        self.clear_overflow();
+        self.enable_overflow_irq();
+    }
+
+    fn start(&self) -> ReturnCode {
+        self.enable();
+        ReturnCode::SUCCESS
+    }
+
+    fn stop(&self) -> ReturnCode {
+        self.disable();
+        ReturnCode::SUCCESS
+    }
+
+    fn is_running(&self) -> bool {
+        let regs: &AstRegisters = &*self.registers;
+        regs.cr.is_set(Control::EN)
+    }
+
+    fn reset(&self) -> ReturnCode {
+        // Stop the counter so that it cannot tic between writing CV and
+        // clearing the prescaler.
+        let running = self.is_running();
+        self.disable();
+        self.set_counter(0);
+        self.clear_prescalar();
+        if running {
+            self.enable();
+        }
+        ReturnCode::SUCCESS
+    }
+
+    /// The AST divides its 32 kHz input clock by `2^(PSEL + 1)`, so `div`
+    /// must be a power of two from 2, the default, to 2^31.
+    fn set_prescaler(&self, div: u32) -> ReturnCode {
+        if div < 2 || !div.is_power_of_two() {
+            return ReturnCode::EINVAL;
+        }
+        if self.is_alarm_irq_enabled() {
+            return ReturnCode::EBUSY;
+        }
+        self.set_prescalar((div.trailing_zeros() - 1) as u8);
+        ReturnCode::SUCCESS
+    }
+
+    fn overflow_pending(&self) -> Option<bool> {
+        let regs: &AstRegisters = &*self.registers;
+        Some(regs.sr.is_set(Status::OVF))
+    }
+
+    fn with_frozen<R>(&self, f: impl FnOnce(u32) -> R) -> R {
+        let enabled = self.is_overflow_irq_enabled();
+        self.disable_overflow_irq();
+        let result = f(self.get_counter());
+        if enabled {
+            // An overflow during `f` left OVF set in the status register, so
+            // the interrupt is taken as soon as it is unmasked.
+            self.enable_overflow_irq();
+        }
+        result
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// An `Ast` whose registers are in `registers` instead of the AST.
+    fn ast(registers: &mut [u32; 22]) -> Ast<'a> {
+        assert_eq!(
+            core::mem::size_of::<AstRegisters>(),
+            core::mem::size_of_val(registers)
+        );
+        Ast {
+            registers: unsafe { StaticRef::new(registers.as_mut_ptr() as *const AstRegisters) },
+            callback: OptionalCell::empty(),
+            token: Cell::new(0),
+            deadline: Cell::new(0),
+            latency: Cell::new(0),
+            overflows: Cell::new(0),
+            overflow_client: OptionalCell::empty(),
+        }
+    }
+
+    #[test]
+    fn test_set_alarm_just_passed() {
+        let mut registers = [0; 22];
+        let ast = ast(&mut registers);
+        Counter::start(&ast);
+        assert_eq!(
+            ast.set_alarm(FreqTicks::new(u32::max_value())),
+            ReturnCode::SUCCESS
+        );
+        assert_eq!(ast.get_alarm(), FreqTicks::new(ALARM0_SYNC_TICS));
+    }
+
+    #[test]
+    fn test_set_alarm_does_not_start_counter() {
+        let mut registers = [0; 22];
+        let ast = ast(&mut registers);
+        assert_eq!(ast.set_alarm(FreqTicks::new(100)), ReturnCode::EOFF);
+        assert!(!ast.is_running());
+
+        Counter::start(&ast);
+        assert_eq!(ast.set_alarm(FreqTicks::new(100)), ReturnCode::SUCCESS);
+        assert_eq!(ast.get_alarm(), FreqTicks::new(100));
+    }
+
+    #[test]
+    fn test_clear_client_keeps_counter_running() {
+        let mut registers = [0; 22];
+        let ast = ast(&mut registers);
+        Counter::start(&ast);
+        assert!(ast.is_running());
+
+        ast.clear_client();
+        assert!(ast.is_running());
+    }
+
+    struct Client {
+        fired: Cell<usize>,
+    }
+
+    impl time::AlarmClient for Client {
+        fn fired(&self) {
+            self.fired.set(self.fired.get() + 1);
+        }
+    }
+
+    #[test]
+    fn test_overflow_does_not_fire_disabled_alarm() {
+        const OVF: u32 = 1;
+        const ALARM0: u32 = 1 << 8;
+        let mut registers = [0; 22];
+        let client = Client {
+            fired: Cell::new(0),
+        };
+        // SR and IMR, as the hardware would set them: the counter matched
+        // AR0 while only the overflow interrupt was enabled.
+        registers[2] = OVF | ALARM0;
+        registers[6] = OVF;
+        let mut ast = ast(&mut registers);
+        ast.callback.set(&client);
+
+        ast.handle_interrupt();
+        assert_eq!(client.fired.get(), 0);
+    }
+
+    #[test]
+    fn test_is_enabled_follows_interrupt_mask() {
+        const ALARM0: u32 = 1 << 8;
+        // A compare match in SR with the alarm interrupt masked.
+        let mut registers = [0; 22];
+        registers[2] = ALARM0;
+        assert!(!ast(&mut registers).is_enabled());
+
+        // The alarm interrupt enabled in IMR, before any match.
+        let mut registers = [0; 22];
+        registers[6] = ALARM0;
+        assert!(ast(&mut registers).is_enabled());
     }
 }
@@ -3,10 +3,18 @@
 use crate::pm::{self, Clock, PBDClock};
 use core::cell::Cell;
 use cortexm4::support;
-use kernel::common::math::log_base_two_u64;
+use kernel::common::math::log_base_two;
 use kernel::common::registers::{register_bitfields, FieldValue, ReadOnly, ReadWrite, WriteOnly};
 use kernel::common::StaticRef;
 use kernel::hil;
+use kernel::hil::time::{Freq32KHz, Frequency};
+use kernel::ReturnCode;
+
+kernel::frequency!(
+    /// The 115 kHz RCSYS oscillator.
+    Freq115KHz,
+    115_000
+);
 
 #[repr(C)]
 pub struct WdtRegisters {
@@ -170,7 +178,10 @@ impl Wdt {
         }
     }
 
-    fn start(&self, period: usize) {
+    fn start(&self, period: u32) -> ReturnCode {
+        if period == 0 {
+            return ReturnCode::EINVAL;
+        }
         self.enabled.set(true);
 
         pm::enable_clock(Clock::PBD(PBDClock::WDT));
@@ -187,15 +198,14 @@ impl Wdt {
         //
         // T_timeout = T_psel = 2^(PSEL + 1) / f_wdt_clk
         //
-        // Period is in ms so use freq in khz for easy integer math
-        let f_clk_khz: u64 = if WDT_REGS.cr.matches_all(Control::CSSEL::RCSYS) {
-            115
+        // The conversion saturates at u32::MAX tics, so the scaler always
+        // fits in the 5-bit PSEL field.
+        let tics = if WDT_REGS.cr.matches_all(Control::CSSEL::RCSYS) {
+            Freq115KHz::ms_to_tics(period)
         } else {
-            // OSC32K
-            32
+            Freq32KHz::ms_to_tics(period)
         };
-        let mult: u64 = f_clk_khz * (period as u64);
-        let scaler = log_base_two_u64(mult); // prefer rounding for longer WD (thus no -1)
+        let scaler = log_base_two(tics); // prefer rounding for longer WD (thus no -1)
 
         let control = Control::CEN::ClockEnable
             + Control::PSEL.val(scaler)
@@ -203,6 +213,7 @@ impl Wdt {
             + Control::DAR::DisableAfterReset
             + Control::EN::Enable;
         self.write_cr(control);
+        ReturnCode::SUCCESS
     }
 
     fn stop(&self) {
@@ -221,12 +232,13 @@ impl Wdt {
 }
 
 impl hil::watchdog::Watchdog for Wdt {
-    fn start(&self, period: usize) {
-        self.start(period);
+    fn start(&self, period_ms: u32) -> ReturnCode {
+        self.start(period_ms)
     }
 
-    fn stop(&self) {
+    fn stop(&self) -> ReturnCode {
         self.stop();
+        ReturnCode::SUCCESS
     }
 
     fn tickle(&self) {
@@ -1,14 +1,20 @@
+use core::cell::Cell;
 use cortexm4;
 use cortexm4::support::atomic;
 use kernel::common::cells::OptionalCell;
 use kernel::common::registers::{register_bitfields, ReadWrite, WriteOnly};
 use kernel::common::StaticRef;
 use kernel::hil;
+use kernel::hil::time::{Freq16KHz, FreqTicks, Ticks};
 use kernel::ClockInterface;
+use kernel::ReturnCode;
 
 use crate::nvic;
 use crate::rcc;
 
+/// The minimum number of tics between the counter and a compare value.
+const MIN_DT: u32 = 1;
+
 /// General purpose timers
 #[repr(C)]
 struct Tim2Registers {
@@ -308,6 +314,8 @@ pub struct Tim2<'a> {
     registers: StaticRef<Tim2Registers>,
     clock: Tim2Clock,
     client: OptionalCell<&'a dyn hil::time::AlarmClient>,
+    /// Token passed to the most recent `set_alarm_with_token`.
+    token: Cell<u32>,
     irqn: u32,
 }
 
@@ -319,6 +327,7 @@ impl Tim2<'a> {
             registers: TIM2_BASE,
             clock: Tim2Clock(rcc::PeripheralClock::APB1(rcc::PCLK1::TIM2)),
             client: OptionalCell::empty(),
+            token: Cell::new(0),
             irqn: nvic::TIM2,
         }
     }
@@ -338,7 +347,8 @@ impl Tim2<'a> {
     pub fn handle_interrupt(&self) {
         self.registers.sr.modify(SR::CC1IF::CLEAR);
 
-        self.client.map(|client| client.fired());
+        self.client
+            .map(|client| client.fired_with_token(self.token.get()));
     }
 
     // starts the timer
@@ -361,16 +371,65 @@ impl hil::time::Alarm<'a> for Tim2<'a> {
         self.client.set(client);
     }
 
-    fn set_alarm(&self, tics: u32) {
-        self.registers.ccr1.set(tics);
+    fn clear_client(&self) {
+        self.disable();
+        self.client.clear();
+    }
+
+    fn set_alarm_with_token(&self, tics: Self::Ticks, token: u32) -> ReturnCode {
+        let tics = tics.into_u32();
+        if !self.registers.cr1.is_set(CR1::CEN) {
+            return ReturnCode::EOFF;
+        }
+        let now = self.registers.cnt.get();
+        // Reached at most `MIN_DT` tics ago: fire as soon as possible.
+        let tics = if hil::time::reached_within(now, tics, MIN_DT, u32::max_value()) {
+            now.wrapping_add(MIN_DT)
+        } else {
+            tics
+        };
+        if tics.wrapping_sub(now) < MIN_DT {
+            return ReturnCode::EINVAL;
+        }
+
+        let mut now = now;
+        let mut compare = tics;
+        loop {
+            self.registers.ccr1.set(compare);
+            let after = self.registers.cnt.get();
+            if !hil::time::compare_missed(now, compare, after, MIN_DT, u32::max_value()) {
+                break;
+            }
+            // Passed while being written; fire as soon as possible instead
+            // of after a full wrap.
+            now = after;
+            compare = after.wrapping_add(MIN_DT);
+        }
         self.registers.dier.modify(DIER::CC1IE::SET);
+        self.token.set(token);
+        ReturnCode::SUCCESS
     }
 
-    fn get_alarm(&self) -> u32 {
-        self.registers.ccr1.get()
+    fn get_alarm(&self) -> Self::Ticks {
+        FreqTicks::new(self.registers.ccr1.get())
     }
 
-    fn disable(&self) {
+    fn now_and_alarm(&self) -> (Self::Ticks, Self::Ticks) {
+        unsafe {
+            atomic(|| {
+                (
+                    FreqTicks::new(self.registers.cnt.get()),
+                    FreqTicks::new(self.registers.ccr1.get()),
+                )
+            })
+        }
+    }
+
+    fn minimum_dt(&self) -> Self::Ticks {
+        FreqTicks::new(MIN_DT)
+    }
+
+    fn disable(&self) -> ReturnCode {
         unsafe {
             atomic(|| {
                 // Disable counter
@@ -378,6 +437,7 @@ impl hil::time::Alarm<'a> for Tim2<'a> {
                 cortexm4::nvic::Nvic::new(self.irqn).clear_pending();
             });
         }
+        ReturnCode::SUCCESS
     }
 
     fn is_enabled(&self) -> bool {
@@ -387,14 +447,11 @@ impl hil::time::Alarm<'a> for Tim2<'a> {
 }
 
 impl hil::time::Time for Tim2<'a> {
-    type Frequency = hil::time::Freq16KHz;
-
-    fn now(&self) -> u32 {
-        self.registers.cnt.get()
-    }
+    type Frequency = Freq16KHz;
+    type Ticks = FreqTicks<Freq16KHz>;
 
-    fn max_tics(&self) -> u32 {
-        core::u32::MAX
+    fn now(&self) -> Self::Ticks {
+        FreqTicks::new(self.registers.cnt.get())
     }
 }
 
@@ -22,6 +22,12 @@ pub struct ListIterator<'a, T: 'a + ?Sized + ListNode<'a, T>> {
     cur: Option<&'a T>,
 }
 
+impl<T: ?Sized + ListNode<'a, T>> Clone for ListIterator<'a, T> {
+    fn clone(&self) -> Self {
+        ListIterator { cur: self.cur }
+    }
+}
+
 impl<T: ?Sized + ListNode<'a, T>> Iterator for ListIterator<'a, T> {
     type Item = &'a T;
 
@@ -43,6 +43,7 @@
 //! use kernel::hil::entropy::Client32;
 //! use kernel::hil::time::Alarm;
 //! use kernel::hil::time::Frequency;
+//! use kernel::hil::time::Ticks;
 //! use kernel::hil::time::AlarmClient;
 //! use kernel::ReturnCode;
 //!
@@ -54,7 +55,7 @@
 //! impl<'a, A: Alarm<'a>> EntropyTest<'a, A> {
 //!     pub fn initialize(&self) {
 //!         let interval = 1 * <A::Frequency>::frequency();
-//!         let tics = self.alarm.now().wrapping_add(interval);
+//!         let tics = self.alarm.now().wrapping_add(A::Ticks::from_u32(interval));
 //!         self.alarm.set_alarm(tics);
 //!     }
 //! }
@@ -73,7 +74,7 @@
 //!             Some(val) => {
 //!                 println!("Entropy {}", val);
 //!                 let interval = 1 * <A::Frequency>::frequency();
-//!                 let tics = self.alarm.now().wrapping_add(interval);
+//!                 let tics = self.alarm.now().wrapping_add(A::Ticks::from_u32(interval));
 //!                 self.alarm.set_alarm(tics);
 //!                 hil::entropy::Continue::Done
 //!             },
@@ -17,6 +17,7 @@ pub mod nonvolatile_storage;
 pub mod pwm;
 pub mod radio;
 pub mod rng;
+pub mod rtc;
 pub mod sensors;
 pub mod spi;
 pub mod symmetric_encryption;
@@ -49,7 +49,7 @@
 //!
 //! ```
 //! use kernel::hil;
-//! use kernel::hil::time::Frequency;
+//! use kernel::hil::time::{Frequency, Ticks};
 //! use kernel::ReturnCode;
 //!
 //! struct RngTest<'a, A: 'a + hil::time::Alarm<'a>> {
@@ -60,7 +60,7 @@
 //! impl<'a, A: hil::time::Alarm<'a>> RngTest<'a, A> {
 //!     pub fn initialize(&self) {
 //!         let interval = 1 * <A::Frequency>::frequency();
-//!         let tics = self.alarm.now().wrapping_add(interval);
+//!         let tics = self.alarm.now().wrapping_add(A::Ticks::from_u32(interval));
 //!         self.alarm.set_alarm(tics);
 //!     }
 //! }
@@ -79,7 +79,7 @@
 //!             Some(random) => {
 //!                 println!("Rand {}", random);
 //!                 let interval = 1 * <A::Frequency>::frequency();
-//!                 let tics = self.alarm.now().wrapping_add(interval);
+//!                 let tics = self.alarm.now().wrapping_add(A::Ticks::from_u32(interval));
 //!                 self.alarm.set_alarm(tics);
 //!                 hil::rng::Continue::Done
 //!             },
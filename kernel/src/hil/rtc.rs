@@ -0,0 +1,156 @@
+//! Interface for real time clocks that keep calendar time.
+//!
+//! Unlike the tick-based traits in [`time`](../time/index.html), an `Rtc`
+//! reports wall-clock time as a [`DateTime`](struct.DateTime.html). All times
+//! are in UTC.
+
+use crate::ReturnCode;
+
+/// A calendar date and time of day, in UTC.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct DateTime {
+    pub year: u16,
+    /// Month of the year, 1 to 12.
+    pub month: u8,
+    /// Day of the month, 1 to 31.
+    pub day: u8,
+    /// Hour of the day, 0 to 23.
+    pub hour: u8,
+    /// Minute of the hour, 0 to 59.
+    pub minute: u8,
+    /// Second of the minute, 0 to 59.
+    pub second: u8,
+}
+
+pub trait Rtc<'a> {
+    /// Set the client for calendar time readings.
+    fn set_client(&'a self, client: &'a dyn RtcClient);
+
+    /// Requests the current date and time. The result is delivered to the
+    /// client with [`RtcClient#callback`](trait.RtcClient.html#tymethod.callback).
+    ///
+    /// Returns `ReturnCode::SUCCESS` if the request was accepted, and
+    /// `ReturnCode::EBUSY` if a reading is already in progress.
+    fn get_time(&self) -> ReturnCode;
+
+    /// Sets the current date and time.
+    ///
+    /// Returns `ReturnCode::EINVAL` if `datetime` is not a valid date.
+    fn set_time(&self, datetime: DateTime) -> ReturnCode;
+}
+
+/// A client of an implementer of the [`Rtc`](trait.Rtc.html) trait.
+pub trait RtcClient {
+    /// Called with the date and time requested by
+    /// [`Rtc#get_time`](trait.Rtc.html#tymethod.get_time).
+    fn callback(&self, datetime: DateTime);
+}
+
+/// Days between 0000-03-01 and 1970-01-01 in the proleptic Gregorian
+/// calendar.
+const DAYS_TO_UNIX_EPOCH: i64 = 719_468;
+/// Days in a 400 year Gregorian cycle.
+const DAYS_PER_ERA: i64 = 146_097;
+
+/// Converts `datetime` to the number of seconds since the Unix epoch
+/// (1970-01-01 00:00:00 UTC), ignoring leap seconds.
+///
+/// Dates before the epoch are clamped to `0`. `datetime` is not validated, so
+/// out of range fields produce meaningless results.
+pub fn to_epoch(datetime: DateTime) -> u64 {
+    // Count years from March, so that the leap day is the last day of the
+    // year.
+    let month = datetime.month as i64;
+    let year = datetime.year as i64 - if month <= 2 { 1 } else { 0 };
+    let era = year / 400;
+    let year_of_era = year - era * 400;
+    let day_of_year =
+        (153 * (if month > 2 { month - 3 } else { month + 9 }) + 2) / 5 + datetime.day as i64 - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    let days = era * DAYS_PER_ERA + day_of_era - DAYS_TO_UNIX_EPOCH;
+
+    let seconds = days * 86400
+        + datetime.hour as i64 * 3600
+        + datetime.minute as i64 * 60
+        + datetime.second as i64;
+    if seconds < 0 {
+        0
+    } else {
+        seconds as u64
+    }
+}
+
+/// Converts a number of seconds since the Unix epoch (1970-01-01 00:00:00
+/// UTC) to a `DateTime`, ignoring leap seconds.
+pub fn from_epoch(seconds: u64) -> DateTime {
+    let days = (seconds / 86400) as i64 + DAYS_TO_UNIX_EPOCH;
+    let seconds_of_day = seconds % 86400;
+
+    let era = days / DAYS_PER_ERA;
+    let day_of_era = days - era * DAYS_PER_ERA;
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_from_march = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month_from_march + 2) / 5 + 1;
+    let month = if month_from_march < 10 {
+        month_from_march + 3
+    } else {
+        month_from_march - 9
+    };
+    let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+
+    DateTime {
+        year: year as u16,
+        month: month as u8,
+        day: day as u8,
+        hour: (seconds_of_day / 3600) as u8,
+        minute: (seconds_of_day / 60 % 60) as u8,
+        second: (seconds_of_day % 60) as u8,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{from_epoch, to_epoch, DateTime};
+
+    fn datetime(year: u16, month: u8, day: u8, hour: u8, minute: u8, second: u8) -> DateTime {
+        DateTime {
+            year,
+            month,
+            day,
+            hour,
+            minute,
+            second,
+        }
+    }
+
+    #[test]
+    fn test_epoch() {
+        assert_eq!(to_epoch(datetime(1970, 1, 1, 0, 0, 0)), 0);
+        assert_eq!(from_epoch(0), datetime(1970, 1, 1, 0, 0, 0));
+    }
+
+    #[test]
+    fn test_leap_day() {
+        let leap_day = datetime(2000, 2, 29, 12, 34, 56);
+        assert_eq!(to_epoch(leap_day), 951_827_696);
+        assert_eq!(from_epoch(951_827_696), leap_day);
+    }
+
+    #[test]
+    fn test_round_trip() {
+        for &seconds in &[2_147_483_648, 4_102_444_799, 1_234_567_890] {
+            assert_eq!(to_epoch(from_epoch(seconds)), seconds);
+        }
+        assert_eq!(
+            from_epoch(4_102_444_799),
+            datetime(2099, 12, 31, 23, 59, 59)
+        );
+    }
+
+    #[test]
+    fn test_before_epoch_clamps() {
+        assert_eq!(to_epoch(datetime(1969, 12, 31, 23, 59, 59)), 0);
+    }
+}
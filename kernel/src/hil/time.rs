@@ -1,177 +1,3829 @@
 //! Hardware agnostic interfaces for counter-like resources.
 
+use crate::common::cells::OptionalCell;
+use crate::hil::gpio::InterruptEdge;
 use crate::ReturnCode;
+use core::cell::Cell;
+use core::cmp::Ordering;
+use core::fmt;
+use core::future::Future;
+use core::marker::PhantomData;
+use core::ops::Add;
+use core::pin::Pin;
+use core::task::{Context, Poll, Waker};
 
-pub trait Time<W = u32> {
-    type Frequency: Frequency;
+#[cfg(any(test, feature = "test-util"))]
+pub mod mock;
 
-    /// Returns the current time in hardware clock units.
-    fn now(&self) -> W;
+/// An integer type holding a value of a hardware clock.
+///
+/// `Ticks` lets `Time` implementations pick the width that matches their
+/// counter (e.g. `u64` for a 64-bit machine timer) instead of squeezing every
+/// clock into a `u32`. Clients that are generic over `Time` should only use
+/// the methods of this trait for arithmetic on clock values, so that
+/// wraparound happens at the right width.
+pub trait Ticks: Clone + Copy + PartialEq + Eq + PartialOrd + Ord + fmt::Debug {
+    /// The width of this type in bits.
+    const WIDTH: u32;
 
-    /// Returns the wrap-around value of the clock.
+    /// Converts the value into a `u32`, truncating it if it is wider than 32
+    /// bits.
+    fn into_u32(self) -> u32;
+
+    /// Converts a `u32` into this type, truncating it if this type is narrower
+    /// than 32 bits.
+    fn from_u32(val: u32) -> Self;
+
+    /// Converts the value into a `u64`, which holds every `Ticks` type
+    /// without loss.
+    fn into_u64(self) -> u64;
+
+    /// Converts a `u64` into this type, truncating it if this type is
+    /// narrower than 64 bits.
+    fn from_u64(val: u64) -> Self;
+
+    /// Returns the width of this type in bits, [`WIDTH`](#associatedconstant.WIDTH).
+    fn width() -> u32 {
+        Self::WIDTH
+    }
+
+    /// Returns the largest value of this type.
+    fn max_value() -> Self;
+
+    /// Adds two values, wrapping around at the width of the type.
+    fn wrapping_add(self, other: Self) -> Self;
+
+    /// Subtracts `other` from `self`, wrapping around at the width of the type.
+    fn wrapping_sub(self, other: Self) -> Self;
+}
+
+impl Ticks for u32 {
+    const WIDTH: u32 = 32;
+
+    fn into_u32(self) -> u32 {
+        self
+    }
+
+    fn from_u32(val: u32) -> Self {
+        val
+    }
+
+    fn into_u64(self) -> u64 {
+        self as u64
+    }
+
+    fn from_u64(val: u64) -> Self {
+        val as u32
+    }
+
+    fn max_value() -> Self {
+        u32::max_value()
+    }
+
+    fn wrapping_add(self, other: Self) -> Self {
+        u32::wrapping_add(self, other)
+    }
+
+    fn wrapping_sub(self, other: Self) -> Self {
+        u32::wrapping_sub(self, other)
+    }
+}
+
+impl Ticks for u64 {
+    const WIDTH: u32 = 64;
+
+    fn into_u32(self) -> u32 {
+        self as u32
+    }
+
+    fn from_u32(val: u32) -> Self {
+        val as u64
+    }
+
+    fn into_u64(self) -> u64 {
+        self
+    }
+
+    fn from_u64(val: u64) -> Self {
+        val
+    }
+
+    fn max_value() -> Self {
+        u64::max_value()
+    }
+
+    fn wrapping_add(self, other: Self) -> Self {
+        u64::wrapping_add(self, other)
+    }
+
+    fn wrapping_sub(self, other: Self) -> Self {
+        u64::wrapping_sub(self, other)
+    }
+}
+
+/// A 32-bit clock value tagged with the `Frequency` of the clock it belongs
+/// to.
+///
+/// Hardware alarms use `FreqTicks<Self::Frequency>` as their `Ticks` type, so
+/// `set_alarm` and friends only accept values that were explicitly made into
+/// clock tics, with `FreqTicks::new`, `Ticks::from_u32` or arithmetic on
+/// other tics. Passing a bare `u32`, such as a value in milliseconds, is a
+/// compile error, as is passing tics of a clock at another frequency.
+pub struct FreqTicks<F: Frequency>(u32, PhantomData<F>);
+
+impl<F: Frequency> FreqTicks<F> {
+    /// Wraps a raw number of clock tics.
+    pub const fn new(tics: u32) -> FreqTicks<F> {
+        FreqTicks(tics, PhantomData)
+    }
+
+    /// Converts the value to milliseconds, rounding down.
+    pub fn into_ms(self) -> u32 {
+        self.into_units(1000)
+    }
+
+    /// Converts the value to microseconds, rounding down.
     ///
-    /// The maximum value of the clock, at which `now` will wrap around. I.e., this should return
-    /// `core::u32::MAX` on a 32-bit-clock, or `(1 << 24) - 1` for a 24-bit clock.
-    fn max_tics(&self) -> W;
+    /// The result saturates at `u32::MAX`, which is reached after about 71
+    /// minutes.
+    pub fn into_us(self) -> u32 {
+        self.into_units(1_000_000)
+    }
+
+    fn into_units(self, per_second: u64) -> u32 {
+        let units =
+            self.0 as u64 * F::frequency_den() as u64 * per_second / F::frequency_num() as u64;
+        core::cmp::min(units, u32::max_value() as u64) as u32
+    }
 }
 
-pub trait Counter<W = u32>: Time<W> {
-    fn start(&self) -> ReturnCode;
-    fn stop(&self) -> ReturnCode;
-    fn is_running(&self) -> bool;
+impl<F: Frequency> Ticks for FreqTicks<F> {
+    const WIDTH: u32 = 32;
+
+    fn into_u32(self) -> u32 {
+        self.0
+    }
+
+    fn from_u32(val: u32) -> Self {
+        FreqTicks::new(val)
+    }
+
+    fn into_u64(self) -> u64 {
+        self.0 as u64
+    }
+
+    fn from_u64(val: u64) -> Self {
+        FreqTicks::new(val as u32)
+    }
+
+    fn max_value() -> Self {
+        FreqTicks::new(u32::max_value())
+    }
+
+    fn wrapping_add(self, other: Self) -> Self {
+        FreqTicks::new(self.0.wrapping_add(other.0))
+    }
+
+    fn wrapping_sub(self, other: Self) -> Self {
+        FreqTicks::new(self.0.wrapping_sub(other.0))
+    }
 }
 
-/// Trait to represent clock frequency in Hz
+// These are implemented by hand because `#[derive]` would require the
+// `Frequency` marker types to implement the derived traits as well.
+
+impl<F: Frequency> Clone for FreqTicks<F> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<F: Frequency> Copy for FreqTicks<F> {}
+
+impl<F: Frequency> PartialEq for FreqTicks<F> {
+    fn eq(&self, other: &Self) -> bool {
+        self.0 == other.0
+    }
+}
+
+impl<F: Frequency> Eq for FreqTicks<F> {}
+
+impl<F: Frequency> PartialOrd for FreqTicks<F> {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl<F: Frequency> Ord for FreqTicks<F> {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.0.cmp(&other.0)
+    }
+}
+
+impl<F: Frequency> fmt::Debug for FreqTicks<F> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "FreqTicks({})", self.0)
+    }
+}
+
+/// A point in time on a clock running at frequency `F`.
 ///
-/// This trait is used as an associated type for `Alarm` so clients can portably
-/// convert native cycles to real-time values.
-pub trait Frequency {
-    /// Returns frequency in Hz.
-    fn frequency() -> u32;
+/// An `Instant` is a raw 32-bit tic count, so it wraps around like the
+/// counter it was read from. Instants are therefore not ordered; the time
+/// between two of them is found with `wrapping_sub`, which is correct as long
+/// as they are less than half a counter period apart. For counters narrower
+/// than 32 bits this only holds for instants read before the counter first
+/// wraps.
+pub struct Instant<F: Frequency>(u32, PhantomData<F>);
+
+impl<F: Frequency> Instant<F> {
+    /// Wraps a raw number of clock tics.
+    pub const fn new(tics: u32) -> Instant<F> {
+        Instant(tics, PhantomData)
+    }
+
+    /// Returns the raw number of clock tics.
+    pub fn tics(self) -> u32 {
+        self.0
+    }
+
+    /// Returns the signed time from `earlier` to `self`.
+    ///
+    /// The result is negative if `earlier` is actually later than `self`.
+    pub fn wrapping_sub(self, earlier: Instant<F>) -> Duration<F> {
+        Duration::from_tics(self.0.wrapping_sub(earlier.0) as i32)
+    }
 }
 
-/// 16MHz `Frequency`
-#[derive(Debug)]
-pub struct Freq16MHz;
-impl Frequency for Freq16MHz {
-    fn frequency() -> u32 {
-        16000000
+impl<F: Frequency> Add<Duration<F>> for Instant<F> {
+    type Output = Instant<F>;
+
+    fn add(self, duration: Duration<F>) -> Instant<F> {
+        Instant::new(self.0.wrapping_add(duration.0 as u32))
     }
 }
 
-/// 32KHz `Frequency`
-#[derive(Debug)]
-pub struct Freq32KHz;
-impl Frequency for Freq32KHz {
-    fn frequency() -> u32 {
-        32768
+/// A signed span of time, in tics of a clock running at frequency `F`.
+///
+/// The frequency is part of the type so that durations and instants of
+/// different clocks cannot be mixed up, and so that `from_ms` knows how many
+/// tics a millisecond is.
+pub struct Duration<F: Frequency>(i32, PhantomData<F>);
+
+impl<F: Frequency> Duration<F> {
+    /// Wraps a signed number of clock tics.
+    pub const fn from_tics(tics: i32) -> Duration<F> {
+        Duration(tics, PhantomData)
+    }
+
+    /// A duration of `ms` milliseconds, rounded to the nearest tic.
+    ///
+    /// Saturates at `i32::max_value()` tics if `ms` is too long.
+    pub fn from_ms(ms: u32) -> Duration<F> {
+        Duration::from_tics(core::cmp::min(F::ms_to_tics(ms), i32::max_value() as u32) as i32)
+    }
+
+    /// A duration of `us` microseconds, rounded to the nearest tic.
+    ///
+    /// Saturates at `i32::max_value()` tics if `us` is too long.
+    pub fn from_us(us: u32) -> Duration<F> {
+        Duration::from_tics(core::cmp::min(F::us_to_tics(us), i32::max_value() as u32) as i32)
+    }
+
+    /// Returns the signed number of clock tics.
+    pub fn tics(self) -> i32 {
+        self.0
     }
 }
 
-/// 16KHz `Frequency`
-#[derive(Debug)]
-pub struct Freq16KHz;
-impl Frequency for Freq16KHz {
-    fn frequency() -> u32 {
-        16000
+impl<F: Frequency> Clone for Instant<F> {
+    fn clone(&self) -> Self {
+        *self
     }
 }
 
-/// 1KHz `Frequency`
-#[derive(Debug)]
-pub struct Freq1KHz;
-impl Frequency for Freq1KHz {
-    fn frequency() -> u32 {
-        1000
+impl<F: Frequency> Copy for Instant<F> {}
+
+impl<F: Frequency> PartialEq for Instant<F> {
+    fn eq(&self, other: &Self) -> bool {
+        self.0 == other.0
     }
 }
 
-/// The `Alarm` trait models a wrapping counter capable of notifying when the
-/// counter reaches a certain value.
+impl<F: Frequency> Eq for Instant<F> {}
+
+impl<F: Frequency> fmt::Debug for Instant<F> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Instant({})", self.0)
+    }
+}
+
+impl<F: Frequency> Clone for Duration<F> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<F: Frequency> Copy for Duration<F> {}
+
+impl<F: Frequency> PartialEq for Duration<F> {
+    fn eq(&self, other: &Self) -> bool {
+        self.0 == other.0
+    }
+}
+
+impl<F: Frequency> Eq for Duration<F> {}
+
+impl<F: Frequency> PartialOrd for Duration<F> {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl<F: Frequency> Ord for Duration<F> {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.0.cmp(&other.0)
+    }
+}
+
+impl<F: Frequency> fmt::Debug for Duration<F> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Duration({})", self.0)
+    }
+}
+
+/// The properties of a `Time` implementor, gathered in one value for code
+/// that lists or compares clocks at runtime, such as a board-info debug
+/// command.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct TimeCapabilities {
+    /// The current frequency of the clock in Hz.
+    pub frequency: u32,
+    /// The number of valid bits in the values returned by `now`.
+    pub width_bits: u32,
+    /// The smallest delta an alarm on this clock can be set for, or `0` if
+    /// the clock is not an `Alarm` or has no minimum.
+    pub min_alarm_dt: u32,
+    /// Whether the clock implements `Counter`, i.e. can be started, stopped
+    /// and possibly reset.
+    pub is_counter: bool,
+}
+
+/// A snapshot of an `Alarm` or `Timer`, for printing the state of all
+/// timers when debugging, e.g. from a panic handler. See
+/// [`Alarm#describe`](trait.Alarm.html#method.describe) and
+/// [`Timer#describe`](trait.Timer.html#method.describe).
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct TimeState {
+    /// `now()` when the snapshot was taken.
+    pub now: u64,
+    /// The current frequency of the clock in Hz.
+    pub frequency: u32,
+    /// Whether the alarm or timer will fire.
+    pub enabled: bool,
+    /// The compare value of an enabled alarm. Always `None` for a timer.
+    pub alarm: Option<u64>,
+    /// The interval of a repeating timer. Always `None` for an alarm.
+    pub interval: Option<u64>,
+    /// Tics until it fires, if it is enabled.
+    pub remaining: Option<u64>,
+}
+
+/// A clock that can be read.
 ///
-/// Alarms represent a resource that keeps track of time in some fixed unit
-/// (usually clock tics). Implementers should use the
-/// [`Client`](trait.Client.html) trait to signal when the counter has
-/// reached a pre-specified value set in [`set_alarm`](#tymethod.set_alarm).
-pub trait Alarm<'a, W = u32>: Time<W> {
-    /// Sets a one-shot alarm to fire when the clock reaches `tics`.
+/// `Time` only reads the counter. Starting and stopping the count is done
+/// through [`Counter`](trait.Counter.html), and nothing in `Alarm` or `Timer`
+/// affects whether the counter is running.
+pub trait Time {
+    type Frequency: Frequency;
+
+    /// The type of the values returned by `now`.
     ///
-    /// [`Client#fired`](trait.Client.html#tymethod.fired) is signaled
-    /// when `tics` is reached.
+    /// Defaults to `u32` so that existing 32-bit clocks do not have to name it.
+    /// Hardware clocks up to 32 bits wide use
+    /// [`FreqTicks<Self::Frequency>`](struct.FreqTicks.html), so that a plain
+    /// number cannot be passed where their tics are expected.
+    type Ticks: Ticks = u32;
+
+    /// The number of valid bits in the values returned by `now`.
     ///
-    /// # Examples
+    /// Defaults to the width of `Self::Ticks`. Counters narrower than their
+    /// `Ticks` type, such as a 24-bit RTC, override it, and the default
+    /// `max_tics()` of `(1 << WIDTH) - 1` follows. Together with `Frequency`
+    /// it gives the real-time span a counter can represent before it wraps,
+    /// e.g. for converting between two clock domains.
+    const WIDTH: u32 = <Self::Ticks as Ticks>::WIDTH;
+
+    /// Returns the current time in hardware clock units.
     ///
-    /// ```ignore
-    /// let delta = 1337;
-    /// let tics = alarm.now().wrapping_add(delta);
-    /// alarm.set_alarm(tics);
-    /// ```
-    fn set_alarm(&self, tics: W);
+    /// Reading a clock in the chip cannot fail. A clock whose reads can,
+    /// such as an external RTC on a bus, returns the last value it read
+    /// successfully here, which is stale by however long reads have been
+    /// failing, and reports the failure through
+    /// [`try_now`](#method.try_now).
+    fn now(&self) -> Self::Ticks;
 
-    /// Returns the value set in [`set_alarm`](#tymethod.set_alarm)
-    fn get_alarm(&self) -> W;
+    /// Returns the current time like [`now`](#tymethod.now), or the error
+    /// that reading the clock failed with, e.g. `ReturnCode::FAIL` for a bus
+    /// error.
+    ///
+    /// Callers that must not act on a stale time use this instead of `now`.
+    /// Defaults to `Ok(self.now())`, for clocks whose reads cannot fail.
+    fn try_now(&self) -> Result<Self::Ticks, ReturnCode> {
+        Ok(self.now())
+    }
 
-    /// Set the client for interrupt events.
-    fn set_client(&'a self, client: &'a dyn AlarmClient);
+    /// Returns the wrap-around value of the clock.
+    ///
+    /// The maximum value of the clock, at which `now` will wrap around. I.e., this should return
+    /// `core::u32::MAX` on a 32-bit-clock, or `(1 << 24) - 1` for a 24-bit clock. The value is
+    /// inclusive: the tic after `max_tics()` is zero.
+    ///
+    /// Defaults to `(1 << WIDTH) - 1`, so counters only need to override
+    /// [`WIDTH`](#associatedconstant.WIDTH). Clients converting a real-time delay into tics can
+    /// use this to detect delays longer than one full period, which would otherwise silently
+    /// alias into a much shorter wait.
+    fn max_tics(&self) -> Self::Ticks {
+        Self::Ticks::from_u64(u64::max_value() >> (64 - Self::WIDTH))
+    }
 
-    /// Returns whether this alarm is currently active (will eventually trigger
-    /// a callback if there is a client).
-    fn is_enabled(&self) -> bool;
+    /// Returns the current frequency of the clock in Hz.
+    ///
+    /// Defaults to `Self::Frequency::frequency()`. Clocks that can be
+    /// reconfigured at runtime (e.g. by changing a prescaler) should override
+    /// this to report the frequency they are currently running at, and
+    /// clients converting between tics and real time should prefer it over the
+    /// associated `Frequency` type.
+    fn frequency(&self) -> u32 {
+        Self::Frequency::frequency()
+    }
 
-    /// Enables the alarm using the previously set `tics` for the alarm.
+    /// Returns the current time as an `Instant`, for wrap-safe arithmetic
+    /// without handling raw tic counts.
+    fn instant(&self) -> Instant<Self::Frequency> {
+        Instant::new(self.now().into_u32())
+    }
+
+    /// Returns `now()` in microseconds, rounded down, at the clock's current
+    /// `frequency()`.
     ///
-    /// Most implementations should use the default implementation which calls `set_alarm` with the
-    /// value returned by `get_alarm` unless there is a more efficient way to achieve the same
-    /// semantics.
-    fn enable(&self) {
-        self.set_alarm(self.get_alarm())
+    /// The conversion is done in 64 bits, so it cannot overflow, but the
+    /// result still wraps when the counter does: it is the time since the
+    /// counter last wrapped, not since boot. Differences between two readings
+    /// are only meaningful within one counter period, unless the clock is a
+    /// 64-bit counter.
+    fn now_us(&self) -> u64 {
+        tics_to_units(self.now().into_u64(), self.frequency(), 1_000_000)
     }
 
-    /// Disables the alarm.
+    /// Returns `now()` in nanoseconds, rounded down, with the same caveats
+    /// as [`now_us`](#method.now_us).
     ///
-    /// The implementation will _always_ disable the alarm and prevent events related to previously
-    /// set alarms from being delivered to the client.
-    fn disable(&self);
-}
+    /// The result is a multiple of the tic length rounded down, e.g. of
+    /// 15.625ns at 64MHz: nanosecond units do not make the clock finer than
+    /// one tic.
+    fn now_ns(&self) -> u64 {
+        tics_to_units(self.now().into_u64(), self.frequency(), 1_000_000_000)
+    }
 
-/// A client of an implementer of the [`Alarm`](trait.Alarm.html) trait.
-pub trait AlarmClient {
-    /// Callback signaled when the alarm's clock reaches the value set in
-    /// [`Alarm#set_alarm`](trait.Alarm.html#tymethod.set_alarm).
-    fn fired(&self);
-}
+    /// Returns `now()` in milliseconds, rounded down, with the same caveats
+    /// as [`now_us`](#method.now_us).
+    fn now_ms(&self) -> u64 {
+        tics_to_units(self.now().into_u64(), self.frequency(), 1000)
+    }
 
-/// The `Timer` trait models a timer that can notify when a particular interval
-/// has elapsed.
-pub trait Timer<'a, W = u32>: Time<W> {
-    /// Set the client for interrupt events.
-    fn set_client(&'a self, client: &'a dyn TimerClient);
+    /// Returns the number of tics from `earlier`, a previous reading of
+    /// `now()`, until now.
+    ///
+    /// The difference is taken modulo the counter width, so it is only
+    /// correct for spans shorter than one counter period: a span of a period
+    /// and a bit reads as just the bit. Use
+    /// [`checked_elapsed_since`](#method.checked_elapsed_since) to catch
+    /// spans that may have been that long.
+    fn elapsed_since(&self, earlier: Self::Ticks) -> Self::Ticks {
+        Self::Ticks::from_u64(wrapping_delta(earlier, self.now(), self.max_tics()))
+    }
 
-    /// Sets a one-shot timer to fire in `interval` clock-tics.
+    /// Returns [`elapsed_since`](#method.elapsed_since), or `None` if it is
+    /// within 1/16 of a period of a full counter period.
     ///
-    /// Calling this method will override any existing oneshot or repeating timer.
-    fn oneshot(&self, interval: W);
+    /// Such a span is suspicious: more likely the counter wrapped more than
+    /// once since `earlier`, or `earlier` was not a reading of this counter,
+    /// than a short interval really took that long. The margin gives up the
+    /// last part of the range for telling these apart.
+    fn checked_elapsed_since(&self, earlier: Self::Ticks) -> Option<Self::Ticks> {
+        let elapsed = self.elapsed_since(earlier);
+        let max = self.max_tics().into_u64();
+        if elapsed.into_u64() > max - max / 16 {
+            None
+        } else {
+            Some(elapsed)
+        }
+    }
 
-    /// Sets repeating timer to fire every `interval` clock-tics.
+    /// Returns whether `now()` has reached `value`, i.e. is at or after it.
     ///
-    /// Calling this method will override any existing oneshot or repeating timer.
-    fn repeat(&self, interval: W);
+    /// The counter wraps, so `value` is taken to be the nearer of its past
+    /// and future occurrences: it has been reached if it lies at most half a
+    /// counter period behind `now()`. The answer is thus only meaningful for a
+    /// `value` within half a period of `now()`; a deadline that passed longer
+    /// ago than that reads as not reached yet. To check a deadline against
+    /// the time it was set, which allows a full period, use
+    /// [`has_expired`](fn.has_expired.html).
+    fn now_is_after(&self, value: Self::Ticks) -> bool {
+        let max = self.max_tics();
+        wrapping_delta(value, self.now(), max) <= max.into_u64() / 2
+    }
 
-    /// Returns the interval for a repeating timer.
+    /// Reads `now()` and combines it with a software high word into a 64-bit
+    /// tic count (see [`extended_timestamp`](fn.extended_timestamp.html)).
     ///
-    /// Returns `None` if the timer is disabled or in oneshot mode and `Some(interval)` if it is
-    /// repeating.
-    fn interval(&self) -> Option<W>;
+    /// `high` is the high word that was current when the counter read
+    /// `low_before`, e.g. both sampled with the overflow interrupt masked. If
+    /// `now()` is below `low_before` the counter has wrapped since, and `high`
+    /// is incremented to account for it. This assumes that at most one wrap
+    /// happens between reading `low_before` and calling this, i.e. that it is
+    /// called within one counter period. This is for counters of at most 32
+    /// bits; a wider counter is cut to its low 32 bits.
+    fn combine64(&self, high: u32, low_before: u32) -> u64 {
+        let now = self.now().into_u32();
+        let high = if now < low_before {
+            high.wrapping_add(1)
+        } else {
+            high
+        };
+        extended_timestamp(high, now, Self::WIDTH)
+    }
 
-    /// Returns whether this is a oneshot (rather than repeating) timer.
-    fn is_oneshot(&self) -> bool {
-        self.interval().is_none()
+    /// Returns whether the clock keeps counting while the chip is in its
+    /// deepest sleep mode that the kernel uses, such as a stop or retention
+    /// mode.
+    ///
+    /// This is for the power manager: a wakeup from deep sleep can only be
+    /// scheduled on an alarm whose clock persists, typically an RTC running
+    /// from a low-frequency oscillator. A clock derived from the core or a
+    /// high-frequency oscillator is frozen during sleep, so its `now()` falls
+    /// behind real time by the time slept and must not be used to measure
+    /// spans that include sleep. Defaults to `false`, which is always safe.
+    fn persists_in_sleep(&self) -> bool {
+        false
     }
 
-    /// Returns whether this is a repeating (rather than oneshot) timer.
-    fn is_repeating(&self) -> bool {
-        self.interval().is_some()
+    /// Returns the properties of this clock.
+    ///
+    /// The default is derived from `frequency()` and `WIDTH`, and reports no
+    /// minimum alarm delta and no `Counter`, since `Time` cannot see either.
+    /// Implementors that are also an `Alarm` or a `Counter` should override
+    /// it to fill those in.
+    fn capabilities(&self) -> TimeCapabilities {
+        TimeCapabilities {
+            frequency: self.frequency(),
+            width_bits: Self::WIDTH,
+            min_alarm_dt: 0,
+            is_counter: false,
+        }
     }
+}
 
-    /// Returns the remaining time in clock tics for a oneshot or repeating timer.
+pub trait Counter<'a>: Time {
+    /// Specify the callback for when the counter wraps around from
+    /// `max_tics()` to zero.
+    fn set_overflow_client(&'a self, client: &'a dyn OverflowClient);
+    fn start(&self) -> ReturnCode;
+    fn stop(&self) -> ReturnCode;
+    fn is_running(&self) -> bool;
+
+    /// Sets the counter back to 0, stopping it first if needed. The counter
+    /// is left running if it was running before.
+    ///
+    /// An armed `Alarm` on the same counter keeps its compare value, which is
+    /// now relative to the new zero: an alarm set for `now() + dt` before the
+    /// reset fires when the counter next reaches that value, not `dt` tics
+    /// later.
     ///
-    /// Returns `None` if the timer is disabled.
-    fn time_remaining(&self) -> Option<W>;
+    /// Returns `ReturnCode::ENOSUPPORT` if the hardware counter cannot be
+    /// reset, which is the default.
+    fn reset(&self) -> ReturnCode {
+        ReturnCode::ENOSUPPORT
+    }
 
-    /// Returns whether this timer is currently active (has time remaining).
-    fn is_enabled(&self) -> bool {
-        self.time_remaining().is_some()
+    /// Divides the clock the counter counts by `div`, trading resolution for
+    /// a longer period before the counter wraps. The counter keeps its value.
+    ///
+    /// Afterwards [`Time#frequency`](trait.Time.html#method.frequency)
+    /// returns the new rate. Capsules that change the prescaler must read it
+    /// back for conversions, as the `Frequency` type only describes the
+    /// default prescaler.
+    ///
+    /// Returns `ReturnCode::EBUSY` without changing anything if an alarm on
+    /// this counter is armed, since its compare value was computed at the old
+    /// rate and would fire at the wrong time; disable the alarm, change the
+    /// prescaler and set the alarm again. Returns `ReturnCode::EINVAL` if the
+    /// hardware cannot divide by `div`, and `ReturnCode::ENOSUPPORT` if the
+    /// prescaler is fixed, which is the default.
+    fn set_prescaler(&self, _div: u32) -> ReturnCode {
+        ReturnCode::ENOSUPPORT
     }
 
-    /// Cancels an outstanding timer.
+    /// Returns whether the counter has wrapped since its overflow was last
+    /// handled, i.e. whether an overflow is latched in hardware but not yet
+    /// signaled to the overflow client.
     ///
-    /// The implementation will _always_ cancel the timer.
-    /// delivered.
-    fn cancel(&self);
+    /// Inside [`with_frozen`](#method.with_frozen) this tells whether the
+    /// counter wrapped while `f` ran. Returns `None` if the hardware has no
+    /// readable overflow flag, which is the default.
+    fn overflow_pending(&self) -> Option<bool> {
+        None
+    }
+
+    /// Calls `f` with the current value of the counter while the overflow
+    /// interrupt is masked, and returns its result.
+    ///
+    /// State that the overflow client updates, such as the high word of a
+    /// counter extended in software, cannot change while `f` runs, so `f` can
+    /// combine it with the value it is passed without a retry loop. A wrap
+    /// that happens while `f` runs is signaled once `f` returns. `f` must be
+    /// short and must not block, since the overflow interrupt, and on some
+    /// chips all interrupts, are held off until it returns.
+    ///
+    /// The default passes `now()` without masking anything, which suffices
+    /// for counters whose overflow client is never called from interrupt
+    /// context. Counters that signal overflows directly from their interrupt
+    /// handler must override it.
+    fn with_frozen<R>(&self, f: impl FnOnce(u32) -> R) -> R
+    where
+        Self: Sized,
+    {
+        f(self.now().into_u32())
+    }
 }
 
-/// A client of an implementer of the [`Timer`](trait.Timer.html) trait.
-pub trait TimerClient {
-    /// Callback signaled when the timer's clock reaches the specified interval.
+/// A client of an implementer of the [`Counter`](trait.Counter.html) trait.
+pub trait OverflowClient {
+    /// Callback signaled when the counter wraps around to zero.
+    fn overflow(&self);
+
+    /// Callback signaled when the counter wraps around to zero, with the
+    /// high word of the counter after the wrap, i.e. the overflow happened at
+    /// the extended timestamp `(count_high, 0)` (see
+    /// [`extended_timestamp`](fn.extended_timestamp.html)).
+    ///
+    /// Counters that keep a high word signal this method. The default
+    /// implementation ignores the high word and calls `overflow`.
+    fn overflow_at(&self, _count_high: u32) {
+        self.overflow();
+    }
+}
+
+/// The `Capture` trait models a counter that can timestamp external events,
+/// such as an edge on a GPIO pin, against its own `now()`.
+///
+/// Hardware input capture latches the counter in the same cycle as the edge.
+/// Implementations without capture hardware may instead read `now()` in the
+/// GPIO interrupt handler; their timestamps are later than the edge by the
+/// interrupt latency, and they should document that lower precision.
+pub trait Capture<'a>: Time {
+    /// Set the client for captured timestamps.
+    fn set_capture_client(&'a self, client: &'a dyn CaptureClient);
+
+    /// Starts timestamping `edge`s on the capture input. Each matching edge
+    /// signals [`CaptureClient#captured`](trait.CaptureClient.html#tymethod.captured).
+    ///
+    /// Returns `ReturnCode::ENOSUPPORT` if the hardware cannot capture that
+    /// kind of edge.
+    fn enable_capture(&self, edge: InterruptEdge) -> ReturnCode;
+
+    /// Stops timestamping edges.
+    fn disable_capture(&self);
+}
+
+/// A client of an implementer of the [`Capture`](trait.Capture.html) trait.
+pub trait CaptureClient {
+    /// Callback signaled with the value of the counter latched at the edge,
+    /// in the same units as `now()`.
+    fn captured(&self, timestamp: u32);
+
+    /// Callback signaled like `captured`, with the high word of the counter
+    /// that `timestamp` belongs to, so that the edge happened at the extended
+    /// timestamp `(count_high, timestamp)` (see
+    /// [`extended_timestamp`](fn.extended_timestamp.html)).
+    ///
+    /// Captures that know the high word signal this method. The default
+    /// implementation ignores the high word and calls `captured`.
+    fn captured_at(&self, _count_high: u32, timestamp: u32) {
+        self.captured(timestamp);
+    }
+}
+
+/// Combines the high word of a counter with a value of the counter into one
+/// extended timestamp, for a counter `width` bits wide.
+///
+/// This is the convention shared by
+/// [`OverflowClient#overflow_at`](trait.OverflowClient.html#method.overflow_at)
+/// and [`CaptureClient#captured_at`](trait.CaptureClient.html#method.captured_at):
+/// `count_high` is the number of overflows signaled since the counter started
+/// (wrapping at 32 bits), and the low part is the counter value within that
+/// period. An overflow is at `(count_high, 0)`, so a capture latched in the
+/// same period sorts after the overflow and one latched in the previous
+/// period, `(count_high - 1, low)`, sorts before it.
+///
+/// A counter 64 bits wide leaves no room for a high word, which is then
+/// ignored.
+pub fn extended_timestamp(count_high: u32, low: u32, width: u32) -> u64 {
+    (count_high as u64).checked_shl(width).unwrap_or(0) | low as u64
+}
+
+/// Trait to represent clock frequency in Hz
+///
+/// This trait is used as an associated type for `Alarm` so clients can portably
+/// convert native cycles to real-time values.
+pub trait Frequency {
+    /// Returns frequency in Hz.
+    ///
+    /// For clocks that do not run at a whole number of Hz this is truncated;
+    /// the exact value is given by `frequency_num` and `frequency_den`.
+    fn frequency() -> u32;
+
+    /// Returns the numerator of the exact frequency in Hz.
+    ///
+    /// Defaults to `frequency()`. A clock running at 32768/3 Hz returns
+    /// `32768` here and `3` from `frequency_den`.
+    fn frequency_num() -> u32 {
+        Self::frequency()
+    }
+
+    /// Returns the denominator of the exact frequency in Hz. Defaults to `1`.
+    ///
+    /// Must be less than 4295, so that converting a full 32-bit count of tics
+    /// to microseconds fits in 64 bits.
+    fn frequency_den() -> u32 {
+        1
+    }
+
+    /// Converts milliseconds to clock tics.
+    ///
+    /// Like all conversions on this trait, the result uses the exact
+    /// frequency `frequency_num / frequency_den`, is rounded to the nearest
+    /// tic and is computed with 64-bit intermediates, so it cannot overflow
+    /// midway. Results that do not fit in a `u32` saturate at `u32::MAX`.
+    fn ms_to_tics(ms: u32) -> u32 {
+        scale(
+            ms,
+            Self::frequency_num() as u64,
+            Self::frequency_den() as u64 * 1000,
+        )
+    }
+
+    /// Converts milliseconds to clock tics like
+    /// [`ms_to_tics`](#method.ms_to_tics), but returns `None` instead of
+    /// saturating if the result does not fit in a `u32`.
+    fn ms_to_tics_checked(ms: u32) -> Option<u32> {
+        scale_checked(
+            ms,
+            Self::frequency_num() as u64,
+            Self::frequency_den() as u64 * 1000,
+        )
+    }
+
+    /// Converts microseconds to clock tics, rounding to the nearest tic.
+    fn us_to_tics(us: u32) -> u32 {
+        scale(
+            us,
+            Self::frequency_num() as u64,
+            Self::frequency_den() as u64 * 1_000_000,
+        )
+    }
+
+    /// Converts clock tics to milliseconds, rounding to the nearest
+    /// millisecond.
+    fn tics_to_ms(tics: u32) -> u32 {
+        scale(
+            tics,
+            Self::frequency_den() as u64 * 1000,
+            Self::frequency_num() as u64,
+        )
+    }
+
+    /// Converts clock tics to microseconds, rounding to the nearest
+    /// microsecond.
+    fn tics_to_us(tics: u32) -> u32 {
+        scale(
+            tics,
+            Self::frequency_den() as u64 * 1_000_000,
+            Self::frequency_num() as u64,
+        )
+    }
+
+    /// Converts nanoseconds to clock tics, rounding to the nearest tic.
+    ///
+    /// A clock cannot resolve less than one tic, 15.625ns at 64MHz, so spans
+    /// shorter than half a tic convert to `0` and a round trip through tics
+    /// is off by up to half a tic. Counts are 64-bit, since 32 bits of
+    /// nanoseconds only span about four seconds, and the conversion uses
+    /// 128-bit intermediates. Results saturate at `u64::MAX`.
+    fn ns_to_tics(ns: u64) -> u64 {
+        scale_u64(
+            ns,
+            Self::frequency_num() as u64,
+            Self::frequency_den() as u64 * 1_000_000_000,
+        )
+    }
+
+    /// Converts clock tics to nanoseconds, rounding to the nearest
+    /// nanosecond, with the same caveats as
+    /// [`ns_to_tics`](#method.ns_to_tics).
+    fn tics_to_ns(tics: u32) -> u64 {
+        scale_u64(
+            tics as u64,
+            Self::frequency_den() as u64 * 1_000_000_000,
+            Self::frequency_num() as u64,
+        )
+    }
+}
+
+/// Computes `value * num / den` rounded to the nearest integer, saturating at
+/// `u32::MAX`.
+fn scale(value: u32, num: u64, den: u64) -> u32 {
+    scale_checked(value, num, den).unwrap_or(u32::max_value())
+}
+
+/// Computes `value * num / den` rounded to the nearest integer, or `None` if
+/// the result does not fit in a `u32`.
+fn scale_checked(value: u32, num: u64, den: u64) -> Option<u32> {
+    let scaled = (value as u64 * num + den / 2) / den;
+    if scaled > u32::max_value() as u64 {
+        None
+    } else {
+        Some(scaled as u32)
+    }
+}
+
+/// Computes `value * num / den` rounded to the nearest integer with 128-bit
+/// intermediates, saturating at `u64::MAX`.
+fn scale_u64(value: u64, num: u64, den: u64) -> u64 {
+    let scaled = (value as u128 * num as u128 + den as u128 / 2) / den as u128;
+    if scaled > u64::max_value() as u128 {
+        u64::max_value()
+    } else {
+        scaled as u64
+    }
+}
+
+/// Converts a count of tics of a `from_hz` clock to tics of a `to_hz` clock.
+///
+/// The runtime counterpart of the conversions on
+/// [`Frequency`](trait.Frequency.html), for frequencies that are only known as
+/// values, e.g. from [`Time#frequency`](trait.Time.html#method.frequency).
+/// Like those, the result is rounded to the nearest tic, is computed with
+/// 64-bit intermediates and saturates at `u32::MAX`.
+///
+/// Panics if `from_hz` is `0`.
+pub fn convert_tics(tics: u32, from_hz: u32, to_hz: u32) -> u32 {
+    scale(tics, to_hz as u64, from_hz as u64)
+}
+
+/// Converts `ms` milliseconds to tics of a `hz` clock in a constant
+/// expression, e.g.
+///
+/// ```ignore
+/// const BOOT_DELAY: u32 = const_ms_to_tics(5, 16_000_000);
+/// ```
+///
+/// Rounds to the nearest tic like [`convert_tics`](fn.convert_tics.html),
+/// but does not saturate: a result that does not fit in a `u32` is a compile
+/// error when evaluated in a constant, and a panic when called at runtime.
+pub const fn const_ms_to_tics(ms: u32, hz: u32) -> u32 {
+    let scaled = (ms as u64 * hz as u64 + 500) / 1000;
+    // Indexing out of bounds exactly if the high word is not 0, which fails
+    // in every build profile, unlike an arithmetic overflow.
+    [scaled as u32][(scaled >> 32 != 0) as usize]
+}
+
+/// Converts a 64-bit count of tics like [`convert_tics`](fn.convert_tics.html),
+/// rounding to the nearest tic and saturating at `u64::MAX`.
+///
+/// Panics if `from_hz` is `0`.
+pub fn convert_tics_u64(tics: u64, from_hz: u32, to_hz: u32) -> u64 {
+    let (from_hz, to_hz) = (from_hz as u64, to_hz as u64);
+    // Whole seconds convert exactly; only the remainder needs rounding, and
+    // it is small enough not to overflow.
+    let seconds = tics / from_hz;
+    let rest = (tics % from_hz * to_hz + from_hz / 2) / from_hz;
+    seconds.saturating_mul(to_hz).saturating_add(rest)
+}
+
+/// 64MHz `Frequency`
+#[derive(Debug)]
+pub struct Freq64MHz;
+impl Frequency for Freq64MHz {
+    fn frequency() -> u32 {
+        64_000_000
+    }
+}
+
+/// 48MHz `Frequency`
+#[derive(Debug)]
+pub struct Freq48MHz;
+impl Frequency for Freq48MHz {
+    fn frequency() -> u32 {
+        48_000_000
+    }
+}
+
+/// 16MHz `Frequency`
+#[derive(Debug)]
+pub struct Freq16MHz;
+impl Frequency for Freq16MHz {
+    fn frequency() -> u32 {
+        16_000_000
+    }
+}
+
+/// 1MHz `Frequency`
+#[derive(Debug)]
+pub struct Freq1MHz;
+impl Frequency for Freq1MHz {
+    fn frequency() -> u32 {
+        1_000_000
+    }
+}
+
+/// 48KHz `Frequency`
+#[derive(Debug)]
+pub struct Freq48KHz;
+impl Frequency for Freq48KHz {
+    fn frequency() -> u32 {
+        48_000
+    }
+}
+
+/// 32KHz `Frequency`
+#[derive(Debug)]
+pub struct Freq32KHz;
+impl Frequency for Freq32KHz {
+    fn frequency() -> u32 {
+        32_768
+    }
+}
+
+/// 16KHz `Frequency`
+#[derive(Debug)]
+pub struct Freq16KHz;
+impl Frequency for Freq16KHz {
+    fn frequency() -> u32 {
+        16_000
+    }
+}
+
+/// 1KHz `Frequency`
+#[derive(Debug)]
+pub struct Freq1KHz;
+impl Frequency for Freq1KHz {
+    fn frequency() -> u32 {
+        1_000
+    }
+}
+
+/// Declares a unit struct that is a `Frequency` of `$hz` Hz, for clocks
+/// without a preset above, such as divided clocks:
+///
+/// ```ignore
+/// kernel::frequency!(pub Freq13107Hz, 13107);
+/// ```
+#[macro_export]
+macro_rules! frequency {
+    ($(#[$attr:meta])* $vis:vis $name:ident, $hz:expr) => {
+        $(#[$attr])*
+        #[derive(Debug)]
+        $vis struct $name;
+        impl $crate::hil::time::Frequency for $name {
+            fn frequency() -> u32 {
+                $hz
+            }
+        }
+    };
+}
+
+/// The `Alarm` trait models a wrapping counter capable of notifying when the
+/// counter reaches a certain value.
+///
+/// Alarms represent a resource that keeps track of time in some fixed unit
+/// (usually clock tics). Implementers should use the
+/// [`Client`](trait.Client.html) trait to signal when the counter has
+/// reached a pre-specified value set in [`set_alarm`](#method.set_alarm).
+///
+/// An alarm is either enabled (armed) or disabled, and moves between the two
+/// as follows:
+///
+/// - [`set_alarm`](#method.set_alarm) records the deadline and enables the
+///   alarm. There is no separate step to enable it.
+/// - Firing disables the alarm before the client is called.
+/// - [`disable`](#tymethod.disable) disables the alarm. Nothing is delivered
+///   while it is disabled, and a deadline that passes meanwhile is not
+///   latched, but `get_alarm()` keeps returning it. Disabling an alarm that
+///   is already disabled does nothing and returns `ReturnCode::SUCCESS`.
+/// - [`enable`](#method.enable) re-arms the deadline last passed to
+///   `set_alarm`. One that passed while the alarm was disabled fires as soon
+///   as possible, if the implementation can tell that it has passed (see
+///   below).
+/// - [`mask`](#method.mask) only holds off delivery. The alarm stays armed,
+///   and a deadline reached while masked is latched and delivered after
+///   [`unmask`](#method.unmask).
+///
+/// A deadline that passes while the alarm cannot fire must not be lost:
+///
+/// - If interrupts are disabled when the deadline is reached, the alarm fires
+///   as soon as they are enabled again. Comparator hardware latches the
+///   match, so this holds as long as the comparator was programmed before
+///   the counter reached it.
+/// - If the counter passes the deadline while `set_alarm` is programming the
+///   hardware, e.g. because it was interrupted, the implementation must
+///   notice after programming and re-arm for the earliest time it can fire,
+///   instead of waiting for the counter to wrap around to the deadline.
+/// - If a deadline passes while the alarm is disabled, `enable` arms the
+///   alarm to fire as soon as possible, if the implementation can tell that
+///   the deadline has passed (see below).
+pub trait Alarm<'a>: Time {
+    /// Sets a one-shot alarm to fire when the clock reaches `tics`.
+    ///
+    /// [`Client#fired`](trait.Client.html#tymethod.fired) is signaled
+    /// when `tics` is reached.
+    ///
+    /// # Examples
+    ///
+    /// ```ignore
+    /// let delta = 1337;
+    /// let tics = alarm.now().wrapping_add(A::Ticks::from_u32(delta));
+    /// alarm.set_alarm(tics);
+    /// ```
+    ///
+    /// Returns `ReturnCode::SUCCESS` if the alarm was armed. Returns
+    /// `ReturnCode::EINVAL` without arming the alarm if `tics` is ahead of
+    /// `now()` by fewer than [`minimum_dt`](#tymethod.minimum_dt) tics, as
+    /// the hardware cannot reliably fire that soon, and `ReturnCode::EOFF` if
+    /// the underlying counter is not running. Virtual alarms
+    /// (`capsules::virtual_alarm`) are the exception: they accept such
+    /// deadlines and fire them up to `minimum_dt` tics late, so they never
+    /// return `ReturnCode::EINVAL`.
+    ///
+    /// `tics` is an absolute counter value. If the counter has already
+    /// reached it, at most `minimum_dt` tics ago, e.g. because the caller was
+    /// interrupted after computing it, the alarm fires immediately, i.e. as
+    /// soon as the hardware allows (see
+    /// [`reached_within`](fn.reached_within.html)). Otherwise the alarm fires
+    /// when the counter reaches `tics`, `(tics - now()) mod (max_tics() + 1)`
+    /// tics from now, so a value further behind `now()` is a deadline almost
+    /// one counter period away; use [`set_alarm_from`](#method.set_alarm_from)
+    /// for deadlines that may have passed longer ago. If the counter reaches
+    /// `tics` while the hardware is being programmed, the alarm fires as soon
+    /// as possible (see above).
+    ///
+    /// All implementations must behave like this whatever their comparator
+    /// does. One that matches on `>=` against a counter wider than `Ticks`
+    /// must extend `tics` to the next time the low bits of the counter reach
+    /// it. `capsules::test::alarm_conformance` checks these rules on
+    /// hardware.
+    ///
+    /// Equivalent to `set_alarm_with_token(tics, 0)`.
+    fn set_alarm(&self, tics: Self::Ticks) -> ReturnCode {
+        self.set_alarm_with_token(tics, 0)
+    }
+
+    /// Sets an alarm like [`set_alarm`](#method.set_alarm) and records
+    /// `token`, which is passed back to
+    /// [`AlarmClient#fired_with_token`](trait.AlarmClient.html#method.fired_with_token)
+    /// when the alarm fires.
+    ///
+    /// This lets a client that multiplexes several logical alarms onto one
+    /// `Alarm` tell which of them fired. Only the token of the most recent
+    /// successful call is kept; the token is left unchanged if the alarm is
+    /// not armed.
+    fn set_alarm_with_token(&self, tics: Self::Ticks, token: u32) -> ReturnCode;
+
+    /// Sets an alarm to fire `dt` tics after `reference`, where `reference`
+    /// is typically an earlier reading of `now()`.
+    ///
+    /// Unlike adding `dt` to `now()` in the caller, this reads the clock
+    /// itself immediately before arming, so the deadline cannot silently end
+    /// up in the past. If the deadline `reference + dt` has already been
+    /// reached (as judged by [`has_expired`](fn.has_expired.html), i.e. less
+    /// than one counter period has passed since `reference`), or is closer
+    /// than [`minimum_dt`](#tymethod.minimum_dt) tics, the alarm is instead
+    /// armed `minimum_dt` tics from now so that it fires as soon as the
+    /// hardware allows rather than a full counter period later.
+    ///
+    /// Returns `ReturnCode::SUCCESS` if the alarm was armed, and
+    /// `ReturnCode::EOFF` if the underlying counter is not running. If
+    /// `set_alarm` returns `ReturnCode::EINVAL`, it is retried once at
+    /// `minimum_dt` tics from now, and its result returned.
+    fn set_alarm_from(&self, reference: Self::Ticks, dt: Self::Ticks) -> ReturnCode {
+        let deadline = reference.wrapping_add(dt);
+        let now = self.now();
+        let min_dt = self.minimum_dt();
+        let tics = if has_expired(reference, deadline, now) || deadline.wrapping_sub(now) < min_dt {
+            now.wrapping_add(min_dt)
+        } else {
+            deadline
+        };
+        match self.set_alarm(tics) {
+            // The clock may have advanced past `now + min_dt` since it was
+            // read. Not retried again, as backends also return `EINVAL` for
+            // deadlines they can never accept.
+            ReturnCode::EINVAL => self.set_alarm(self.now().wrapping_add(min_dt)),
+            result => result,
+        }
+    }
+
+    /// Sets an alarm for `tics`, or for `minimum_dt` tics from now if `tics`
+    /// is closer than that, and returns the value actually programmed.
+    /// Returns the error from `set_alarm` if the alarm was not armed, after
+    /// retrying once at `minimum_dt` tics from now on `ReturnCode::EINVAL`.
+    ///
+    /// [`set_alarm`](#method.set_alarm) rejects such deadlines with
+    /// `ReturnCode::EINVAL`, and hardware without that check may not tell
+    /// them apart from a deadline a full counter period away. This is for
+    /// callers that compute `tics` from input they do not control, e.g. a
+    /// delay requested by an app, and would rather fire slightly late: the
+    /// alarm always fires at least [`minimum_dt`](#tymethod.minimum_dt) tics
+    /// after it was set, and the return value tells the caller whether and
+    /// by how much the request was moved.
+    ///
+    /// Only deadlines within `minimum_dt` tics after `now()` are clamped. A
+    /// deadline that passed more than `minimum_dt` tics ago cannot be told
+    /// apart from one far in the future without a reference point; use
+    /// [`set_alarm_from`](#method.set_alarm_from) for those.
+    fn set_alarm_clamped(&self, tics: Self::Ticks) -> Result<Self::Ticks, ReturnCode> {
+        let now = self.now();
+        let min_dt = self.minimum_dt();
+        let mut clamped = if tics.wrapping_sub(now) < min_dt {
+            now.wrapping_add(min_dt)
+        } else {
+            tics
+        };
+        let mut result = self.set_alarm(clamped);
+        if result == ReturnCode::EINVAL {
+            // The clock may have advanced past `now + min_dt` since it was
+            // read.
+            clamped = self.now().wrapping_add(min_dt);
+            result = self.set_alarm(clamped);
+        }
+        match result {
+            ReturnCode::SUCCESS => Ok(clamped),
+            error => Err(error),
+        }
+    }
+
+    /// Sets an alarm `duration_ms` milliseconds from now, at the clock's
+    /// current `frequency()`.
+    ///
+    /// Returns `ReturnCode::ESIZE` without arming the alarm if the delay is
+    /// longer than one counter period, i.e. more than `max_tics()` tics.
+    /// Converting such a delay to tics would otherwise alias it to a much
+    /// shorter one; callers can instead chain several shorter alarms.
+    fn try_set_alarm_after(&self, duration_ms: u32) -> ReturnCode {
+        match scale_checked(duration_ms, self.frequency() as u64, 1000) {
+            Some(tics) if tics as u64 <= self.max_tics().into_u64() => {
+                self.set_alarm_from(self.now(), Self::Ticks::from_u32(tics))
+            }
+            _ => ReturnCode::ESIZE,
+        }
+    }
+
+    /// Sets an alarm for the next counter value `v` with `v % period ==
+    /// phase` that is at least [`minimum_dt`](#tymethod.minimum_dt) tics
+    /// away, so that alarms fire on boundaries aligned to the counter's zero
+    /// rather than relative to when they were set, e.g. for sampling in step
+    /// with other nodes whose counters were zeroed together. A client that
+    /// calls this again from `fired()` gets a repeating aligned cadence,
+    /// which realigns on every fire.
+    ///
+    /// Unless `period` divides the counter's range of `max_tics() + 1`
+    /// values, the aligned values restart at `phase` when the counter wraps,
+    /// so the one interval that spans the wrap is `(max_tics() + 1) % period`
+    /// tics long, or that plus `period`, instead of `period`. All other
+    /// intervals are exact.
+    ///
+    /// Returns `ReturnCode::EINVAL` without arming the alarm if `period` is
+    /// `0` or larger than `max_tics()`, or `phase` is not less than `period`.
+    /// If `set_alarm` returns `ReturnCode::EINVAL`, the next aligned value is
+    /// computed from the current time and tried once more.
+    fn set_alarm_aligned(&self, period: u32, phase: u32) -> ReturnCode {
+        let max = self.max_tics().into_u64();
+        if period == 0 || period as u64 > max || phase >= period {
+            return ReturnCode::EINVAL;
+        }
+        // In 128 bits, as the range of a 64-bit counter does not fit in 64.
+        let (period, phase, range) = (period as u128, phase as u128, max as u128 + 1);
+        // The first aligned value at or after `tics`, not wrapped.
+        let align = |tics: u128| {
+            let aligned = tics - tics % period + phase;
+            if aligned < tics {
+                aligned + period
+            } else {
+                aligned
+            }
+        };
+        let next = || {
+            let now = self.now().into_u64() as u128;
+            let earliest = now + self.minimum_dt().into_u64() as u128;
+            let next = align(earliest);
+            if next >= range {
+                // Past the wrap, where the aligned values restart at `phase`.
+                align(earliest.saturating_sub(range))
+            } else {
+                next
+            }
+        };
+        match self.set_alarm(Self::Ticks::from_u64(next() as u64)) {
+            // The clock may have advanced past the aligned value since it was
+            // read.
+            ReturnCode::EINVAL => self.set_alarm(Self::Ticks::from_u64(next() as u64)),
+            result => result,
+        }
+    }
+
+    /// Returns the value set in [`set_alarm`](#method.set_alarm)
+    fn get_alarm(&self) -> Self::Ticks;
+
+    /// Returns `(now(), get_alarm())` as a consistent pair.
+    ///
+    /// Implementations should read both with interrupts masked, so that code
+    /// deciding whether to reprogram the alarm does not see a current time
+    /// and a compare value with an alarm interrupt between them. The default
+    /// implementation simply reads one after the other.
+    fn now_and_alarm(&self) -> (Self::Ticks, Self::Ticks) {
+        (self.now(), self.get_alarm())
+    }
+
+    /// Returns the minimum number of tics between `now()` and a value passed
+    /// to [`set_alarm`](#method.set_alarm) for the alarm to fire reliably.
+    ///
+    /// Comparator-based hardware alarms typically need 2-3 tics, a software
+    /// alarm can usually return 1. Callers that compute short deadlines should
+    /// schedule at least `minimum_dt()` tics ahead, e.g.
+    ///
+    /// ```ignore
+    /// let dt = core::cmp::max(requested_dt, alarm.minimum_dt());
+    /// alarm.set_alarm(alarm.now().wrapping_add(dt));
+    /// ```
+    fn minimum_dt(&self) -> Self::Ticks;
+
+    /// Makes the alarm fire up to `tics` tics before each deadline, to make
+    /// up for the interrupt dispatch latency between the compare match and
+    /// the client's `fired()` callback.
+    ///
+    /// Later calls to [`set_alarm`](#method.set_alarm) program the hardware
+    /// for `tics - compensation`, but never for less than
+    /// [`minimum_dt`](#tymethod.minimum_dt) tics after `now()` (see
+    /// [`compensate_latency`](fn.compensate_latency.html)), so a short
+    /// deadline is compensated less rather than programmed behind the
+    /// counter. `get_alarm()` still returns the requested deadline. An alarm
+    /// that is already armed is not reprogrammed.
+    ///
+    /// The compensation starts at `0`. Returns `ReturnCode::ENOSUPPORT` if the
+    /// alarm cannot compensate for latency, which is the default.
+    fn set_latency_compensation(&self, _tics: u32) -> ReturnCode {
+        ReturnCode::ENOSUPPORT
+    }
+
+    /// Set the client for interrupt events.
+    fn set_client(&'a self, client: &'a dyn AlarmClient);
+
+    /// Disables the alarm and removes the client set with
+    /// [`set_client`](#tymethod.set_client), e.g. when tearing down a capsule
+    /// or swapping clients in a test.
+    ///
+    /// Until a client is set again, a fire, such as one the hardware had
+    /// already latched, calls nobody.
+    fn clear_client(&self);
+
+    /// Returns whether this alarm is currently active (will eventually trigger
+    /// a callback if there is a client).
+    fn is_enabled(&self) -> bool;
+
+    /// Returns the compare value of the armed alarm, or `None` if there is no
+    /// pending wakeup from this alarm.
+    ///
+    /// Intended for the power manager of a tickless kernel: together with
+    /// `now()` it gives how long the chip can sleep before this alarm needs
+    /// it, e.g. to decide whether a deep sleep mode is worth entering.
+    fn next_wakeup(&self) -> Option<Self::Ticks> {
+        if self.is_enabled() {
+            Some(self.get_alarm())
+        } else {
+            None
+        }
+    }
+
+    /// Enables the alarm using the previously set `tics` for the alarm.
+    ///
+    /// Most implementations should use the default implementation which calls `set_alarm` with the
+    /// value returned by `get_alarm` unless there is a more efficient way to achieve the same
+    /// semantics.
+    ///
+    /// A deadline that passed while the alarm was disabled fires as soon as possible, as described
+    /// in the trait documentation. The default implementation can only tell this for a deadline
+    /// reached at most [`minimum_dt`](#tymethod.minimum_dt) tics ago, and takes one that passed
+    /// longer ago as a counter period away. Implementations that remember when the deadline was
+    /// set should instead arm it for `minimum_dt` tics from now, and return `ReturnCode::EINVAL`
+    /// if the deadline already fired or `set_alarm` was never called, as there is then no
+    /// deadline to re-arm. Otherwise returns what `set_alarm` returns.
+    fn enable(&self) -> ReturnCode {
+        self.set_alarm(self.get_alarm())
+    }
+
+    /// Disables the alarm.
+    ///
+    /// The implementation will _always_ disable the alarm and prevent events related to previously
+    /// set alarms from being delivered to the client.
+    ///
+    /// This only affects pending notifications. The underlying counter keeps running and `now()`
+    /// keeps advancing, since other clients may share it; use
+    /// [`Counter#stop`](trait.Counter.html#tymethod.stop) to stop the count.
+    ///
+    /// Disabling cannot fail and is idempotent: it always returns `ReturnCode::SUCCESS`, also if
+    /// the alarm was not enabled. The deadline is kept for [`enable`](#method.enable), but not
+    /// latched; to hold off a callback without disarming the alarm, use [`mask`](#method.mask)
+    /// instead.
+    fn disable(&self) -> ReturnCode;
+
+    /// Holds off calling the client until [`unmask`](#method.unmask), e.g.
+    /// for the length of a critical section, without changing the deadline.
+    ///
+    /// Masking only gates delivery: `get_alarm()` and `is_enabled()` are
+    /// unchanged, and a deadline reached while masked is latched and
+    /// delivered as soon as possible after `unmask`, like an interrupt that
+    /// was pending while interrupts were off. This differs from
+    /// [`disable`](#tymethod.disable), after which nothing is delivered
+    /// until [`enable`](#method.enable) (see the trait documentation), and
+    /// from [`Counter#stop`](trait.Counter.html#tymethod.stop), which stops
+    /// `now()` advancing for every alarm on the counter.
+    ///
+    /// Masking an alarm that is already masked does nothing. Setting or
+    /// disabling the alarm while masked discards a latched deadline, but the
+    /// alarm stays masked. Returns `ReturnCode::ENOSUPPORT` if the alarm
+    /// cannot be masked, which is the default.
+    fn mask(&self) -> ReturnCode {
+        ReturnCode::ENOSUPPORT
+    }
+
+    /// Lifts a [`mask`](#method.mask). If the deadline was reached while
+    /// masked, the client is called as soon as possible.
+    ///
+    /// Unmasking an alarm that is not masked does nothing. Returns
+    /// `ReturnCode::ENOSUPPORT` if the alarm cannot be masked, which is the
+    /// default.
+    fn unmask(&self) -> ReturnCode {
+        ReturnCode::ENOSUPPORT
+    }
+
+    /// Returns how many tics are left until the armed alarm fires, or `None`
+    /// if it is not enabled.
+    ///
+    /// Use this instead of subtracting `now()` from `get_alarm()`, which gets
+    /// the wrap wrong. An alarm whose deadline has already passed but whose
+    /// callback has not been delivered yet reports `Some(0)`; deadlines more
+    /// than half a counter period away are treated as having passed. Both
+    /// values are read with [`now_and_alarm`](#method.now_and_alarm), and the
+    /// difference is taken modulo the counter width.
+    fn remaining(&self) -> Option<Self::Ticks> {
+        if !self.is_enabled() {
+            return None;
+        }
+        let (now, alarm) = self.now_and_alarm();
+        Some(ticks_remaining(now, alarm, self.max_tics()))
+    }
+
+    /// Disables the alarm like [`disable`](#tymethod.disable) and returns how
+    /// many tics were left until it would have fired, as computed by
+    /// [`remaining`](#method.remaining).
+    ///
+    /// Returns `None` if no alarm was armed. Disabling cannot fail, so the
+    /// `ReturnCode` is always `ReturnCode::SUCCESS`.
+    ///
+    /// Can be used to re-arm an alarm with the same effective deadline after
+    /// the counter was stopped, e.g.
+    ///
+    /// ```ignore
+    /// let (_, remaining) = alarm.disable_and_peek();
+    /// // ... sleep with the counter stopped ...
+    /// if let Some(dt) = remaining {
+    ///     alarm.set_alarm_from(alarm.now(), dt);
+    /// }
+    /// ```
+    fn disable_and_peek(&self) -> (ReturnCode, Option<Self::Ticks>) {
+        let remaining = self.remaining();
+        (self.disable(), remaining)
+    }
+
+    /// Postpones the armed alarm by `extra_tics`, so that it fires at
+    /// `get_alarm() + extra_tics` instead.
+    ///
+    /// The alarm is reprogrammed in place rather than disabled and set again,
+    /// so it cannot fire in between. If the new deadline has already passed,
+    /// e.g. the old one fired but was not delivered yet and `extra_tics` is
+    /// small, the alarm fires as soon as possible, as with
+    /// [`set_alarm_from`](#method.set_alarm_from).
+    ///
+    /// Returns `ReturnCode::EINVAL` without changing anything if no alarm is
+    /// armed, or if the new deadline would be more than `max_tics()` tics
+    /// from now and so alias to an earlier one.
+    fn snooze(&self, extra_tics: u32) -> ReturnCode {
+        if !self.is_enabled() {
+            return ReturnCode::EINVAL;
+        }
+        let (now, alarm) = self.now_and_alarm();
+        let max = self.max_tics().into_u64();
+        let delta = wrapping_delta(now, alarm, self.max_tics());
+        if delta > max / 2 {
+            // Passed: measure from the old deadline, which is in the past.
+            if extra_tics as u64 > max {
+                return ReturnCode::EINVAL;
+            }
+            self.set_alarm_from(alarm, Self::Ticks::from_u32(extra_tics))
+        } else {
+            match delta.checked_add(extra_tics as u64) {
+                Some(dt) if dt <= max => self.set_alarm_from(now, Self::Ticks::from_u64(dt)),
+                _ => ReturnCode::EINVAL,
+            }
+        }
+    }
+
+    /// Returns a snapshot of the alarm's state from its getters, for trace
+    /// dumps. The values are read one after another, so a snapshot taken
+    /// while the alarm fires may be inconsistent.
+    fn describe(&self) -> TimeState {
+        TimeState {
+            now: self.now().into_u64(),
+            frequency: self.frequency(),
+            enabled: self.is_enabled(),
+            alarm: self.next_wakeup().map(Ticks::into_u64),
+            interval: None,
+            remaining: self.remaining().map(Ticks::into_u64),
+        }
+    }
+}
+
+/// A client of an implementer of the [`Alarm`](trait.Alarm.html) trait.
+pub trait AlarmClient {
+    /// Callback signaled when the alarm's clock reaches the value set in
+    /// [`Alarm#set_alarm`](trait.Alarm.html#method.set_alarm).
     fn fired(&self);
+
+    /// Callback signaled when the alarm's clock reaches the value set in
+    /// [`Alarm#set_alarm_with_token`](trait.Alarm.html#tymethod.set_alarm_with_token),
+    /// with the token that was passed there.
+    ///
+    /// Alarms always signal this method. The default implementation ignores
+    /// the token and calls `fired`.
+    fn fired_with_token(&self, _token: u32) {
+        self.fired();
+    }
+}
+
+/// An `AlarmClient` that forwards every callback to two other clients, in
+/// order.
+///
+/// Alarms only store a single client. Setting a `TeeAlarmClient` as the client
+/// lets a second client, such as a tracing layer, observe every firing
+/// without changing the client that owns the alarm.
+pub struct TeeAlarmClient<'a> {
+    first: &'a dyn AlarmClient,
+    second: &'a dyn AlarmClient,
+}
+
+impl TeeAlarmClient<'a> {
+    pub const fn new(
+        first: &'a dyn AlarmClient,
+        second: &'a dyn AlarmClient,
+    ) -> TeeAlarmClient<'a> {
+        TeeAlarmClient { first, second }
+    }
+}
+
+impl AlarmClient for TeeAlarmClient<'a> {
+    fn fired(&self) {
+        self.first.fired();
+        self.second.fired();
+    }
+
+    fn fired_with_token(&self, token: u32) {
+        self.first.fired_with_token(token);
+        self.second.fired_with_token(token);
+    }
+}
+
+/// An `AlarmClient` that wakes the task awaiting a
+/// [`DelayFuture`](struct.DelayFuture.html).
+///
+/// It must be set as the client of the alarm the delays are taken on, once,
+/// before the first delay. Only one delay per alarm can be outstanding.
+pub struct AlarmWaker {
+    waker: Cell<Option<Waker>>,
+    fired: Cell<bool>,
+}
+
+impl AlarmWaker {
+    pub const fn new() -> AlarmWaker {
+        AlarmWaker {
+            waker: Cell::new(None),
+            fired: Cell::new(false),
+        }
+    }
+}
+
+impl AlarmClient for AlarmWaker {
+    fn fired(&self) {
+        self.fired.set(true);
+        if let Some(waker) = self.waker.take() {
+            waker.wake();
+        }
+    }
+}
+
+/// A `Future` that completes once a delay on an `Alarm` has elapsed, for
+/// `alarm.delay_ms(&waker, ms).await` in async code.
+///
+/// The delay is counted from when the future is created, and the alarm is
+/// armed when it is first polled. Delays longer than the alarm can be set for
+/// are taken in steps of half its period, re-arming from the previous step's
+/// deadline so that no time is lost between steps. Dropping the future before
+/// it completes disables the alarm. It does not allocate and works with any
+/// executor.
+pub struct DelayFuture<'a, A: Alarm<'a>> {
+    alarm: &'a A,
+    waker: &'a AlarmWaker,
+    reference: A::Ticks,
+    /// Tics left to wait from `reference`.
+    remaining: u64,
+    armed: bool,
+    done: bool,
+}
+
+impl<A: Alarm<'a>> DelayFuture<'a, A> {
+    /// The tics the next step waits for from `reference`.
+    fn step(&self) -> u64 {
+        core::cmp::min(self.remaining, self.alarm.max_tics().into_u64() / 2)
+    }
+
+    fn arm_step(&mut self) {
+        self.armed = true;
+        self.waker.fired.set(false);
+        self.alarm
+            .set_alarm_from(self.reference, A::Ticks::from_u64(self.step()));
+    }
+}
+
+// The future holds no references to itself, so it can be moved after being
+// polled whatever the alarm's `Ticks` type is.
+impl<A: Alarm<'a>> Unpin for DelayFuture<'a, A> {}
+
+impl<A: Alarm<'a>> Future for DelayFuture<'a, A> {
+    type Output = ();
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<()> {
+        let this = self.get_mut();
+        if this.armed && this.waker.fired.get() {
+            // One step has elapsed: finish, or move on to the next one.
+            let step = this.step();
+            this.remaining -= step;
+            if this.remaining == 0 {
+                this.armed = false;
+                this.done = true;
+                return Poll::Ready(());
+            }
+            this.reference = this.reference.wrapping_add(A::Ticks::from_u64(step));
+            this.armed = false;
+        }
+        // Store the waker before arming, so that an alarm that fires right
+        // away finds it.
+        this.waker.waker.set(Some(cx.waker().clone()));
+        if !this.armed {
+            this.arm_step();
+        }
+        Poll::Pending
+    }
+}
+
+impl<A: Alarm<'a>> Drop for DelayFuture<'a, A> {
+    fn drop(&mut self) {
+        if self.armed && !self.done {
+            self.alarm.disable();
+            self.waker.waker.set(None);
+        }
+    }
+}
+
+/// Delays for async code on any `Alarm`.
+pub trait AlarmDelay<'a>: Alarm<'a> + Sized {
+    /// Returns a future that completes `ms` milliseconds from now, at the
+    /// alarm's current `frequency()`. `waker` must be the alarm's client.
+    ///
+    /// Delays longer than one counter period are taken in several steps.
+    fn delay_ms(&'a self, waker: &'a AlarmWaker, ms: u32) -> DelayFuture<'a, Self> {
+        DelayFuture {
+            alarm: self,
+            waker,
+            reference: self.now(),
+            remaining: scale_u64(ms as u64, self.frequency() as u64, 1000),
+            armed: false,
+            done: false,
+        }
+    }
+}
+
+impl<A: Alarm<'a>> AlarmDelay<'a> for A {}
+
+/// A `Time` counting at exactly frequency `F`.
+///
+/// Code whose timing is only correct at one frequency can bound its clock
+/// with `T: TimeAt<Freq1KHz>` instead of converting with `frequency()` at
+/// runtime. Passing a clock that counts at another frequency is then a
+/// compile error, and an explicit converter such as
+/// `capsules::frequency_convert_alarm::FrequencyConvertAlarm` has to be
+/// inserted. This is a shorthand for `Time<Frequency = F>`, which every
+/// `Time` with that frequency implements.
+pub trait TimeAt<F: Frequency>: Time<Frequency = F> {}
+
+impl<F: Frequency, T: Time<Frequency = F>> TimeAt<F> for T {}
+
+/// An `Alarm` counting at exactly frequency `F`; see
+/// [`TimeAt`](trait.TimeAt.html).
+pub trait AlarmAt<'a, F: Frequency>: Alarm<'a> + TimeAt<F> {}
+
+impl<F: Frequency, A: Alarm<'a> + TimeAt<F>> AlarmAt<'a, F> for A {}
+
+/// A client of a [`Timeout`](struct.Timeout.html).
+pub trait TimeoutClient {
+    /// Called when the guarded operation did not complete in time. The
+    /// client should abort it.
+    fn timed_out(&self);
+}
+
+/// Aborts an operation that does not complete within a deadline.
+///
+/// The owner calls [`start`](#method.start) when starting an operation, such
+/// as a bus transaction, and [`clear`](#method.clear) from the operation's
+/// completion callback. If the deadline passes first, the client's
+/// `timed_out` is called instead.
+///
+/// Completion and the deadline can happen at the same time, e.g. with both
+/// interrupts pending. Whichever callback runs first wins and the other is a
+/// no-op: `clear` disarms the alarm so `timed_out` is not called, and after
+/// `timed_out` has been called `clear` returns `false`, telling the
+/// completion callback that the operation was already aborted.
+///
+/// The `Timeout` must be the alarm's client.
+pub struct Timeout<'a, A: Alarm<'a>> {
+    alarm: &'a A,
+    armed: Cell<bool>,
+    client: OptionalCell<&'a dyn TimeoutClient>,
+}
+
+impl<A: Alarm<'a>> Timeout<'a, A> {
+    pub const fn new(alarm: &'a A) -> Timeout<'a, A> {
+        Timeout {
+            alarm,
+            armed: Cell::new(false),
+            client: OptionalCell::empty(),
+        }
+    }
+
+    pub fn set_client(&self, client: &'a dyn TimeoutClient) {
+        self.client.set(client);
+    }
+
+    /// Calls the client `dt` tics from now unless `clear` is called first,
+    /// replacing the deadline if the timeout is already running.
+    ///
+    /// Returns what [`Alarm#set_alarm_from`](trait.Alarm.html#method.set_alarm_from)
+    /// returns. The timeout only runs if that is `ReturnCode::SUCCESS`.
+    pub fn start(&self, dt: A::Ticks) -> ReturnCode {
+        let result = self.alarm.set_alarm_from(self.alarm.now(), dt);
+        self.armed.set(result == ReturnCode::SUCCESS);
+        result
+    }
+
+    /// Stops the timeout because the operation completed.
+    ///
+    /// Returns `true` if the timeout was still running, so the operation
+    /// completed in time, and `false` if it was not running, in particular if
+    /// `timed_out` has already been called for it.
+    pub fn clear(&self) -> bool {
+        let armed = self.armed.replace(false);
+        if armed {
+            self.alarm.disable();
+        }
+        armed
+    }
+
+    pub fn is_running(&self) -> bool {
+        self.armed.get()
+    }
+}
+
+impl<A: Alarm<'a>> AlarmClient for Timeout<'a, A> {
+    fn fired(&self) {
+        if self.armed.replace(false) {
+            self.client.map(|client| client.timed_out());
+        }
+    }
+}
+
+/// An alarm multiplexed onto a shared underlying alarm, as seen by
+/// [`dispatch_fired`](fn.dispatch_fired.html).
+pub trait DispatchClient<T: Ticks> {
+    /// If the alarm is armed and has expired at `now`, disarms it, marks it as
+    /// due and returns `true`. Must not call any client.
+    fn mark_if_expired(&self, now: T) -> bool;
+
+    /// Clears the mark set by `mark_if_expired`, returning whether it was
+    /// set.
+    fn take_due(&self) -> bool;
+
+    /// Calls the alarm's client.
+    fn fire(&self);
+}
+
+/// Calls every client in `clients` that has expired at `now`, and returns how
+/// many were called.
+///
+/// The set of expired clients is fixed before the first callback runs, so a
+/// client that re-arms itself or another client from `fired()`, even for a
+/// deadline that has already passed, is not called again in the same pass. A
+/// multiplexer should program its underlying alarm for any such deadline
+/// after `dispatch_fired` returns, rather than calling it again directly.
+///
+/// A client that is disabled by an earlier callback in the same pass should
+/// clear its due mark, so that it is not called.
+pub fn dispatch_fired<'a, T, C, I>(clients: I, now: T) -> usize
+where
+    T: Ticks,
+    C: DispatchClient<T> + 'a + ?Sized,
+    I: Iterator<Item = &'a C> + Clone,
+{
+    let due = clients
+        .clone()
+        .filter(|client| client.mark_if_expired(now))
+        .count();
+    if due == 0 {
+        return 0;
+    }
+    let mut fired = 0;
+    for client in clients.filter(|client| client.take_due()) {
+        client.fire();
+        fired += 1;
+    }
+    fired
+}
+
+/// The `Timer` trait models a timer that can notify when a particular interval
+/// has elapsed.
+pub trait Timer<'a>: Time {
+    /// Set the client for interrupt events.
+    fn set_client(&'a self, client: &'a dyn TimerClient);
+
+    /// Sets a one-shot timer to fire in `interval` clock-tics.
+    ///
+    /// Calling this method will override any existing oneshot or repeating timer.
+    fn oneshot(&self, interval: Self::Ticks);
+
+    /// Sets repeating timer to fire every `interval` clock-tics.
+    ///
+    /// Calling this method will override any existing oneshot or repeating timer.
+    fn repeat(&self, interval: Self::Ticks);
+
+    /// Sets a repeating timer to fire every `interval` clock-tics, `count`
+    /// times in total, after which it stops by itself.
+    ///
+    /// `count == 0` means no limit, like [`repeat`](#tymethod.repeat), and
+    /// `count == 1` fires once, like [`oneshot`](#tymethod.oneshot). The timer
+    /// is repeating until the last fire starts, so `interval()` and
+    /// `change_interval` work as for `repeat`; when the client is called for
+    /// the last time the timer is already disabled.
+    ///
+    /// Calling [`cancel`](#tymethod.cancel), `oneshot`, `repeat` or `repeat_n`
+    /// again, also from the `fired()` callback, discards the fires that are
+    /// left of the sequence; nothing of it carries over to a later timer.
+    ///
+    /// Returns `EINVAL` without changing the timer if `interval` is `0`.
+    fn repeat_n(&self, interval: Self::Ticks, count: u32) -> ReturnCode;
+
+    /// Returns the smallest `divisor` that
+    /// [`set_tick_callback`](#method.set_tick_callback) accepts.
+    ///
+    /// Defaults to `1`. Timers that re-arm an alarm after every fire should
+    /// return the alarm's `minimum_dt()`, and timers with hardware auto-reload
+    /// the shortest period their reload logic supports.
+    fn min_tick_divisor(&self) -> u32 {
+        1
+    }
+
+    /// Calls the client every `divisor` tics of the timer's clock until the
+    /// timer is cancelled, e.g. to drive a software PWM.
+    ///
+    /// This is `repeat(divisor)` for callers that count native tics. Timers
+    /// with a hardware auto-reload register should implement it with that,
+    /// so that nothing is reprogrammed per period. Others re-arm after every
+    /// fire, which is what the default does through `repeat`.
+    ///
+    /// The callback rate is at most `frequency() / min_tick_divisor()` per
+    /// second, and is only sustainable if each period is longer than the
+    /// interrupt entry, the dispatch to the client and the client's `fired()`
+    /// together. That is typically several microseconds on a Cortex-M, so
+    /// rates above some tens of kHz need auto-reload hardware and a very short
+    /// callback. A timer that falls behind fires late rather than skipping
+    /// periods, so missed periods are caught up back to back.
+    ///
+    /// Returns `ENOSUPPORT` without changing the timer if `divisor` is `0` or
+    /// below `min_tick_divisor()`.
+    fn set_tick_callback(&self, divisor: u32) -> ReturnCode {
+        if divisor == 0 || divisor < self.min_tick_divisor() {
+            return ReturnCode::ENOSUPPORT;
+        }
+        self.repeat(Self::Ticks::from_u32(divisor));
+        ReturnCode::SUCCESS
+    }
+
+    /// Returns the interval for a repeating timer.
+    ///
+    /// Returns `None` if the timer is disabled or in oneshot mode and `Some(interval)` if it is
+    /// repeating.
+    fn interval(&self) -> Option<Self::Ticks>;
+
+    /// Changes the interval of a repeating timer without restarting it.
+    ///
+    /// The change takes effect at the next fire: the period in progress
+    /// keeps its length, and every period after it is `interval` clock-tics
+    /// long. When called from the timer's own `fired()` callback, the period
+    /// that the fire just completed is over, so the new interval already
+    /// applies to the period that starts with this fire.
+    ///
+    /// Returns `EINVAL` if the timer is disabled or in oneshot mode.
+    fn change_interval(&self, interval: Self::Ticks) -> ReturnCode;
+
+    /// Returns whether this is a oneshot (rather than repeating) timer.
+    ///
+    /// Derived from `interval()`, so a disabled timer also reports `true`.
+    /// Implementors only need to track the reload interval, not a separate
+    /// mode flag.
+    fn is_oneshot(&self) -> bool {
+        self.interval().is_none()
+    }
+
+    /// Returns whether this is a repeating (rather than oneshot) timer.
+    fn is_repeating(&self) -> bool {
+        self.interval().is_some()
+    }
+
+    /// Returns the remaining time in clock tics for a oneshot or repeating timer.
+    ///
+    /// Returns `None` if the timer is disabled. A timer that is about to fire
+    /// returns `Some(0)`, which is distinct from being disabled.
+    fn time_remaining(&self) -> Option<Self::Ticks>;
+
+    /// Returns how far into the current period of a repeating timer it is,
+    /// in clock tics: `0` right after it fires, counting up to `interval()`.
+    ///
+    /// Returns `None` if the timer is disabled or in oneshot mode, since then
+    /// there is no `interval()` to measure against. The default computes it
+    /// from `interval()` and `time_remaining()`, so it has the resolution of
+    /// the underlying counter, and may be off by the tics that pass between
+    /// the two reads. Between `change_interval` and the next fire the period
+    /// in progress does not have the new length and the result is only
+    /// approximate; it is `0` while more than `interval()` remains.
+    fn elapsed_in_period(&self) -> Option<Self::Ticks> {
+        let interval = self.interval()?;
+        let remaining = self.time_remaining()?;
+        if remaining > interval {
+            Some(Self::Ticks::from_u32(0))
+        } else {
+            Some(interval.wrapping_sub(remaining))
+        }
+    }
+
+    /// Returns whether this timer is currently active (has time remaining).
+    fn is_enabled(&self) -> bool {
+        self.time_remaining().is_some()
+    }
+
+    /// Cancels an outstanding timer.
+    ///
+    /// The implementation will _always_ cancel the timer, and
+    /// `time_remaining()` returns `None` afterwards.
+    fn cancel(&self);
+
+    /// Stops the timer and remembers how many tics were left until it would
+    /// have fired, so that [`resume`](#method.resume) can continue it with
+    /// its phase intact.
+    ///
+    /// A paused timer does not fire and reports itself as disabled:
+    /// `time_remaining()` and `interval()` return `None`. A repeating timer
+    /// keeps its interval, and the fires left of a `repeat_n` sequence, for
+    /// when it is resumed. Pausing a timer that is already paused does
+    /// nothing. `cancel`, `oneshot`, `repeat` and `repeat_n` discard the
+    /// paused state.
+    ///
+    /// Returns `EINVAL` if the timer is neither armed nor paused, and
+    /// `ENOSUPPORT` if the timer cannot be paused, which is the default.
+    fn pause(&self) -> ReturnCode {
+        ReturnCode::ENOSUPPORT
+    }
+
+    /// Continues a timer stopped with [`pause`](#method.pause).
+    ///
+    /// The timer first fires after the tics that were left when it was
+    /// paused. A oneshot timer is then done; a repeating timer continues to
+    /// fire every `interval()` from there, as if the time it was paused had
+    /// not passed.
+    ///
+    /// Returns `EINVAL` if the timer is not paused, and `ENOSUPPORT` if the
+    /// timer cannot be paused, which is the default.
+    fn resume(&self) -> ReturnCode {
+        ReturnCode::ENOSUPPORT
+    }
+
+    /// Returns a snapshot of the timer's state from its getters, for trace
+    /// dumps, like [`Alarm#describe`](trait.Alarm.html#method.describe).
+    fn describe(&self) -> TimeState {
+        TimeState {
+            now: self.now().into_u64(),
+            frequency: self.frequency(),
+            enabled: self.is_enabled(),
+            alarm: None,
+            interval: self.interval().map(Ticks::into_u64),
+            remaining: self.time_remaining().map(Ticks::into_u64),
+        }
+    }
+}
+
+/// A client of an implementer of the [`Timer`](trait.Timer.html) trait.
+pub trait TimerClient {
+    /// Callback signaled when the timer's clock reaches the specified interval.
+    fn fired(&self);
+}
+
+/// Returns whether `deadline` has passed at `now`, for a deadline that was
+/// computed at `reference` (usually the value of `now()` when the alarm was
+/// set).
+///
+/// All three values are interpreted relative to `reference`, so the result is
+/// correct across a counter wrap as long as less than one full counter period
+/// has elapsed since `reference`. A `now` that is one tic past `deadline` and a
+/// `now` that is almost a full period past it are both reported as expired.
+pub fn has_expired<T: Ticks>(reference: T, deadline: T, now: T) -> bool {
+    now.wrapping_sub(reference) >= deadline.wrapping_sub(reference)
+}
+
+/// Returns the value to program into a compare register for an alarm at
+/// `deadline` that should fire `compensation` tics early.
+///
+/// The result is never less than `min_dt` tics after `now`: the compensation
+/// is reduced as needed, and a `deadline` that is itself closer than `min_dt`
+/// is returned unchanged. For implementers of
+/// [`Alarm#set_latency_compensation`](trait.Alarm.html#method.set_latency_compensation).
+pub fn compensate_latency<T: Ticks>(now: T, deadline: T, compensation: u32, min_dt: T) -> T {
+    let dt = deadline.wrapping_sub(now).into_u64();
+    let slack = dt.saturating_sub(min_dt.into_u64());
+    deadline.wrapping_sub(T::from_u64(core::cmp::min(compensation as u64, slack)))
+}
+
+/// Returns whether a comparator programmed for `deadline` may have missed it,
+/// because the counter read `now` before and `after` programming it, and is
+/// now closer than `min_dt` tics to the deadline or past it.
+///
+/// For implementers of [`Alarm`](trait.Alarm.html), whose `set_alarm` must
+/// then re-arm for the earliest time the hardware can fire instead of waiting
+/// for the counter to wrap around to `deadline`. Distances are taken modulo
+/// `max_tics + 1`, so this works for counters narrower than `T`.
+pub fn compare_missed<T: Ticks>(now: T, deadline: T, after: T, min_dt: T, max_tics: T) -> bool {
+    let dt = wrapping_delta(now, deadline, max_tics);
+    let elapsed = wrapping_delta(now, after, max_tics);
+    elapsed.saturating_add(min_dt.into_u64()) > dt
+}
+
+/// Returns whether the counter reached `deadline` at most `min_dt` tics
+/// before `now`.
+///
+/// For implementers of [`Alarm`](trait.Alarm.html), whose `set_alarm` must
+/// fire such a deadline as soon as possible instead of a full counter period
+/// later. Distances are taken modulo `max_tics + 1`, so this works for
+/// counters narrower than `T`.
+pub fn reached_within<T: Ticks>(now: T, deadline: T, min_dt: T, max_tics: T) -> bool {
+    wrapping_delta(deadline, now, max_tics) <= min_dt.into_u64()
+}
+
+/// Returns the number of tics from `now` until `deadline`, or `0` if `deadline`
+/// has already passed.
+///
+/// The difference `deadline - now` is taken modulo `max_tics + 1`, so this
+/// works for counters narrower than `T`, and interpreted as a signed wrapping
+/// delta: deadlines less than half a counter period in the past count as
+/// expired, everything else is treated as lying in the future.
+pub fn ticks_remaining<T: Ticks>(now: T, deadline: T, max_tics: T) -> T {
+    let delta = wrapping_delta(now, deadline, max_tics);
+    if delta > max_tics.into_u64() / 2 {
+        T::from_u32(0)
+    } else {
+        T::from_u64(delta)
+    }
+}
+
+/// Returns the number of tics between `earlier` and the current value of
+/// `time`, taking into account counters that are narrower than their `Ticks`
+/// type. The result is only meaningful for spans shorter than one counter
+/// period.
+fn tics_since<T: Time>(time: &T, earlier: T::Ticks) -> u64 {
+    wrapping_delta(earlier, time.now(), time.max_tics())
+}
+
+/// Returns the number of tics from `earlier` to `later` on a counter that
+/// wraps after `max_tics`, which may be narrower than `T`.
+fn wrapping_delta<T: Ticks>(earlier: T, later: T, max_tics: T) -> u64 {
+    later.wrapping_sub(earlier).into_u64() & max_tics.into_u64()
+}
+
+/// Converts `tics` of a `hz` clock to units of `1 / per_second` seconds,
+/// rounding down. Whole seconds are converted separately so that the
+/// intermediate products of a 64-bit count cannot overflow.
+fn tics_to_units(tics: u64, hz: u32, per_second: u64) -> u64 {
+    let hz = hz as u64;
+    (tics / hz)
+        .wrapping_mul(per_second)
+        .wrapping_add(tics % hz * per_second / hz)
+}
+
+/// Read-only access to a shared clock.
+///
+/// `TimeRef` implements `Time` by forwarding to the clock it wraps, and
+/// nothing else, even if that clock is also a `Counter` or an `Alarm`. This
+/// is the recommended way for a board to share a clock with capsules that
+/// only need to read it: a capsule given a `TimeRef` cannot arm, disable or
+/// take over the client of the alarm, nor stop the counter, that other
+/// capsules depend on. Its `capabilities()` accordingly report no alarm and
+/// no counter.
+///
+/// ```ignore
+/// let time = static_init!(
+///     TimeRef<'static, sam4l::ast::Ast>,
+///     TimeRef::new(&sam4l::ast::AST)
+/// );
+/// ```
+pub struct TimeRef<'a, T: Time> {
+    time: &'a T,
+}
+
+impl<T: Time> TimeRef<'a, T> {
+    pub const fn new(time: &'a T) -> TimeRef<'a, T> {
+        TimeRef { time }
+    }
+}
+
+impl<T: Time> Time for TimeRef<'a, T> {
+    type Frequency = T::Frequency;
+    type Ticks = T::Ticks;
+    const WIDTH: u32 = T::WIDTH;
+
+    fn now(&self) -> T::Ticks {
+        self.time.now()
+    }
+
+    fn try_now(&self) -> Result<T::Ticks, ReturnCode> {
+        self.time.try_now()
+    }
+
+    fn max_tics(&self) -> T::Ticks {
+        self.time.max_tics()
+    }
+
+    fn frequency(&self) -> u32 {
+        self.time.frequency()
+    }
+
+    fn persists_in_sleep(&self) -> bool {
+        self.time.persists_in_sleep()
+    }
+}
+
+/// Busy-waits for a number of milliseconds or microseconds.
+///
+/// `BusyDelay` only ever reads `now()`: it never sets an alarm or touches the
+/// client of the underlying clock, so it can be used during board
+/// initialization before interrupts are enabled. It spins the CPU for the
+/// whole delay and is intended for early bring-up only. Capsules should use an
+/// `Alarm` instead.
+pub struct BusyDelay<'a, T: Time> {
+    time: &'a T,
+}
+
+impl<T: Time> BusyDelay<'a, T> {
+    pub fn new(time: &'a T) -> BusyDelay<'a, T> {
+        BusyDelay { time }
+    }
+
+    /// Spins for at least `ms` milliseconds.
+    pub fn delay_ms(&self, ms: u32) {
+        self.delay(ms as u64, 1000);
+    }
+
+    /// Spins for at least `us` microseconds.
+    pub fn delay_us(&self, us: u32) {
+        self.delay(us as u64, 1_000_000);
+    }
+
+    /// Spins for at least `value / per_second` seconds.
+    fn delay(&self, value: u64, per_second: u64) {
+        if value == 0 {
+            return;
+        }
+        // Rounded up, and one more because the first `now()` may be read
+        // just before the counter tics, so that the delay is never shorter
+        // than requested.
+        let frequency = self.time.frequency() as u64;
+        let mut tics = (value * frequency + per_second - 1) / per_second + 1;
+        // Wait in steps of at most half a counter period, so that each step
+        // ends well before `now()` could wrap past its starting point.
+        let max_step = self.time.max_tics().into_u64() / 2;
+        while tics > 0 {
+            let step = core::cmp::min(tics, max_step);
+            let start = self.time.now();
+            while tics_since(self.time, start) < step {}
+            tics -= step;
+        }
+    }
+}
+
+/// Measures time elapsed since a starting point.
+///
+/// `Stopwatch` only reads `now()`, so like `BusyDelay` it can be used anywhere
+/// without affecting alarms. Elapsed time can be queried repeatedly while it
+/// keeps running, and measurements are only correct for spans shorter than
+/// one counter period.
+pub struct Stopwatch<'a, T: Time> {
+    time: &'a T,
+    start: Cell<T::Ticks>,
+}
+
+impl<T: Time> Stopwatch<'a, T> {
+    /// Creates a stopwatch that starts counting immediately.
+    pub fn new(time: &'a T) -> Stopwatch<'a, T> {
+        Stopwatch {
+            time,
+            start: Cell::new(time.now()),
+        }
+    }
+
+    /// Restarts the measurement from the current time.
+    pub fn start(&self) {
+        self.start.set(self.time.now());
+    }
+
+    /// Returns the number of tics since the last `start()`, saturating at
+    /// `u32::MAX` on counters wider than 32 bits.
+    pub fn elapsed_tics(&self) -> u32 {
+        let tics = tics_since(self.time, self.start.get());
+        core::cmp::min(tics, u32::max_value() as u64) as u32
+    }
+
+    /// Returns the number of microseconds since the last `start()`, rounded
+    /// to the nearest microsecond and saturating at `u32::MAX`.
+    pub fn elapsed_us(&self) -> u32 {
+        let tics = tics_since(self.time, self.start.get());
+        let us = convert_tics_u64(tics, self.time.frequency(), 1_000_000);
+        core::cmp::min(us, u32::max_value() as u64) as u32
+    }
+}
+
+/// Permits an action at most `tokens_per_period` times per `period_ms`
+/// milliseconds.
+///
+/// `RateLimiter` is a token bucket that refills from `now()`: it starts full,
+/// each permitted action takes one token, and tokens flow back evenly over
+/// the period. Like `Stopwatch` it only reads `now()`. Elapsed time is
+/// measured modulo the counter period, so it must be called at least once
+/// per counter period to refill correctly; since the bucket is full after one
+/// `period_ms`, this only matters if `period_ms` is longer than that.
+pub struct RateLimiter<'a, T: Time> {
+    time: &'a T,
+    tokens_per_period: u32,
+    /// The period in tics.
+    period: u64,
+    /// A full bucket, `tokens_per_period * period`.
+    capacity: u64,
+    /// Tokens in the bucket, in units of `1 / period` tokens so that partial
+    /// refills are not lost.
+    credit: Cell<u64>,
+    last: Cell<T::Ticks>,
+}
+
+impl<T: Time> RateLimiter<'a, T> {
+    /// Returns `ReturnCode::EINVAL` if `tokens_per_period` tokens of
+    /// `period_ms` at the clock's frequency cannot be counted in 64 bits.
+    pub fn new(
+        time: &'a T,
+        tokens_per_period: u32,
+        period_ms: u32,
+    ) -> Result<RateLimiter<'a, T>, ReturnCode> {
+        let period = core::cmp::max(period_ms as u64 * time.frequency() as u64 / 1000, 1);
+        let capacity = period
+            .checked_mul(tokens_per_period as u64)
+            .ok_or(ReturnCode::EINVAL)?;
+        Ok(RateLimiter {
+            time,
+            tokens_per_period,
+            period,
+            capacity,
+            credit: Cell::new(capacity),
+            last: Cell::new(time.now()),
+        })
+    }
+
+    /// Takes a token and returns `true` if one is available, otherwise
+    /// returns `false` without taking one.
+    pub fn try_acquire(&self) -> bool {
+        let now = self.time.now();
+        let elapsed = wrapping_delta(self.last.get(), now, self.time.max_tics());
+        self.last.set(now);
+        // A full period refills the whole bucket, so longer waits can be
+        // cut short before multiplying.
+        let refill = core::cmp::min(elapsed, self.period) * self.tokens_per_period as u64;
+        self.credit.set(core::cmp::min(
+            self.credit.get().saturating_add(refill),
+            self.capacity,
+        ));
+
+        if self.credit.get() >= self.period {
+            self.credit.set(self.credit.get() - self.period);
+            true
+        } else {
+            false
+        }
+    }
+}
+
+/// A moving average of values sampled at uneven intervals.
+///
+/// Each [`update`](#method.update) moves the average towards the new value
+/// in proportion to the time since the previous update, measured with
+/// `now()`: a value sampled one full `window_ms` after the previous one
+/// replaces the average, and one sampled half a window after it moves the
+/// average halfway there. This smooths e.g. a temperature sensor that is
+/// read whenever a capsule gets around to it, without letting a burst of
+/// closely spaced samples dominate the average.
+///
+/// Only integer math is used. The average is kept scaled by the window
+/// length in tics, so that small steps are not lost to rounding. The window
+/// is at most one counter period, and like `RateLimiter` the average must be
+/// updated at least once per counter period to measure intervals correctly.
+pub struct TimeWeightedAvg<'a, T: Time> {
+    time: &'a T,
+    /// The window in tics.
+    window: u64,
+    /// The average times `window`.
+    sum: Cell<i64>,
+    /// `now()` at the last update, if there was one.
+    last: Cell<Option<T::Ticks>>,
+}
+
+impl<T: Time> TimeWeightedAvg<'a, T> {
+    pub fn new(time: &'a T, window_ms: u32) -> TimeWeightedAvg<'a, T> {
+        let window = window_ms as u64 * time.frequency() as u64 / 1000;
+        TimeWeightedAvg {
+            time,
+            window: core::cmp::min(core::cmp::max(window, 1), time.max_tics().into_u64()),
+            sum: Cell::new(0),
+            last: Cell::new(None),
+        }
+    }
+
+    /// Adds `value`, sampled now, to the average. The first value becomes
+    /// the average.
+    pub fn update(&self, value: i32) {
+        let now = self.time.now();
+        let window = self.window as i64;
+        let target = value as i64 * window;
+        match self.last.replace(Some(now)) {
+            None => self.sum.set(target),
+            Some(last) => {
+                let elapsed = wrapping_delta(last, now, self.time.max_tics());
+                let weight = core::cmp::min(elapsed, self.window) as i128;
+                let step = (target as i128 - self.sum.get() as i128) * weight / window as i128;
+                self.sum.set(self.sum.get() + step as i64);
+            }
+        }
+    }
+
+    /// Returns the average, rounded down, or `0` before the first update.
+    pub fn get(&self) -> i32 {
+        self.sum.get().div_euclid(self.window as i64) as i32
+    }
+}
+
+/// Spins until `cond()` returns `true` or `timeout_tics` tics have elapsed.
+///
+/// For polling a hardware status bit with a timeout, e.g. waiting for an
+/// oscillator to stabilize during board bring-up. Only `now()` is read, no
+/// alarm is armed, so this works before interrupts are enabled. `cond` is
+/// called at least once, before the first check of the timeout, and elapsed
+/// time is computed modulo the counter width, so a wait that straddles a
+/// rollover times out correctly.
+///
+/// Returns `ReturnCode::SUCCESS` once `cond()` returns `true`, or
+/// `ReturnCode::FAIL` if the timeout elapsed first.
+pub fn wait_until<T: Time>(
+    time: &T,
+    timeout_tics: u32,
+    mut cond: impl FnMut() -> bool,
+) -> ReturnCode {
+    let start = time.now();
+    loop {
+        if cond() {
+            return ReturnCode::SUCCESS;
+        }
+        if tics_since(time, start) >= timeout_tics as u64 {
+            return ReturnCode::FAIL;
+        }
+    }
+}
+
+/// Reads `now()` `samples + 1` times in a tight loop and returns the smallest
+/// and largest number of tics observed between consecutive reads.
+///
+/// This is a diagnostic for board bring-up, e.g. to check whether a slow
+/// clock that is synchronized into a faster domain can read back stale or
+/// skipped values. Deltas are computed modulo the counter width, so reads
+/// that straddle a rollover are measured correctly. Returns `(0, 0)` if
+/// `samples` is `0`.
+pub fn measure_read_jitter<T: Time>(time: &T, samples: usize) -> (u32, u32) {
+    if samples == 0 {
+        return (0, 0);
+    }
+    let mut min = u32::max_value();
+    let mut max = 0;
+    let mut last = time.now();
+    for _ in 0..samples {
+        let now = time.now();
+        let delta = wrapping_delta(last, now, time.max_tics());
+        let delta = core::cmp::min(delta, u32::max_value() as u64) as u32;
+        min = core::cmp::min(min, delta);
+        max = core::cmp::max(max, delta);
+        last = now;
+    }
+    (min, max)
+}
+
+/// Runs `f` and measures how many tics of `counter` it takes, returning
+/// `f`'s result and the count.
+///
+/// The count is computed modulo the counter width, so it is only correct if
+/// the counter wraps at most once and `f` takes less than a full period. To
+/// rule out that the count is off by a multiple of the period, `f` runs
+/// inside [`Counter#with_frozen`](trait.Counter.html#method.with_frozen) and
+/// the counter's overflow flag is checked before and after: if the counter
+/// wrapped, the count is `None`. The measurement is then discarded even when
+/// it would have been correct, e.g. when a wrap was already pending before
+/// `f` ran.
+///
+/// A counter that cannot tell whether it wrapped (see
+/// [`Counter#overflow_pending`](trait.Counter.html#method.overflow_pending))
+/// always gets the count modulo its width, which is at most `max_tics()`.
+/// It is then up to the caller to bound `f` to less than a full period.
+///
+/// Like `with_frozen`, this holds off the overflow interrupt while `f` runs,
+/// so `f` must be short and must not block.
+pub fn measure_cycles<'a, C: Counter<'a>, R>(
+    counter: &C,
+    f: impl FnOnce() -> R,
+) -> (R, Option<u32>) {
+    counter.with_frozen(|start| {
+        let pending_before = counter.overflow_pending();
+        let result = f();
+        let end = counter.now().into_u32();
+        let cycles = end.wrapping_sub(start) & counter.max_tics().into_u32();
+        match (pending_before, counter.overflow_pending()) {
+            (Some(false), Some(false)) => (result, Some(cycles)),
+            (Some(_), Some(_)) => (result, None),
+            // No overflow flag: one wrap is taken care of by the modulo
+            // count, more are ruled out only by the caller's bound on `f`.
+            _ => (result, Some(cycles)),
+        }
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::mock::{MockAlarm, VirtualTimer};
+    use super::RateLimiter;
+    use super::{compare_missed, const_ms_to_tics, convert_tics, convert_tics_u64, reached_within};
+    use super::{
+        compensate_latency, has_expired, ticks_remaining, BusyDelay, Freq16MHz, Freq1KHz,
+        Freq32KHz, Freq64MHz,
+    };
+    use super::{
+        extended_timestamp, measure_read_jitter, wait_until, Duration, Instant, TimeCapabilities,
+    };
+    use super::{measure_cycles, Counter, OverflowClient, TimeState, TimeWeightedAvg};
+    use super::{Alarm, AlarmClient, Frequency, Stopwatch, TeeAlarmClient, Time};
+    use super::{AlarmAt, FreqTicks, Ticks, TimeAt, TimeRef, Timer, TimerClient};
+    use super::{AlarmDelay, AlarmWaker, Timeout, TimeoutClient};
+    use crate::ReturnCode;
+    use core::cell::Cell;
+    use core::future::Future;
+    use core::pin::Pin;
+    use core::task::{Context, Poll, RawWaker, RawWakerVTable, Waker};
+
+    /// A clock that advances by `step` tics every time it is read and wraps
+    /// after `max`.
+    struct SteppingTime {
+        now: Cell<u32>,
+        step: u32,
+        max: u32,
+        reads: Cell<usize>,
+    }
+
+    impl SteppingTime {
+        fn new(start: u32, step: u32, max: u32) -> SteppingTime {
+            SteppingTime {
+                now: Cell::new(start),
+                step,
+                max,
+                reads: Cell::new(0),
+            }
+        }
+    }
+
+    impl Time for SteppingTime {
+        type Frequency = Freq1KHz;
+
+        fn now(&self) -> u32 {
+            let now = self.now.get();
+            self.now.set(now.wrapping_add(self.step) & self.max);
+            self.reads.set(self.reads.get() + 1);
+            now
+        }
+
+        fn max_tics(&self) -> u32 {
+            self.max
+        }
+    }
+
+    /// A 16-bit counter with an overflow flag, moved by hand.
+    struct FlagCounter {
+        now: Cell<u32>,
+        overflow: Cell<bool>,
+        has_flag: bool,
+    }
+
+    impl FlagCounter {
+        fn new(now: u32, has_flag: bool) -> FlagCounter {
+            FlagCounter {
+                now: Cell::new(now),
+                overflow: Cell::new(false),
+                has_flag,
+            }
+        }
+
+        fn advance(&self, tics: u32) {
+            let now = self.now.get() + tics;
+            if now > 0xFFFF {
+                self.overflow.set(true);
+            }
+            self.now.set(now & 0xFFFF);
+        }
+    }
+
+    impl Time for FlagCounter {
+        type Frequency = Freq1KHz;
+
+        fn now(&self) -> u32 {
+            self.now.get()
+        }
+
+        fn max_tics(&self) -> u32 {
+            0xFFFF
+        }
+    }
+
+    impl Counter<'a> for FlagCounter {
+        fn set_overflow_client(&'a self, _client: &'a dyn OverflowClient) {}
+
+        fn start(&self) -> ReturnCode {
+            ReturnCode::SUCCESS
+        }
+
+        fn stop(&self) -> ReturnCode {
+            ReturnCode::SUCCESS
+        }
+
+        fn is_running(&self) -> bool {
+            true
+        }
+
+        fn overflow_pending(&self) -> Option<bool> {
+            if self.has_flag {
+                Some(self.overflow.get())
+            } else {
+                None
+            }
+        }
+    }
+
+    /// An alarm that records the last value it was armed with.
+    struct FakeAlarm {
+        now: Cell<u32>,
+        alarm: Cell<Option<u32>>,
+        /// Whether every value is rejected with `EINVAL`, like a deadline a
+        /// backend can never accept.
+        rejects: Cell<bool>,
+        /// Calls of `set_alarm_with_token`.
+        sets: Cell<usize>,
+    }
+
+    impl FakeAlarm {
+        fn new(now: u32) -> FakeAlarm {
+            FakeAlarm {
+                now: Cell::new(now),
+                alarm: Cell::new(None),
+                rejects: Cell::new(false),
+                sets: Cell::new(0),
+            }
+        }
+    }
+
+    impl Time for FakeAlarm {
+        type Frequency = Freq1KHz;
+
+        fn now(&self) -> u32 {
+            self.now.get()
+        }
+    }
+
+    impl Alarm<'a> for FakeAlarm {
+        fn set_alarm_with_token(&self, tics: u32, _token: u32) -> ReturnCode {
+            self.sets.set(self.sets.get() + 1);
+            if self.rejects.get() || tics.wrapping_sub(self.now.get()) < self.minimum_dt() {
+                return ReturnCode::EINVAL;
+            }
+            self.alarm.set(Some(tics));
+            ReturnCode::SUCCESS
+        }
+
+        fn get_alarm(&self) -> u32 {
+            self.alarm.get().unwrap_or(0)
+        }
+
+        fn minimum_dt(&self) -> u32 {
+            2
+        }
+
+        fn set_client(&'a self, _client: &'a dyn AlarmClient) {}
+
+        fn is_enabled(&self) -> bool {
+            self.alarm.get().is_some()
+        }
+
+        fn clear_client(&self) {}
+
+        fn disable(&self) -> ReturnCode {
+            self.alarm.set(None);
+            ReturnCode::SUCCESS
+        }
+    }
+
+    /// A 64-bit counter, with an alarm like `FakeAlarm`.
+    struct U64Alarm {
+        now: Cell<u64>,
+        alarm: Cell<Option<u64>>,
+    }
+
+    impl U64Alarm {
+        fn new(now: u64) -> U64Alarm {
+            U64Alarm {
+                now: Cell::new(now),
+                alarm: Cell::new(None),
+            }
+        }
+    }
+
+    impl Time for U64Alarm {
+        type Frequency = Freq16MHz;
+        type Ticks = u64;
+
+        fn now(&self) -> u64 {
+            self.now.get()
+        }
+    }
+
+    impl Alarm<'a> for U64Alarm {
+        fn set_alarm_with_token(&self, tics: u64, _token: u32) -> ReturnCode {
+            if tics.wrapping_sub(self.now.get()) < self.minimum_dt() {
+                return ReturnCode::EINVAL;
+            }
+            self.alarm.set(Some(tics));
+            ReturnCode::SUCCESS
+        }
+
+        fn get_alarm(&self) -> u64 {
+            self.alarm.get().unwrap_or(0)
+        }
+
+        fn minimum_dt(&self) -> u64 {
+            2
+        }
+
+        fn set_client(&'a self, _client: &'a dyn AlarmClient) {}
+
+        fn is_enabled(&self) -> bool {
+            self.alarm.get().is_some()
+        }
+
+        fn clear_client(&self) {}
+
+        fn disable(&self) -> ReturnCode {
+            self.alarm.set(None);
+            ReturnCode::SUCCESS
+        }
+    }
+
+    #[test]
+    fn test_u64_time_does_not_truncate() {
+        // Ten hours at 16 MHz, more than 2^32 tics.
+        let tics = 10 * 3600 * 16_000_000;
+        let time = U64Alarm::new(tics);
+        assert_eq!(time.now_ms(), 10 * 3600 * 1000);
+        assert_eq!(time.now_us(), 10 * 3600 * 1_000_000);
+        assert_eq!(time.now_ns(), 10 * 3600 * 1_000_000_000);
+
+        assert_eq!(time.elapsed_since(1), tics - 1);
+        assert_eq!(time.checked_elapsed_since(1), Some(tics - 1));
+        assert!(time.now_is_after(1));
+        assert!(!time.now_is_after(tics + (1 << 40)));
+        let stopwatch = Stopwatch::new(&time);
+        time.now.set(tics + (1 << 33));
+        // 2^33 tics saturate, but the 2^29 microseconds they take still fit.
+        assert_eq!(stopwatch.elapsed_tics(), u32::max_value());
+        assert_eq!(stopwatch.elapsed_us(), 536_870_912);
+    }
+
+    /// A 24-bit counter that only declares its width.
+    struct NarrowTime;
+
+    impl Time for NarrowTime {
+        type Frequency = Freq32KHz;
+        const WIDTH: u32 = 24;
+
+        fn now(&self) -> u32 {
+            0
+        }
+    }
+
+    #[test]
+    fn test_width_and_max_tics_agree() {
+        assert_eq!(<U64Alarm as Time>::WIDTH, 64);
+        assert_eq!(U64Alarm::new(0).max_tics(), u64::max_value());
+        assert_eq!(U64Alarm::new(0).capabilities().width_bits, 64);
+        assert_eq!(<FakeAlarm as Time>::WIDTH, 32);
+        assert_eq!(FakeAlarm::new(0).max_tics(), u32::max_value());
+        assert_eq!(NarrowTime.max_tics(), (1 << 24) - 1);
+    }
+
+    #[test]
+    fn test_u64_alarm_does_not_truncate() {
+        let now = 1 << 40;
+        let alarm = U64Alarm::new(now);
+        assert_eq!(alarm.set_alarm_from(now, 1 << 35), ReturnCode::SUCCESS);
+        assert_eq!(alarm.get_alarm(), now + (1 << 35));
+        assert_eq!(alarm.remaining(), Some(1 << 35));
+        assert_eq!(alarm.describe().remaining, Some(1 << 35));
+
+        assert_eq!(alarm.snooze(10), ReturnCode::SUCCESS);
+        assert_eq!(alarm.remaining(), Some((1 << 35) + 10));
+
+        // Aligned to the zero of the whole counter, not of its low 32 bits.
+        assert_eq!(alarm.set_alarm_aligned(1000, 0), ReturnCode::SUCCESS);
+        assert_eq!(alarm.get_alarm(), (now + 2 + 999) / 1000 * 1000);
+    }
+
+    #[test]
+    fn test_time_ref() {
+        let alarm = MockAlarm::new();
+        alarm.set_frequency(32768);
+        alarm.set_now(1234);
+        let time = TimeRef::new(&alarm);
+        assert_eq!(time.now(), 1234);
+        assert_eq!(time.frequency(), 32768);
+        assert_eq!(time.capabilities().min_alarm_dt, 0);
+        assert!(!time.capabilities().is_counter);
+    }
+
+    #[test]
+    fn test_set_alarm_aligned() {
+        let alarm = FakeAlarm::new(1000);
+        assert_eq!(alarm.set_alarm_aligned(100, 30), ReturnCode::SUCCESS);
+        assert_eq!(alarm.alarm.get(), Some(1030));
+        alarm.now.set(1028);
+        alarm.set_alarm_aligned(100, 30);
+        assert_eq!(alarm.alarm.get(), Some(1030));
+        // Closer than `minimum_dt`.
+        alarm.now.set(1029);
+        alarm.set_alarm_aligned(100, 30);
+        assert_eq!(alarm.alarm.get(), Some(1130));
+
+        // 2^32 % 100 is 96, so the last aligned value before the wrap is
+        // `u32::MAX - 65`, and the next one 96 tics later is 30.
+        alarm.now.set(u32::max_value() - 67);
+        alarm.set_alarm_aligned(100, 30);
+        assert_eq!(alarm.alarm.get(), Some(u32::max_value() - 65));
+        alarm.now.set(u32::max_value() - 10);
+        alarm.set_alarm_aligned(100, 30);
+        assert_eq!(alarm.alarm.get(), Some(30));
+        alarm.now.set(u32::max_value());
+        alarm.set_alarm_aligned(100, 0);
+        assert_eq!(alarm.alarm.get(), Some(100));
+
+        alarm.disable();
+        assert_eq!(alarm.set_alarm_aligned(0, 0), ReturnCode::EINVAL);
+        assert_eq!(alarm.set_alarm_aligned(100, 100), ReturnCode::EINVAL);
+        assert_eq!(alarm.alarm.get(), None);
+    }
+
+    #[test]
+    fn test_set_alarm_from_future_deadline() {
+        let alarm = FakeAlarm::new(100);
+        assert_eq!(alarm.set_alarm_from(90, 20), ReturnCode::SUCCESS);
+        assert_eq!(alarm.alarm.get(), Some(110));
+
+        let alarm = FakeAlarm::new(u32::max_value() - 5);
+        assert_eq!(
+            alarm.set_alarm_from(u32::max_value() - 10, 20),
+            ReturnCode::SUCCESS
+        );
+        assert_eq!(alarm.alarm.get(), Some(9));
+    }
+
+    #[test]
+    fn test_compare_missed() {
+        let max = u32::max_value();
+        assert!(!compare_missed(100u32, 110, 101, 2, max));
+        assert!(!compare_missed(100u32, 110, 108, 2, max));
+        assert!(compare_missed(100u32, 110, 109, 2, max));
+        assert!(compare_missed(100u32, 110, 115, 2, max));
+        // A 24-bit counter that wrapped while the comparator was programmed.
+        let max: u32 = (1 << 24) - 1;
+        assert!(!compare_missed(max - 1, 5, 1, 2, max));
+        assert!(compare_missed(max - 1, 5, 6, 2, max));
+    }
+
+    #[test]
+    fn test_reached_within() {
+        let max = u32::max_value();
+        assert!(reached_within(100u32, 100, 2, max));
+        assert!(reached_within(100u32, 98, 2, max));
+        assert!(!reached_within(100u32, 97, 2, max));
+        assert!(!reached_within(100u32, 101, 2, max));
+        // A 24-bit counter that wrapped since the deadline.
+        let max: u32 = (1 << 24) - 1;
+        assert!(reached_within(1u32, max, 2, max));
+        assert!(!reached_within(1u32, max - 2, 2, max));
+    }
+
+    #[test]
+    fn test_set_alarm_clamped() {
+        let alarm = FakeAlarm::new(100);
+        assert_eq!(alarm.set_alarm_clamped(150), Ok(150));
+        assert_eq!(alarm.alarm.get(), Some(150));
+
+        // At or just after now: moved to the earliest reliable deadline.
+        assert_eq!(alarm.set_alarm_clamped(100), Ok(102));
+        assert_eq!(alarm.set_alarm_clamped(101), Ok(102));
+        assert_eq!(alarm.alarm.get(), Some(102));
+        assert_eq!(alarm.set_alarm_clamped(102), Ok(102));
+
+        let alarm = FakeAlarm::new(u32::max_value());
+        assert_eq!(alarm.set_alarm_clamped(u32::max_value()), Ok(1));
+    }
+
+    #[test]
+    fn test_set_alarm_gives_up_on_rejected_deadlines() {
+        let alarm = FakeAlarm::new(100);
+        alarm.rejects.set(true);
+        assert_eq!(alarm.set_alarm_from(100, 50), ReturnCode::EINVAL);
+        assert_eq!(alarm.set_alarm_clamped(150), Err(ReturnCode::EINVAL));
+        assert_eq!(alarm.set_alarm_aligned(10, 0), ReturnCode::EINVAL);
+        // Each tried once and retried once.
+        assert_eq!(alarm.sets.get(), 6);
+        assert_eq!(alarm.alarm.get(), None);
+    }
+
+    #[test]
+    fn test_set_alarm_from_expired_deadline() {
+        // The deadline passed 30 tics ago; fire as soon as possible
+        // instead of a full period later.
+        let alarm = FakeAlarm::new(100);
+        assert_eq!(alarm.set_alarm_from(50, 20), ReturnCode::SUCCESS);
+        assert_eq!(alarm.alarm.get(), Some(102));
+
+        // The deadline is closer than minimum_dt.
+        let alarm = FakeAlarm::new(100);
+        assert_eq!(alarm.set_alarm_from(90, 11), ReturnCode::SUCCESS);
+        assert_eq!(alarm.alarm.get(), Some(102));
+    }
+
+    #[test]
+    fn test_disable_and_peek() {
+        let alarm = FakeAlarm::new(100);
+        assert_eq!(alarm.disable_and_peek(), (ReturnCode::SUCCESS, None));
+
+        alarm.set_alarm(150);
+        assert_eq!(alarm.disable_and_peek(), (ReturnCode::SUCCESS, Some(50)));
+        assert!(!alarm.is_enabled());
+
+        // Across a wrap.
+        alarm.now.set(u32::max_value() - 4);
+        alarm.set_alarm(5);
+        assert_eq!(alarm.disable_and_peek(), (ReturnCode::SUCCESS, Some(10)));
+
+        // Expired, but not yet delivered.
+        alarm.set_alarm(110);
+        alarm.now.set(112);
+        assert_eq!(alarm.disable_and_peek(), (ReturnCode::SUCCESS, Some(0)));
+    }
+
+    #[test]
+    fn test_snooze() {
+        let alarm = FakeAlarm::new(100);
+        assert_eq!(alarm.snooze(10), ReturnCode::EINVAL);
+
+        alarm.set_alarm(150);
+        assert_eq!(alarm.snooze(25), ReturnCode::SUCCESS);
+        assert_eq!(alarm.alarm.get(), Some(175));
+
+        // Across a wrap.
+        alarm.now.set(u32::max_value() - 4);
+        alarm.set_alarm(u32::max_value());
+        alarm.snooze(10);
+        assert_eq!(alarm.alarm.get(), Some(9));
+
+        // Expired, but not yet delivered: measured from the old deadline,
+        // and as soon as possible once that has passed too.
+        alarm.set_alarm(110);
+        alarm.now.set(112);
+        alarm.snooze(20);
+        assert_eq!(alarm.alarm.get(), Some(130));
+        alarm.set_alarm(114);
+        alarm.now.set(116);
+        alarm.snooze(1);
+        assert_eq!(alarm.alarm.get(), Some(118));
+
+        // More than a period away.
+        alarm.now.set(100);
+        alarm.set_alarm(150);
+        assert_eq!(alarm.snooze(u32::max_value() - 10), ReturnCode::EINVAL);
+        assert_eq!(alarm.alarm.get(), Some(150));
+    }
+
+    #[test]
+    fn test_remaining() {
+        let alarm = FakeAlarm::new(100);
+        assert_eq!(alarm.remaining(), None);
+
+        alarm.set_alarm(150);
+        assert_eq!(alarm.remaining(), Some(50));
+        assert!(alarm.is_enabled());
+
+        // Across a wrap.
+        alarm.now.set(u32::max_value() - 4);
+        alarm.set_alarm(5);
+        assert_eq!(alarm.remaining(), Some(10));
+
+        // Expired, but not yet delivered.
+        alarm.set_alarm(110);
+        alarm.now.set(112);
+        assert_eq!(alarm.remaining(), Some(0));
+    }
+
+    #[test]
+    fn test_next_wakeup() {
+        let alarm = FakeAlarm::new(100);
+        assert_eq!(alarm.next_wakeup(), None);
+        alarm.set_alarm(150);
+        assert_eq!(alarm.next_wakeup(), Some(150));
+        alarm.disable();
+        assert_eq!(alarm.next_wakeup(), None);
+    }
+
+    /// Records the order in which clients of a `TeeAlarmClient` are called.
+    struct OrderedClient<'a> {
+        id: u32,
+        order: &'a Cell<u32>,
+    }
+
+    impl AlarmClient for OrderedClient<'a> {
+        fn fired(&self) {
+            self.order.set(self.order.get() * 10 + self.id);
+        }
+    }
+
+    #[test]
+    fn test_tee_alarm_client_calls_both_in_order() {
+        let order = Cell::new(0);
+        let first = OrderedClient {
+            id: 1,
+            order: &order,
+        };
+        let second = OrderedClient {
+            id: 2,
+            order: &order,
+        };
+        let tee = TeeAlarmClient::new(&first, &second);
+        tee.fired();
+        assert_eq!(order.get(), 12);
+        tee.fired_with_token(7);
+        assert_eq!(order.get(), 1212);
+    }
+
+    struct TimeoutCounter {
+        timed_out: Cell<usize>,
+    }
+
+    impl TimeoutClient for TimeoutCounter {
+        fn timed_out(&self) {
+            self.timed_out.set(self.timed_out.get() + 1);
+        }
+    }
+
+    /// Only accepts clocks at 1 kHz, and relies on that in its arithmetic.
+    fn ms_until<'a, A: AlarmAt<'a, Freq1KHz>>(alarm: &A) -> u32 {
+        alarm
+            .get_alarm()
+            .into_u32()
+            .wrapping_sub(alarm.now().into_u32())
+    }
+
+    fn seconds<T: TimeAt<Freq1KHz>>(time: &T) -> u32 {
+        time.now().into_u32() / 1000
+    }
+
+    #[test]
+    fn test_frequency_bounds() {
+        // Neither compiles with a clock at another frequency, such as a
+        // `FrequencyConvertAlarm` to 32 kHz wrapping the mock.
+        let alarm = MockAlarm::new();
+        alarm.set_now(2100);
+        alarm.set_alarm(2150);
+        assert_eq!(ms_until(&alarm), 50);
+        assert_eq!(seconds(&alarm), 2);
+    }
+
+    #[test]
+    fn test_timeout() {
+        let alarm = MockAlarm::new();
+        let timeout = Timeout::new(&alarm);
+        let client = TimeoutCounter {
+            timed_out: Cell::new(0),
+        };
+        alarm.set_client(&timeout);
+        timeout.set_client(&client);
+
+        // Completes in time.
+        assert_eq!(timeout.start(10), ReturnCode::SUCCESS);
+        alarm.advance(5);
+        assert!(timeout.clear());
+        alarm.advance(10);
+        assert_eq!(client.timed_out.get(), 0);
+
+        // Times out, then the late completion is ignored.
+        timeout.start(10);
+        alarm.advance(10);
+        assert_eq!(client.timed_out.get(), 1);
+        assert!(!timeout.is_running());
+        assert!(!timeout.clear());
+    }
+
+    #[test]
+    fn test_timeout_completes_with_alarm_pending() {
+        let alarm = MockAlarm::new();
+        let timeout = Timeout::new(&alarm);
+        let client = TimeoutCounter {
+            timed_out: Cell::new(0),
+        };
+        alarm.set_client(&timeout);
+        timeout.set_client(&client);
+
+        // The deadline passes while the completion is being handled.
+        timeout.start(10);
+        alarm.set_interrupts_enabled(false);
+        alarm.advance(10);
+        assert!(timeout.clear());
+        alarm.set_interrupts_enabled(true);
+        assert_eq!(client.timed_out.get(), 0);
+    }
+
+    /// A `Waker` that counts how often it is woken, in `count`.
+    fn counting_waker(count: &Cell<usize>) -> Waker {
+        fn clone(data: *const ()) -> RawWaker {
+            RawWaker::new(data, &VTABLE)
+        }
+        fn wake(data: *const ()) {
+            let count = unsafe { &*(data as *const Cell<usize>) };
+            count.set(count.get() + 1);
+        }
+        fn drop(_data: *const ()) {}
+        static VTABLE: RawWakerVTable = RawWakerVTable::new(clone, wake, wake, drop);
+        unsafe { Waker::from_raw(RawWaker::new(count as *const _ as *const (), &VTABLE)) }
+    }
+
+    #[test]
+    fn test_delay_future() {
+        let alarm = MockAlarm::new();
+        let alarm_waker = AlarmWaker::new();
+        alarm.set_client(&alarm_waker);
+        let wakes = Cell::new(0);
+        let waker = counting_waker(&wakes);
+        let mut cx = Context::from_waker(&waker);
+
+        alarm.advance(5);
+        let mut delay = alarm.delay_ms(&alarm_waker, 10);
+        assert_eq!(Pin::new(&mut delay).poll(&mut cx), Poll::Pending);
+        assert_eq!(alarm.get_alarm(), 15);
+        alarm.advance(9);
+        assert_eq!(wakes.get(), 0);
+        assert_eq!(Pin::new(&mut delay).poll(&mut cx), Poll::Pending);
+        alarm.advance(1);
+        assert_eq!(wakes.get(), 1);
+        assert_eq!(Pin::new(&mut delay).poll(&mut cx), Poll::Ready(()));
+    }
+
+    #[test]
+    fn test_delay_future_longer_than_period() {
+        let alarm = MockAlarm::new();
+        alarm.set_frequency(1_000_000_000);
+        let alarm_waker = AlarmWaker::new();
+        alarm.set_client(&alarm_waker);
+        let wakes = Cell::new(0);
+        let waker = counting_waker(&wakes);
+        let mut cx = Context::from_waker(&waker);
+
+        // 10 s at 1 GHz is 10^10 tics, more than twice the 32-bit period.
+        let mut delay = alarm.delay_ms(&alarm_waker, 10_000);
+        let mut elapsed: u64 = 0;
+        while Pin::new(&mut delay).poll(&mut cx) == Poll::Pending {
+            let step = alarm.get_alarm().wrapping_sub(alarm.now());
+            alarm.advance(step);
+            elapsed += step as u64;
+        }
+        assert_eq!(elapsed, 10_000_000_000);
+        assert!(wakes.get() > 2);
+    }
+
+    #[test]
+    fn test_dropped_delay_disables_alarm() {
+        let alarm = MockAlarm::new();
+        let alarm_waker = AlarmWaker::new();
+        alarm.set_client(&alarm_waker);
+        let wakes = Cell::new(0);
+        let waker = counting_waker(&wakes);
+        let mut cx = Context::from_waker(&waker);
+
+        let mut delay = alarm.delay_ms(&alarm_waker, 10);
+        assert_eq!(Pin::new(&mut delay).poll(&mut cx), Poll::Pending);
+        core::mem::drop(delay);
+        assert!(!alarm.is_enabled());
+        alarm.advance(20);
+        assert_eq!(wakes.get(), 0);
+    }
+
+    /// A timer that only records what it was asked to do.
+    struct FakeTimer {
+        interval: Cell<Option<u32>>,
+        remaining: Cell<Option<u32>>,
+        count: Cell<u32>,
+    }
+
+    impl FakeTimer {
+        fn new() -> FakeTimer {
+            FakeTimer {
+                interval: Cell::new(None),
+                remaining: Cell::new(None),
+                count: Cell::new(0),
+            }
+        }
+    }
+
+    impl Time for FakeTimer {
+        type Frequency = Freq1KHz;
+
+        fn now(&self) -> u32 {
+            0
+        }
+    }
+
+    impl Timer<'a> for FakeTimer {
+        fn set_client(&'a self, _client: &'a dyn TimerClient) {}
+
+        fn oneshot(&self, interval: u32) {
+            self.interval.set(None);
+            self.remaining.set(Some(interval));
+        }
+
+        fn repeat(&self, interval: u32) {
+            self.interval.set(Some(interval));
+            self.remaining.set(Some(interval));
+        }
+
+        fn repeat_n(&self, interval: u32, count: u32) -> ReturnCode {
+            if interval == 0 {
+                return ReturnCode::EINVAL;
+            }
+            self.repeat(interval);
+            self.count.set(count);
+            ReturnCode::SUCCESS
+        }
+
+        fn interval(&self) -> Option<u32> {
+            self.interval.get()
+        }
+
+        fn change_interval(&self, interval: u32) -> ReturnCode {
+            match self.interval.get() {
+                Some(_) => {
+                    self.interval.set(Some(interval));
+                    ReturnCode::SUCCESS
+                }
+                None => ReturnCode::EINVAL,
+            }
+        }
+
+        fn time_remaining(&self) -> Option<u32> {
+            self.remaining.get()
+        }
+
+        fn cancel(&self) {
+            self.interval.set(None);
+            self.remaining.set(None);
+        }
+    }
+
+    #[test]
+    fn test_timer_mode_follows_interval() {
+        let timer = FakeTimer::new();
+        timer.repeat(10);
+        assert!(timer.is_repeating());
+        assert!(!timer.is_oneshot());
+
+        timer.oneshot(10);
+        assert!(timer.is_oneshot());
+        assert!(!timer.is_repeating());
+    }
+
+    #[test]
+    fn test_cancelled_timer_has_no_time_remaining() {
+        let timer = FakeTimer::new();
+        timer.repeat(10);
+        assert_eq!(timer.time_remaining(), Some(10));
+        assert!(timer.is_enabled());
+
+        timer.cancel();
+        assert_eq!(timer.time_remaining(), None);
+        assert!(!timer.is_enabled());
+    }
+
+    #[test]
+    fn test_has_expired_without_wrap() {
+        assert!(!has_expired(100u32, 200, 150));
+        assert!(has_expired(100u32, 200, 200));
+        assert!(has_expired(100u32, 200, 201));
+    }
+
+    #[test]
+    fn test_has_expired_across_wrap() {
+        let reference = u32::max_value() - 10;
+        let deadline = 10u32;
+        assert!(!has_expired(reference, deadline, u32::max_value()));
+        assert!(!has_expired(reference, deadline, 0));
+        assert!(!has_expired(reference, deadline, 9));
+        assert!(has_expired(reference, deadline, 10));
+        assert!(has_expired(reference, deadline, 11));
+    }
+
+    #[test]
+    fn test_has_expired_long_past_deadline() {
+        let reference = u32::max_value() - 10;
+        let deadline = 10u32;
+        // Almost a full period since `reference`: still reported as expired
+        // rather than as a deadline in the future.
+        assert!(has_expired(reference, deadline, reference.wrapping_sub(1)));
+    }
+
+    #[test]
+    fn test_compensate_latency() {
+        assert_eq!(compensate_latency(100u32, 200, 3, 2), 197);
+        assert_eq!(compensate_latency(100u32, 200, 0, 2), 200);
+        // Never closer to `now` than `min_dt`.
+        assert_eq!(compensate_latency(100u32, 104, 3, 2), 102);
+        assert_eq!(compensate_latency(100u32, 101, 3, 2), 101);
+        // Across a wrap.
+        assert_eq!(
+            compensate_latency(u32::max_value() - 5, 5, 8, 2),
+            u32::max_value() - 2
+        );
+    }
+
+    #[test]
+    fn test_latency_compensation_unsupported_by_default() {
+        let alarm = FakeAlarm::new(0);
+        assert_eq!(alarm.set_latency_compensation(3), ReturnCode::ENOSUPPORT);
+    }
+
+    #[test]
+    fn test_ticks_remaining() {
+        let max = u32::max_value();
+        assert_eq!(ticks_remaining(100, 150, max), 50);
+        assert_eq!(ticks_remaining(150, 150, max), 0);
+        assert_eq!(ticks_remaining(151, 150, max), 0);
+    }
+
+    #[test]
+    fn test_ticks_remaining_across_wrap() {
+        let max = u32::max_value();
+        assert_eq!(ticks_remaining(max - 4, 5, max), 10);
+        assert_eq!(ticks_remaining(max, 0, max), 1);
+        // `now` has just wrapped past a deadline at the end of the period.
+        assert_eq!(ticks_remaining(2, max - 2, max), 0);
+    }
+
+    #[test]
+    fn test_ticks_remaining_narrow_counter() {
+        let max: u32 = (1 << 24) - 1;
+        assert_eq!(ticks_remaining(max - 4, 5, max), 10);
+        // 20 tics behind across the 24-bit wrap: passed, where a 32-bit
+        // sign test would see a deadline far in the future.
+        assert_eq!(ticks_remaining(10, max - 10, max), 0);
+        assert_eq!(ticks_remaining(0u64, 1 << 40, u64::max_value()), 1 << 40);
+    }
+
+    #[test]
+    fn test_busy_delay_ms() {
+        let time = SteppingTime::new(0, 1, u32::max_value());
+        BusyDelay::new(&time).delay_ms(10);
+        // One more tic than requested, and the clock ticks once per read, so
+        // the last read returned 11 tics after the start.
+        assert_eq!(time.now.get(), 12);
+    }
+
+    #[test]
+    fn test_busy_delay_across_narrow_wrap() {
+        let max = (1 << 24) - 1;
+        let time = SteppingTime::new(max - 5, 1, max);
+        BusyDelay::new(&time).delay_ms(10);
+        assert_eq!(time.now.get(), 6);
+    }
+
+    /// A 32 kHz counter driven by a time in nanoseconds that advances by
+    /// `step_ns` on every read, so reads land anywhere within a tic.
+    struct NanosTime {
+        ns: Cell<u64>,
+        step_ns: u64,
+    }
+
+    impl Time for NanosTime {
+        type Frequency = Freq32KHz;
+
+        fn now(&self) -> u32 {
+            let ns = self.ns.get();
+            self.ns.set(ns + self.step_ns);
+            (ns * 32768 / 1_000_000_000) as u32
+        }
+    }
+
+    #[test]
+    fn test_busy_delay_is_a_lower_bound() {
+        // Start 1 ns before the counter tics.
+        let start = 1_000_000_000 / 32768;
+        let time = NanosTime {
+            ns: Cell::new(start),
+            step_ns: 100,
+        };
+        // The last read was one step before `ns`.
+        BusyDelay::new(&time).delay_us(1);
+        assert!(time.ns.get() - time.step_ns - start >= 1000);
+
+        time.ns.set(start);
+        BusyDelay::new(&time).delay_ms(1);
+        assert!(time.ns.get() - time.step_ns - start >= 1_000_000);
+    }
+
+    #[test]
+    fn test_busy_delay_longer_than_period() {
+        let max = 0xff;
+        let time = SteppingTime::new(0, 1, max);
+        // 1000 tics on a 256 tic counter needs several steps.
+        BusyDelay::new(&time).delay_ms(1000);
+        assert!(time.reads.get() > 1000);
+    }
+
+    #[test]
+    fn test_stopwatch() {
+        let time = SteppingTime::new(0, 1, u32::max_value());
+        let stopwatch = Stopwatch::new(&time);
+        time.now.set(10);
+        assert_eq!(stopwatch.elapsed_tics(), 10);
+        // Still running.
+        time.now.set(25);
+        assert_eq!(stopwatch.elapsed_us(), 25_000);
+
+        // Restart.
+        time.now.set(100);
+        stopwatch.start();
+        time.now.set(103);
+        assert_eq!(stopwatch.elapsed_tics(), 3);
+    }
+
+    #[test]
+    fn test_stopwatch_across_narrow_wrap() {
+        let max = (1 << 24) - 1;
+        let time = SteppingTime::new(max - 5, 1, max);
+        let stopwatch = Stopwatch::new(&time);
+        time.now.set(4);
+        assert_eq!(stopwatch.elapsed_tics(), 10);
+    }
+
+    #[test]
+    fn test_default_capabilities() {
+        let time = SteppingTime::new(0, 1, u32::max_value());
+        assert_eq!(
+            time.capabilities(),
+            TimeCapabilities {
+                frequency: 1000,
+                width_bits: 32,
+                min_alarm_dt: 0,
+                is_counter: false,
+            }
+        );
+    }
+
+    #[test]
+    fn test_extended_timestamp_orders_overflow_and_captures() {
+        let width = 24;
+        let before = extended_timestamp(2, (1 << 24) - 1, width);
+        let overflow = extended_timestamp(3, 0, width);
+        let after = extended_timestamp(3, 5, width);
+        assert!(before < overflow && overflow < after);
+        assert_eq!(after, (3 << 24) + 5);
+        // No room for a high word.
+        assert_eq!(extended_timestamp(3, 5, 64), 5);
+    }
+
+    #[test]
+    fn test_elapsed_since() {
+        let time = FakeAlarm::new(10);
+        assert_eq!(time.elapsed_since(4), 6);
+        assert_eq!(time.checked_elapsed_since(4), Some(6));
+
+        // Across a wrap.
+        assert_eq!(time.elapsed_since(u32::max_value() - 1), 12);
+        assert_eq!(time.checked_elapsed_since(u32::max_value() - 1), Some(12));
+
+        // Almost a full period, or `earlier` a little in the future.
+        assert_eq!(time.elapsed_since(12), u32::max_value() - 1);
+        assert_eq!(time.checked_elapsed_since(12), None);
+    }
+
+    #[test]
+    fn test_now_is_after() {
+        let time = FakeAlarm::new(10);
+        assert!(time.now_is_after(4));
+        assert!(time.now_is_after(10));
+        assert!(!time.now_is_after(11));
+
+        // Across a wrap, either way.
+        assert!(time.now_is_after(u32::max_value() - 1));
+        time.now.set(u32::max_value() - 1);
+        assert!(!time.now_is_after(3));
+
+        // More than half a period behind reads as ahead.
+        assert!(!time.now_is_after(u32::max_value() / 2 - 10));
+
+        let time = SteppingTime::new(0xFF_FFF0, 0, 0xFF_FFFF);
+        assert!(time.now_is_after(0xFF_FF00));
+        assert!(!time.now_is_after(0x10));
+    }
+
+    #[test]
+    fn test_describe() {
+        let alarm = FakeAlarm::new(100);
+        alarm.set_alarm(150);
+        assert_eq!(
+            alarm.describe(),
+            TimeState {
+                now: 100,
+                frequency: 1000,
+                enabled: true,
+                alarm: Some(150),
+                interval: None,
+                remaining: Some(50),
+            }
+        );
+
+        let timer = VirtualTimer::new();
+        timer.repeat(10);
+        timer.tick(3);
+        assert_eq!(
+            timer.describe(),
+            TimeState {
+                now: 3,
+                frequency: 1000,
+                enabled: true,
+                alarm: None,
+                interval: Some(10),
+                remaining: Some(7),
+            }
+        );
+        timer.cancel();
+        assert!(!timer.describe().enabled);
+    }
+
+    #[test]
+    fn test_const_ms_to_tics() {
+        const BOOT_DELAY: u32 = const_ms_to_tics(5, 16_000_000);
+        assert_eq!(BOOT_DELAY, 80_000);
+        assert_eq!(const_ms_to_tics(1, 32768), convert_tics(1, 1000, 32768));
+        assert_eq!(const_ms_to_tics(1, 32768), 33);
+        assert_eq!(
+            const_ms_to_tics(131_071, 32768),
+            convert_tics(131_071, 1000, 32768)
+        );
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_const_ms_to_tics_overflows() {
+        // Panics in release builds too, as it does not rely on overflow
+        // checks.
+        let ms = 1_000_000;
+        const_ms_to_tics(ms, 16_000_000);
+    }
+
+    #[test]
+    fn test_combine64() {
+        let time = FakeAlarm::new(10);
+        assert_eq!(time.combine64(2, 5), (2 << 32) + 10);
+        assert_eq!(time.combine64(2, 10), (2 << 32) + 10);
+
+        // Wrapped after `low_before` was read.
+        time.now.set(3);
+        assert_eq!(time.combine64(2, u32::max_value() - 1), (3 << 32) + 3);
+    }
+
+    #[test]
+    fn test_convert_tics() {
+        assert_eq!(convert_tics(32768, 32768, 1000), 1000);
+        // 33 tics at 32768 Hz are 1.007ms, and 16 tics are 0.488ms.
+        assert_eq!(convert_tics(33, 32768, 1000), 1);
+        assert_eq!(convert_tics(16, 32768, 1000), 0);
+        assert_eq!(convert_tics(17, 32768, 1000), 1);
+        assert_eq!(convert_tics(3, 1000, 1_000_000), 3000);
+        assert_eq!(
+            convert_tics(u32::max_value(), 1000, 1_000_000),
+            u32::max_value()
+        );
+
+        assert_eq!(
+            convert_tics_u64(1 << 40, 32768, 1_000_000),
+            33_554_432_000_000
+        );
+        assert_eq!(convert_tics_u64(17, 32768, 1000), 1);
+        assert_eq!(convert_tics_u64(u64::max_value(), 1, 2), u64::max_value());
+    }
+
+    #[test]
+    fn test_now_in_real_units() {
+        let alarm = MockAlarm::new();
+        alarm.set_frequency(32768);
+        alarm.set_now(u32::max_value());
+        // A full 32-bit count at 32768 Hz, about 36.4 hours.
+        assert_eq!(alarm.now_ms(), 131_071_999);
+        assert_eq!(alarm.now_us(), 131_071_999_969);
+
+        alarm.set_now(33);
+        assert_eq!(alarm.try_now(), Ok(33));
+        assert_eq!(alarm.now_ms(), 1);
+        assert_eq!(alarm.now_us(), 1007);
+        assert_eq!(alarm.now_ns(), 1_007_080);
+
+        alarm.set_frequency(64_000_000);
+        alarm.set_now(u32::max_value());
+        assert_eq!(alarm.now_ns(), 67_108_863_984);
+    }
+
+    #[test]
+    fn test_rate_limiter() {
+        let alarm = MockAlarm::new();
+        // 4 per second at 1kHz: one token every 250 tics.
+        let limiter = RateLimiter::new(&alarm, 4, 1000).unwrap();
+        for _ in 0..4 {
+            assert!(limiter.try_acquire());
+        }
+        assert!(!limiter.try_acquire());
+
+        alarm.advance(249);
+        assert!(!limiter.try_acquire());
+        alarm.advance(1);
+        assert!(limiter.try_acquire());
+        assert!(!limiter.try_acquire());
+
+        // Refills to at most a full bucket.
+        alarm.advance(10_000);
+        for _ in 0..4 {
+            assert!(limiter.try_acquire());
+        }
+        assert!(!limiter.try_acquire());
+    }
+
+    #[test]
+    fn test_rate_limiter_across_wrap() {
+        let alarm = MockAlarm::new();
+        alarm.set_now(u32::max_value() - 100);
+        let limiter = RateLimiter::new(&alarm, 1, 1000).unwrap();
+        assert!(limiter.try_acquire());
+        alarm.advance(999);
+        assert!(!limiter.try_acquire());
+        alarm.advance(1);
+        assert!(limiter.try_acquire());
+    }
+
+    #[test]
+    fn test_rate_limiter_rejects_overflow() {
+        let alarm = MockAlarm::new();
+        alarm.set_frequency(u32::max_value());
+        assert!(RateLimiter::new(&alarm, u32::max_value(), u32::max_value()).is_err());
+
+        // A bucket that only just fits refills without overflowing.
+        let limiter = RateLimiter::new(&alarm, 1_000, u32::max_value()).unwrap();
+        alarm.advance(u32::max_value());
+        assert!(limiter.try_acquire());
+    }
+
+    #[test]
+    fn test_time_weighted_avg() {
+        let alarm = MockAlarm::new();
+        let avg = TimeWeightedAvg::new(&alarm, 1000);
+        assert_eq!(avg.get(), 0);
+        avg.update(100);
+        assert_eq!(avg.get(), 100);
+
+        // Half a window: halfway.
+        alarm.advance(500);
+        avg.update(200);
+        assert_eq!(avg.get(), 150);
+
+        // A burst of samples right after each other barely moves it.
+        for _ in 0..10 {
+            avg.update(-1000);
+        }
+        assert_eq!(avg.get(), 150);
+
+        // More than a window: replaced.
+        alarm.advance(2000);
+        avg.update(-50);
+        assert_eq!(avg.get(), -50);
+        alarm.advance(100);
+        avg.update(950);
+        assert_eq!(avg.get(), 50);
+    }
+
+    #[test]
+    fn test_time_weighted_avg_across_wrap() {
+        let alarm = MockAlarm::new();
+        alarm.set_now(u32::max_value() - 100);
+        let avg = TimeWeightedAvg::new(&alarm, 1000);
+        avg.update(0);
+        alarm.advance(250);
+        avg.update(1000);
+        assert_eq!(avg.get(), 250);
+    }
+
+    #[test]
+    fn test_measure_read_jitter() {
+        let time = SteppingTime::new(0, 3, u32::max_value());
+        assert_eq!(measure_read_jitter(&time, 10), (3, 3));
+        assert_eq!(time.reads.get(), 11);
+        assert_eq!(measure_read_jitter(&time, 0), (0, 0));
+    }
+
+    #[test]
+    fn test_measure_read_jitter_across_narrow_wrap() {
+        let max = (1 << 24) - 1;
+        let time = SteppingTime::new(max - 5, 2, max);
+        assert_eq!(measure_read_jitter(&time, 8), (2, 2));
+    }
+
+    #[test]
+    fn test_measure_cycles() {
+        let counter = FlagCounter::new(100, true);
+        let (result, cycles) = measure_cycles(&counter, || {
+            counter.advance(500);
+            7
+        });
+        assert_eq!((result, cycles), (7, Some(500)));
+
+        // A wrap, once or several times, discards the count.
+        let counter = FlagCounter::new(0xFF00, true);
+        assert_eq!(measure_cycles(&counter, || counter.advance(0x200)).1, None);
+        let counter = FlagCounter::new(100, true);
+        assert_eq!(
+            measure_cycles(&counter, || counter.advance(0x10005)).1,
+            None
+        );
+
+        // A counter that cannot tell falls back to the count modulo its
+        // width, which still survives a single wrap.
+        let counter = FlagCounter::new(100, false);
+        assert_eq!(measure_cycles(&counter, || counter.advance(5)).1, Some(5));
+        let counter = FlagCounter::new(0xFF00, false);
+        assert_eq!(
+            measure_cycles(&counter, || counter.advance(0x200)).1,
+            Some(0x200)
+        );
+    }
+
+    #[test]
+    fn test_wait_until() {
+        let time = SteppingTime::new(0, 1, u32::max_value());
+        let mut polls = 0;
+        let result = wait_until(&time, 100, || {
+            polls += 1;
+            polls == 5
+        });
+        assert_eq!(result, ReturnCode::SUCCESS);
+        assert_eq!(polls, 5);
+
+        // Already true: no timeout, even with none to spare.
+        assert_eq!(wait_until(&time, 0, || true), ReturnCode::SUCCESS);
+    }
+
+    #[test]
+    fn test_wait_until_times_out_across_wrap() {
+        let max = (1 << 24) - 1;
+        let time = SteppingTime::new(max - 5, 1, max);
+        let mut polls = 0;
+        let result = wait_until(&time, 10, || {
+            polls += 1;
+            false
+        });
+        assert_eq!(result, ReturnCode::FAIL);
+        assert_eq!(polls, 10);
+    }
+
+    #[test]
+    fn test_ms_to_tics_rounds() {
+        // 1ms is 32.768 tics at 32KHz.
+        assert_eq!(Freq32KHz::ms_to_tics(1), 33);
+        assert_eq!(Freq32KHz::ms_to_tics(1000), 32768);
+        assert_eq!(Freq1KHz::ms_to_tics(1234), 1234);
+        assert_eq!(Freq16MHz::us_to_tics(3), 48);
+    }
+
+    #[test]
+    fn test_ms_to_tics_does_not_overflow() {
+        // 200s on a 16MHz clock overflows a 32-bit intermediate product but
+        // still fits in the result.
+        assert_eq!(Freq16MHz::ms_to_tics(200_000), 3_200_000_000);
+        // An hour does not fit in 32 bits of tics at all and saturates rather
+        // than wrapping around to a short delay.
+        assert_eq!(Freq16MHz::ms_to_tics(60 * 60 * 1000), u32::max_value());
+    }
+
+    #[test]
+    fn test_ms_to_tics_checked() {
+        assert_eq!(Freq16MHz::ms_to_tics_checked(200_000), Some(3_200_000_000));
+        assert_eq!(Freq16MHz::ms_to_tics_checked(2 * 60 * 60 * 1000), None);
+    }
+
+    #[test]
+    fn test_try_set_alarm_after() {
+        let alarm = FakeAlarm::new(100);
+        assert_eq!(alarm.try_set_alarm_after(50), ReturnCode::SUCCESS);
+        assert_eq!(alarm.get_alarm(), 150);
+
+        // Two hours on a 16MHz clock is longer than a 32-bit period.
+        let fast = MockAlarm::new();
+        fast.set_frequency(16_000_000);
+        assert_eq!(
+            fast.try_set_alarm_after(2 * 60 * 60 * 1000),
+            ReturnCode::ESIZE
+        );
+        assert!(!fast.is_enabled());
+        assert_eq!(fast.try_set_alarm_after(60 * 1000), ReturnCode::SUCCESS);
+        assert_eq!(fast.get_alarm(), 960_000_000);
+    }
+
+    #[test]
+    fn test_tics_to_real_time() {
+        assert_eq!(Freq32KHz::tics_to_ms(33), 1);
+        assert_eq!(Freq32KHz::tics_to_ms(32768), 1000);
+        assert_eq!(Freq32KHz::tics_to_us(1), 31);
+        assert_eq!(Freq16MHz::tics_to_us(16), 1);
+        assert_eq!(Freq16MHz::tics_to_ms(u32::max_value()), 268_435);
+    }
+
+    #[test]
+    fn test_ns_conversions() {
+        // 15.625ns per tic.
+        assert_eq!(Freq64MHz::tics_to_ns(1), 16);
+        assert_eq!(Freq64MHz::tics_to_ns(64), 1000);
+        assert_eq!(Freq64MHz::ns_to_tics(1000), 64);
+        assert_eq!(Freq64MHz::ns_to_tics(7), 0);
+        assert_eq!(Freq64MHz::ns_to_tics(8), 1);
+        assert_eq!(Freq64MHz::ns_to_tics(60 * 1_000_000_000), 3_840_000_000);
+        assert_eq!(Freq32KHz::tics_to_ns(u32::max_value()), 131_071_999_969_482);
+        assert_eq!(Freq32KHzDiv3::tics_to_ns(1), 91_553);
+        assert_eq!(Freq1Hz::ns_to_tics(u64::max_value()), 18_446_744_074);
+    }
+
+    crate::frequency!(Freq1Hz, 1);
+    crate::frequency!(Freq13107Hz, 13107);
+
+    /// The 32768Hz clock divided by three.
+    struct Freq32KHzDiv3;
+
+    impl Frequency for Freq32KHzDiv3 {
+        fn frequency() -> u32 {
+            10922
+        }
+
+        fn frequency_num() -> u32 {
+            32768
+        }
+
+        fn frequency_den() -> u32 {
+            3
+        }
+    }
+
+    #[test]
+    fn test_fractional_frequency() {
+        // A minute is exactly 655360 tics; 10922Hz would give 655320.
+        assert_eq!(Freq32KHzDiv3::ms_to_tics(60_000), 655_360);
+        assert_eq!(Freq32KHzDiv3::tics_to_ms(655_360), 60_000);
+        assert_eq!(
+            Freq32KHzDiv3::tics_to_us(u32::max_value()),
+            u32::max_value()
+        );
+        assert_eq!(Freq32KHzDiv3::us_to_tics(1000), 11);
+        assert_eq!(Freq1KHz::frequency_den(), 1);
+    }
+
+    #[test]
+    fn test_frequency_macro() {
+        assert_eq!(Freq13107Hz::frequency(), 13107);
+        assert_eq!(Freq13107Hz::ms_to_tics(1000), 13107);
+        assert_eq!(Freq32KHz::frequency(), 32768);
+    }
+
+    #[test]
+    fn test_freq_ticks() {
+        let start: FreqTicks<Freq32KHz> = FreqTicks::new(u32::max_value() - 9);
+        let later = start.wrapping_add(FreqTicks::new(32_778));
+        assert_eq!(later.into_u32(), 32_768);
+        assert_eq!(later.wrapping_sub(start).into_ms(), 1000);
+        assert_eq!(FreqTicks::<Freq32KHz>::new(33).into_us(), 1007);
+        assert_eq!(
+            FreqTicks::<Freq1KHz>::max_value().into_us(),
+            u32::max_value()
+        );
+    }
+
+    #[test]
+    fn test_instant_arithmetic() {
+        let time = FakeAlarm::new(100);
+        let start = time.instant();
+        time.now.set(350);
+        assert_eq!(time.instant().wrapping_sub(start), Duration::from_tics(250));
+        assert_eq!(start.wrapping_sub(time.instant()).tics(), -250);
+        assert_eq!(start + Duration::from_ms(250), time.instant());
+        assert_eq!(
+            start + Duration::from_tics(-101),
+            Instant::new(u32::max_value())
+        );
+    }
+
+    #[test]
+    fn test_instant_across_wrap() {
+        let start: Instant<Freq1KHz> = Instant::new(u32::max_value() - 9);
+        let later = start + Duration::from_ms(20);
+        assert_eq!(later.tics(), 10);
+        assert_eq!(later.wrapping_sub(start), Duration::from_ms(20));
+        assert!(later.wrapping_sub(start) > Duration::from_tics(0));
+    }
+
+    #[test]
+    fn test_duration_from_ms() {
+        assert_eq!(Duration::<Freq32KHz>::from_ms(1).tics(), 33);
+        assert_eq!(Duration::<Freq16MHz>::from_us(3).tics(), 48);
+        assert_eq!(
+            Duration::<Freq16MHz>::from_ms(60 * 60 * 1000).tics(),
+            i32::max_value()
+        );
+    }
 }
@@ -0,0 +1,1138 @@
+//! Test doubles for the time HIL.
+//!
+//! Only built for the kernel's own tests and with the `test-util` feature,
+//! which crates can enable in their `[dev-dependencies]`.
+
+use crate::common::cells::OptionalCell;
+use crate::hil::time::{self, Alarm, AlarmClient, Counter, Freq1KHz, OverflowClient, Time};
+use crate::hil::time::{Timer, TimerClient};
+use crate::ReturnCode;
+use core::cell::Cell;
+
+/// An `Alarm` whose clock only moves when told to.
+///
+/// `now()` starts at `0` and is moved forward with
+/// [`advance`](#method.advance), which synchronously calls the client when
+/// the armed alarm is crossed. This makes timeout logic testable on the host
+/// without hardware or real waiting.
+///
+/// Its `Frequency` type is `Freq1KHz`, but the runtime
+/// [`frequency`](../trait.Time.html#method.frequency) can be changed with
+/// [`set_frequency`](#method.set_frequency) to stand in for other clocks.
+///
+/// It follows the enable/disable rules of the [`Alarm`](../trait.Alarm.html)
+/// trait strictly, including returning `ReturnCode::EINVAL` from `enable()`
+/// before any deadline was set. Interrupts can be masked with
+/// [`set_interrupts_enabled`](#method.set_interrupts_enabled) to test code
+/// that runs with interrupts disabled past a deadline, and the clock can be
+/// made to move by itself with [`set_auto_advance`](#method.set_auto_advance)
+/// to drive code that polls `now()`.
+///
+/// It is also a `Counter`. Stopping it drops the armed alarm, and
+/// `set_alarm` returns `ReturnCode::EOFF` until it is started again. Unless
+/// it persists in sleep (see
+/// [`set_persists_in_sleep`](#method.set_persists_in_sleep)), starting it
+/// again restarts the count from `0`. `advance` moves the clock also while
+/// it is stopped, standing in for the time that passed.
+pub struct MockAlarm<'a> {
+    now: Cell<u32>,
+    /// Tics `now()` advances by on every read.
+    auto_advance: Cell<u32>,
+    /// Set while `advance` runs, to keep reads from the client from
+    /// advancing the clock again.
+    advancing: Cell<bool>,
+    frequency: Cell<u32>,
+    /// The last deadline set, if any.
+    alarm: Cell<Option<u32>>,
+    /// `now` when the last deadline was set.
+    reference: Cell<u32>,
+    token: Cell<u32>,
+    armed: Cell<bool>,
+    /// Whether the last deadline has been delivered to the client.
+    delivered: Cell<bool>,
+    interrupts_enabled: Cell<bool>,
+    /// Set when the deadline was reached with interrupts disabled.
+    pending: Cell<bool>,
+    running: Cell<bool>,
+    persists_in_sleep: Cell<bool>,
+    client: OptionalCell<&'a dyn AlarmClient>,
+}
+
+impl Default for MockAlarm<'a> {
+    fn default() -> MockAlarm<'a> {
+        MockAlarm::new()
+    }
+}
+
+impl MockAlarm<'a> {
+    pub fn new() -> MockAlarm<'a> {
+        MockAlarm {
+            now: Cell::new(0),
+            auto_advance: Cell::new(0),
+            advancing: Cell::new(false),
+            frequency: Cell::new(1000),
+            alarm: Cell::new(None),
+            reference: Cell::new(0),
+            token: Cell::new(0),
+            armed: Cell::new(false),
+            delivered: Cell::new(false),
+            interrupts_enabled: Cell::new(true),
+            pending: Cell::new(false),
+            running: Cell::new(true),
+            persists_in_sleep: Cell::new(false),
+            client: OptionalCell::empty(),
+        }
+    }
+
+    /// Sets the clock to `now` without firing the alarm.
+    pub fn set_now(&self, now: u32) {
+        self.now.set(now);
+    }
+
+    /// Makes every read of `now()` first advance the clock by
+    /// `tics_per_read`, as [`advance`](#method.advance) does, so that code
+    /// that polls `now()` in a loop, such as a busy wait or a timeout, makes
+    /// progress on its own. The alarm still fires when a read crosses it.
+    ///
+    /// Reads from the client while the clock advances, e.g. from `fired()`,
+    /// do not advance it further. `0`, the default, turns this off.
+    pub fn set_auto_advance(&self, tics_per_read: u32) {
+        self.auto_advance.set(tics_per_read);
+    }
+
+    /// Sets the value reported by `Time::frequency`.
+    pub fn set_frequency(&self, hz: u32) {
+        self.frequency.set(hz);
+    }
+
+    /// Sets the value reported by `Time::persists_in_sleep`, and whether the
+    /// count survives stopping the counter. `false` by default.
+    pub fn set_persists_in_sleep(&self, persists: bool) {
+        self.persists_in_sleep.set(persists);
+    }
+
+    /// Masks or unmasks the alarm interrupt.
+    ///
+    /// While masked, reaching the deadline is latched like a hardware compare
+    /// match instead of calling the client. Unmasking then calls the client
+    /// right away, at the current time.
+    pub fn set_interrupts_enabled(&self, enabled: bool) {
+        self.interrupts_enabled.set(enabled);
+        if enabled && self.pending.get() {
+            self.fire();
+        }
+    }
+
+    /// Moves the clock forward by `tics`.
+    ///
+    /// If the alarm is reached on the way, the clock stops at the alarm
+    /// value, the alarm is disarmed and the client is called, and then the
+    /// clock moves on. An alarm the client sets from its callback therefore
+    /// also fires within the same call if it falls before the end of the
+    /// advance.
+    pub fn advance(&self, tics: u32) {
+        let advancing = self.advancing.replace(true);
+        let mut remaining = tics;
+        while let (true, false, Some(alarm)) =
+            (self.armed.get(), self.pending.get(), self.alarm.get())
+        {
+            let until_alarm = alarm.wrapping_sub(self.now.get());
+            if until_alarm > remaining {
+                break;
+            }
+            self.now.set(alarm);
+            remaining -= until_alarm;
+            if self.interrupts_enabled.get() {
+                self.fire();
+            } else {
+                self.pending.set(true);
+            }
+        }
+        self.now.set(self.now.get().wrapping_add(remaining));
+        self.advancing.set(advancing);
+    }
+
+    fn fire(&self) {
+        self.armed.set(false);
+        self.pending.set(false);
+        self.delivered.set(true);
+        let token = self.token.get();
+        self.client.map(|client| client.fired_with_token(token));
+    }
+}
+
+impl Time for MockAlarm<'a> {
+    type Frequency = Freq1KHz;
+
+    fn now(&self) -> u32 {
+        if self.auto_advance.get() > 0 && !self.advancing.get() {
+            self.advance(self.auto_advance.get());
+        }
+        self.now.get()
+    }
+
+    fn frequency(&self) -> u32 {
+        self.frequency.get()
+    }
+
+    fn persists_in_sleep(&self) -> bool {
+        self.persists_in_sleep.get()
+    }
+}
+
+impl Counter<'a> for MockAlarm<'a> {
+    fn set_overflow_client(&'a self, _client: &'a dyn OverflowClient) {}
+
+    fn start(&self) -> ReturnCode {
+        if !self.running.replace(true) && !self.persists_in_sleep.get() {
+            self.now.set(0);
+        }
+        ReturnCode::SUCCESS
+    }
+
+    fn stop(&self) -> ReturnCode {
+        self.disable();
+        self.running.set(false);
+        ReturnCode::SUCCESS
+    }
+
+    fn is_running(&self) -> bool {
+        self.running.get()
+    }
+}
+
+impl Alarm<'a> for MockAlarm<'a> {
+    fn set_alarm_with_token(&self, tics: u32, token: u32) -> ReturnCode {
+        if !self.running.get() {
+            return ReturnCode::EOFF;
+        }
+        let (now, min_dt) = (self.now.get(), self.minimum_dt());
+        let tics = if time::reached_within(now, tics, min_dt, u32::max_value()) {
+            now.wrapping_add(min_dt)
+        } else {
+            tics
+        };
+        if tics.wrapping_sub(now) < min_dt {
+            return ReturnCode::EINVAL;
+        }
+        self.alarm.set(Some(tics));
+        self.reference.set(self.now.get());
+        self.token.set(token);
+        self.armed.set(true);
+        self.delivered.set(false);
+        self.pending.set(false);
+        ReturnCode::SUCCESS
+    }
+
+    fn get_alarm(&self) -> u32 {
+        self.alarm.get().unwrap_or(0)
+    }
+
+    fn minimum_dt(&self) -> u32 {
+        1
+    }
+
+    fn set_client(&'a self, client: &'a dyn AlarmClient) {
+        self.client.set(client);
+    }
+
+    fn clear_client(&self) {
+        self.disable();
+        self.client.clear();
+    }
+
+    fn is_enabled(&self) -> bool {
+        self.armed.get()
+    }
+
+    fn enable(&self) -> ReturnCode {
+        let alarm = match self.alarm.get() {
+            Some(alarm) if !self.delivered.get() => alarm,
+            _ => return ReturnCode::EINVAL,
+        };
+        if self.armed.get() {
+            return ReturnCode::SUCCESS;
+        }
+        let now = self.now.get();
+        if time::has_expired(self.reference.get(), alarm, now) {
+            // Passed while disabled: fire as soon as possible.
+            self.set_alarm_with_token(now.wrapping_add(self.minimum_dt()), self.token.get())
+        } else {
+            self.armed.set(true);
+            ReturnCode::SUCCESS
+        }
+    }
+
+    fn disable(&self) -> ReturnCode {
+        self.armed.set(false);
+        self.pending.set(false);
+        ReturnCode::SUCCESS
+    }
+
+    /// Same as `set_interrupts_enabled(false)`.
+    fn mask(&self) -> ReturnCode {
+        self.set_interrupts_enabled(false);
+        ReturnCode::SUCCESS
+    }
+
+    /// Same as `set_interrupts_enabled(true)`, so a latched deadline calls
+    /// the client before this returns.
+    fn unmask(&self) -> ReturnCode {
+        self.set_interrupts_enabled(true);
+        ReturnCode::SUCCESS
+    }
+}
+
+/// A `Counter` whose value only moves when told to.
+///
+/// `now()` starts at `0` and is moved forward with
+/// [`advance`](#method.advance), which calls the overflow client when the
+/// count wraps. The width and the runtime frequency can be changed to stand
+/// in for other counters; its `Frequency` type is `Freq1KHz`.
+pub struct MockCounter<'a> {
+    now: Cell<u32>,
+    max: Cell<u32>,
+    frequency: Cell<u32>,
+    running: Cell<bool>,
+    /// Calls of `with_frozen`.
+    frozen_reads: Cell<usize>,
+    client: OptionalCell<&'a dyn OverflowClient>,
+}
+
+impl Default for MockCounter<'a> {
+    fn default() -> MockCounter<'a> {
+        MockCounter::new()
+    }
+}
+
+impl MockCounter<'a> {
+    pub fn new() -> MockCounter<'a> {
+        MockCounter {
+            now: Cell::new(0),
+            max: Cell::new(u32::max_value()),
+            frequency: Cell::new(1000),
+            running: Cell::new(true),
+            frozen_reads: Cell::new(0),
+            client: OptionalCell::empty(),
+        }
+    }
+
+    /// Sets the count to `now` without calling the overflow client.
+    pub fn set_now(&self, now: u32) {
+        self.now.set(now & self.max.get());
+    }
+
+    /// Sets the value reported by `Time::frequency`.
+    pub fn set_frequency(&self, hz: u32) {
+        self.frequency.set(hz);
+    }
+
+    /// Makes the counter wrap after `max`, e.g. `0xFFFF` for a 16-bit one.
+    pub fn set_max_tics(&self, max: u32) {
+        self.max.set(max);
+        self.now.set(self.now.get() & max);
+    }
+
+    /// Moves the count forward by `tics`, if the counter is running, and
+    /// calls the overflow client if it wrapped.
+    pub fn advance(&self, tics: u32) {
+        if !self.running.get() {
+            return;
+        }
+        let max = self.max.get();
+        let now = self.now.get();
+        self.now.set(now.wrapping_add(tics) & max);
+        if tics > max - now {
+            self.client.map(|client| client.overflow());
+        }
+    }
+
+    /// Returns how often `with_frozen` was called.
+    pub fn frozen_reads(&self) -> usize {
+        self.frozen_reads.get()
+    }
+}
+
+impl Time for MockCounter<'a> {
+    type Frequency = Freq1KHz;
+
+    fn now(&self) -> u32 {
+        self.now.get()
+    }
+
+    fn max_tics(&self) -> u32 {
+        self.max.get()
+    }
+
+    fn frequency(&self) -> u32 {
+        self.frequency.get()
+    }
+}
+
+impl Counter<'a> for MockCounter<'a> {
+    fn set_overflow_client(&'a self, client: &'a dyn OverflowClient) {
+        self.client.set(client);
+    }
+
+    fn start(&self) -> ReturnCode {
+        self.running.set(true);
+        ReturnCode::SUCCESS
+    }
+
+    fn stop(&self) -> ReturnCode {
+        self.running.set(false);
+        ReturnCode::SUCCESS
+    }
+
+    fn is_running(&self) -> bool {
+        self.running.get()
+    }
+
+    fn with_frozen<R>(&self, f: impl FnOnce(u32) -> R) -> R {
+        self.frozen_reads.set(self.frozen_reads.get() + 1);
+        f(self.now.get())
+    }
+}
+
+/// An `AlarmClient`, `TimerClient` and `OverflowClient` that counts its
+/// callbacks.
+///
+/// It records how often it was called, the token of the last
+/// `fired_with_token` and, if created with [`with_clock`](#method.with_clock),
+/// the clock's `now()` at the last callback.
+pub struct CountingClient<'a> {
+    clock: Option<&'a MockAlarm<'a>>,
+    fires: Cell<usize>,
+    fired_at: Cell<Option<u32>>,
+    token: Cell<Option<u32>>,
+}
+
+impl Default for CountingClient<'a> {
+    fn default() -> CountingClient<'a> {
+        CountingClient::new()
+    }
+}
+
+impl CountingClient<'a> {
+    pub fn new() -> CountingClient<'a> {
+        CountingClient {
+            clock: None,
+            fires: Cell::new(0),
+            fired_at: Cell::new(None),
+            token: Cell::new(None),
+        }
+    }
+
+    /// Creates a client that also records `clock.now()` at each callback.
+    pub fn with_clock(clock: &'a MockAlarm<'a>) -> CountingClient<'a> {
+        CountingClient {
+            clock: Some(clock),
+            ..CountingClient::new()
+        }
+    }
+
+    /// Returns the number of callbacks so far.
+    pub fn fires(&self) -> usize {
+        self.fires.get()
+    }
+
+    /// Returns the clock's `now()` at the last callback, or `None` before the
+    /// first one or without a clock.
+    pub fn fired_at(&self) -> Option<u32> {
+        self.fired_at.get()
+    }
+
+    /// Returns the token of the last `fired_with_token`, if any.
+    pub fn token(&self) -> Option<u32> {
+        self.token.get()
+    }
+
+    /// Forgets the callbacks so far.
+    pub fn reset(&self) {
+        self.fires.set(0);
+        self.fired_at.set(None);
+        self.token.set(None);
+    }
+
+    fn record(&self) {
+        self.fires.set(self.fires.get() + 1);
+        if let Some(clock) = self.clock {
+            self.fired_at.set(Some(clock.now()));
+        }
+    }
+}
+
+impl AlarmClient for CountingClient<'a> {
+    fn fired(&self) {
+        self.record();
+    }
+
+    fn fired_with_token(&self, token: u32) {
+        self.token.set(Some(token));
+        self.record();
+    }
+}
+
+impl TimerClient for CountingClient<'a> {
+    fn fired(&self) {
+        self.record();
+    }
+}
+
+impl OverflowClient for CountingClient<'a> {
+    fn overflow(&self) {
+        self.record();
+    }
+}
+
+/// A clock and alarm that does nothing.
+///
+/// `now()` is always `0`, setting the alarm is accepted but it never fires,
+/// and `is_enabled()` and `is_running()` are always `false`. It stands in for
+/// a time source in tests of capsules that need an `Alarm` or `Counter` to be
+/// constructed but never use it, where a [`MockAlarm`](struct.MockAlarm.html)
+/// would be more than needed.
+pub struct NullAlarm;
+
+impl Time for NullAlarm {
+    type Frequency = Freq1KHz;
+
+    fn now(&self) -> u32 {
+        0
+    }
+}
+
+impl Counter<'a> for NullAlarm {
+    fn set_overflow_client(&'a self, _client: &'a dyn OverflowClient) {}
+
+    fn start(&self) -> ReturnCode {
+        ReturnCode::SUCCESS
+    }
+
+    fn stop(&self) -> ReturnCode {
+        ReturnCode::SUCCESS
+    }
+
+    fn is_running(&self) -> bool {
+        false
+    }
+}
+
+impl Alarm<'a> for NullAlarm {
+    fn set_alarm_with_token(&self, _tics: u32, _token: u32) -> ReturnCode {
+        ReturnCode::SUCCESS
+    }
+
+    fn get_alarm(&self) -> u32 {
+        0
+    }
+
+    fn minimum_dt(&self) -> u32 {
+        0
+    }
+
+    fn set_client(&'a self, _client: &'a dyn AlarmClient) {}
+
+    fn is_enabled(&self) -> bool {
+        false
+    }
+
+    fn clear_client(&self) {}
+
+    fn enable(&self) -> ReturnCode {
+        ReturnCode::SUCCESS
+    }
+
+    fn disable(&self) -> ReturnCode {
+        ReturnCode::SUCCESS
+    }
+}
+
+/// A `Timer` whose clock only moves when told to.
+///
+/// `now()` starts at `0` and is moved forward with [`tick`](#method.tick),
+/// which synchronously calls the client at every oneshot or repeat boundary
+/// crossed, in order. A repeating timer fires once per elapsed interval even
+/// for a large jump, so capsules with retransmit or polling timers can be
+/// tested on the host as a whole.
+pub struct VirtualTimer<'a> {
+    now: Cell<u32>,
+    deadline: Cell<u32>,
+    interval: Cell<Option<u32>>,
+    /// Fires left of a `repeat_n` sequence, or `0` for no limit.
+    fires_left: Cell<u32>,
+    armed: Cell<bool>,
+    /// Tics that were left when the timer was paused.
+    paused: Cell<Option<u32>>,
+    /// Set while the client is being called.
+    firing: Cell<bool>,
+    client: OptionalCell<&'a dyn TimerClient>,
+}
+
+impl Default for VirtualTimer<'a> {
+    fn default() -> VirtualTimer<'a> {
+        VirtualTimer::new()
+    }
+}
+
+impl VirtualTimer<'a> {
+    pub fn new() -> VirtualTimer<'a> {
+        VirtualTimer {
+            now: Cell::new(0),
+            deadline: Cell::new(0),
+            interval: Cell::new(None),
+            fires_left: Cell::new(0),
+            armed: Cell::new(false),
+            paused: Cell::new(None),
+            firing: Cell::new(false),
+            client: OptionalCell::empty(),
+        }
+    }
+
+    /// Moves the clock forward by `n` tics.
+    ///
+    /// Each time the timer's deadline is reached on the way, the clock stops
+    /// there, a repeating timer is reloaded (or a oneshot disarmed) and the
+    /// client is called, and then the clock moves on. Timers the client arms
+    /// from its callback also fire within the same call if they fall before
+    /// the end of the tick.
+    pub fn tick(&self, n: u32) {
+        let mut remaining = n;
+        loop {
+            let until_deadline = self.deadline.get().wrapping_sub(self.now.get());
+            if !self.armed.get() || until_deadline > remaining {
+                break;
+            }
+            self.now.set(self.deadline.get());
+            remaining -= until_deadline;
+            match (self.interval.get(), self.fires_left.get()) {
+                (Some(_), 1) | (None, _) => {
+                    self.interval.set(None);
+                    self.armed.set(false);
+                }
+                (Some(interval), left) => {
+                    self.fires_left.set(left.saturating_sub(1));
+                    self.deadline
+                        .set(self.deadline.get().wrapping_add(interval));
+                }
+            }
+            self.firing.set(true);
+            self.client.map(|client| client.fired());
+            self.firing.set(false);
+        }
+        self.now.set(self.now.get().wrapping_add(remaining));
+    }
+
+    fn arm(&self, interval: u32, repeat: Option<u32>) {
+        self.deadline.set(self.now.get().wrapping_add(interval));
+        self.interval.set(repeat);
+        self.fires_left.set(0);
+        self.armed.set(true);
+        self.paused.set(None);
+    }
+}
+
+impl Time for VirtualTimer<'a> {
+    type Frequency = Freq1KHz;
+
+    fn now(&self) -> u32 {
+        self.now.get()
+    }
+}
+
+impl Timer<'a> for VirtualTimer<'a> {
+    fn set_client(&'a self, client: &'a dyn TimerClient) {
+        self.client.set(client);
+    }
+
+    fn oneshot(&self, interval: u32) {
+        self.arm(interval, None);
+    }
+
+    /// Panics if `interval` is `0`, which would fire forever within one
+    /// `tick`.
+    fn repeat(&self, interval: u32) {
+        assert!(interval > 0, "VirtualTimer cannot repeat every 0 tics");
+        self.arm(interval, Some(interval));
+    }
+
+    fn repeat_n(&self, interval: u32, count: u32) -> ReturnCode {
+        if interval == 0 {
+            return ReturnCode::EINVAL;
+        }
+        self.arm(interval, Some(interval));
+        self.fires_left.set(count);
+        ReturnCode::SUCCESS
+    }
+
+    fn interval(&self) -> Option<u32> {
+        if self.armed.get() {
+            self.interval.get()
+        } else {
+            None
+        }
+    }
+
+    fn change_interval(&self, interval: u32) -> ReturnCode {
+        if self.interval().is_none() {
+            return ReturnCode::EINVAL;
+        }
+        assert!(interval > 0, "VirtualTimer cannot repeat every 0 tics");
+        self.interval.set(Some(interval));
+        if self.firing.get() {
+            // The period starting with this fire has already been reloaded.
+            self.deadline.set(self.now.get().wrapping_add(interval));
+        }
+        ReturnCode::SUCCESS
+    }
+
+    fn time_remaining(&self) -> Option<u32> {
+        if self.armed.get() {
+            Some(self.deadline.get().wrapping_sub(self.now.get()))
+        } else {
+            None
+        }
+    }
+
+    fn cancel(&self) {
+        self.armed.set(false);
+        self.interval.set(None);
+        self.fires_left.set(0);
+        self.paused.set(None);
+    }
+
+    fn pause(&self) -> ReturnCode {
+        if self.paused.get().is_some() {
+            return ReturnCode::SUCCESS;
+        }
+        match self.time_remaining() {
+            Some(remaining) => {
+                self.paused.set(Some(remaining));
+                self.armed.set(false);
+                ReturnCode::SUCCESS
+            }
+            None => ReturnCode::EINVAL,
+        }
+    }
+
+    fn resume(&self) -> ReturnCode {
+        match self.paused.take() {
+            Some(remaining) => {
+                self.deadline.set(self.now.get().wrapping_add(remaining));
+                self.armed.set(true);
+                ReturnCode::SUCCESS
+            }
+            None => ReturnCode::EINVAL,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{CountingClient, MockAlarm, MockCounter, NullAlarm, VirtualTimer};
+    use crate::hil::time::{Alarm, AlarmClient, Counter, Time, Timer, TimerClient};
+    use crate::ReturnCode;
+    use core::cell::Cell;
+
+    struct Client<'a> {
+        alarm: &'a MockAlarm<'a>,
+        fired_at: Cell<Option<u32>>,
+        rearm: Cell<Option<u32>>,
+    }
+
+    impl AlarmClient for Client<'a> {
+        fn fired(&self) {
+            self.fired_at.set(Some(self.alarm.now()));
+            if let Some(dt) = self.rearm.take() {
+                self.alarm.set_alarm(self.alarm.now().wrapping_add(dt));
+            }
+        }
+    }
+
+    #[test]
+    fn test_fires_when_crossed() {
+        let alarm = MockAlarm::new();
+        let client = Client {
+            alarm: &alarm,
+            fired_at: Cell::new(None),
+            rearm: Cell::new(None),
+        };
+        alarm.set_client(&client);
+        alarm.set_alarm(10);
+
+        alarm.advance(9);
+        assert_eq!(client.fired_at.get(), None);
+        alarm.advance(5);
+        assert_eq!(client.fired_at.get(), Some(10));
+        assert_eq!(alarm.now(), 14);
+        assert!(!alarm.is_enabled());
+    }
+
+    #[test]
+    fn test_just_passed_deadline_fires_next_tic() {
+        let alarm = MockAlarm::new();
+        let client = Client {
+            alarm: &alarm,
+            fired_at: Cell::new(None),
+            rearm: Cell::new(None),
+        };
+        alarm.set_client(&client);
+        alarm.advance(10);
+        assert_eq!(alarm.set_alarm(9), ReturnCode::SUCCESS);
+        assert_eq!(alarm.get_alarm(), 11);
+
+        alarm.advance(1);
+        assert_eq!(client.fired_at.get(), Some(11));
+    }
+
+    #[test]
+    fn test_stopping_drops_alarm() {
+        let alarm = MockAlarm::new();
+        let client = CountingClient::with_clock(&alarm);
+        alarm.set_client(&client);
+        alarm.set_alarm(10);
+        alarm.advance(5);
+        alarm.stop();
+        assert!(!alarm.is_enabled());
+        assert_eq!(alarm.set_alarm(20), ReturnCode::EOFF);
+
+        // Restarts from 0 unless it persists in sleep.
+        alarm.advance(5);
+        alarm.start();
+        assert_eq!(alarm.now(), 0);
+        alarm.set_alarm(10);
+        alarm.advance(10);
+        assert_eq!((client.fires(), client.fired_at()), (1, Some(10)));
+
+        alarm.set_persists_in_sleep(true);
+        alarm.stop();
+        alarm.advance(5);
+        alarm.start();
+        assert_eq!(alarm.now(), 15);
+    }
+
+    #[test]
+    fn test_mock_counter_overflows() {
+        let counter = MockCounter::new();
+        let client = CountingClient::new();
+        counter.set_overflow_client(&client);
+        counter.set_max_tics(0xFFFF);
+        counter.set_now(0xFFF0);
+        counter.advance(0x0F);
+        assert_eq!((counter.now(), client.fires()), (0xFFFF, 0));
+        counter.advance(2);
+        assert_eq!((counter.now(), client.fires()), (1, 1));
+
+        // A stopped counter keeps its value.
+        counter.stop();
+        counter.advance(10);
+        assert_eq!(counter.now(), 1);
+    }
+
+    #[test]
+    fn test_rearm_within_advance() {
+        let alarm = MockAlarm::new();
+        let client = Client {
+            alarm: &alarm,
+            fired_at: Cell::new(None),
+            rearm: Cell::new(Some(10)),
+        };
+        alarm.set_client(&client);
+        alarm.set_alarm(10);
+
+        alarm.advance(25);
+        assert_eq!(client.fired_at.get(), Some(20));
+        assert_eq!(alarm.now(), 25);
+    }
+
+    #[test]
+    fn test_null_alarm() {
+        fn use_time<A: Alarm<'a, Ticks = u32> + Counter<'a>>(alarm: &A) {
+            assert_eq!(alarm.set_alarm(10), ReturnCode::SUCCESS);
+            assert_eq!(alarm.now(), 0);
+            assert!(!alarm.is_enabled());
+            assert!(!alarm.is_running());
+        }
+        use_time(&NullAlarm);
+    }
+
+    #[test]
+    fn test_enable_without_set_alarm() {
+        let alarm = MockAlarm::new();
+        assert_eq!(alarm.enable(), ReturnCode::EINVAL);
+        assert!(!alarm.is_enabled());
+    }
+
+    #[test]
+    fn test_enable_disable_transitions() {
+        let alarm = MockAlarm::new();
+        let client = Client {
+            alarm: &alarm,
+            fired_at: Cell::new(None),
+            rearm: Cell::new(None),
+        };
+        alarm.set_client(&client);
+
+        // Setting the alarm enables it.
+        assert_eq!(alarm.set_alarm(10), ReturnCode::SUCCESS);
+        assert!(alarm.is_enabled());
+
+        // Disabling twice is harmless.
+        assert_eq!(alarm.disable(), ReturnCode::SUCCESS);
+        assert_eq!(alarm.disable(), ReturnCode::SUCCESS);
+        assert!(!alarm.is_enabled());
+        alarm.advance(5);
+        assert_eq!(client.fired_at.get(), None);
+
+        // Enabling re-arms the deadline set before.
+        assert_eq!(alarm.enable(), ReturnCode::SUCCESS);
+        assert_eq!(alarm.get_alarm(), 10);
+        alarm.advance(5);
+        assert_eq!(client.fired_at.get(), Some(10));
+        assert!(!alarm.is_enabled());
+
+        // Once the deadline has passed there is nothing left to re-arm.
+        assert_eq!(alarm.enable(), ReturnCode::EINVAL);
+        assert_eq!(alarm.disable(), ReturnCode::SUCCESS);
+    }
+
+    #[test]
+    fn test_deadline_with_interrupts_disabled() {
+        let alarm = MockAlarm::new();
+        let client = Client {
+            alarm: &alarm,
+            fired_at: Cell::new(None),
+            rearm: Cell::new(None),
+        };
+        alarm.set_client(&client);
+        alarm.set_alarm(10);
+
+        alarm.set_interrupts_enabled(false);
+        alarm.advance(50);
+        assert_eq!(client.fired_at.get(), None);
+        // Fires as soon as interrupts are back, not after a wrap.
+        alarm.set_interrupts_enabled(true);
+        assert_eq!(client.fired_at.get(), Some(50));
+        assert!(!alarm.is_enabled());
+    }
+
+    #[test]
+    fn test_enable_after_deadline_passed() {
+        let alarm = MockAlarm::new();
+        let client = Client {
+            alarm: &alarm,
+            fired_at: Cell::new(None),
+            rearm: Cell::new(None),
+        };
+        alarm.set_client(&client);
+        alarm.set_alarm(10);
+        alarm.disable();
+        alarm.advance(50);
+
+        assert_eq!(alarm.enable(), ReturnCode::SUCCESS);
+        alarm.advance(1);
+        assert_eq!(client.fired_at.get(), Some(51));
+    }
+
+    #[test]
+    fn test_clear_client() {
+        let alarm = MockAlarm::new();
+        let client = Client {
+            alarm: &alarm,
+            fired_at: Cell::new(None),
+            rearm: Cell::new(None),
+        };
+        alarm.set_client(&client);
+        alarm.set_alarm(10);
+        alarm.clear_client();
+        assert!(!alarm.is_enabled());
+
+        // Nobody to call, even if the alarm is armed again.
+        alarm.set_alarm(20);
+        alarm.advance(50);
+        assert_eq!(client.fired_at.get(), None);
+
+        alarm.set_client(&client);
+        alarm.set_alarm(60);
+        alarm.advance(10);
+        assert_eq!(client.fired_at.get(), Some(60));
+    }
+
+    #[test]
+    fn test_auto_advance() {
+        let alarm = MockAlarm::new();
+        let client = Client {
+            alarm: &alarm,
+            fired_at: Cell::new(None),
+            rearm: Cell::new(None),
+        };
+        alarm.set_client(&client);
+        alarm.set_alarm(25);
+        alarm.set_auto_advance(10);
+
+        // A busy wait for 60 tics.
+        let start = alarm.now();
+        while alarm.now().wrapping_sub(start) < 60 {}
+        assert_eq!(alarm.now(), 80);
+        // The client's own read did not move the clock.
+        assert_eq!(client.fired_at.get(), Some(25));
+
+        alarm.set_auto_advance(0);
+        assert_eq!(alarm.now(), 80);
+    }
+
+    struct TimerClientLog<'a> {
+        timer: &'a VirtualTimer<'a>,
+        fired: Cell<usize>,
+        fired_at: Cell<u32>,
+        /// Interval to arm a oneshot for from the callback.
+        oneshot: Cell<Option<u32>>,
+    }
+
+    impl TimerClientLog<'a> {
+        fn new(timer: &'a VirtualTimer<'a>) -> TimerClientLog<'a> {
+            TimerClientLog {
+                timer,
+                fired: Cell::new(0),
+                fired_at: Cell::new(0),
+                oneshot: Cell::new(None),
+            }
+        }
+    }
+
+    impl TimerClient for TimerClientLog<'a> {
+        fn fired(&self) {
+            self.fired.set(self.fired.get() + 1);
+            self.fired_at.set(self.timer.now());
+            if let Some(interval) = self.oneshot.take() {
+                self.timer.oneshot(interval);
+            }
+        }
+    }
+
+    #[test]
+    fn test_virtual_timer_repeats_across_large_tick() {
+        let timer = VirtualTimer::new();
+        let client = TimerClientLog::new(&timer);
+        timer.set_client(&client);
+
+        timer.repeat(10);
+        timer.tick(1005);
+        assert_eq!(client.fired.get(), 100);
+        assert_eq!(client.fired_at.get(), 1000);
+        assert_eq!(timer.now(), 1005);
+        assert_eq!(timer.time_remaining(), Some(5));
+    }
+
+    #[test]
+    fn test_virtual_timer_repeat_n() {
+        let timer = VirtualTimer::new();
+        let client = TimerClientLog::new(&timer);
+        timer.set_client(&client);
+
+        assert_eq!(timer.repeat_n(0, 3), ReturnCode::EINVAL);
+        assert!(!timer.is_enabled());
+
+        assert_eq!(timer.repeat_n(10, 3), ReturnCode::SUCCESS);
+        timer.tick(25);
+        assert_eq!(client.fired.get(), 2);
+        assert!(timer.is_repeating());
+        timer.tick(100);
+        assert_eq!(client.fired.get(), 3);
+        assert_eq!(client.fired_at.get(), 30);
+        assert!(!timer.is_enabled());
+
+        // Cancelling drops the rest of the sequence.
+        timer.repeat_n(10, 3);
+        timer.tick(10);
+        timer.cancel();
+        timer.repeat(10);
+        timer.tick(100);
+        assert_eq!(client.fired.get(), 14);
+    }
+
+    #[test]
+    fn test_virtual_timer_oneshot_rearmed_from_callback() {
+        let timer = VirtualTimer::new();
+        let client = TimerClientLog::new(&timer);
+        timer.set_client(&client);
+
+        timer.oneshot(10);
+        client.oneshot.set(Some(7));
+        timer.tick(30);
+        assert_eq!(client.fired.get(), 2);
+        assert_eq!(client.fired_at.get(), 17);
+        assert!(!timer.is_enabled());
+
+        timer.tick(100);
+        assert_eq!(client.fired.get(), 2);
+    }
+
+    #[test]
+    fn test_virtual_timer_elapsed_in_period() {
+        let timer = VirtualTimer::new();
+        assert_eq!(timer.elapsed_in_period(), None);
+
+        timer.repeat(10);
+        timer.tick(3);
+        assert_eq!(timer.elapsed_in_period(), Some(3));
+        timer.tick(14);
+        assert_eq!(timer.elapsed_in_period(), Some(7));
+
+        // The period in progress is still 10 tics long.
+        timer.change_interval(4);
+        assert_eq!(timer.elapsed_in_period(), Some(1));
+        timer.tick(3);
+        assert_eq!(timer.elapsed_in_period(), Some(0));
+        timer.tick(5);
+        assert_eq!(timer.elapsed_in_period(), Some(1));
+
+        timer.oneshot(10);
+        assert_eq!(timer.elapsed_in_period(), None);
+        timer.cancel();
+        assert_eq!(timer.elapsed_in_period(), None);
+    }
+
+    #[test]
+    fn test_virtual_timer_pause_resume() {
+        let timer = VirtualTimer::new();
+        let client = TimerClientLog::new(&timer);
+        timer.set_client(&client);
+        assert_eq!(timer.pause(), ReturnCode::EINVAL);
+        assert_eq!(timer.resume(), ReturnCode::EINVAL);
+
+        timer.repeat(10);
+        timer.tick(13);
+        assert_eq!(timer.pause(), ReturnCode::SUCCESS);
+        assert_eq!(timer.pause(), ReturnCode::SUCCESS);
+        assert!(!timer.is_enabled());
+        timer.tick(100);
+        assert_eq!(client.fired.get(), 1);
+
+        // The 7 tics left, then every 10 tics again.
+        assert_eq!(timer.resume(), ReturnCode::SUCCESS);
+        timer.tick(6);
+        assert_eq!(client.fired.get(), 1);
+        timer.tick(11);
+        assert_eq!(client.fired.get(), 3);
+        assert_eq!(client.fired_at.get(), 130);
+        assert_eq!(timer.interval(), Some(10));
+
+        // A oneshot fires once after resuming.
+        timer.oneshot(10);
+        timer.tick(4);
+        timer.pause();
+        timer.tick(50);
+        timer.resume();
+        timer.tick(100);
+        assert_eq!(client.fired.get(), 4);
+        assert_eq!(client.fired_at.get(), 190);
+        assert!(!timer.is_enabled());
+
+        // Cancelling drops the paused state.
+        timer.repeat(10);
+        timer.pause();
+        timer.cancel();
+        assert_eq!(timer.resume(), ReturnCode::EINVAL);
+    }
+}
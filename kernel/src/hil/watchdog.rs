@@ -1,14 +1,24 @@
 //! Interface for a watchdog timer.
 
+use crate::ReturnCode;
+
 pub trait Watchdog {
-    /// Enable the watchdog timer. Period is the time in milliseconds
-    /// the watchdog will timeout if not serviced.
-    fn start(&self, period: usize);
+    /// Enable the watchdog timer. `period_ms` is the time in milliseconds
+    /// after which the watchdog resets the chip if it is not serviced.
+    ///
+    /// Implementations convert `period_ms` to the hardware timeout with the
+    /// [`Frequency`](../time/trait.Frequency.html) conversions of the watchdog
+    /// clock and pick the closest timeout the hardware supports. Returns
+    /// `ReturnCode::EINVAL` if `period_ms` cannot be configured.
+    fn start(&self, period_ms: u32) -> ReturnCode;
 
     /// Disable the watchdog timer.
-    fn stop(&self);
+    fn stop(&self) -> ReturnCode;
 
     /// Service the watchdog to let the hardware know the application
     /// is still executing.
+    ///
+    /// This must be called more frequently than the `period_ms` passed to
+    /// `start`, or the watchdog resets the chip.
     fn tickle(&self);
 }